@@ -46,7 +46,7 @@ pub fn process() {
     process_pixels(
         Arc::new(screen.clone()),
         Arc::new(camera),
-        Arc::new(world),
+        Arc::new(Scene::new(world)),
         Arc::new(render_config),
         pixel_pb,
     );
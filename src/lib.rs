@@ -9,20 +9,11 @@ pub mod gen {
     pub fn create_random_world() -> HittableList {
         let mut world = HittableList::new();
 
-        let material_ground = Arc::new(Material::Lambertian {
-            albedo: Vec3::new(0.8, 0.8, 0.0),
-        });
-        let material_center = Arc::new(Material::Lambertian {
-            albedo: Vec3::new(0.1, 0.2, 0.5),
-        });
-        let material_left = Arc::new(Material::Dielectric {
-            index_of_refraction: 1.5,
-        });
-        // let material_left = Arc::new(Material::Metal { albedo: Vec3::new(0.8, 0.8, 0.8), fuzz: 0.3 });
-        let material_right = Arc::new(Material::Metal {
-            albedo: Vec3::new(0.8, 0.6, 0.2),
-            fuzz: 0.0,
-        });
+        let material_ground = Arc::new(Material::lambertian(Vec3::new(0.8, 0.8, 0.0)));
+        let material_center = Arc::new(Material::lambertian(Vec3::new(0.1, 0.2, 0.5)));
+        let material_left = Arc::new(Material::dielectric(1.5));
+        // let material_left = Arc::new(Material::metal(Vec3::new(0.8, 0.8, 0.8), 0.3));
+        let material_right = Arc::new(Material::metal(Vec3::new(0.8, 0.6, 0.2), 0.0));
 
         // Some objects: Spheres
         let sphere_1: Arc<dyn Hittable> = Arc::new(Sphere::new(
@@ -34,23 +25,23 @@ pub mod gen {
         let sphere_2: Arc<dyn Hittable> =
             Arc::new(Sphere::new(Point::new(0., 0., -1.), 0.5, material_center));
 
-        let sphere_3: Arc<dyn Hittable> = Arc::new(Sphere::new(
-            Point::new(-1., 0., -1.),
-            0.5,
-            material_left.clone(),
+        // A hollow glass ball: the outer sphere with a smaller one carved out of it.
+        let sphere_3: Arc<dyn Hittable> = Arc::new(Csg::difference(
+            Arc::new(Sphere::new(
+                Point::new(-1., 0., -1.),
+                0.5,
+                material_left.clone(),
+            )),
+            Arc::new(Sphere::new(Point::new(-1., 0., -1.), 0.4, material_left)),
         ));
 
         let sphere_4: Arc<dyn Hittable> =
             Arc::new(Sphere::new(Point::new(1., 0., -1.), 0.5, material_right));
 
-        let sphere_5: Arc<dyn Hittable> =
-            Arc::new(Sphere::new(Point::new(-1., 0., -1.), -0.4, material_left));
-
         world.push(sphere_1);
         world.push(sphere_2);
         world.push(sphere_3);
         world.push(sphere_4);
-        world.push(sphere_5);
         world
     }
 
@@ -59,9 +50,7 @@ pub mod gen {
 
         let mut world = HittableList::new();
 
-        let ground_material = Material::Lambertian {
-            albedo: Vec3::new(0.5, 0.5, 0.5),
-        };
+        let ground_material = Material::lambertian(Vec3::new(0.5, 0.5, 0.5));
 
         let ground_sphere = Sphere::new(Point::new(0., -1000., 0.), 1000., Arc::new(ground_material));
 
@@ -80,20 +69,18 @@ pub mod gen {
 
                         if choice_of_material < 0.8 {
                             // diffuse: lambertian (matte)
-                            Material::Lambertian {
-                                albedo: Vec3::rand_uniform(0., 1.) * Vec3::rand_uniform(0., 1.),
-                            }
+                            Material::lambertian(
+                                Vec3::rand_uniform(0., 1.) * Vec3::rand_uniform(0., 1.),
+                            )
                         } else if choice_of_material < 0.95 {
                             // metal
-                            Material::Metal {
-                                albedo: Vec3::rand_uniform(0.5, 1.),
-                                fuzz: rng.gen_range((0.)..(0.5)),
-                            }
+                            Material::metal(
+                                Vec3::rand_uniform(0.5, 1.),
+                                rng.gen_range((0.)..(0.5)),
+                            )
                         } else {
                             // glass : dielectric
-                            Material::Dielectric {
-                                index_of_refraction: 1.5,
-                            }
+                            Material::dielectric(1.5)
                         }
                     };
 
@@ -106,16 +93,9 @@ pub mod gen {
             }
         }
 
-        let material_1 = Arc::new(Material::Dielectric {
-            index_of_refraction: 1.5,
-        });
-        let material_2 = Arc::new(Material::Lambertian {
-            albedo: Vec3::new(0.4, 0.2, 0.1),
-        });
-        let material_3 = Arc::new(Material::Metal {
-            albedo: Vec3::new(0.7, 0.6, 0.5),
-            fuzz: 0.0,
-        });
+        let material_1 = Arc::new(Material::dielectric(1.5));
+        let material_2 = Arc::new(Material::lambertian(Vec3::new(0.4, 0.2, 0.1)));
+        let material_3 = Arc::new(Material::metal(Vec3::new(0.7, 0.6, 0.5), 0.0));
 
         world.push(Arc::new(Sphere::new(
             Point::new(0., 1., 0.),
@@ -135,4 +115,84 @@ pub mod gen {
 
         world
     }
+
+    /// Procedural shapes that spheres cannot express, rendered by sphere tracing distance fields.
+    pub fn create_sdf_world() -> HittableList {
+        let mut world = HittableList::new();
+
+        let ground_material = Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5)));
+        world.push(Arc::new(Sphere::new(
+            Point::new(0., -1000., 0.),
+            1000.,
+            ground_material,
+        )));
+
+        // A Mandelbulb in the middle.
+        let mut mandelbulb = SdfHittable::new(
+            Arc::new(Mandelbulb::new(Point::new(0., 1.2, 0.), 1.)),
+            Arc::new(Material::lambertian(Vec3::new(0.7, 0.3, 0.2))),
+        );
+        mandelbulb.step_scale = 0.5;
+        world.push(Arc::new(mandelbulb));
+
+        // Two blobs melting into each other.
+        let blobs = SdfSmoothUnion {
+            a: Arc::new(SdfSphere {
+                center: Point::new(-3.3, 0.6, 0.),
+                radius: 0.6,
+            }),
+            b: Arc::new(SdfSphere {
+                center: Point::new(-2.6, 0.9, 0.4),
+                radius: 0.45,
+            }),
+            smoothness: 0.4,
+        };
+        world.push(Arc::new(SdfHittable::new(
+            Arc::new(blobs),
+            Arc::new(Material::dielectric(1.5)),
+        )));
+
+        // A twisted, rounded column.
+        let column = SdfTranslate {
+            sdf: Arc::new(SdfTwist {
+                sdf: Arc::new(SdfRound {
+                    sdf: Arc::new(SdfBox {
+                        center: Point::new(0., 1., 0.),
+                        half_extents: Vec3::new(0.3, 1., 0.3),
+                    }),
+                    radius: 0.05,
+                }),
+                rate: 1.5,
+            }),
+            offset: Vec3::new(3., 0., 0.),
+        };
+        let mut column = SdfHittable::new(
+            Arc::new(column),
+            Arc::new(Material::metal(Vec3::new(0.8, 0.8, 0.9), 0.1)),
+        );
+        column.step_scale = 0.5;
+        world.push(Arc::new(column));
+
+        // A field of small tori repeated along the ground, clipped to a slab behind the scene.
+        let tori = SdfIntersection(
+            Arc::new(SdfRepeat {
+                sdf: Arc::new(SdfTorus {
+                    center: Point::new(0., 0.1, 0.),
+                    major_radius: 0.3,
+                    minor_radius: 0.08,
+                }),
+                period: Vec3::new(1., 0., 1.),
+            }),
+            Arc::new(SdfBox {
+                center: Point::new(0., 0.1, -4.),
+                half_extents: Vec3::new(6., 0.2, 1.5),
+            }),
+        );
+        world.push(Arc::new(SdfHittable::new(
+            Arc::new(tori),
+            Arc::new(Material::lambertian(Vec3::new(0.2, 0.4, 0.7))),
+        )));
+
+        world
+    }
 }
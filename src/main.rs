@@ -34,7 +34,10 @@ fn main() {
     );
 
     // Render.
-    let render_config = RenderConfig::new(32, 8);
+    let render_config = parse_args(RenderConfig::new(32, 8)).unwrap_or_else(|error| {
+        eprintln!("{}\n{}", error, USAGE);
+        std::process::exit(2);
+    });
 
     // Progress bar.
     // Draw every 1% to prevent frequent Rwlock-ing.
@@ -44,7 +47,7 @@ fn main() {
     let pixels = process_pixels(
         Arc::new(screen.clone()),
         Arc::new(camera),
-        Arc::new(world),
+        Arc::new(Scene::new(world)),
         Arc::new(render_config),
         pixel_pb,
     );
@@ -65,4 +68,30 @@ fn main() {
     .unwrap();
     progress_bar.set_position(total_bytes_written as u64);
     progress_bar.finish();
-}
\ No newline at end of file
+}
+
+const USAGE: &str = "Usage: tracer [--clay] [--ao RADIUS SAMPLES]";
+
+/// Adjust `render_config` by the command line: `--clay` renders every object in gray clay, and
+/// `--ao RADIUS SAMPLES` renders ambient occlusion instead of light.
+fn parse_args(mut render_config: RenderConfig) -> Result<RenderConfig, String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--clay" => render_config.clay = true,
+            "--ao" => {
+                let radius = args
+                    .next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .ok_or("--ao needs a numeric RADIUS.")?;
+                let samples = args
+                    .next()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .ok_or("--ao needs a whole number of SAMPLES.")?;
+                render_config.integrator = Integrator::AmbientOcclusion { radius, samples };
+            }
+            _ => return Err(format!("Unknown argument {}.", arg)),
+        }
+    }
+    Ok(render_config)
+}
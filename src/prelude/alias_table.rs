@@ -0,0 +1,124 @@
+/// Constant time sampling of a discrete distribution (Vose, "A Linear Algorithm for Generating
+/// Random Numbers with a Given Distribution", 1991).
+#[derive(Clone, Debug, Default)]
+pub struct AliasTable {
+    bins: Vec<AliasBin>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AliasBin {
+    /// Probability of choosing this entry.
+    pmf: f64,
+    /// Probability of keeping this bin's own entry once the bin is picked.
+    threshold: f64,
+    alias: usize,
+}
+
+impl AliasTable {
+    /// A table choosing each entry in proportion to its non-negative weight.
+    pub fn new(weights: &[f64]) -> Self {
+        let total = weights.iter().map(|weight| weight.max(0.)).sum::<f64>();
+        if total <= 0. || !total.is_finite() {
+            return Self {
+                bins: weights
+                    .iter()
+                    .map(|_| AliasBin {
+                        pmf: 0.,
+                        threshold: 0.,
+                        alias: 0,
+                    })
+                    .collect(),
+            };
+        }
+
+        let count = weights.len() as f64;
+        let mut bins = weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| AliasBin {
+                pmf: weight.max(0.) / total,
+                threshold: 0.,
+                alias: index,
+            })
+            .collect::<Vec<_>>();
+
+        // Fill bins that are under the average from ones that are over it.
+        let scaled = bins.iter().map(|bin| bin.pmf * count).collect::<Vec<_>>();
+        let (mut under, mut over): (Vec<_>, Vec<_>) = (0..bins.len())
+            .map(|index| (index, scaled[index]))
+            .partition(|(_, p)| *p < 1.);
+        while let (Some((small, small_p)), Some((large, large_p))) = (under.pop(), over.pop()) {
+            bins[small].threshold = small_p;
+            bins[small].alias = large;
+
+            let remainder = large_p + small_p - 1.;
+            match remainder < 1. {
+                true => under.push((large, remainder)),
+                false => over.push((large, remainder)),
+            }
+        }
+        // What is left is within rounding of full.
+        for (index, _) in under.into_iter().chain(over) {
+            bins[index].threshold = 1.;
+            bins[index].alias = index;
+        }
+
+        Self { bins }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bins.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+
+    /// An entry and its probability for `u` uniform in [0, 1), or `None` if all weights are zero.
+    pub fn sample(&self, u: f64) -> Option<(usize, f64)> {
+        if self.bins.is_empty() {
+            return None;
+        }
+        let scaled = u * self.bins.len() as f64;
+        let bin = (scaled as usize).min(self.bins.len() - 1);
+        let index = match scaled - (bin as f64) < self.bins[bin].threshold {
+            true => bin,
+            false => self.bins[bin].alias,
+        };
+        let pmf = self.bins[index].pmf;
+        (pmf > 0.).then_some((index, pmf))
+    }
+
+    /// The probability of `sample` choosing `index`.
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.bins[index].pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AliasTable;
+
+    #[test]
+    fn entries_are_chosen_in_proportion_to_their_weights() {
+        let weights = [1., 0., 5., 2., 0.5, 1.5];
+        let table = AliasTable::new(&weights);
+        let total = weights.iter().sum::<f64>();
+
+        let samples = 200_000;
+        let mut counts = [0; 6];
+        for i in 0..samples {
+            let (index, pmf) = table.sample((i as f64 + 0.5) / samples as f64).unwrap();
+            assert_eq!(pmf, table.pmf(index));
+            counts[index] += 1;
+        }
+        for (index, weight) in weights.iter().enumerate() {
+            assert!((table.pmf(index) - weight / total).abs() < 1e-12);
+            let frequency = counts[index] as f64 / samples as f64;
+            assert!((frequency - weight / total).abs() < 1e-3, "{}", index);
+        }
+
+        assert!(AliasTable::new(&[0., 0.]).sample(0.3).is_none());
+        assert!(AliasTable::new(&[]).sample(0.3).is_none());
+    }
+}
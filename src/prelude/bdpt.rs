@@ -0,0 +1,715 @@
+//! Bidirectional path tracing (Veach, "Robust Monte Carlo Methods for Light Transport Simulation",
+//! 1997): a path from the camera and a path from a light, joined at every pair of their vertices
+//! and weighted against each other with the power heuristic.
+//!
+//! Lights at infinity, the background and emissive geometry are only found from the camera, as
+//! `ray_color` finds them. Scattering is taken to be symmetric, so refraction does not scale light
+//! traced from the lights.
+use crate::prelude::{
+    power_heuristic, rng, Bsdf, Camera, HitRecord, LinAlgOp, Material, Point, Ray, Scatter, Scene,
+    Vec3,
+};
+use rand::Rng;
+use std::borrow::Cow;
+
+/// Light reaching the camera along `ray` over paths of up to `max_depth` bounces. Paths from the
+/// lights that reach the camera directly land elsewhere on the image, so they are passed to
+/// `splat` with where they cross the viewport instead.
+pub fn bidirectional_color(
+    ray: &Ray,
+    camera: &Camera,
+    scene: &Scene,
+    max_depth: isize,
+    splat: &mut impl FnMut((f64, f64), Vec3),
+) -> Vec3 {
+    if max_depth <= 0 {
+        return Vec3::default();
+    }
+    let max_depth = max_depth as usize;
+    let (camera_path, mut color) = camera_subpath(ray, camera, scene, max_depth);
+    let light_path = light_subpath(ray, scene, max_depth);
+
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            if s + t < 2 || s + t - 2 > max_depth {
+                continue;
+            }
+            match (s, t) {
+                // Found by the camera path on its own, or not at all.
+                (0, _) | (1, 1) => {}
+                (_, 1) => connect_to_camera(scene, camera, &light_path[..s], splat),
+                (1, _) => color += connect_to_light(scene, camera, &camera_path[..t]),
+                _ => color += connect(scene, camera, &light_path[..s], &camera_path[..t]),
+            }
+        }
+    }
+    color
+}
+
+#[derive(Clone, Debug)]
+enum VertexKind {
+    Camera,
+    /// The index of the light in the scene.
+    Light(usize),
+    /// With the ray that arrived there.
+    Surface(Box<HitRecord>, Ray),
+}
+
+#[derive(Clone, Debug)]
+struct Vertex {
+    kind: VertexKind,
+    point: Point,
+    /// The true surface normal, which turns densities per solid angle into ones per area. Zero in
+    /// media, at the camera and at lights at a single point.
+    normal: Vec3,
+    /// The throughput of the path up to the vertex, divided by the density of sampling it.
+    beta: Vec3,
+    /// Density per area of the path sampling the vertex from the one before it.
+    pdf_fwd: f64,
+    /// Density per area of the other path sampling the vertex from the one after it.
+    pdf_rev: f64,
+    /// Scattered through a delta lobe, which no connection can reach.
+    is_delta: bool,
+}
+
+impl Vertex {
+    fn camera(ray: &Ray) -> Self {
+        Self {
+            kind: VertexKind::Camera,
+            point: ray.origin,
+            normal: Vec3::default(),
+            beta: Vec3::new(1., 1., 1.),
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            is_delta: false,
+        }
+    }
+
+    fn light(index: usize, point: Point, normal: Vec3, beta: Vec3, pdf_fwd: f64) -> Self {
+        Self {
+            kind: VertexKind::Light(index),
+            point,
+            normal,
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.,
+            is_delta: false,
+        }
+    }
+
+    /// Reached from `previous` along `ray`, whose direction was sampled with the density `pdf`
+    /// per solid angle.
+    fn surface(hit_record: HitRecord, ray: Ray, beta: Vec3, previous: &Vertex, pdf: f64) -> Self {
+        let normal = match *hit_record.material {
+            Material::Isotropic { .. } => Vec3::default(),
+            _ => hit_record.geometric_normal,
+        };
+        let mut vertex = Self {
+            point: hit_record.point,
+            kind: VertexKind::Surface(Box::new(hit_record), ray),
+            normal,
+            beta,
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            is_delta: false,
+        };
+        vertex.pdf_fwd = previous.convert_density(pdf, &vertex);
+        vertex
+    }
+
+    /// A density per solid angle of the direction to `next` as one per area there.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let offset = next.point - self.point;
+        let distance2 = offset.norm_squared();
+        if distance2 == 0. {
+            return 0.;
+        }
+        let cos_theta = match next.normal == Vec3::default() {
+            true => 1.,
+            false => offset.dot(next.normal).abs() / distance2.sqrt(),
+        };
+        pdf * cos_theta / distance2
+    }
+
+    /// How a surface scatters the light it receives from `next` back along the ray that reached
+    /// it, times the cosine there.
+    fn f(&self, next: &Vertex) -> Vec3 {
+        let VertexKind::Surface(hit_record, ray) = &self.kind else {
+            return Vec3::default();
+        };
+        let direction = (next.point - self.point).unit_vector();
+        match hit_record.agrees_with_geometry(direction) {
+            true => hit_record.material.eval(ray, hit_record, direction),
+            false => Vec3::default(),
+        }
+    }
+
+    /// Density per area at `next` of sampling it from this vertex, arriving from `previous`.
+    fn pdf(&self, scene: &Scene, camera: &Camera, previous: Option<&Vertex>, next: &Vertex) -> f64 {
+        let direction = (next.point - self.point).unit_vector();
+        let pdf = match &self.kind {
+            VertexKind::Camera => camera.pdf(&Ray::new(&self.point, &direction)).1,
+            VertexKind::Light(_) => return self.pdf_light(scene, next),
+            VertexKind::Surface(hit_record, ray) => {
+                let incoming = previous.map_or(*ray, |previous| {
+                    ray.spawn(&previous.point, &(self.point - previous.point))
+                });
+                let hit_record = facing(hit_record, incoming.direction);
+                hit_record.material.pdf(&incoming, &hit_record, direction)
+            }
+        };
+        self.convert_density(pdf, next)
+    }
+
+    /// Density per area at `next` of a light at this vertex sending light to it.
+    fn pdf_light(&self, scene: &Scene, next: &Vertex) -> f64 {
+        let VertexKind::Light(index) = self.kind else {
+            return 0.;
+        };
+        let direction = (next.point - self.point).unit_vector();
+        scene.lights[index]
+            .emission_pdf(self.point, direction)
+            .map_or(0., |pdf| self.convert_density(pdf.direction, next))
+    }
+
+    /// Density per area of a path from the lights starting at this vertex, towards `next`.
+    fn pdf_light_origin(&self, scene: &Scene, next: &Vertex) -> f64 {
+        let VertexKind::Light(index) = self.kind else {
+            return 0.;
+        };
+        let direction = (next.point - self.point).unit_vector();
+        scene.lights[index]
+            .emission_pdf(self.point, direction)
+            .map_or(0., |pdf| scene.emitter_table().pmf(index) * pdf.position)
+    }
+}
+
+/// `hit_record` as a ray arriving along `direction` sees it, which may be from the other side.
+fn facing(hit_record: &HitRecord, direction: Vec3) -> Cow<'_, HitRecord> {
+    if direction.dot(hit_record.geometric_normal) <= 0. {
+        return Cow::Borrowed(hit_record);
+    }
+    let mut flipped = hit_record.clone();
+    flipped.normal = -flipped.normal;
+    flipped.geometric_normal = -flipped.geometric_normal;
+    flipped.is_front_facing = !flipped.is_front_facing;
+    Cow::Owned(flipped)
+}
+
+/// The normal lights are picked for at a hit, zero in media.
+fn sampling_normal(hit_record: &HitRecord) -> Vec3 {
+    match *hit_record.material {
+        Material::Isotropic { .. } => Vec3::default(),
+        _ => hit_record.normal,
+    }
+}
+
+/// Whether dispersion has left a spectral path with only its hero wavelength, whose throughput
+/// then carries the share of the others.
+fn is_hero_only(ray: &Ray) -> bool {
+    ray.wavelengths
+        .is_some_and(|lambda| lambda[1] == lambda[0] && lambda[2] == lambda[0])
+}
+
+/// Extend `path` from its last vertex along `ray`, whose direction was sampled with the density
+/// `pdf` per solid angle, until it has `max_vertices` or stops scattering. `visit` sees each ray
+/// with the path it leaves, what it hits and the throughput along it.
+fn random_walk(
+    scene: &Scene,
+    mut ray: Ray,
+    mut beta: Vec3,
+    mut pdf: f64,
+    max_vertices: usize,
+    path: &mut Vec<Vertex>,
+    mut visit: impl FnMut(&[Vertex], &Ray, Option<&HitRecord>, Vec3, f64),
+) {
+    loop {
+        let hit_record = scene
+            .hit(&ray, 0.001, f64::INFINITY)
+            .map(|hit_record| hit_record.with_shading(&ray));
+        visit(path, &ray, hit_record.as_ref(), beta, pdf);
+        let Some(hit_record) = hit_record else {
+            return;
+        };
+
+        let vertex = Vertex::surface(hit_record.clone(), ray, beta, path.last().unwrap(), pdf);
+        path.push(vertex);
+        if path.len() >= max_vertices {
+            return;
+        }
+
+        let material = &hit_record.material;
+        let Some(sample) = material.sample(&ray, &hit_record) else {
+            return;
+        };
+        // Shading normals can send rays through the true surface, which would leak light.
+        if !hit_record.agrees_with_geometry(sample.ray.direction) {
+            return;
+        }
+        let pdf_rev = match sample.is_delta {
+            true => 0.,
+            false => {
+                let wi = sample.ray.direction.unit_vector();
+                let reverse = sample.ray.spawn(&(hit_record.point + wi), &(-wi));
+                let hit_record = facing(&hit_record, reverse.direction);
+                material.pdf(&reverse, &hit_record, -ray.direction.unit_vector())
+            }
+        };
+
+        let count = path.len();
+        path[count - 1].is_delta = sample.is_delta;
+        path[count - 2].pdf_rev = path[count - 1].convert_density(pdf_rev, &path[count - 2]);
+
+        beta = beta * sample.weight;
+        pdf = match sample.is_delta {
+            true => 0.,
+            false => sample.pdf,
+        };
+        ray = sample.ray;
+        if beta == Vec3::default() {
+            return;
+        }
+    }
+}
+
+/// The path from the camera along `ray`, with the light it finds on its own.
+fn camera_subpath(
+    ray: &Ray,
+    camera: &Camera,
+    scene: &Scene,
+    max_depth: usize,
+) -> (Vec<Vertex>, Vec3) {
+    let mut path = vec![Vertex::camera(ray)];
+    let mut color = Vec3::default();
+    let (_, pdf) = camera.pdf(ray);
+    // One more vertex than bounces, whose emission may still be seen.
+    random_walk(
+        scene,
+        *ray,
+        Vec3::new(1., 1., 1.),
+        pdf,
+        max_depth + 2,
+        &mut path,
+        |path, ray, hit_record, beta, pdf| {
+            color += beta * found_light(scene, camera, path, ray, hit_record, pdf);
+        },
+    );
+    (path, color)
+}
+
+/// A path from a light picked by power, empty if no light sends any.
+fn light_subpath(ray: &Ray, scene: &Scene, max_depth: usize) -> Vec<Vertex> {
+    let mut path = vec![];
+    let Some((index, pmf)) = scene.emitter_table().sample(rng().gen()) else {
+        return path;
+    };
+    let Some(emission) = scene.lights[index].sample_emission(ray) else {
+        return path;
+    };
+    let pdf = emission.pdf;
+    if pdf.direction <= 0. {
+        return path;
+    }
+
+    let pdf_origin = pmf * pdf.position;
+    path.push(Vertex::light(
+        index,
+        emission.ray.origin,
+        pdf.normal,
+        emission.radiance / pdf_origin,
+        pdf_origin,
+    ));
+    let cos_theta = match pdf.is_delta_position() {
+        true => 1.,
+        false => emission.ray.direction.dot(pdf.normal).abs(),
+    };
+    random_walk(
+        scene,
+        emission.ray,
+        emission.radiance * (cos_theta / (pdf_origin * pdf.direction)),
+        pdf.direction,
+        max_depth + 1,
+        &mut path,
+        |_, _, _, _, _| {},
+    );
+    path
+}
+
+/// Light that `ray`, leaving the end of the camera `path` in a direction sampled with density
+/// `pdf`, finds by itself: lights in front of what it hits, its emission, or the background.
+fn found_light(
+    scene: &Scene,
+    camera: &Camera,
+    path: &[Vertex],
+    ray: &Ray,
+    hit_record: Option<&HitRecord>,
+    pdf: f64,
+) -> Vec3 {
+    let last = path.last().unwrap();
+    let t_max = hit_record.map_or(f64::INFINITY, |hit_record| hit_record.time);
+    let direction = ray.direction.unit_vector();
+
+    // The nearest light in front of whatever the ray hits, or all lights at infinity if it escapes.
+    let light_hits = scene
+        .lights
+        .iter()
+        .enumerate()
+        .filter_map(|(index, light)| Some((index, light.hit(ray)?)))
+        .filter(|(_, (time, _))| *time > 0.001 && *time <= t_max)
+        .collect::<Vec<_>>();
+    let nearest = light_hits
+        .iter()
+        .map(|(_, (time, _))| *time)
+        .fold(f64::INFINITY, f64::min);
+    let mut color = Vec3::default();
+    for (index, (time, radiance)) in light_hits {
+        if time != nearest {
+            continue;
+        }
+        let weight = match time.is_finite() {
+            true => {
+                let point = ray.at(time);
+                let normal = scene.lights[index]
+                    .emission_pdf(point, -direction)
+                    .map_or(Vec3::default(), |pdf| pdf.normal);
+                let mut vertex = Vertex::light(index, point, normal, Vec3::default(), 0.);
+                vertex.pdf_fwd = last.convert_density(pdf, &vertex);
+                mis_weight(scene, camera, &[], None, path, &vertex)
+            }
+            false => match &last.kind {
+                VertexKind::Surface(hit_record, _)
+                    if !last.is_delta && hit_record.material.flags(hit_record).non_delta =>
+                {
+                    let selection = scene.light_sampler().pmf(
+                        hit_record.point,
+                        sampling_normal(hit_record),
+                        index,
+                    );
+                    let light_pdf = selection * scene.lights[index].pdf(ray.origin, direction);
+                    power_heuristic(pdf, light_pdf)
+                }
+                _ => 1.,
+            },
+        };
+        color += radiance * weight;
+    }
+
+    color
+        + match hit_record {
+            Some(hit_record) => hit_record.material.emitted(ray, hit_record),
+            None => scene.background.radiance(ray),
+        }
+}
+
+/// Light from one of the scene's lights, sampled from the end of `camera_path`.
+fn connect_to_light(scene: &Scene, camera: &Camera, camera_path: &[Vertex]) -> Vec3 {
+    let pt = camera_path.last().unwrap();
+    let VertexKind::Surface(hit_record, ray) = &pt.kind else {
+        return Vec3::default();
+    };
+    let material = &hit_record.material;
+    if !material.flags(hit_record).non_delta {
+        return Vec3::default();
+    }
+
+    let normal = sampling_normal(hit_record);
+    let Some((index, selection)) =
+        scene
+            .light_sampler()
+            .sample(hit_record.point, normal, rng().gen())
+    else {
+        return Vec3::default();
+    };
+    let light = &scene.lights[index];
+    let Some(sample) = light.sample(hit_record.point, ray) else {
+        return Vec3::default();
+    };
+    if !hit_record.agrees_with_geometry(sample.direction) {
+        return Vec3::default();
+    }
+    let value = material.eval(ray, hit_record, sample.direction);
+    if value == Vec3::default() {
+        return Vec3::default();
+    }
+    let shadow_ray = ray.spawn(&hit_record.point, &sample.direction);
+    if scene
+        .hit(&shadow_ray, 0.001, sample.distance - 0.001)
+        .is_some()
+    {
+        return Vec3::default();
+    }
+
+    let (light_pdf, weight) = match (sample.is_delta(), sample.distance.is_finite()) {
+        (true, false) => (selection, 1.),
+        (false, false) => {
+            let light_pdf = selection * sample.pdf;
+            let bsdf_pdf = material.pdf(ray, hit_record, sample.direction);
+            (light_pdf, power_heuristic(light_pdf, bsdf_pdf))
+        }
+        (is_delta, true) => {
+            let point = hit_record.point + sample.direction * sample.distance;
+            let Some(emission) = light.emission_pdf(point, -sample.direction) else {
+                return Vec3::default();
+            };
+            let mut vertex = Vertex::light(index, point, emission.normal, Vec3::default(), 0.);
+            vertex.pdf_fwd = vertex.pdf_light_origin(scene, pt);
+            let weight = mis_weight(
+                scene,
+                camera,
+                &[],
+                Some(&vertex),
+                &camera_path[..camera_path.len() - 1],
+                pt,
+            );
+            let light_pdf = match is_delta {
+                true => selection,
+                false => selection * sample.pdf,
+            };
+            (light_pdf, weight)
+        }
+    };
+    pt.beta * value * sample.radiance * (weight / light_pdf)
+}
+
+/// Join the end of `light_path` straight to the camera, splatting the light it brings.
+fn connect_to_camera(
+    scene: &Scene,
+    camera: &Camera,
+    light_path: &[Vertex],
+    splat: &mut impl FnMut((f64, f64), Vec3),
+) {
+    let qs = light_path.last().unwrap();
+    let VertexKind::Surface(_, ray) = &qs.kind else {
+        return;
+    };
+    let Some(lens) = camera.sample_lens(qs.point) else {
+        return;
+    };
+    let pt = Vertex::camera(&Ray::new(&lens.origin, &(qs.point - lens.origin)));
+    let value = qs.f(&pt);
+    if value == Vec3::default() {
+        return;
+    }
+    let to_lens = lens.origin - qs.point;
+    let shadow_ray = ray.spawn(&qs.point, &to_lens.unit_vector());
+    if scene
+        .hit(&shadow_ray, 0.001, to_lens.norm() - 0.001)
+        .is_some()
+    {
+        return;
+    }
+
+    let weight = mis_weight(
+        scene,
+        camera,
+        &light_path[..light_path.len() - 1],
+        Some(qs),
+        &[],
+        &pt,
+    );
+    splat(
+        lens.viewport,
+        qs.beta * value * (lens.importance * weight / lens.pdf),
+    );
+}
+
+/// Join the ends of two paths that have both left their first vertex.
+fn connect(scene: &Scene, camera: &Camera, light_path: &[Vertex], camera_path: &[Vertex]) -> Vec3 {
+    let (qs, pt) = (light_path.last().unwrap(), camera_path.last().unwrap());
+    let (VertexKind::Surface(_, light_ray), VertexKind::Surface(_, camera_ray)) =
+        (&qs.kind, &pt.kind)
+    else {
+        return Vec3::default();
+    };
+    let value = pt.f(qs) * qs.f(pt);
+    if value == Vec3::default() {
+        return Vec3::default();
+    }
+    let offset = qs.point - pt.point;
+    let distance = offset.norm();
+    let shadow_ray = camera_ray.spawn(&pt.point, &(offset / distance));
+    if scene.hit(&shadow_ray, 0.001, distance - 0.001).is_some() {
+        return Vec3::default();
+    }
+
+    // Each path's hero carries the other wavelengths' share, which only one of them may count.
+    let hero_share = match is_hero_only(light_ray) && is_hero_only(camera_ray) {
+        true => 1. / 3.,
+        false => 1.,
+    };
+    let weight = mis_weight(
+        scene,
+        camera,
+        &light_path[..light_path.len() - 1],
+        Some(qs),
+        &camera_path[..camera_path.len() - 1],
+        pt,
+    );
+    pt.beta * value * qs.beta * (hero_share * weight / (distance * distance))
+}
+
+/// The power heuristic weight of joining `qs`, which ends `light_path`, to `pt`, which ends
+/// `camera_path`, against every other way of sampling the same path. Without `qs`, `pt` is on a
+/// light.
+fn mis_weight(
+    scene: &Scene,
+    camera: &Camera,
+    light_path: &[Vertex],
+    qs: Option<&Vertex>,
+    camera_path: &[Vertex],
+    pt: &Vertex,
+) -> f64 {
+    let s = light_path.len() + qs.is_some() as usize;
+    let t = camera_path.len() + 1;
+    if s + t == 2 {
+        return 1.;
+    }
+    let (pt_minus, qs_minus) = (camera_path.last(), light_path.last());
+
+    // Densities and delta flags along both paths, with those around the join as the joined path
+    // has them.
+    let densities = |vertex: &Vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.is_delta);
+    let mut camera_pdfs = camera_path
+        .iter()
+        .chain([pt])
+        .map(densities)
+        .collect::<Vec<_>>();
+    let mut light_pdfs = light_path
+        .iter()
+        .chain(qs)
+        .map(densities)
+        .collect::<Vec<_>>();
+
+    camera_pdfs[t - 1] = match qs {
+        Some(qs) => (pt.pdf_fwd, qs.pdf(scene, camera, qs_minus, pt), false),
+        None => (
+            pt.pdf_fwd,
+            pt.pdf_light_origin(scene, pt_minus.unwrap()),
+            false,
+        ),
+    };
+    if let Some(pt_minus) = pt_minus {
+        camera_pdfs[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(scene, camera, Some(qs), pt_minus),
+            None => pt.pdf_light(scene, pt_minus),
+        };
+    }
+    if let Some(qs) = qs {
+        light_pdfs[s - 1] = (qs.pdf_fwd, pt.pdf(scene, camera, pt_minus, qs), false);
+        if let Some(qs_minus) = qs_minus {
+            light_pdfs[s - 2].1 = qs.pdf(scene, camera, Some(pt), qs_minus);
+        }
+    }
+    let is_delta_light = light_path
+        .first()
+        .or(qs)
+        .is_some_and(|light| light.normal == Vec3::default());
+
+    // Ratios of the density of each other strategy to this one's, moving the join along the path.
+    let remap = |pdf: f64| if pdf != 0. { pdf } else { 1. };
+    let mut sum = 0.;
+    let mut ratio = 1.;
+    for i in (1..t).rev() {
+        ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+        // Lights are never joined straight to the camera.
+        let is_light_to_camera = i == 1 && s + t - i == 1;
+        if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 && !is_light_to_camera {
+            sum += ratio * ratio;
+        }
+    }
+    ratio = 1.;
+    for i in (0..s).rev() {
+        ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+        let is_delta_before = match i {
+            0 => is_delta_light,
+            _ => light_pdfs[i - 1].2,
+        };
+        if !light_pdfs[i].2 && !is_delta_before {
+            sum += ratio * ratio;
+        }
+    }
+    1. / (1. + sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::bidirectional_color;
+    use crate::prelude::test_scenes::{camera, glass_ball, room};
+    use crate::prelude::{
+        luminance, ray_color, Camera, Light, Point, PointLight, Scene, SphereLight, Vec3,
+    };
+    use rand::{thread_rng, Rng};
+    use std::sync::Arc;
+
+    /// A glass ball, if it has an index of refraction, over a white floor in a black room, lit
+    /// from above.
+    fn scene(light: Arc<dyn Light>, index_of_refraction: Option<f64>) -> Scene {
+        room(
+            0.7,
+            index_of_refraction.into_iter().map(glass_ball).collect(),
+            light,
+        )
+    }
+
+    /// The average luminance over the viewport, with the light splatted onto it.
+    fn average(scene: &Scene, camera: &Camera, samples: usize, bidirectional: bool) -> f64 {
+        let mut rng = thread_rng();
+        let mut splats = 0.;
+        let mut total = 0.;
+        for _ in 0..samples {
+            let ray = camera.get_ray(rng.gen(), rng.gen());
+            total += luminance(match bidirectional {
+                true => bidirectional_color(&ray, camera, scene, 6, &mut |(s, t), color| {
+                    if (0. ..1.).contains(&s) && (0. ..1.).contains(&t) {
+                        splats += luminance(color);
+                    }
+                }),
+                false => ray_color(&ray, scene, 6),
+            });
+        }
+        (total + splats) / samples as f64
+    }
+
+    #[test]
+    fn agrees_with_path_tracing() {
+        let scene = scene(
+            Arc::new(SphereLight::new(
+                Point::new(0.5, 3., 0.),
+                0.4,
+                Vec3::new(8., 8., 8.),
+            )),
+            Some(1.5),
+        );
+        let camera = camera(Point::new(0., 3., 3.), Point::new(0., 0.5, 0.), 50.);
+        let (bidirectional, unidirectional) = (
+            average(&scene, &camera, 100_000, true),
+            average(&scene, &camera, 200_000, false),
+        );
+        assert!(
+            (bidirectional - unidirectional).abs() < 0.03 * unidirectional,
+            "{} and {}",
+            bidirectional,
+            unidirectional
+        );
+    }
+
+    #[test]
+    fn focuses_point_lights_through_glass() {
+        // Looking at the floor in the shadow of the ball, which path tracing leaves dark since it
+        // cannot find a point light through glass.
+        let camera = camera(Point::new(0., 2., 4.), Point::new(0., 0., 0.75), 12.);
+        let light = Arc::new(PointLight::new(
+            Point::new(0., 3., -1.5),
+            Vec3::new(4., 4., 4.),
+            0.,
+        ));
+        let (with_ball, without_ball) = (scene(light.clone(), Some(1.5)), scene(light, None));
+        let unlit = average(&with_ball, &camera, 20_000, false);
+        let lit = average(&without_ball, &camera, 20_000, false);
+        assert!(unlit < 0.1 * lit, "{} and {}", unlit, lit);
+
+        // The ball gathers the light it shadows into a caustic brighter than the bare floor.
+        let caustic = average(&with_ball, &camera, 50_000, true);
+        assert!(caustic > 1.3 * lit, "{} and {}", caustic, lit);
+    }
+}
@@ -0,0 +1,259 @@
+//! Evaluating and sampling how materials scatter light, for integrators that combine strategies.
+//!
+//! Directions are unit vectors pointing away from the surface: `wo` back along the incoming ray
+//! (which is passed in its place, as it also carries the wavelengths) and `wi` towards where light
+//! arrives from.
+use crate::prelude::{HitRecord, Ray, Vec3};
+use std::f64::consts::PI;
+
+pub trait Bsdf {
+    /// Sample a direction to continue a path in.
+    fn sample(&self, ray: &Ray, hit_record: &HitRecord) -> Option<BsdfSample>;
+    /// The scattering function times the cosine between `wi` and the shading normal, so that
+    /// `eval / pdf` is the weight of a sample in `wi`. Phase functions have no cosine. Delta
+    /// lobes are left out. Layered materials may return an unbiased estimate.
+    fn eval(&self, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> Vec3;
+    /// The density per solid angle of `sample` choosing `wi`, leaving out delta lobes. Layered
+    /// materials may return an approximation, which is still fine for weighting strategies.
+    fn pdf(&self, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> f64;
+    fn flags(&self, hit_record: &HitRecord) -> BsdfFlags;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    /// `eval / pdf` of the sampled direction, or its estimate.
+    pub weight: Vec3,
+    pub ray: Ray,
+    /// Zero for delta lobes.
+    pub pdf: f64,
+    /// Sampled from a delta lobe, which `eval` and `pdf` cannot see.
+    pub is_delta: bool,
+}
+
+impl BsdfSample {
+    pub fn new(weight: Vec3, ray: Ray, pdf: f64) -> Self {
+        Self {
+            weight,
+            ray,
+            pdf,
+            is_delta: false,
+        }
+    }
+
+    pub fn delta(weight: Vec3, ray: Ray) -> Self {
+        Self {
+            weight,
+            ray,
+            pdf: 0.,
+            is_delta: true,
+        }
+    }
+}
+
+/// Which kinds of lobes a material has at a point.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BsdfFlags {
+    /// Perfectly specular lobes, which only sampling can find.
+    pub delta: bool,
+    /// Lobes with a density, which `eval` and `pdf` describe and lights can be sampled for.
+    pub non_delta: bool,
+    /// Lobes `sample` draws from a density `pdf` only approximates, so that their weights can not
+    /// be found again as `eval / pdf`.
+    pub approximate_pdf: bool,
+}
+
+impl BsdfFlags {
+    pub const DELTA: BsdfFlags = BsdfFlags {
+        delta: true,
+        non_delta: false,
+        approximate_pdf: false,
+    };
+    pub const NON_DELTA: BsdfFlags = BsdfFlags {
+        delta: false,
+        non_delta: true,
+        approximate_pdf: false,
+    };
+
+    pub fn union(self, other: BsdfFlags) -> BsdfFlags {
+        BsdfFlags {
+            delta: self.delta || other.delta,
+            non_delta: self.non_delta || other.non_delta,
+            approximate_pdf: self.approximate_pdf || other.approximate_pdf,
+        }
+    }
+}
+
+/// The density of the direction of `center + radius * p` for `p` uniform in the unit ball, where
+/// `center` is a unit vector. This is how fuzzy metals perturb their reflections.
+pub fn fuzz_pdf(direction: Vec3, center: Vec3, radius: f64) -> f64 {
+    let cos_theta = direction.0 * center.0 + direction.1 * center.1 + direction.2 * center.2;
+    let sin2_theta = 1. - cos_theta * cos_theta;
+    let radius2 = radius * radius;
+    if sin2_theta > radius2 {
+        return 0.;
+    }
+
+    // Integrate r² dr along the direction through the ball.
+    let half_chord = (radius2 - sin2_theta).sqrt();
+    let (near, far) = (cos_theta - half_chord, cos_theta + half_chord);
+    let swept = match radius < 1. {
+        true if cos_theta <= 0. => return 0.,
+        true => (far.powi(3) - near.powi(3)) / 3.,
+        false => far.powi(3) / 3.,
+    };
+    swept / (4. / 3. * PI * radius2 * radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fuzz_pdf, Bsdf};
+    use crate::prelude::{
+        ComplexIor, HitRecord, Hittable, LinAlgOp, LinAlgRandGen, Material, Point, Principled, Ray,
+        SolidColor, Sphere, Vec3,
+    };
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    const SAMPLES: usize = 50_000;
+
+    fn hit(material: Material, inside: bool) -> (Ray, HitRecord) {
+        let sphere = Sphere::new(Point::new(0., 0., 0.), 1., Arc::new(material));
+        let direction = Vec3::new(0.6, 0., if inside { 0.8 } else { -0.8 });
+        let ray = Ray::new(&(Point::new(0., 0., 1.) - direction), &direction);
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        (ray, hit)
+    }
+
+    /// Directions jittered within a grid of `rows` bands of latitude by `columns` of longitude, each
+    /// covering the same solid angle. They estimate integrals over the sphere far more steadily
+    /// than independent directions, where integrands have narrow peaks.
+    fn stratified_directions(rows: usize, columns: usize) -> impl Iterator<Item = Vec3> {
+        (0..rows * columns).map(move |cell| {
+            let cos_theta =
+                -1. + 2. * ((cell / columns) as f64 + rand::random::<f64>()) / rows as f64;
+            let phi = 2. * PI * ((cell % columns) as f64 + rand::random::<f64>()) / columns as f64;
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+        })
+    }
+
+    #[test]
+    fn fuzz_pdf_integrates_to_one() {
+        let samples = 300_000;
+        for radius in [0.5, 0.9, 1.5] {
+            let center = Vec3::new(0.3, 0.4, 0.5).unit_vector();
+            let integral = (0..samples)
+                .map(|_| fuzz_pdf(Vec3::random_unit_vector(), center, radius))
+                .sum::<f64>()
+                * 4.
+                * PI
+                / samples as f64;
+            assert!((integral - 1.).abs() < 0.03, "{}: {}", radius, integral);
+        }
+    }
+
+    #[test]
+    fn samples_agree_with_eval_and_pdf() {
+        let principled = Principled {
+            roughness: Arc::new(SolidColor::gray(0.5)),
+            transmission: Arc::new(SolidColor::gray(0.5)),
+            ..Principled::default()
+        };
+        let materials = [
+            Material::lambertian(Vec3::new(0.8, 0.5, 0.2)),
+            Material::metal(Vec3::new(0.9, 0.8, 0.7), 0.6),
+            Material::rough_conductor(ComplexIor::GOLD, 0.5),
+            Material::rough_dielectric(1.5, 0.6),
+            Material::Principled(Box::new(principled)),
+            Material::isotropic(Vec3::new(0.5, 0.5, 0.5)),
+            Material::mix(
+                Material::lambertian(Vec3::new(0.8, 0.8, 0.8)),
+                Material::rough_conductor(ComplexIor::COPPER, 0.5),
+                0.5,
+            ),
+            Material::coated(Material::lambertian(Vec3::new(0.8, 0.3, 0.3)), 1.5, 0.5),
+        ];
+
+        for (index, material) in materials.into_iter().enumerate() {
+            for inside in [false, true] {
+                let (ray, hit) = hit(material.clone(), inside);
+                assert!(material.flags(&hit).non_delta);
+
+                // The mean weight of samples is the albedo, which integrating eval finds too. Where
+                // the pdf is exact, directions are drawn from it half of the time to cut variance.
+                let is_exact = !material.flags(&hit).approximate_pdf;
+                let mut sampled = Vec3::default();
+                let mut integrated = Vec3::default();
+                for _ in 0..SAMPLES {
+                    let sample = material.sample(&ray, &hit);
+                    if let Some(sample) = sample {
+                        assert!(!sample.is_delta && sample.pdf > 0.);
+                        sampled += sample.weight;
+                    }
+
+                    let wi = match sample {
+                        Some(sample) if is_exact && rand::random::<bool>() => {
+                            sample.ray.direction.unit_vector()
+                        }
+                        None if is_exact && rand::random::<bool>() => continue,
+                        _ => Vec3::random_unit_vector(),
+                    };
+                    let density = match is_exact {
+                        true => 0.5 * material.pdf(&ray, &hit, wi) + 0.5 / (4. * PI),
+                        false => 1. / (4. * PI),
+                    };
+                    integrated += material.eval(&ray, &hit, wi) / density;
+                }
+                let (sampled, integrated) = (sampled / SAMPLES as f64, integrated / SAMPLES as f64);
+                assert!(
+                    (sampled - integrated).norm() < 0.04,
+                    "material {} (inside: {}): sampled {} but evaluated {}",
+                    index,
+                    inside,
+                    sampled,
+                    integrated
+                );
+
+                // Sampled densities are the pdf of the sampled direction, which integrates to at
+                // most one, and weights are eval / pdf where neither is estimated.
+                let sample = std::iter::repeat_with(|| material.sample(&ray, &hit))
+                    .flatten()
+                    .next()
+                    .unwrap();
+                let wi = sample.ray.direction.unit_vector();
+                let pdf = material.pdf(&ray, &hit, wi);
+                if !matches!(material, Material::Coated { .. } | Material::Mix { .. }) {
+                    assert!((sample.pdf - pdf).abs() < 1e-6 * pdf.max(1.));
+                    let weight = material.eval(&ray, &hit, wi) / pdf;
+                    assert!((sample.weight - weight).norm() < 1e-6, "material {}", index);
+                }
+                let integral = stratified_directions(400, SAMPLES / 400)
+                    .map(|wi| material.pdf(&ray, &hit, wi))
+                    .sum::<f64>()
+                    * 4.
+                    * PI
+                    / SAMPLES as f64;
+                // Stratified, the integral varies by under 0.005 between runs.
+                assert!(integral < 1.01, "material {}: {}", index, integral);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_materials_are_delta() {
+        for material in [
+            Material::dielectric(1.5),
+            Material::metal(Vec3::new(0.9, 0.9, 0.9), 0.),
+            Material::thin_dielectric(1.5),
+        ] {
+            let (ray, hit) = hit(material.clone(), false);
+            assert_eq!(material.flags(&hit), super::BsdfFlags::DELTA);
+
+            let sample = material.sample(&ray, &hit).unwrap();
+            assert!(sample.is_delta);
+            let wi = sample.ray.direction.unit_vector();
+            assert_eq!(material.eval(&ray, &hit, wi), Vec3::default());
+            assert_eq!(material.pdf(&ray, &hit, wi), 0.);
+        }
+    }
+}
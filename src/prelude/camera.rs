@@ -1,4 +1,4 @@
-use crate::prelude::{LinAlgOp, LinAlgRandGen, Point, Ray, Vec3};
+use crate::prelude::{LinAlgOp, LinAlgRandGen, Point, Ray, RayDifferentials, Vec3};
 
 #[derive(Clone, Debug)]
 pub struct Camera {
@@ -32,6 +32,15 @@ pub enum Angle {
     Radians(Radians),
 }
 
+impl From<Angle> for Radians {
+    fn from(angle: Angle) -> Self {
+        match angle {
+            Angle::Radians(radians) => radians,
+            Angle::Degrees(degrees) => Radians::from(degrees),
+        }
+    }
+}
+
 impl Camera {
     pub fn new(
         look_from: Point,
@@ -42,10 +51,7 @@ impl Camera {
         aperture: f64,
         focus_distance: f64,
     ) -> Self {
-        let theta = match vertical_field_of_view {
-            Angle::Radians(radians) => radians.0,
-            Angle::Degrees(degrees) => Radians::from(degrees).0,
-        };
+        let theta = Radians::from(vertical_field_of_view).0;
 
         let h = (theta / 2.).tan();
         let viewport_height = 2.0 * h;
@@ -84,6 +90,97 @@ impl Camera {
                 - offset),
         )
     }
+
+    /// Like `get_ray`, but also trace out the rays through `(s + ds, t)` and `(s, t + dt)`, where
+    /// `ds` and `dt` are the size of a pixel in viewport coordinates.
+    pub fn get_ray_with_differentials(&self, s: f64, t: f64, ds: f64, dt: f64) -> Ray {
+        let mut ray = self.get_ray(s, t);
+
+        // Reuse the lens sample so that the offsets only differ by the pixel step.
+        let focus_point = ray.origin + ray.direction;
+        ray.differentials = Some(RayDifferentials {
+            x_origin: ray.origin,
+            x_direction: focus_point + ds * self.horizontal - ray.origin,
+            y_origin: ray.origin,
+            y_direction: focus_point + dt * self.vertical - ray.origin,
+        });
+        ray
+    }
+
+    /// How much a ray leaving the lens at `ray.origin` counts towards the image, with where it
+    /// crosses the viewport in the coordinates `get_ray` takes. Importance integrates to one over
+    /// the lens and the directions through the viewport.
+    pub fn importance(&self, ray: &Ray) -> Option<(f64, (f64, f64))> {
+        let direction = ray.direction.unit_vector();
+        let cos_theta = -direction.dot(self.w);
+        if cos_theta <= 0. {
+            return None;
+        }
+        let focus_distance = self.focus_distance();
+        let offset = ray.origin + direction * (focus_distance / cos_theta) - self.lower_left_corner;
+        let viewport = (
+            offset.dot(self.horizontal) / self.horizontal.norm_squared(),
+            offset.dot(self.vertical) / self.vertical.norm_squared(),
+        );
+        let (_, pdf_direction) = self.pdf(ray);
+        Some((pdf_direction / (self.lens_area() * cos_theta), viewport))
+    }
+
+    /// The densities of `get_ray` generating `ray`: per area of the lens (one for a pinhole) and
+    /// per solid angle of its direction.
+    pub fn pdf(&self, ray: &Ray) -> (f64, f64) {
+        let cos_theta = -ray.direction.unit_vector().dot(self.w);
+        if cos_theta <= 0. {
+            return (0., 0.);
+        }
+        let focus_distance = self.focus_distance();
+        let viewport_area = self.horizontal.norm() * self.vertical.norm();
+        (
+            1. / self.lens_area(),
+            focus_distance * focus_distance / (viewport_area * cos_theta.powi(3)),
+        )
+    }
+
+    /// A point on the lens to join `point` to, for paths traced from lights.
+    pub fn sample_lens(&self, point: Point) -> Option<LensSample> {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+        let origin = self.origin + self.u * rd.0 + self.v * rd.1;
+        let to_point = point - origin;
+        let distance = to_point.norm();
+        let (importance, viewport) = self.importance(&Ray::new(&origin, &to_point))?;
+        let cos_theta = -to_point.dot(self.w) / distance;
+        Some(LensSample {
+            origin,
+            importance,
+            pdf: distance * distance / (cos_theta * self.lens_area()),
+            viewport,
+        })
+    }
+
+    fn focus_distance(&self) -> f64 {
+        (self.origin - self.lower_left_corner - self.horizontal / 2. - self.vertical / 2.)
+            .dot(self.w)
+    }
+
+    /// One for a pinhole, whose single point stands in for the lens.
+    fn lens_area(&self) -> f64 {
+        match self.lens_radius > 0. {
+            true => std::f64::consts::PI * self.lens_radius * self.lens_radius,
+            false => 1.,
+        }
+    }
+}
+
+/// A point on the lens seen from a point in the scene.
+#[derive(Clone, Copy, Debug)]
+pub struct LensSample {
+    pub origin: Point,
+    /// Of the ray from `origin` to the point.
+    pub importance: f64,
+    /// Density per solid angle of the direction from the point to `origin`.
+    pub pdf: f64,
+    /// Where the ray crosses the viewport, in the coordinates `get_ray` takes.
+    pub viewport: (f64, f64),
 }
 
 #[derive(Clone, Debug, Default)]
@@ -102,3 +199,65 @@ impl Image {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Angle, Camera, Degrees};
+    use crate::prelude::{Onb, Point, Ray, Vec3};
+    use rand::{thread_rng, Rng};
+    use std::f64::consts::PI;
+
+    fn camera(aperture: f64) -> Camera {
+        Camera::new(
+            Point::new(1., 2., 3.),
+            Point::new(0., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Angle::Degrees(Degrees(40.)),
+            1.5,
+            aperture,
+            2.,
+        )
+    }
+
+    #[test]
+    fn importance_finds_where_rays_cross_the_viewport() {
+        let (camera, mut rng) = (camera(0.5), thread_rng());
+        for _ in 0..100 {
+            let (s, t) = (rng.gen(), rng.gen());
+            let (_, viewport) = camera.importance(&camera.get_ray(s, t)).unwrap();
+            assert!((viewport.0 - s).abs() < 1e-9 && (viewport.1 - t).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn importance_integrates_to_one() {
+        // By the midpoint rule over a cone of directions from a pinhole that holds the viewport,
+        // counting only those through it.
+        let camera = camera(0.);
+        let basis = Onb::new(-camera.w);
+        let cos_max = (PI / 4.).cos();
+        let steps = 1000;
+        let cell = (1. - cos_max) * 2. * PI / (steps * steps) as f64;
+        let mut total = 0.;
+        for i in 0..steps {
+            let cos_theta = cos_max + (1. - cos_max) * (i as f64 + 0.5) / steps as f64;
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            for j in 0..steps {
+                let phi = 2. * PI * (j as f64 + 0.5) / steps as f64;
+                let direction = basis.local(Vec3::new(
+                    sin_theta * phi.cos(),
+                    sin_theta * phi.sin(),
+                    cos_theta,
+                ));
+                if let Some((importance, (s, t))) =
+                    camera.importance(&Ray::new(&camera.origin, &direction))
+                {
+                    if (0. ..1.).contains(&s) && (0. ..1.).contains(&t) {
+                        total += importance * cos_theta * cell;
+                    }
+                }
+            }
+        }
+        assert!((total - 1.).abs() < 1e-3, "{}", total);
+    }
+}
@@ -29,6 +29,33 @@ impl From<Pixel> for Vec3 {
     }
 }
 
+/// Decode an sRGB-encoded channel value in `[0, 1]` to linear light.
+pub fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear channel value in `[0, 1]` with the sRGB transfer curve.
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Convert CIE XYZ to linear sRGB (D65 white point).
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    Vec3(
+        3.240_454_2 * xyz.0 - 1.537_138_5 * xyz.1 - 0.498_531_4 * xyz.2,
+        -0.969_266_0 * xyz.0 + 1.876_010_8 * xyz.1 + 0.041_556_0 * xyz.2,
+        0.055_643_4 * xyz.0 - 0.204_025_9 * xyz.1 + 1.057_225_2 * xyz.2,
+    )
+}
+
 pub fn write_pixel<W: Write>(writer: &mut W, pixel: Pixel) -> Result<usize> {
     let mut total_bytes_written: usize = 0;
 
@@ -123,6 +150,15 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn srgb_transfer_roundtrip() {
+        for value in [0., 0.002, 0.04, 0.2, 0.5, 0.9, 1.] {
+            let roundtrip = super::linear_to_srgb(super::srgb_to_linear(value));
+            assert!((roundtrip - value).abs() < 1e-12);
+        }
+        assert!((super::srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+
     #[test]
     fn convert_from_vec3() {
         let v = Vec3::new(0.5, 0.3, 0.2);
@@ -0,0 +1,258 @@
+use crate::prelude::{HitRecord, Hittable, Ray};
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// Everything in the left child that is not in the right child.
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(&self, inside_left: bool, inside_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_left || inside_right,
+            CsgOperation::Intersection => inside_left && inside_right,
+            CsgOperation::Difference => inside_left && !inside_right,
+        }
+    }
+}
+
+/// A boolean combination of two closed objects.
+///
+/// Both children are intersected along the ray from `max_distance` behind its origin, and the
+/// combined solid's boundary is wherever being inside the result changes while walking through
+/// the children's hits in order.
+pub struct Csg {
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
+    pub operation: CsgOperation,
+    /// How far behind a ray's origin the children are looked for, to tell whether it starts
+    /// inside them. Parts of the solid further back are missed.
+    pub max_distance: f64,
+}
+
+impl Csg {
+    pub fn new(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>, operation: CsgOperation) -> Self {
+        Self {
+            left,
+            right,
+            operation,
+            max_distance: 1e4,
+        }
+    }
+
+    pub fn union(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        Self::new(left, right, CsgOperation::Union)
+    }
+
+    pub fn intersection(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        Self::new(left, right, CsgOperation::Intersection)
+    }
+
+    pub fn difference(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        Self::new(left, right, CsgOperation::Difference)
+    }
+
+    /// Where the ray crosses the boundary of the result, up to `t_max`.
+    fn boundaries(&self, ray: &Ray, t_max: f64) -> Vec<HitRecord> {
+        // Children need not handle unbounded queries.
+        let t_min = -self.max_distance / ray.direction.norm();
+        let left_hits = self.left.hit_all(ray, t_min, t_max);
+        let right_hits = self.right.hit_all(ray, t_min, t_max);
+
+        // If a child's first hit is an exit, the ray started inside it.
+        let mut inside_left = left_hits.first().is_some_and(|hit| !hit.is_front_facing);
+        let mut inside_right = right_hits.first().is_some_and(|hit| !hit.is_front_facing);
+
+        let mut events = left_hits
+            .into_iter()
+            .map(|hit| (true, hit))
+            .chain(right_hits.into_iter().map(|hit| (false, hit)))
+            .collect::<Vec<(bool, HitRecord)>>();
+        events.sort_by(|(_, hit_1), (_, hit_2)| {
+            hit_1
+                .time
+                .partial_cmp(&hit_2.time)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        let mut boundaries = vec![];
+        for (from_left, hit) in events {
+            let was_inside = self.operation.contains(inside_left, inside_right);
+            if from_left {
+                inside_left = hit.is_front_facing;
+            } else {
+                inside_right = hit.is_front_facing;
+            }
+            let is_inside = self.operation.contains(inside_left, inside_right);
+
+            if was_inside == is_inside {
+                continue;
+            }
+
+            // Whether the child's normals, which face the ray, need to turn around. The carved out
+            // part of a difference faces into the right child.
+            let carved = !from_left && self.operation == CsgOperation::Difference;
+            let flip = if (hit.is_front_facing != is_inside) != carved {
+                -1.
+            } else {
+                1.
+            };
+
+            boundaries.push(HitRecord {
+                normal: flip * hit.normal,
+                geometric_normal: flip * hit.geometric_normal,
+                is_front_facing: is_inside,
+                ..hit
+            });
+        }
+        boundaries
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.boundaries(ray, t_max)
+            .into_iter()
+            .find(|hit| t_min <= hit.time && hit.time <= t_max)
+    }
+
+    fn hit_all(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        self.boundaries(ray, t_max)
+            .into_iter()
+            .filter(|hit| t_min <= hit.time && hit.time <= t_max)
+            .collect()
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "Csg {{ operation: {:?}, left: {}, right: {} }}",
+            self.operation,
+            self.left.metadata(),
+            self.right.metadata()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Csg;
+    use crate::prelude::{
+        Hittable, Material, Point, Ray, SdfBox, SdfHittable, SdfSphere, Sphere, Vec3,
+    };
+    use std::sync::Arc;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn sphere(x: f64, radius: f64) -> Arc<dyn Hittable> {
+        let material = Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5)));
+        Arc::new(Sphere::new(Point::new(x, 0., 0.), radius, material))
+    }
+
+    fn along_x() -> Ray {
+        Ray::new(&Point::new(-10., 0., 0.), &Vec3::new(1., 0., 0.))
+    }
+
+    fn times(hittable: &dyn Hittable) -> Vec<(f64, bool)> {
+        hittable
+            .hit_all(&along_x(), 0., f64::INFINITY)
+            .into_iter()
+            .map(|hit| (hit.time, hit.is_front_facing))
+            .collect()
+    }
+
+    fn assert_times(observed: Vec<(f64, bool)>, expected: Vec<(f64, bool)>) {
+        assert_eq!(
+            observed.len(),
+            expected.len(),
+            "{:?} vs {:?}",
+            observed,
+            expected
+        );
+        for ((t1, front1), (t2, front2)) in observed.into_iter().zip(expected) {
+            assert!((t1 - t2).abs() < TOLERANCE, "{} vs {}", t1, t2);
+            assert_eq!(front1, front2);
+        }
+    }
+
+    #[test]
+    fn lens_is_intersection_of_two_spheres() {
+        let lens = Csg::intersection(sphere(-0.5, 1.), sphere(0.5, 1.));
+        // Enters the right sphere at x = -0.5 and leaves the left sphere at x = 0.5.
+        assert_times(times(&lens), vec![(9.5, true), (10.5, false)]);
+    }
+
+    #[test]
+    fn union_merges_overlapping_intervals() {
+        let union = Csg::union(sphere(-0.5, 1.), sphere(0.5, 1.));
+        assert_times(times(&union), vec![(8.5, true), (11.5, false)]);
+    }
+
+    #[test]
+    fn difference_hollows_out_and_flips_inner_normals() {
+        let shell = Csg::difference(sphere(0., 1.), sphere(0., 0.5));
+        assert_times(
+            times(&shell),
+            vec![(9., true), (9.5, false), (10.5, true), (11., false)],
+        );
+
+        // Leaving the shell into the cavity, the normal still faces the incoming ray.
+        let cavity_wall = shell.hit_all(&along_x(), 0., f64::INFINITY)[1].clone();
+        assert!((cavity_wall.normal - Vec3::new(-1., 0., 0.)).norm() < TOLERANCE);
+    }
+
+    #[test]
+    fn ray_starting_inside_the_result() {
+        let shell = Csg::difference(sphere(0., 1.), sphere(0., 0.5));
+        let ray = Ray::new(&Point::new(-0.75, 0., 0.), &Vec3::new(1., 0., 0.));
+        let first = shell.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((first.time - 0.25).abs() < TOLERANCE);
+        assert!(!first.is_front_facing);
+    }
+
+    #[test]
+    fn children_other_than_spheres() {
+        let material = Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5)));
+        let traced = |x: f64, radius: f64| -> Arc<dyn Hittable> {
+            Arc::new(SdfHittable::new(
+                Arc::new(SdfSphere {
+                    center: Point::new(x, 0., 0.),
+                    radius,
+                }),
+                material.clone(),
+            ))
+        };
+        // Sphere tracing is only as exact as its epsilon.
+        let assert_close = |observed: Vec<(f64, bool)>, expected: Vec<(f64, bool)>| {
+            assert_eq!(observed.len(), expected.len(), "{:?}", observed);
+            for ((t1, front1), (t2, front2)) in observed.into_iter().zip(expected) {
+                assert!((t1 - t2).abs() < 1e-3, "{} vs {}", t1, t2);
+                assert_eq!(front1, front2);
+            }
+        };
+
+        let union = Csg::union(traced(-0.5, 1.), sphere(0.5, 1.));
+        assert_close(times(&union), vec![(8.5, true), (11.5, false)]);
+        let shell = Csg::difference(sphere(0., 1.), traced(0., 0.5));
+        assert_close(
+            times(&shell),
+            vec![(9., true), (9.5, false), (10.5, true), (11., false)],
+        );
+
+        // A box rounded off by a sphere, entered where the box is.
+        let cube: Arc<dyn Hittable> = Arc::new(SdfHittable::new(
+            Arc::new(SdfBox {
+                center: Point::new(0., 0., 0.),
+                half_extents: Vec3::new(1., 1., 1.),
+            }),
+            material,
+        ));
+        let ray = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -1.));
+        let rounded = Csg::intersection(cube, sphere(0., 1.2));
+        let hit = rounded.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        assert!((hit.time - 4.).abs() < 1e-3 && hit.is_front_facing);
+    }
+}
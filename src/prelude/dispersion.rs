@@ -0,0 +1,64 @@
+/// The wavelength, in nanometres, of the helium d-line that catalogue refractive indices quote.
+pub const D_LINE: f64 = 587.56;
+
+/// How the index of refraction of a transparent material varies with wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispersion {
+    /// `n = a + b / λ²`, with `λ` in micrometres.
+    Cauchy { a: f64, b: f64 },
+    /// `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, with `λ` in micrometres.
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    /// Schott N-BK7, the common crown glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+    /// Schott SF11, a dense flint glass that splits white light strongly.
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.737_596_95, 0.313_747_346, 1.898_781_01],
+        c: [0.013_188_707, 0.062_306_814_2, 155.236_29],
+    };
+
+    pub fn index_of_refraction(&self, wavelength: f64) -> f64 {
+        let micrometres = wavelength * 1e-3;
+        let squared = micrometres * micrometres;
+
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / squared,
+            Dispersion::Sellmeier { b, c } => {
+                let terms: f64 = (0..3)
+                    .map(|term| b[term] * squared / (squared - c[term]))
+                    .sum();
+                (1. + terms).sqrt()
+            }
+        }
+    }
+
+    /// How little the material disperses light; crown glasses are above 50, flints below.
+    pub fn abbe_number(&self) -> f64 {
+        let (blue, yellow, red) = (486.13, D_LINE, 656.27);
+        (self.index_of_refraction(yellow) - 1.)
+            / (self.index_of_refraction(blue) - self.index_of_refraction(red))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dispersion, D_LINE};
+
+    #[test]
+    fn catalogue_glasses() {
+        assert!((Dispersion::BK7.index_of_refraction(D_LINE) - 1.5168).abs() < 1e-4);
+        assert!((Dispersion::SF11.index_of_refraction(D_LINE) - 1.7847).abs() < 1e-4);
+
+        assert!((Dispersion::BK7.abbe_number() - 64.17).abs() < 0.1);
+        assert!(Dispersion::SF11.abbe_number() < 30.);
+
+        // Blue bends more than red.
+        let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.005 };
+        assert!(cauchy.index_of_refraction(450.) > cauchy.index_of_refraction(650.));
+    }
+}
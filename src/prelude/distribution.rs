@@ -0,0 +1,150 @@
+/// A piecewise-constant density over [0, 1), sampled by inverting its cumulative distribution.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    /// The function the density follows, one value per equal-width piece.
+    pub function: Vec<f64>,
+    cdf: Vec<f64>,
+    /// The integral of `function` over [0, 1).
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    /// A density proportional to the non-negative `function`, or uniform if it is zero.
+    pub fn new(function: &[f64]) -> Self {
+        let count = function.len() as f64;
+        let function = function.iter().map(|f| f.max(0.)).collect::<Vec<_>>();
+        let mut cdf = vec![0.; function.len() + 1];
+        for (i, f) in function.iter().enumerate() {
+            cdf[i + 1] = cdf[i] + f / count;
+        }
+        let integral = cdf[function.len()];
+
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = match integral > 0. {
+                true => *c / integral,
+                false => i as f64 / count,
+            };
+        }
+        Self {
+            function,
+            cdf,
+            integral,
+        }
+    }
+
+    /// A point for `u` uniform in [0, 1), with its density and the piece it is in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let count = self.function.len();
+        let piece = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(count - 1);
+        let width = self.cdf[piece + 1] - self.cdf[piece];
+        let offset = match width > 0. {
+            true => (u - self.cdf[piece]) / width,
+            false => 0.,
+        };
+        (
+            ((piece as f64 + offset) / count as f64).min(1. - f64::EPSILON),
+            self.pdf_of_piece(piece),
+            piece,
+        )
+    }
+
+    /// The density at `x` in [0, 1).
+    pub fn pdf(&self, x: f64) -> f64 {
+        let piece = ((x * self.function.len() as f64) as usize).min(self.function.len() - 1);
+        self.pdf_of_piece(piece)
+    }
+
+    fn pdf_of_piece(&self, piece: usize) -> f64 {
+        match self.integral > 0. {
+            true => self.function[piece] / self.integral,
+            false => 1.,
+        }
+    }
+}
+
+/// A piecewise-constant density over [0, 1)², given by a function on a grid of `width` by
+/// `height` cells stored row by row, sampled row first.
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    /// One per row, over the columns.
+    conditional: Vec<Distribution1D>,
+    /// Over the rows.
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f64], width: usize, height: usize) -> Self {
+        assert_eq!(
+            function.len(),
+            width * height,
+            "A distribution needs exactly one value per cell."
+        );
+        let conditional = function
+            .chunks(width)
+            .map(Distribution1D::new)
+            .collect::<Vec<_>>();
+        let marginal = Distribution1D::new(
+            &conditional
+                .iter()
+                .map(|row| row.integral)
+                .collect::<Vec<_>>(),
+        );
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// A point `(x, y)` for `u` uniform in [0, 1)², with its density.
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample(u.1);
+        let (x, pdf_x, _) = self.conditional[row].sample(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    /// The density at `(x, y)` in [0, 1)².
+    pub fn pdf(&self, (x, y): (f64, f64)) -> f64 {
+        let row = ((y * self.conditional.len() as f64) as usize).min(self.conditional.len() - 1);
+        self.conditional[row].pdf(x) * self.marginal.pdf(y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Distribution2D;
+
+    #[test]
+    fn samples_follow_the_function() {
+        let function = [0., 1., 2., 3., 0., 0., 4., 0., 2.];
+        let distribution = Distribution2D::new(&function, 3, 3);
+        let total = function.iter().sum::<f64>();
+
+        let (steps, mut counts) = (400, [0; 9]);
+        for i in 0..steps {
+            for j in 0..steps {
+                let u = (
+                    (i as f64 + 0.5) / steps as f64,
+                    (j as f64 + 0.5) / steps as f64,
+                );
+                let ((x, y), pdf) = distribution.sample(u);
+                assert!((pdf - distribution.pdf((x, y))).abs() < 1e-12);
+                counts[(y * 3.) as usize * 3 + (x * 3.) as usize] += 1;
+            }
+        }
+        for (cell, f) in function.iter().enumerate() {
+            let expected = f / total;
+            let frequency = counts[cell] as f64 / (steps * steps) as f64;
+            assert!((frequency - expected).abs() < 1e-3, "{}", cell);
+            // The density is the fraction of samples per area of the cell.
+            let center = (
+                (cell % 3) as f64 / 3. + 1. / 6.,
+                (cell / 3) as f64 / 3. + 1. / 6.,
+            );
+            assert!((distribution.pdf(center) / 9. - expected).abs() < 1e-12);
+        }
+    }
+}
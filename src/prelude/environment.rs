@@ -0,0 +1,235 @@
+use crate::prelude::{
+    luminance, rng, ColorEncoding, Distribution2D, Light, LightSample, LinAlgOp, Point, Ray,
+    TextureImage, Vec3,
+};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::io::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Light arriving from infinitely far away, as an equirectangular (latitude-longitude) image
+/// with the y axis up.
+///
+/// The top row of the image is straight up and the left edge is towards +x, with longitude
+/// increasing towards +z. It is sampled in proportion to the light it sends, so that small, bright
+/// regions such as the sun are found directly.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    pub image: Arc<TextureImage>,
+    /// Multiplies the radiance in `image`.
+    pub scale: f64,
+    /// Over the image, in proportion to luminance times the sine of the polar angle.
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Arc<TextureImage>, scale: f64) -> Self {
+        let height = image.height;
+        let function = (0..height)
+            .flat_map(|y| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                let image = &image;
+                (0..image.width).map(move |x| luminance(image.pixel(x, y)) * sin_theta)
+            })
+            .collect::<Vec<_>>();
+        Self {
+            distribution: Distribution2D::new(&function, image.width, height),
+            image,
+            scale,
+        }
+    }
+
+    /// Load an image as `TextureImage::open` does; HDR images should be PFM files.
+    pub fn open<P: AsRef<Path>>(path: P, scale: f64) -> Result<Self> {
+        Ok(Self::new(
+            Arc::new(TextureImage::open(path, ColorEncoding::Srgb)?),
+            scale,
+        ))
+    }
+
+    /// Linear RGB radiance arriving from `direction`, a unit vector.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (u, v) = direction_to_uv(direction);
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        self.image.pixel(x, y) * self.scale
+    }
+}
+
+impl Light for EnvironmentMap {
+    fn sample(&self, _point: Point, ray: &Ray) -> Option<LightSample> {
+        let mut rng = rng();
+        let ((u, v), pdf) = self.distribution.sample((rng.gen(), rng.gen()));
+        let direction = uv_to_direction(u, v);
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0. || sin_theta <= 0. {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: ray.radiance(self.radiance(direction)),
+            // From the image to the sphere of directions.
+            pdf: pdf / (2. * PI * PI * sin_theta),
+        })
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, Vec3)> {
+        let direction = ray.direction.unit_vector();
+        Some((f64::INFINITY, ray.radiance(self.radiance(direction))))
+    }
+
+    fn pdf(&self, _point: Point, direction: Vec3) -> f64 {
+        let (u, v) = direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        match sin_theta > 0. {
+            true => self.distribution.pdf((u, v)) / (2. * PI * PI * sin_theta),
+            false => 0.,
+        }
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "EnvironmentMap {{ width: {}, height: {}, scale: {} }}",
+            self.image.width, self.image.height, self.scale
+        )
+    }
+}
+
+/// Image coordinates in [0, 1)² of a unit vector, from the top left.
+fn direction_to_uv(direction: Vec3) -> (f64, f64) {
+    let phi = direction.2.atan2(direction.0).rem_euclid(2. * PI);
+    let theta = direction.1.clamp(-1., 1.).acos();
+    (phi / (2. * PI), theta / PI)
+}
+
+fn uv_to_direction(u: f64, v: f64) -> Vec3 {
+    let (phi, theta) = (2. * PI * u, PI * v);
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.cos(),
+        theta.sin() * phi.sin(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{direction_to_uv, EnvironmentMap};
+    use crate::prelude::{Light, LinAlgOp, Point, Ray, TextureImage, Vec3};
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    /// A dim sky with a small, bright sun.
+    fn environment() -> EnvironmentMap {
+        let (width, height) = (32, 16);
+        let pixels = (0..width * height)
+            .map(|i| match (i % width, i / width) {
+                (20, 4) => Vec3::new(500., 450., 400.),
+                (_, y) if y < height / 2 => Vec3::new(0.3, 0.5, 1.),
+                _ => Vec3::new(0.1, 0.1, 0.1),
+            })
+            .collect();
+        EnvironmentMap::new(Arc::new(TextureImage::new(width, height, pixels)), 1.)
+    }
+
+    #[test]
+    fn pdf_matches_a_histogram_of_samples() {
+        let environment = environment();
+        let (width, height) = (environment.image.width, environment.image.height);
+        let point = Point::new(0., 0., 0.);
+
+        let samples = 200_000;
+        let mut histogram = vec![0; width * height];
+        for _ in 0..samples {
+            let sample = environment.sample(point, &Ray::default()).unwrap();
+            let pdf = environment.pdf(point, sample.direction);
+            // Near the poles the polar angle only survives the round trip to a few digits.
+            assert!((sample.pdf - pdf).abs() < 1e-6 * pdf);
+
+            let (u, v) = direction_to_uv(sample.direction);
+            let x = ((u * width as f64) as usize).min(width - 1);
+            let y = ((v * height as f64) as usize).min(height - 1);
+            histogram[y * width + x] += 1;
+        }
+
+        // The fraction of samples in each pixel is its density integrated over its solid angle,
+        // which is estimated at many points inside it.
+        for y in 0..height {
+            for x in 0..width {
+                let (theta_0, theta_1) = (
+                    PI * y as f64 / height as f64,
+                    PI * (y + 1) as f64 / height as f64,
+                );
+                let steps = 8;
+                let expected = (0..steps)
+                    .map(|i| {
+                        let theta = theta_0 + (theta_1 - theta_0) * (i as f64 + 0.5) / steps as f64;
+                        let phi = 2. * PI * (x as f64 + 0.5) / width as f64;
+                        let direction = Vec3::new(
+                            theta.sin() * phi.cos(),
+                            theta.cos(),
+                            theta.sin() * phi.sin(),
+                        );
+                        environment.pdf(point, direction) * theta.sin() * (theta_1 - theta_0)
+                            / steps as f64
+                            * (2. * PI / width as f64)
+                    })
+                    .sum::<f64>();
+                let frequency = histogram[y * width + x] as f64 / samples as f64;
+                assert!(
+                    (frequency - expected).abs() < 0.1 * expected + 1e-3,
+                    "({}, {}): {} instead of {}",
+                    x,
+                    y,
+                    frequency,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sampling_finds_the_sun() {
+        let environment = environment();
+        let point = Point::new(0., 0., 0.);
+        let up = Vec3::new(0., 1., 0.);
+
+        // Irradiance on an upward surface, exactly as a sum over the pixels above the horizon.
+        let (width, height) = (environment.image.width, environment.image.height);
+        let exact = (0..height / 2)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (theta_0, theta_1) = (
+                    PI * y as f64 / height as f64,
+                    PI * (y + 1) as f64 / height as f64,
+                );
+                let projected_solid_angle =
+                    PI / width as f64 * (theta_1.sin().powi(2) - theta_0.sin().powi(2));
+                environment.image.pixel(x, y).1 * projected_solid_angle
+            })
+            .sum::<f64>();
+        let samples = 20_000;
+        let estimate = (0..samples)
+            .map(|_| {
+                let sample = environment.sample(point, &Ray::default()).unwrap();
+                sample.radiance.1 * sample.direction.dot(up).max(0.) / sample.pdf
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!(
+            (estimate - exact).abs() < 0.02 * exact,
+            "{} and {}",
+            estimate,
+            exact
+        );
+
+        // Rays escaping towards the sun see it.
+        let sun = environment
+            .sample(point, &Ray::default())
+            .unwrap()
+            .direction;
+        let (_, radiance) = environment.hit(&Ray::new(&point, &sun)).unwrap();
+        assert_eq!(radiance, environment.radiance(sun));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::prelude::Vec3;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Light that paths from the lights splat onto the image, summed per pixel from any thread.
+///
+/// Pixels are found from viewport coordinates the way `process_pixels` makes camera rays, so
+/// rows count up from the bottom.
+#[derive(Debug)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    /// The bits of each channel's sum, row by row.
+    pixels: Vec<[AtomicU64; 3]>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: (0..width * height)
+                .map(|_| [0., 0., 0.].map(|zero: f64| AtomicU64::new(zero.to_bits())))
+                .collect(),
+        }
+    }
+
+    /// The pixel the viewport coordinates `(s, t)` fall into, if any.
+    pub fn pixel_at(&self, (s, t): (f64, f64)) -> Option<(usize, usize)> {
+        let col = s * (self.width - 1) as f64;
+        let row = t * (self.height - 1) as f64;
+        let is_inside =
+            col >= 0. && row >= 0. && col < self.width as f64 && row < self.height as f64;
+        is_inside.then_some((row as usize, col as usize))
+    }
+
+    /// Add `value` to the pixel at the viewport coordinates `(s, t)`, if there is one.
+    pub fn splat(&self, viewport: (f64, f64), value: Vec3) {
+        let Some((row, col)) = self.pixel_at(viewport) else {
+            return;
+        };
+        let channels = &self.pixels[row * self.width + col];
+        for (channel, value) in channels.iter().zip([value.0, value.1, value.2]) {
+            if value == 0. || !value.is_finite() {
+                continue;
+            }
+            let _ = channel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+        }
+    }
+
+    /// Everything splatted onto a pixel.
+    pub fn sum(&self, row: usize, col: usize) -> Vec3 {
+        let [r, g, b] = self.pixels[row * self.width + col]
+            .each_ref()
+            .map(|channel| f64::from_bits(channel.load(Ordering::Relaxed)));
+        Vec3::new(r, g, b)
+    }
+
+    /// The radiance splats add to a pixel, when one path from the lights was traced for each of
+    /// `samples_per_pixel` camera samples in every pixel.
+    ///
+    /// Importance integrates to one over the viewport, so a path adds the integral of radiance
+    /// over the pixel's share of the viewport.
+    pub fn radiance(&self, row: usize, col: usize, samples_per_pixel: usize) -> Vec3 {
+        let pixel_area = 1. / ((self.width - 1) * (self.height - 1)) as f64;
+        let paths = (self.width * self.height * samples_per_pixel) as f64;
+        self.sum(row, col) / (paths * pixel_area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Film;
+    use crate::prelude::Vec3;
+
+    #[test]
+    fn splats_add_up_in_their_pixel() {
+        let film = Film::new(5, 3);
+        film.splat((0.3, 0.9), Vec3::new(1., 2., 3.));
+        film.splat((0.3, 0.9), Vec3::new(1., 0., 0.));
+        film.splat((1.1, 0.), Vec3::new(1., 1., 1.));
+        film.splat((-0.1, 0.5), Vec3::new(1., 1., 1.));
+
+        assert_eq!(film.pixel_at((0.3, 0.9)), Some((1, 1)));
+        assert_eq!(film.sum(1, 1), Vec3::new(2., 2., 3.));
+        assert_eq!(film.sum(0, 4), Vec3::new(1., 1., 1.));
+        assert_eq!(film.pixel_at((-0.1, 0.5)), None);
+        assert_eq!(film.radiance(1, 1, 2), Vec3::new(2., 2., 3.) * 8. / 30.);
+    }
+}
@@ -0,0 +1,688 @@
+//! Path guiding by the SD-tree of Müller et al. ("Practical Path Guiding for Efficient
+//! Light-Transport Simulation", 2017).
+//!
+//! A binary tree over space holds in each of its leaves a quadtree over directions, which learns
+//! from the paths passing through how much light arrives from where. Rendering goes in passes of
+//! doubling samples per pixel. Each pass samples directions from what the one before learned,
+//! mixed with sampling the BSDF so that estimates stay unbiased, while learning anew into finer
+//! trees. The last pass takes the samples left over, and every pass counts toward the image.
+use crate::prelude::progress_bars::ProgressBar;
+use crate::prelude::{
+    gamma2_correct, guided_ray_color, pixel_ray, rng, Bsdf, BsdfSample, Camera, HitRecord, Image,
+    Integrator, LinAlgOp, Pixel, Point, Ray, RenderConfig, Scene, Vec3,
+};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// How often directions are sampled from the guide rather than the BSDF, once it has learned.
+const GUIDED_FRACTION: f64 = 0.5;
+/// Regions of space split once this many samples, times the square root of the samples per pixel
+/// of the pass, were recorded in them.
+const SPATIAL_THRESHOLD: f64 = 12_000.;
+/// Quadrants receiving more than this fraction of the light of their region are subdivided.
+const DIRECTIONAL_THRESHOLD: f64 = 0.01;
+const MAX_DIRECTIONAL_DEPTH: usize = 20;
+
+/// Map a direction to the unit square, by the cosine of its angle to z and its angle around z.
+/// Areas on the sphere are kept, up to a factor of 4π.
+fn to_square(direction: Vec3) -> (f64, f64) {
+    let direction = direction.unit_vector();
+    let phi = direction.1.atan2(direction.0);
+    (
+        ((direction.2 + 1.) / 2.).clamp(0., 1.),
+        (phi / (2. * PI)).rem_euclid(1.),
+    )
+}
+
+fn from_square((x, y): (f64, f64)) -> Vec3 {
+    let cos_theta = 2. * x - 1.;
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * y;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// The quadrant of the unit square a point is in, numbered as x then y halves, and where the
+/// point is within it, scaled back up to the unit square.
+fn quadrant((x, y): (f64, f64)) -> (usize, (f64, f64)) {
+    let (right, top) = (x >= 0.5, y >= 0.5);
+    (
+        usize::from(right) + 2 * usize::from(top),
+        (
+            2. * x - f64::from(u8::from(right)),
+            2. * y - f64::from(u8::from(top)),
+        ),
+    )
+}
+
+fn add(sum: &AtomicU64, value: f64) {
+    let _ = sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((f64::from_bits(bits) + value).to_bits())
+    });
+}
+
+#[derive(Debug, Default)]
+struct QuadNode {
+    /// The bits of the light recorded in each quadrant.
+    sums: [AtomicU64; 4],
+    /// Indices of the nodes subdividing each quadrant, 0 where it is not.
+    children: [u32; 4],
+}
+
+impl QuadNode {
+    fn sums(&self) -> [f64; 4] {
+        self.sums
+            .each_ref()
+            .map(|sum| f64::from_bits(sum.load(Ordering::Relaxed)))
+    }
+}
+
+/// Light arriving from all directions, on the unit square of `to_square`, which is subdivided
+/// more finely where more of it comes from. A tree that recorded nothing is uniform.
+#[derive(Debug)]
+struct QuadTree {
+    nodes: Vec<QuadNode>,
+}
+
+impl Clone for QuadTree {
+    fn clone(&self) -> Self {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|node| QuadNode {
+                sums: node.sums().map(|sum| AtomicU64::new(sum.to_bits())),
+                children: node.children,
+            })
+            .collect();
+        Self { nodes }
+    }
+}
+
+impl QuadTree {
+    fn new() -> Self {
+        Self {
+            nodes: vec![QuadNode::default()],
+        }
+    }
+
+    fn total(&self) -> f64 {
+        self.nodes[0].sums().iter().sum()
+    }
+
+    /// Add light arriving at a point of the unit square, from any thread.
+    fn record(&self, mut point: (f64, f64), value: f64) {
+        let mut node = &self.nodes[0];
+        loop {
+            let (quadrant, inner) = quadrant(point);
+            add(&node.sums[quadrant], value);
+            match node.children[quadrant] {
+                0 => return,
+                child => (node, point) = (&self.nodes[child as usize], inner),
+            }
+        }
+    }
+
+    /// The density of `sample` at a point of the unit square.
+    fn pdf(&self, mut point: (f64, f64)) -> f64 {
+        let mut pdf = 1.;
+        let mut node = &self.nodes[0];
+        loop {
+            let sums = node.sums();
+            let total: f64 = sums.iter().sum();
+            if total <= 0. {
+                return pdf;
+            }
+            let (quadrant, inner) = quadrant(point);
+            pdf *= 4. * sums[quadrant] / total;
+            match node.children[quadrant] {
+                0 => return pdf,
+                child => (node, point) = (&self.nodes[child as usize], inner),
+            }
+        }
+    }
+
+    /// A point of the unit square, in proportion to the light recorded there.
+    fn sample(&self, (mut u, mut v): (f64, f64)) -> (f64, f64) {
+        let (mut origin, mut size) = ((0., 0.), 1.);
+        let mut node = &self.nodes[0];
+        loop {
+            let sums = node.sums();
+            let total: f64 = sums.iter().sum();
+            if total <= 0. {
+                return (origin.0 + size * u, origin.1 + size * v);
+            }
+            // The column by its light, then the quadrant within it.
+            let left = (sums[0] + sums[2]) / total;
+            let right = u >= left;
+            u = match right {
+                true => (u - left) / (1. - left),
+                false => u / left,
+            };
+            let column = [sums[usize::from(right)], sums[2 + usize::from(right)]];
+            let bottom = column[0] / (column[0] + column[1]);
+            let top = v >= bottom;
+            v = match top {
+                true => (v - bottom) / (1. - bottom),
+                false => v / bottom,
+            };
+            // Rounding must not carry samples out of the quadrant.
+            (u, v) = (u.clamp(0., 1.), v.clamp(0., 1.));
+
+            size /= 2.;
+            origin.0 += size * f64::from(u8::from(right));
+            origin.1 += size * f64::from(u8::from(top));
+            let quadrant = usize::from(right) + 2 * usize::from(top);
+            match node.children[quadrant] {
+                0 => return (origin.0 + size * u, origin.1 + size * v),
+                child => node = &self.nodes[child as usize],
+            }
+        }
+    }
+
+    /// An empty tree subdividing the quadrants that received more than `DIRECTIONAL_THRESHOLD`
+    /// of this one's light, and merging the others.
+    fn refined(&self) -> Self {
+        let mut refined = Self { nodes: vec![] };
+        let total = self.total();
+        if total > 0. {
+            self.refine_into(&mut refined, Some(0), self.nodes[0].sums(), total, 0);
+        } else {
+            refined.nodes.push(QuadNode::default());
+        }
+        refined
+    }
+
+    /// Add to `refined` the node for a square with `sums` in its quadrants, which is `node` of
+    /// this tree if it has one, returning its index.
+    fn refine_into(
+        &self,
+        refined: &mut Self,
+        node: Option<usize>,
+        sums: [f64; 4],
+        total: f64,
+        depth: usize,
+    ) -> u32 {
+        let index = refined.nodes.len();
+        refined.nodes.push(QuadNode::default());
+        for (quadrant, sum) in sums.into_iter().enumerate() {
+            if depth + 1 >= MAX_DIRECTIONAL_DEPTH || sum <= DIRECTIONAL_THRESHOLD * total {
+                continue;
+            }
+            let child = node
+                .map(|node| self.nodes[node].children[quadrant] as usize)
+                .filter(|&child| child != 0);
+            let child_sums = match child {
+                Some(child) => self.nodes[child].sums(),
+                // Unknown further down, so spread evenly.
+                None => [sum / 4.; 4],
+            };
+            refined.nodes[index].children[quadrant] =
+                self.refine_into(refined, child, child_sums, total, depth + 1);
+        }
+        index as u32
+    }
+}
+
+/// Where in space the light arriving is learned about together.
+#[derive(Debug)]
+struct Region {
+    /// What the pass before learned, which this one samples from.
+    sampling: QuadTree,
+    /// What this pass learns.
+    recording: QuadTree,
+    /// Recorded by this pass.
+    samples: AtomicU64,
+}
+
+#[derive(Debug)]
+enum SpatialNode {
+    Leaf(Region),
+    /// Halved across `axis`, with children below and above.
+    Split {
+        axis: usize,
+        children: [usize; 2],
+    },
+}
+
+/// How light arriving is distributed over space and direction, learned from paths.
+#[derive(Debug)]
+pub struct SdTree {
+    min: [f64; 3],
+    max: [f64; 3],
+    nodes: Vec<SpatialNode>,
+    /// Whether paths record the light they find.
+    pub is_learning: bool,
+}
+
+impl SdTree {
+    /// A tree over the box from `min` to `max`, which has learned nothing yet. Points outside
+    /// count as the nearest inside.
+    pub fn new(min: Point, max: Point) -> Self {
+        Self {
+            min: [min.0, min.1, min.2],
+            max: [max.0, max.1, max.2],
+            nodes: vec![SpatialNode::Leaf(Region {
+                sampling: QuadTree::new(),
+                recording: QuadTree::new(),
+                samples: AtomicU64::new(0),
+            })],
+            is_learning: true,
+        }
+    }
+
+    /// A tree over what the camera sees.
+    pub fn for_view(
+        image: &Image,
+        camera: &Camera,
+        scene: &Scene,
+        render_config: &RenderConfig,
+    ) -> Self {
+        let (mut min, mut max) = ([f64::INFINITY; 3], [f64::NEG_INFINITY; 3]);
+        for row in 0..image.height {
+            for col in 0..image.width {
+                let (ray, _) = pixel_ray(row, col, camera, image, render_config);
+                if let Some(hit_record) = scene.hit(&ray, 0.001, f64::INFINITY) {
+                    let point = hit_record.point;
+                    for (axis, value) in [point.0, point.1, point.2].into_iter().enumerate() {
+                        min[axis] = min[axis].min(value);
+                        max[axis] = max[axis].max(value);
+                    }
+                }
+            }
+        }
+        if min[0] > max[0] {
+            return Self::new(Point::default(), Point::default());
+        }
+        // A cube, for regions to be split evenly.
+        let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0., f64::max);
+        let pad = 0.01 * extent.max(1e-3);
+        Self::new(
+            Point::new(min[0] - pad, min[1] - pad, min[2] - pad),
+            Point::new(
+                min[0] + extent + pad,
+                min[1] + extent + pad,
+                min[2] + extent + pad,
+            ),
+        )
+    }
+
+    fn region(&self, point: Point) -> &Region {
+        let (mut min, mut max) = (self.min, self.max);
+        let point = [point.0, point.1, point.2];
+        let mut index = 0;
+        loop {
+            match &self.nodes[index] {
+                SpatialNode::Leaf(region) => return region,
+                SpatialNode::Split { axis, children } => {
+                    let middle = (min[*axis] + max[*axis]) / 2.;
+                    let above = point[*axis] >= middle;
+                    match above {
+                        true => min[*axis] = middle,
+                        false => max[*axis] = middle,
+                    }
+                    index = children[usize::from(above)];
+                }
+            }
+        }
+    }
+
+    /// How often a hit in `region` samples the guide. Never where the material's pdf is only
+    /// approximate, since mixing in the guide weights every sample by it.
+    fn guided_fraction(region: &Region, hit_record: &HitRecord) -> f64 {
+        let flags = hit_record.material.flags(hit_record);
+        match region.sampling.total() > 0. && !flags.approximate_pdf {
+            true => GUIDED_FRACTION,
+            false => 0.,
+        }
+    }
+
+    /// Sample a direction from the material hit or from the light learned to arrive there.
+    pub fn sample(&self, ray: &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
+        let material = &hit_record.material;
+        let region = self.region(hit_record.point);
+        let guided = Self::guided_fraction(region, hit_record);
+        if guided <= 0. {
+            return material.sample(ray, hit_record);
+        }
+
+        let mut rng = rng();
+        if rng.gen::<f64>() >= guided {
+            let mut sample = material.sample(ray, hit_record)?;
+            if sample.is_delta {
+                sample.weight /= 1. - guided;
+                return Some(sample);
+            }
+            let direction = sample.ray.direction.unit_vector();
+            sample.pdf = self.pdf(ray, hit_record, direction);
+            sample.weight = material.eval(ray, hit_record, direction) / sample.pdf;
+            return Some(sample);
+        }
+
+        let direction = from_square(region.sampling.sample((rng.gen(), rng.gen())));
+        let pdf = self.pdf(ray, hit_record, direction);
+        (pdf > 0.).then(|| {
+            BsdfSample::new(
+                material.eval(ray, hit_record, direction) / pdf,
+                ray.spawn(&hit_record.point, &direction),
+                pdf,
+            )
+        })
+    }
+
+    /// The density `sample` finds a direction with, but for the material's delta lobes.
+    pub fn pdf(&self, ray: &Ray, hit_record: &HitRecord, direction: Vec3) -> f64 {
+        let region = self.region(hit_record.point);
+        let guided = Self::guided_fraction(region, hit_record);
+        let bsdf_pdf = hit_record.material.pdf(ray, hit_record, direction);
+        match guided > 0. {
+            true => {
+                let guide_pdf = region.sampling.pdf(to_square(direction)) / (4. * PI);
+                (1. - guided) * bsdf_pdf + guided * guide_pdf
+            }
+            false => bsdf_pdf,
+        }
+    }
+
+    /// Record `value` as arriving at `point` from `direction`, while learning.
+    pub fn record(&self, point: Point, direction: Vec3, value: f64) {
+        if !self.is_learning || !value.is_finite() || value < 0. {
+            return;
+        }
+        let region = self.region(point);
+        region.recording.record(to_square(direction), value);
+        region.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sample from what was recorded over the pass just rendered with `samples_per_pixel`, and
+    /// learn anew into finer trees.
+    pub fn refine(&mut self, samples_per_pixel: usize) {
+        let threshold = SPATIAL_THRESHOLD * (samples_per_pixel as f64).sqrt();
+        self.split(0, 0, threshold);
+
+        for node in &mut self.nodes {
+            if let SpatialNode::Leaf(region) = node {
+                region.sampling = std::mem::replace(&mut region.recording, QuadTree::new());
+                region.recording = region.sampling.refined();
+                region.samples = AtomicU64::new(0);
+            }
+        }
+    }
+
+    /// Split the regions under `index` with more samples than `threshold`, sharing them out.
+    fn split(&mut self, index: usize, depth: usize, threshold: f64) {
+        let children = match &self.nodes[index] {
+            SpatialNode::Split { children, .. } => *children,
+            SpatialNode::Leaf(region) => {
+                let samples = region.samples.load(Ordering::Relaxed);
+                if (samples as f64) <= threshold {
+                    return;
+                }
+                let recording = region.recording.clone();
+                let children = [self.nodes.len(), self.nodes.len() + 1];
+                for _ in children {
+                    self.nodes.push(SpatialNode::Leaf(Region {
+                        sampling: QuadTree::new(),
+                        recording: recording.clone(),
+                        samples: AtomicU64::new(samples / 2),
+                    }));
+                }
+                self.nodes[index] = SpatialNode::Split {
+                    axis: depth % 3,
+                    children,
+                };
+                children
+            }
+        };
+        for child in children {
+            self.split(child, depth + 1, threshold);
+        }
+    }
+}
+
+/// Render by path tracing guided by an `SdTree`, with `render_config.integrator` set to
+/// `GuidedPathTracer`.
+pub fn guided_path_tracing(
+    image: &Image,
+    camera: &Camera,
+    scene: &Scene,
+    render_config: &RenderConfig,
+    progress_bar: ProgressBar,
+) -> Vec<Pixel> {
+    guided_colors(image, camera, scene, render_config, progress_bar)
+        .into_iter()
+        .map(|color| gamma2_correct(color, 2).into())
+        .collect()
+}
+
+/// The linear colors of `guided_path_tracing`, from the top row down.
+fn guided_colors(
+    image: &Image,
+    camera: &Camera,
+    scene: &Scene,
+    render_config: &RenderConfig,
+    progress_bar: ProgressBar,
+) -> Vec<Vec3> {
+    assert_eq!(
+        render_config.integrator,
+        Integrator::GuidedPathTracer,
+        "Guided path tracing needs its integrator to be selected."
+    );
+    let samples_per_pixel = render_config.samples_per_pixel;
+    let pixel_count = image.width * image.height;
+    progress_bar.set_length((pixel_count * samples_per_pixel) as u64);
+
+    let mut guide = SdTree::for_view(image, camera, scene, render_config);
+    let mut sums = vec![Vec3::default(); pixel_count];
+    let mut done = 0;
+    let mut pass_samples = 1;
+    while done < samples_per_pixel {
+        // The last pass takes the rest, once it could not be followed by one twice as long.
+        let left = samples_per_pixel - done;
+        if left < 3 * pass_samples {
+            pass_samples = left;
+        }
+        guide.is_learning = pass_samples < left;
+
+        #[cfg(feature = "parallel")]
+        let pixel_iter = sums.par_iter_mut();
+        #[cfg(not(feature = "parallel"))]
+        let pixel_iter = sums.iter_mut();
+
+        pixel_iter.enumerate().for_each(|(index, sum)| {
+            let (row, col) = (image.height - index / image.width - 1, index % image.width);
+            for _ in 0..pass_samples {
+                let (ray, wavelengths) = pixel_ray(row, col, camera, image, render_config);
+                let color = guided_ray_color(&ray, scene, render_config.max_depth, &guide);
+                *sum += match &wavelengths {
+                    Some(wavelengths) => wavelengths.to_rgb(color),
+                    None => color,
+                };
+            }
+        });
+        progress_bar.inc((pixel_count * pass_samples) as u64);
+
+        done += pass_samples;
+        if guide.is_learning {
+            guide.refine(pass_samples);
+        }
+        pass_samples *= 2;
+    }
+
+    sums.into_iter()
+        .map(|sum| sum / samples_per_pixel as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_square, guided_colors, to_square, QuadTree, SdTree};
+    use crate::prelude::test_scenes::{assert_agrees_with_path_tracing, lit_ball, lit_ball_camera};
+    use crate::prelude::{
+        luminance, progress_bars, ray_color, Bsdf, BsdfSample, Hittable, Image, Integrator,
+        LinAlgOp, LinAlgRandGen, Material, Point, Quad, Ray, RenderConfig, Scene, Vec3,
+    };
+    use rand::{thread_rng, Rng};
+    use std::sync::Arc;
+
+    /// A ball on a floor lit by a small light off to the side.
+    fn scene() -> Scene {
+        lit_ball(Material::lambertian(Vec3::new(0.8, 0.3, 0.3)))
+    }
+
+    #[test]
+    fn directions_map_to_the_square_and_back() {
+        for _ in 0..100 {
+            let direction = Vec3::random_unit_vector();
+            let (x, y) = to_square(direction);
+            assert!((0. ..=1.).contains(&x) && (0. ..=1.).contains(&y));
+            assert!((from_square((x, y)) - direction).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn quadtrees_sample_what_they_learned() {
+        // Light mostly from one corner, learned over two rounds to subdivide there.
+        let mut rng = thread_rng();
+        let mut tree = QuadTree::new();
+        for _ in 0..2 {
+            tree = tree.refined();
+            for _ in 0..10_000 {
+                let point = match rng.gen::<f64>() < 0.8 {
+                    true => (0.1 * rng.gen::<f64>(), 0.9 + 0.1 * rng.gen::<f64>()),
+                    false => (rng.gen(), rng.gen()),
+                };
+                tree.record(point, 1.);
+            }
+        }
+        assert!(tree.nodes.len() > 1);
+
+        // The density integrates to one, and samples land where it says.
+        let cells = 64;
+        let mut integral = 0.;
+        let mut expected = [0.; 4];
+        for i in 0..cells {
+            for j in 0..cells {
+                let point = (
+                    (i as f64 + 0.5) / cells as f64,
+                    (j as f64 + 0.5) / cells as f64,
+                );
+                let mass = tree.pdf(point) / (cells * cells) as f64;
+                integral += mass;
+                expected[usize::from(point.0 >= 0.5) + 2 * usize::from(point.1 >= 0.5)] += mass;
+            }
+        }
+        assert!((integral - 1.).abs() < 1e-6, "{}", integral);
+
+        let samples = 100_000;
+        let mut found = [0.; 4];
+        for _ in 0..samples {
+            let (x, y) = tree.sample((rng.gen(), rng.gen()));
+            assert!((0. ..=1.).contains(&x) && (0. ..=1.).contains(&y));
+            found[usize::from(x >= 0.5) + 2 * usize::from(y >= 0.5)] += 1. / samples as f64;
+        }
+        for (found, expected) in found.iter().zip(&expected) {
+            assert!(
+                (found - expected).abs() < 0.01,
+                "{:?} {:?}",
+                found,
+                expected
+            );
+        }
+        assert!(expected[2] > 0.8);
+    }
+
+    #[test]
+    fn guides_learn_where_light_comes_from() {
+        let scene = scene();
+        let point = Point::new(-1., 0., 1.);
+        let mut guide = SdTree::new(Point::new(-2., -1., -2.), Point::new(2., 3., 2.));
+        let toward_light = (Point::new(1., 2.5, 0.) - point).unit_vector();
+        for _ in 0..2 {
+            for _ in 0..20_000 {
+                let direction = Vec3::random_unit_vector();
+                let ray = crate::prelude::Ray::new(&point, &direction);
+                let value = luminance(ray_color(&ray, &scene, 1));
+                guide.record(point, direction, value * 4. * std::f64::consts::PI);
+            }
+            guide.refine(1);
+        }
+
+        let density = |direction: Vec3| {
+            guide.region(point).sampling.pdf(to_square(direction)) / (4. * std::f64::consts::PI)
+        };
+        let away = -toward_light;
+        assert!(density(toward_light) > 10. * density(away));
+    }
+
+    #[test]
+    fn agrees_with_path_tracing() {
+        let scene = scene();
+        let camera = lit_ball_camera();
+        let image = Image::new(8, 1.);
+        let render_config = RenderConfig {
+            integrator: Integrator::GuidedPathTracer,
+            ..RenderConfig::new(1000, 5)
+        };
+        let colors = guided_colors(
+            &image,
+            &camera,
+            &scene,
+            &render_config,
+            progress_bars::hidden(),
+        );
+
+        assert_agrees_with_path_tracing(&colors, &camera, &scene, &image, &render_config);
+    }
+
+    #[test]
+    fn guided_samples_agree_with_coated_materials() {
+        // Coats only approximate the density they sample with, so guiding must leave them be:
+        // guided or not, the mean weight of samples is the albedo.
+        let coated = Material::coated(Material::lambertian(Vec3::new(0.9, 0.9, 0.9)), 1.5, 0.3);
+        let floor = Quad::new(
+            Point::new(-2., 0., -2.),
+            Vec3::new(0., 0., 4.),
+            Vec3::new(4., 0., 0.),
+            Arc::new(coated),
+        );
+        let ray = Ray::new(&Point::new(-1., 1., 0.), &Vec3::new(1., -1., 0.));
+        let hit_record = floor.hit(&ray, 1e-3, f64::INFINITY).unwrap();
+
+        // Light learned to come from one side, to sample toward.
+        let mut guide = SdTree::new(Point::new(-2., -1., -2.), Point::new(2., 3., 2.));
+        for _ in 0..1000 {
+            let direction =
+                (Vec3::new(1., 1., 0.) + 0.5 * Vec3::random_unit_vector()).unit_vector();
+            guide.record(hit_record.point, direction, 1.);
+        }
+        guide.refine(1);
+
+        let samples = 200_000;
+        let mean = |sample: &dyn Fn() -> Option<BsdfSample>| {
+            let (mut sum, mut squares) = (Vec3::default(), Vec3::default());
+            for _ in 0..samples {
+                if let Some(sample) = sample() {
+                    sum += sample.weight;
+                    squares += sample.weight * sample.weight;
+                }
+            }
+            let mean = sum / samples as f64;
+            (
+                mean,
+                (squares / samples as f64 - mean * mean) / samples as f64,
+            )
+        };
+        let (guided, guided_variance) = mean(&|| guide.sample(&ray, &hit_record));
+        let (unguided, unguided_variance) = mean(&|| hit_record.material.sample(&ray, &hit_record));
+        let error = 5. * (guided_variance.0 + unguided_variance.0).sqrt();
+        assert!(
+            (guided.0 - unguided.0).abs() < error,
+            "{} and {}",
+            guided,
+            unguided
+        );
+    }
+}
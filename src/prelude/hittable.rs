@@ -1,4 +1,4 @@
-use crate::prelude::{LinAlgOp, Material, Point, Ray, Vec3};
+use crate::prelude::{Interior, LinAlgOp, Material, Point, Ray, UvDerivatives, Vec3};
 use std::sync::Arc;
 
 pub struct Sphere {
@@ -21,23 +21,130 @@ impl Sphere {
 pub struct HitRecord {
     /// The point of intersection.
     pub point: Point,
-    /// The canonical vector that is normal to the object's surface at the point of intersection.
+    /// The shading normal at the point of intersection, on the same side of the surface as the ray.
+    ///
+    /// Smooth triangles and normal-perturbing materials may tilt it away from the geometry.
     pub normal: Vec3,
+    /// The true normal of the surface, on the same side as the ray.
+    pub geometric_normal: Vec3,
     /// The moment in time that the ray hits the object.
     pub time: f64,
     /// true if the ray hits from outside the object, false otherwise.
     pub is_front_facing: bool,
+    /// Surface texture coordinates at the point of intersection.
+    pub u: f64,
+    pub v: f64,
+    /// The footprint of the pixel in texture space, when the ray carries differentials.
+    pub uv_derivatives: Option<UvDerivatives>,
+    /// How the point moves with the texture coordinates, if the surface is parametrised.
+    pub tangents: Option<SurfaceTangents>,
     /// The kind of material is hit.
     pub material: Arc<Material>,
+    /// What fills the object hit, if it is `Nested` among others.
+    pub interior: Option<Arc<Interior>>,
+    /// The index of refraction of what surrounds the object hit, for the surfaces of objects
+    /// nested in others. Set by integrators that track it with a `MediumStack`.
+    pub surrounding_ior: f64,
+}
+
+/// Partial derivatives of a surface point with respect to its texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceTangents {
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
 }
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    /// Every intersection of the ray with the object within `[t_min, t_max]`, sorted by time.
+    ///
+    /// The default walks along the ray by repeatedly asking for the nearest hit, which is enough for
+    /// any object whose `hit` is deterministic.
+    fn hit_all(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        let mut hits = vec![];
+        let mut time = t_min;
+
+        while let Some(hit_record) = self.hit(ray, time, t_max) {
+            time = hit_record.time + 1e-8 * hit_record.time.abs().max(1.);
+            hits.push(hit_record);
+        }
+        hits
+    }
+    /// The nearest hit within `[t_min, t_max]` whose material is not masked out, looking on past
+    /// those that are. Masks are applied here rather than in `hit`, so that they cut holes in any
+    /// object, and only once however objects are nested.
+    fn hit_opaque(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut time = t_min;
+        loop {
+            let hit_record = self.hit(ray, time, t_max)?;
+            if !hit_record.is_masked_out() {
+                return Some(hit_record);
+            }
+            time = hit_record.time + 1e-8 * hit_record.time.abs().max(1.);
+        }
+    }
     fn metadata(&self) -> String {
         String::from("Unknown")
     }
 }
 
+impl Sphere {
+    fn hit_record_at(&self, ray: &Ray, hit_time: f64) -> HitRecord {
+        let outward_facing_normal = (ray.at(hit_time) - self.center) / self.radius;
+        let is_front_facing = ray.direction.dot(outward_facing_normal) < 0.;
+        let (u, v) = Sphere::uv(outward_facing_normal);
+
+        // Partial derivatives of the spherical mapping, scaled back up to the sphere's radius.
+        let relative = outward_facing_normal * self.radius.abs();
+        let two_pi = 2. * std::f64::consts::PI;
+        let dpdu = two_pi * Vec3::new(relative.2, 0., -relative.0);
+        let theta = v * std::f64::consts::PI;
+        let (sin_phi, cos_phi) = (two_pi * u).sin_cos();
+        let dpdv = std::f64::consts::PI
+            * self.radius.abs()
+            * Vec3::new(-cos_phi * theta.cos(), theta.sin(), sin_phi * theta.cos());
+
+        let normal = if is_front_facing {
+            outward_facing_normal
+        } else {
+            -outward_facing_normal
+        };
+
+        HitRecord {
+            point: ray.at(hit_time),
+            normal,
+            geometric_normal: normal,
+            time: hit_time,
+            is_front_facing,
+            u,
+            v,
+            uv_derivatives: UvDerivatives::from_ray(
+                ray,
+                ray.at(hit_time),
+                outward_facing_normal,
+                dpdu,
+                dpdv,
+            ),
+            tangents: Some(SurfaceTangents { dpdu, dpdv }),
+            material: self.material.clone(),
+            interior: None,
+            surrounding_ior: 1.,
+        }
+    }
+
+    /// Spherical texture coordinates of a point on the unit sphere: `u` goes around the y-axis
+    /// starting from -x, and `v` runs from the bottom pole (0) to the top pole (1).
+    pub fn uv(point: Point) -> (f64, f64) {
+        let theta = (-point.1).clamp(-1., 1.).acos();
+        let phi = (-point.2).atan2(point.0) + std::f64::consts::PI;
+
+        (
+            phi / (2. * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
+}
+
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let oc: Vec3 = ray.origin - self.center;
@@ -61,22 +168,28 @@ impl Hittable for Sphere {
         };
         hit_time?; // If None, return already.
 
-        let hit_time = hit_time.unwrap();
+        Some(self.hit_record_at(ray, hit_time.unwrap()))
+    }
 
-        let outward_facing_normal = (ray.at(hit_time) - self.center) / self.radius;
-        let is_front_facing = ray.direction.dot(outward_facing_normal) < 0.;
+    fn hit_all(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        let oc: Vec3 = ray.origin - self.center;
+        let a = ray.direction.norm_squared();
+        let half_b = oc.dot(ray.direction);
+        let c = oc.norm_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
 
-        Some(HitRecord {
-            point: ray.at(hit_time),
-            normal: if is_front_facing {
-                outward_facing_normal
-            } else {
-                -outward_facing_normal
-            },
-            time: hit_time,
-            is_front_facing,
-            material: self.material.clone(),
-        })
+        if discriminant < 0. {
+            return vec![];
+        }
+
+        [
+            (-half_b - discriminant.sqrt()) / a,
+            (-half_b + discriminant.sqrt()) / a,
+        ]
+        .into_iter()
+        .filter(|&time| t_min <= time && time <= t_max)
+        .map(|time| self.hit_record_at(ray, time))
+        .collect()
     }
 
     fn metadata(&self) -> String {
@@ -53,4 +53,20 @@ impl Hittable for HittableList {
                     .unwrap_or(Ordering::Equal)
             })
     }
+
+    fn hit_all(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        let mut hits = self
+            .objects
+            .iter()
+            .flat_map(|object| object.hit_all(ray, t_min, t_max))
+            .collect::<Vec<HitRecord>>();
+
+        hits.sort_by(|hit_record_1, hit_record_2| {
+            hit_record_1
+                .time
+                .partial_cmp(&hit_record_2.time)
+                .unwrap_or(Ordering::Equal)
+        });
+        hits
+    }
 }
@@ -0,0 +1,1046 @@
+//! Lights that are not made of emissive geometry, which paths reach by sampling them directly.
+use crate::prelude::{
+    luminance, rng, Angle, LinAlgOp, LinAlgRandGen, Onb, Point, Radians, Ray, Vec3,
+};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::sync::Arc;
+
+pub trait Light: Send + Sync {
+    /// Sample light arriving at `point`, as seen by `ray`, the ray that reached it.
+    fn sample(&self, point: Point, ray: &Ray) -> Option<LightSample>;
+    /// Where `ray` meets the emitting surface of the light, as the time and the radiance towards
+    /// the ray. Lights at a single point or in a single direction are never met.
+    fn hit(&self, _ray: &Ray) -> Option<(f64, Vec3)> {
+        None
+    }
+    /// The density per solid angle of `sample` choosing `direction` from `point`.
+    fn pdf(&self, _point: Point, _direction: Vec3) -> f64 {
+        0.
+    }
+    /// Where the light is and how it shines, for choosing among lights. `None` for lights at
+    /// infinity.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+    /// Sample light leaving the light, for paths traced from it. `None` for lights at infinity,
+    /// which only paths from the camera find.
+    fn sample_emission(&self, _ray: &Ray) -> Option<EmissionSample> {
+        None
+    }
+    /// The densities of `sample_emission` sending light from `point` on the light in `direction`.
+    fn emission_pdf(&self, _point: Point, _direction: Vec3) -> Option<EmissionPdf> {
+        None
+    }
+    fn metadata(&self) -> String {
+        String::from("Unknown")
+    }
+}
+
+/// Light arriving at a point from a sampled direction.
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    /// Unit vector from the point towards the light.
+    pub direction: Vec3,
+    /// How far along `direction` the light is, infinite for distant lights.
+    pub distance: f64,
+    /// Incident radiance, or irradiance for delta lights.
+    pub radiance: Vec3,
+    /// Density per solid angle of `direction`, zero for lights at a single point or direction.
+    pub pdf: f64,
+}
+
+impl LightSample {
+    pub fn is_delta(&self) -> bool {
+        self.pdf == 0.
+    }
+}
+
+/// Light leaving a light along a sampled ray.
+#[derive(Clone, Copy, Debug)]
+pub struct EmissionSample {
+    /// From a point on the light, with a unit direction.
+    pub ray: Ray,
+    /// Radiance along the ray, or radiant intensity for lights at a single point.
+    pub radiance: Vec3,
+    pub pdf: EmissionPdf,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EmissionPdf {
+    /// Density per area of the point on the light, one for lights at a single point.
+    pub position: f64,
+    /// Density per solid angle of the direction.
+    pub direction: f64,
+    /// The light's surface normal at the point, zero for lights at a single point.
+    pub normal: Vec3,
+}
+
+impl EmissionPdf {
+    pub fn is_delta_position(&self) -> bool {
+        self.normal == Vec3::default()
+    }
+}
+
+/// A conservative summary of one or more lights: a ball containing them, their total power, and
+/// the cone of directions they emit in (Conty Estevez and Kulla, "Importance Sampling of Many
+/// Lights with Adaptive Tree Splitting", 2018).
+#[derive(Clone, Copy, Debug)]
+pub struct LightBounds {
+    pub center: Point,
+    pub radius: f64,
+    /// Emitted power, by luminance.
+    pub power: f64,
+    /// Unit vector around which the emitting surfaces face.
+    pub axis: Vec3,
+    /// Cosine of the widest angle between a surface normal and `axis`.
+    pub cos_theta_o: f64,
+    /// Cosine of the widest angle beyond a surface normal that light leaves at.
+    pub cos_theta_e: f64,
+    /// Whether surfaces emit on both sides.
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    /// Lights shining in every direction from a ball.
+    pub fn omnidirectional(center: Point, radius: f64, power: f64) -> Self {
+        Self {
+            center,
+            radius,
+            power,
+            axis: Vec3::new(0., 0., 1.),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+            two_sided: false,
+        }
+    }
+
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.power == 0. {
+            return *other;
+        }
+        if other.power == 0. {
+            return *self;
+        }
+
+        // The smallest ball around both balls.
+        let offset = other.center - self.center;
+        let distance = offset.norm();
+        let (center, radius) = if distance + other.radius <= self.radius {
+            (self.center, self.radius)
+        } else if distance + self.radius <= other.radius {
+            (other.center, other.radius)
+        } else {
+            let radius = 0.5 * (distance + self.radius + other.radius);
+            (
+                self.center + offset * ((radius - self.radius) / distance),
+                radius,
+            )
+        };
+
+        let (axis, theta_o) = union_cones(
+            (self.axis, self.cos_theta_o.clamp(-1., 1.).acos()),
+            (other.axis, other.cos_theta_o.clamp(-1., 1.).acos()),
+        );
+        LightBounds {
+            center,
+            radius,
+            power: self.power + other.power,
+            axis,
+            cos_theta_o: theta_o.cos(),
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// An estimate of how much the lights could contribute at `point` with the surface `normal`,
+    /// which is zero only where they cannot. A zero normal stands for points in media.
+    pub fn importance(&self, point: Point, normal: Vec3) -> f64 {
+        let offset = point - self.center;
+        let distance2 = offset.norm_squared();
+        if self.power <= 0. {
+            return 0.;
+        }
+        // Within the ball, any direction may be the one to the lights.
+        let (cos_theta_b, sin_theta_b) = match distance2 > self.radius * self.radius {
+            true => {
+                let sin2 = self.radius * self.radius / distance2;
+                ((1. - sin2).sqrt(), sin2.sqrt())
+            }
+            false => (-1., 0.),
+        };
+        let to_point = match distance2 > 0. {
+            true => offset / distance2.sqrt(),
+            false => self.axis,
+        };
+
+        // The smallest angle between an emitting normal and the direction to the point.
+        let mut cos_theta_w = self.axis.dot(to_point);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = (1. - cos_theta_w * cos_theta_w).max(0.).sqrt();
+        let sin_theta_o = (1. - self.cos_theta_o * self.cos_theta_o).max(0.).sqrt();
+        let (cos_theta_x, sin_theta_x) =
+            angle_difference((cos_theta_w, sin_theta_w), (self.cos_theta_o, sin_theta_o));
+        let (cos_theta, _) =
+            angle_difference((cos_theta_x, sin_theta_x), (cos_theta_b, sin_theta_b));
+        if cos_theta <= self.cos_theta_e {
+            return 0.;
+        }
+
+        let mut importance =
+            self.power * cos_theta / distance2.max(self.radius * self.radius).max(1e-12);
+        if normal != Vec3::default() {
+            let cos_theta_i = to_point.dot(normal.unit_vector()).abs();
+            let sin_theta_i = (1. - cos_theta_i * cos_theta_i).max(0.).sqrt();
+            let (cos_theta_i, _) =
+                angle_difference((cos_theta_i, sin_theta_i), (cos_theta_b, sin_theta_b));
+            importance *= cos_theta_i;
+        }
+        importance.max(0.)
+    }
+}
+
+/// The cosine and sine of `max(0, a - b)` for angles in [0, π] given by theirs.
+fn angle_difference((cos_a, sin_a): (f64, f64), (cos_b, sin_b): (f64, f64)) -> (f64, f64) {
+    match cos_a > cos_b {
+        true => (1., 0.),
+        false => (cos_a * cos_b + sin_a * sin_b, sin_a * cos_b - cos_a * sin_b),
+    }
+}
+
+/// The smallest cone around two cones, each an axis and a half-angle.
+fn union_cones((axis_a, theta_a): (Vec3, f64), (axis_b, theta_b): (Vec3, f64)) -> (Vec3, f64) {
+    let theta_d = axis_a.dot(axis_b).clamp(-1., 1.).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (axis_a, theta_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (axis_b, theta_b);
+    }
+
+    let theta = 0.5 * (theta_a + theta_d + theta_b);
+    let rotation_axis = axis_a.cross(axis_b);
+    if theta >= PI || rotation_axis.norm_squared() == 0. {
+        return (axis_a, PI);
+    }
+    // Turn the first axis towards the second by the angle the cone grows by (Rodrigues).
+    let k = rotation_axis.unit_vector();
+    let angle = theta - theta_a;
+    let axis = axis_a * angle.cos() + k.cross(axis_a) * angle.sin();
+    (axis.unit_vector(), theta)
+}
+
+/// A ball of uniform radiance.
+#[derive(Clone, Debug)]
+pub struct SphereLight {
+    pub center: Point,
+    pub radius: f64,
+    pub radiance: Vec3,
+}
+
+impl SphereLight {
+    pub fn new(center: Point, radius: f64, radiance: Vec3) -> Self {
+        Self {
+            center,
+            radius,
+            radiance,
+        }
+    }
+
+    /// The cosine of the half-angle the ball subtends from `point`, if `point` is outside it.
+    fn cos_max(&self, point: Point) -> Option<f64> {
+        let sin2_max = self.radius * self.radius / (self.center - point).norm_squared();
+        (sin2_max < 1.).then(|| (1. - sin2_max).sqrt())
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, point: Point, ray: &Ray) -> Option<LightSample> {
+        let cos_max = self.cos_max(point)?;
+        let to_center = self.center - point;
+        let direction = Onb::new(to_center.unit_vector()).local(sample_cone(cos_max));
+
+        // The near side of the ball along the sampled direction.
+        let along = direction.dot(to_center);
+        let offset2 = (to_center.norm_squared() - along * along).min(self.radius * self.radius);
+        Some(LightSample {
+            direction,
+            distance: along - (self.radius * self.radius - offset2).sqrt(),
+            radiance: ray.radiance(self.radiance),
+            pdf: cone_pdf(cos_max),
+        })
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, Vec3)> {
+        let to_center = self.center - ray.origin;
+        if to_center.norm_squared() <= self.radius * self.radius {
+            return None;
+        }
+        let a = ray.direction.norm_squared();
+        let half_b = ray.direction.dot(to_center);
+        let discriminant = half_b * half_b - a * (to_center.norm_squared() - self.radius.powi(2));
+        let time = (half_b - discriminant.sqrt()) / a;
+        (discriminant >= 0. && time > 0.).then(|| (time, ray.radiance(self.radiance)))
+    }
+
+    fn pdf(&self, point: Point, direction: Vec3) -> f64 {
+        match self.cos_max(point) {
+            Some(cos_max) if direction.dot((self.center - point).unit_vector()) >= cos_max => {
+                cone_pdf(cos_max)
+            }
+            _ => 0.,
+        }
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let area = 4. * PI * self.radius * self.radius;
+        Some(LightBounds::omnidirectional(
+            self.center,
+            self.radius,
+            PI * area * luminance(self.radiance),
+        ))
+    }
+
+    fn sample_emission(&self, ray: &Ray) -> Option<EmissionSample> {
+        let normal = Vec3::random_unit_vector();
+        let point = self.center + self.radius * normal;
+        let direction = sample_cosine(normal);
+        Some(EmissionSample {
+            ray: ray.spawn(&point, &direction),
+            radiance: ray.radiance(self.radiance),
+            pdf: self.emission_pdf(point, direction)?,
+        })
+    }
+
+    fn emission_pdf(&self, point: Point, direction: Vec3) -> Option<EmissionPdf> {
+        let normal = (point - self.center).unit_vector();
+        Some(EmissionPdf {
+            position: 1. / (4. * PI * self.radius * self.radius),
+            direction: direction.dot(normal).max(0.) / PI,
+            normal,
+        })
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "SphereLight {{ center: {}, radius: {}, radiance: {} }}",
+            self.center, self.radius, self.radiance
+        )
+    }
+}
+
+/// A light of the same `intensity` in every direction, which casts soft shadows when given a
+/// `radius`.
+#[derive(Clone, Debug)]
+pub struct PointLight {
+    pub position: Point,
+    /// Radiant intensity, per steradian.
+    pub intensity: Vec3,
+    pub radius: f64,
+}
+
+impl PointLight {
+    pub fn new(position: Point, intensity: Vec3, radius: f64) -> Self {
+        Self {
+            position,
+            intensity,
+            radius,
+        }
+    }
+
+    /// A ball that emits as much light, if the light has a size.
+    fn sphere(&self) -> Option<SphereLight> {
+        (self.radius > 0.).then(|| {
+            SphereLight::new(
+                self.position,
+                self.radius,
+                self.intensity / (PI * self.radius * self.radius),
+            )
+        })
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Point, ray: &Ray) -> Option<LightSample> {
+        if let Some(sphere) = self.sphere() {
+            return sphere.sample(point, ray);
+        }
+        let to_light = self.position - point;
+        let distance = to_light.norm();
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: ray.radiance(self.intensity) / (distance * distance),
+            pdf: 0.,
+        })
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, Vec3)> {
+        self.sphere()?.hit(ray)
+    }
+
+    fn pdf(&self, point: Point, direction: Vec3) -> f64 {
+        self.sphere()
+            .map_or(0., |sphere| sphere.pdf(point, direction))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::omnidirectional(
+            self.position,
+            self.radius,
+            4. * PI * luminance(self.intensity),
+        ))
+    }
+
+    fn sample_emission(&self, ray: &Ray) -> Option<EmissionSample> {
+        if let Some(sphere) = self.sphere() {
+            return sphere.sample_emission(ray);
+        }
+        let direction = Vec3::random_unit_vector();
+        Some(EmissionSample {
+            ray: ray.spawn(&self.position, &direction),
+            radiance: ray.radiance(self.intensity),
+            pdf: self.emission_pdf(self.position, direction)?,
+        })
+    }
+
+    fn emission_pdf(&self, point: Point, direction: Vec3) -> Option<EmissionPdf> {
+        match self.sphere() {
+            Some(sphere) => sphere.emission_pdf(point, direction),
+            None => Some(EmissionPdf {
+                position: 1.,
+                direction: 1. / (4. * PI),
+                normal: Vec3::default(),
+            }),
+        }
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "PointLight {{ position: {}, intensity: {}, radius: {} }}",
+            self.position, self.intensity, self.radius
+        )
+    }
+}
+
+/// A point light shining into a cone, fading out towards its edge.
+#[derive(Clone, Debug)]
+pub struct SpotLight {
+    pub position: Point,
+    /// Unit vector the light points along.
+    pub direction: Vec3,
+    /// Radiant intensity along `direction`, per steradian.
+    pub intensity: Vec3,
+    /// Half-angle in radians within which the light is at full strength.
+    pub falloff_start: f64,
+    /// Half-angle in radians beyond which there is no light.
+    pub cone_angle: f64,
+    /// How the intensity varies with the angle from `direction`, on top of the cone.
+    pub profile: Option<Arc<IesProfile>>,
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Point,
+        target: Point,
+        intensity: Vec3,
+        falloff_start: Angle,
+        cone_angle: Angle,
+    ) -> Self {
+        Self {
+            position,
+            direction: (target - position).unit_vector(),
+            intensity,
+            falloff_start: Radians::from(falloff_start).0,
+            cone_angle: Radians::from(cone_angle).0,
+            profile: None,
+        }
+    }
+
+    /// The fraction of the intensity sent at `theta` radians from `direction`.
+    pub fn falloff(&self, theta: f64) -> f64 {
+        let profile = self
+            .profile
+            .as_ref()
+            .map_or(1., |profile| profile.value(theta));
+        if theta <= self.falloff_start {
+            return profile;
+        }
+        let (cos_start, cos_end) = (self.falloff_start.cos(), self.cone_angle.cos());
+        let x = ((theta.cos() - cos_end) / (cos_start - cos_end)).clamp(0., 1.);
+        profile * x * x * (3. - 2. * x)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Point, ray: &Ray) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.norm();
+        let direction = to_light / distance;
+        let falloff = self.falloff((-direction).dot(self.direction).clamp(-1., 1.).acos());
+        (falloff > 0.).then(|| LightSample {
+            direction,
+            distance,
+            radiance: ray.radiance(self.intensity) * (falloff / (distance * distance)),
+            pdf: 0.,
+        })
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            center: self.position,
+            radius: 0.,
+            // Over the solid angle of a cone halfway through the falloff, as PBRT does.
+            power: 2.
+                * PI
+                * (1. - ((self.falloff_start + self.cone_angle) / 2.).cos())
+                * luminance(self.intensity),
+            axis: self.direction,
+            cos_theta_o: self.falloff_start.cos(),
+            cos_theta_e: (self.cone_angle - self.falloff_start).max(0.).cos(),
+            two_sided: false,
+        })
+    }
+
+    fn sample_emission(&self, ray: &Ray) -> Option<EmissionSample> {
+        let direction = Onb::new(self.direction).local(sample_cone(self.cone_angle.cos()));
+        let falloff = self.falloff(direction.dot(self.direction).clamp(-1., 1.).acos());
+        Some(EmissionSample {
+            ray: ray.spawn(&self.position, &direction),
+            radiance: ray.radiance(self.intensity) * falloff,
+            pdf: self.emission_pdf(self.position, direction)?,
+        })
+    }
+
+    fn emission_pdf(&self, _point: Point, direction: Vec3) -> Option<EmissionPdf> {
+        let cos_max = self.cone_angle.cos();
+        Some(EmissionPdf {
+            position: 1.,
+            direction: match direction.dot(self.direction) >= cos_max {
+                true => cone_pdf(cos_max),
+                false => 0.,
+            },
+            normal: Vec3::default(),
+        })
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "SpotLight {{ position: {}, direction: {}, intensity: {}, cone_angle: {} }}",
+            self.position, self.direction, self.intensity, self.cone_angle
+        )
+    }
+}
+
+/// A rotationally symmetric photometric profile: relative intensity by angle from the axis.
+#[derive(Clone, Debug)]
+pub struct IesProfile {
+    /// Ascending, in radians.
+    pub angles: Vec<f64>,
+    /// One per angle, scaled so the brightest is 1.
+    pub values: Vec<f64>,
+}
+
+impl IesProfile {
+    /// `angles` in degrees, ascending from the axis.
+    pub fn new(angles: Vec<f64>, values: Vec<f64>) -> Self {
+        assert_eq!(
+            angles.len(),
+            values.len(),
+            "A profile needs exactly one value per angle."
+        );
+        let brightest = values.iter().cloned().fold(0., f64::max);
+        Self {
+            angles: angles.into_iter().map(f64::to_radians).collect(),
+            values: values
+                .into_iter()
+                .map(|value| match brightest > 0. {
+                    true => value / brightest,
+                    false => 0.,
+                })
+                .collect(),
+        }
+    }
+
+    /// Read the vertical candela distribution of an IESNA LM-63 photometric file, averaged over
+    /// its horizontal angles.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        let (_, after_tilt) = text
+            .split_once("TILT=")
+            .ok_or_else(|| invalid("An IES file needs a TILT line."))?;
+        let (tilt, data) = after_tilt.split_once('\n').unwrap_or((after_tilt, ""));
+
+        let mut numbers = data.split_whitespace().map(|token| {
+            token
+                .parse::<f64>()
+                .map_err(|_| invalid("An IES file has a malformed number."))
+        });
+        let mut next = || {
+            numbers
+                .next()
+                .unwrap_or_else(|| Err(invalid("An IES file ends too early.")))
+        };
+
+        // Lamp tilt factors do not change the shape of the distribution.
+        if tilt.trim() == "INCLUDE" {
+            next()?;
+            let count = next()? as usize;
+            for _ in 0..2 * count {
+                next()?;
+            }
+        }
+
+        let mut header = [0.; 13];
+        for value in header.iter_mut() {
+            *value = next()?;
+        }
+        let (vertical, horizontal) = (header[3] as usize, header[4] as usize);
+        if vertical == 0 || horizontal == 0 {
+            return Err(invalid("An IES file needs at least one angle each way."));
+        }
+
+        let angles = (0..vertical).map(|_| next()).collect::<Result<Vec<_>>>()?;
+        for _ in 0..horizontal {
+            next()?;
+        }
+        let mut values = vec![0.; vertical];
+        for _ in 0..horizontal {
+            for value in values.iter_mut() {
+                *value += next()? / horizontal as f64;
+            }
+        }
+        Ok(Self::new(angles, values))
+    }
+
+    /// Linearly interpolated at `theta` radians from the axis, dark beyond the last angle.
+    pub fn value(&self, theta: f64) -> f64 {
+        let next = self.angles.partition_point(|&angle| angle <= theta);
+        match next {
+            0 => self.values.first().cloned().unwrap_or(0.),
+            next if next == self.angles.len() => match theta <= self.angles[next - 1] {
+                true => self.values[next - 1],
+                false => 0.,
+            },
+            next => {
+                let (start, end) = (self.angles[next - 1], self.angles[next]);
+                let t = (theta - start) / (end - start);
+                (1. - t) * self.values[next - 1] + t * self.values[next]
+            }
+        }
+    }
+}
+
+/// Light from very far away, such as the sun. With an angular diameter it is a disk in the sky,
+/// which softens the shadows it casts.
+#[derive(Clone, Debug)]
+pub struct DirectionalLight {
+    /// Unit vector towards the light.
+    pub direction: Vec3,
+    /// Irradiance on a surface facing the light.
+    pub irradiance: Vec3,
+    /// In radians.
+    pub angular_diameter: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3, angular_diameter: Angle) -> Self {
+        Self {
+            direction: direction.unit_vector(),
+            irradiance,
+            angular_diameter: Radians::from(angular_diameter).0,
+        }
+    }
+
+    fn cos_max(&self) -> f64 {
+        (self.angular_diameter / 2.).cos()
+    }
+
+    /// The radiance of the disk, which integrates to `irradiance` over it.
+    fn radiance(&self) -> Vec3 {
+        self.irradiance / (PI * (self.angular_diameter / 2.).sin().powi(2))
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Point, ray: &Ray) -> Option<LightSample> {
+        if self.angular_diameter <= 0. {
+            return Some(LightSample {
+                direction: self.direction,
+                distance: f64::INFINITY,
+                radiance: ray.radiance(self.irradiance),
+                pdf: 0.,
+            });
+        }
+        let cos_max = self.cos_max();
+        Some(LightSample {
+            direction: Onb::new(self.direction).local(sample_cone(cos_max)),
+            distance: f64::INFINITY,
+            radiance: ray.radiance(self.radiance()),
+            pdf: cone_pdf(cos_max),
+        })
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, Vec3)> {
+        let is_within = ray.direction.unit_vector().dot(self.direction) >= self.cos_max();
+        (self.angular_diameter > 0. && is_within)
+            .then(|| (f64::INFINITY, ray.radiance(self.radiance())))
+    }
+
+    fn pdf(&self, _point: Point, direction: Vec3) -> f64 {
+        let cos_max = self.cos_max();
+        match self.angular_diameter > 0. && direction.dot(self.direction) >= cos_max {
+            true => cone_pdf(cos_max),
+            false => 0.,
+        }
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "DirectionalLight {{ direction: {}, irradiance: {}, angular_diameter: {} }}",
+            self.direction, self.irradiance, self.angular_diameter
+        )
+    }
+}
+
+/// A parallelogram spanned by the edges `u` and `v` from `corner`, emitting from the side `u × v`
+/// points to.
+#[derive(Clone, Debug)]
+pub struct RectLight {
+    pub corner: Point,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub radiance: Vec3,
+}
+
+impl RectLight {
+    pub fn new(corner: Point, u: Vec3, v: Vec3, radiance: Vec3) -> Self {
+        Self {
+            corner,
+            u,
+            v,
+            radiance,
+        }
+    }
+
+    /// Convert a density per area at `to_light` from a point to one per solid angle there.
+    fn solid_angle_pdf(&self, to_light: Vec3) -> f64 {
+        let normal = self.u.cross(self.v);
+        let cos_light = -to_light.unit_vector().dot(normal.unit_vector());
+        match cos_light > 0. {
+            true => to_light.norm_squared() / (cos_light * normal.norm()),
+            false => 0.,
+        }
+    }
+}
+
+impl Light for RectLight {
+    fn sample(&self, point: Point, ray: &Ray) -> Option<LightSample> {
+        let mut rng = rng();
+        let to_light = self.corner + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v - point;
+        let pdf = self.solid_angle_pdf(to_light);
+        (pdf > 0.).then(|| LightSample {
+            direction: to_light.unit_vector(),
+            distance: to_light.norm(),
+            radiance: ray.radiance(self.radiance),
+            pdf,
+        })
+    }
+
+    fn hit(&self, ray: &Ray) -> Option<(f64, Vec3)> {
+        let normal = self.u.cross(self.v);
+        let denominator = normal.dot(ray.direction);
+        if denominator >= 0. {
+            return None;
+        }
+        let time = normal.dot(self.corner - ray.origin) / denominator;
+        let offset = ray.at(time) - self.corner;
+        let w = normal / normal.norm_squared();
+        let (alpha, beta) = (w.dot(offset.cross(self.v)), w.dot(self.u.cross(offset)));
+        let is_inside = (0. ..=1.).contains(&alpha) && (0. ..=1.).contains(&beta);
+        (time > 0. && is_inside).then(|| (time, ray.radiance(self.radiance)))
+    }
+
+    fn pdf(&self, point: Point, direction: Vec3) -> f64 {
+        self.hit(&Ray::new(&point, &direction))
+            .map_or(0., |(time, _)| self.solid_angle_pdf(time * direction))
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let normal = self.u.cross(self.v);
+        Some(LightBounds {
+            center: self.corner + 0.5 * (self.u + self.v),
+            radius: 0.5 * (self.u + self.v).norm().max((self.u - self.v).norm()),
+            power: PI * normal.norm() * luminance(self.radiance),
+            axis: normal.unit_vector(),
+            cos_theta_o: 1.,
+            cos_theta_e: 0.,
+            two_sided: false,
+        })
+    }
+
+    fn sample_emission(&self, ray: &Ray) -> Option<EmissionSample> {
+        let mut rng = rng();
+        let point = self.corner + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v;
+        let direction = sample_cosine(self.u.cross(self.v).unit_vector());
+        Some(EmissionSample {
+            ray: ray.spawn(&point, &direction),
+            radiance: ray.radiance(self.radiance),
+            pdf: self.emission_pdf(point, direction)?,
+        })
+    }
+
+    fn emission_pdf(&self, _point: Point, direction: Vec3) -> Option<EmissionPdf> {
+        let normal = self.u.cross(self.v);
+        Some(EmissionPdf {
+            position: 1. / normal.norm(),
+            direction: direction.dot(normal.unit_vector()).max(0.) / PI,
+            normal: normal.unit_vector(),
+        })
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "RectLight {{ corner: {}, u: {}, v: {}, radiance: {} }}",
+            self.corner, self.u, self.v, self.radiance
+        )
+    }
+}
+
+/// A direction uniformly distributed in the cone of `cos_max` around the `z` axis.
+fn sample_cone(cos_max: f64) -> Vec3 {
+    let mut rng = rng();
+    let cos_theta = 1. - rng.gen::<f64>() * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * PI * rng.gen::<f64>();
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+fn cone_pdf(cos_max: f64) -> f64 {
+    1. / (2. * PI * (1. - cos_max))
+}
+
+/// A unit vector distributed by its cosine with `normal`.
+fn sample_cosine(normal: Vec3) -> Vec3 {
+    let direction = normal + Vec3::random_unit_vector();
+    match direction.near_zero() {
+        true => normal,
+        false => direction.unit_vector(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DirectionalLight, IesProfile, Light, PointLight, RectLight, SphereLight, SpotLight,
+    };
+    use crate::prelude::{
+        luminance, ray_color, Angle, Degrees, HittableList, LinAlgOp, Material, Point, Quad, Ray,
+        Scene, Sphere, Vec3,
+    };
+    use std::sync::Arc;
+
+    fn area_lights() -> Vec<Arc<dyn Light>> {
+        vec![
+            Arc::new(SphereLight::new(
+                Point::new(1., 3., 0.),
+                0.5,
+                Vec3::new(4., 4., 4.),
+            )),
+            Arc::new(PointLight::new(
+                Point::new(0., 4., 0.),
+                Vec3::new(1., 1., 1.),
+                0.5,
+            )),
+            Arc::new(RectLight::new(
+                Point::new(-1., 2., -1.),
+                Vec3::new(2., 0., 0.),
+                Vec3::new(0., 0., 2.),
+                Vec3::new(1., 1., 1.),
+            )),
+            Arc::new(DirectionalLight::new(
+                Vec3::new(0.3, 1., 0.2),
+                Vec3::new(1., 1., 1.),
+                Angle::Degrees(Degrees(5.)),
+            )),
+        ]
+    }
+
+    #[test]
+    fn samples_lie_on_the_light_with_their_pdf() {
+        let point = Point::new(0.2, 0., 0.1);
+        for light in area_lights() {
+            for _ in 0..1000 {
+                let sample = light.sample(point, &Ray::default()).unwrap();
+                let pdf = light.pdf(point, sample.direction);
+                assert!(
+                    (pdf - sample.pdf).abs() < 1e-6 * pdf,
+                    "{}",
+                    light.metadata()
+                );
+
+                let (time, radiance) = light.hit(&Ray::new(&point, &sample.direction)).unwrap();
+                assert_eq!(radiance, sample.radiance);
+                let distance_error = (time - sample.distance) / time;
+                assert!(time.is_infinite() || distance_error.abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn emission_carries_the_power_of_the_light() {
+        let mut lights = area_lights();
+        lights.truncate(3);
+        lights.push(Arc::new(PointLight::new(
+            Point::new(0., 4., 0.),
+            Vec3::new(1., 2., 3.),
+            0.,
+        )));
+
+        for light in lights {
+            let samples = 20_000;
+            let power = (0..samples)
+                .map(|_| {
+                    let sample = light.sample_emission(&Ray::default()).unwrap();
+                    let direction = sample.ray.direction;
+                    let pdf = light.emission_pdf(sample.ray.origin, direction).unwrap();
+                    assert!((pdf.direction - sample.pdf.direction).abs() < 1e-9);
+                    assert_eq!(pdf.position, sample.pdf.position);
+
+                    let cos_theta = match pdf.is_delta_position() {
+                        true => 1.,
+                        false => direction.dot(pdf.normal),
+                    };
+                    luminance(sample.radiance) * cos_theta / (pdf.position * pdf.direction)
+                })
+                .sum::<f64>()
+                / samples as f64;
+            let expected = light.bounds().unwrap().power;
+            assert!(
+                (power - expected).abs() < 0.02 * expected,
+                "{}: {} instead of {}",
+                light.metadata(),
+                power,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn sized_lights_keep_their_irradiance() {
+        let point = Point::new(0., 0., 0.);
+        let up = Vec3::new(0., 1., 0.);
+        let irradiance = |light: &dyn Light, direction: Vec3| {
+            let samples = 20_000;
+            (0..samples)
+                .map(|_| {
+                    let sample = light.sample(point, &Ray::default()).unwrap();
+                    let cos_theta = sample.direction.dot(direction);
+                    match sample.is_delta() {
+                        true => sample.radiance.1 * cos_theta,
+                        false => sample.radiance.1 * cos_theta / sample.pdf,
+                    }
+                })
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        let intensity = Vec3::new(8., 8., 8.);
+        let point_light = PointLight::new(Point::new(0., 2., 0.), intensity, 0.);
+        let ball = PointLight::new(Point::new(0., 2., 0.), intensity, 0.5);
+        assert!((irradiance(&point_light, up) - 2.).abs() < 1e-9);
+        assert!((irradiance(&ball, up) - 2.).abs() < 0.02);
+
+        let sun = |degrees| {
+            DirectionalLight::new(up, Vec3::new(3., 3., 3.), Angle::Degrees(Degrees(degrees)))
+        };
+        assert!((irradiance(&sun(0.), up) - 3.).abs() < 1e-9);
+        assert!((irradiance(&sun(10.), up) - 3.).abs() < 0.01);
+    }
+
+    #[test]
+    fn spotlights_follow_their_cone_and_profile() {
+        let mut spot = SpotLight::new(
+            Point::new(0., 4., 0.),
+            Point::new(0., 0., 0.),
+            Vec3::new(1., 1., 1.),
+            Angle::Degrees(Degrees(20.)),
+            Angle::Degrees(Degrees(30.)),
+        );
+        assert_eq!(spot.falloff(0.1), 1.);
+        assert!((0. ..1.).contains(&spot.falloff(25_f64.to_radians())));
+        assert!(spot
+            .sample(Point::new(4., 0., 0.), &Ray::default())
+            .is_none());
+
+        let ies = "IESNA:LM-63-2002\n[TEST] spot\nTILT=NONE\n1 1000 1 3 2 1 1 0 0 0\n1 1 100\n\
+                   0 10 20\n0 90\n100 50 0\n100 30 0\n";
+        let profile = IesProfile::read(&mut ies.as_bytes()).unwrap();
+        assert_eq!(profile.values, vec![1., 0.4, 0.]);
+        assert!((profile.value(5_f64.to_radians()) - 0.7).abs() < 1e-9);
+        assert_eq!(profile.value(40_f64.to_radians()), 0.);
+        assert!(IesProfile::read(&mut "TILT=NONE\n1 1000".as_bytes()).is_err());
+
+        spot.profile = Some(Arc::new(profile));
+        let sample = spot
+            .sample(Point::new(0., 0., 0.), &Ray::default())
+            .unwrap();
+        assert!((sample.radiance.1 - 1. / 16.).abs() < 1e-9);
+        let sample = spot.sample(
+            Point::new(4_f64 * 10_f64.to_radians().tan(), 0., 0.),
+            &Ray::default(),
+        );
+        let distance2 = 16. / 10_f64.to_radians().cos().powi(2);
+        assert!((sample.unwrap().radiance.1 - 0.4 / distance2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn floors_reflect_direct_light() {
+        // A white floor in a black room, seen from the side.
+        let albedo = 0.5;
+        let mut world = HittableList::new();
+        world.push(Arc::new(Quad::new(
+            Point::new(-5., 0., -5.),
+            Vec3::new(0., 0., 10.),
+            Vec3::new(10., 0., 0.),
+            Arc::new(Material::lambertian(Vec3::new(albedo, albedo, albedo))),
+        )));
+        world.push(Arc::new(Sphere::new(
+            Point::new(0., 0., 0.),
+            50.,
+            Arc::new(Material::lambertian(Vec3::new(0., 0., 0.))),
+        )));
+        let ray = Ray::new(&Point::new(3., 1., 0.), &Vec3::new(-3., -1., 0.));
+
+        let radiance = |light: Arc<dyn Light>| {
+            let mut scene = Scene::new(world.clone());
+            scene.push_light(light);
+            let samples = 20_000;
+            (0..samples)
+                .map(|_| ray_color(&ray, &scene, 3).1)
+                .sum::<f64>()
+                / samples as f64
+        };
+
+        // Lambertian reflection of the irradiance under the light.
+        let point_light = PointLight::new(Point::new(0., 2., 0.), Vec3::new(4., 4., 4.), 0.);
+        let expected = albedo / std::f64::consts::PI;
+        assert!((radiance(Arc::new(point_light)) - expected).abs() < 1e-9);
+
+        // Found both by sampling the light and by bouncing into it.
+        let ball = SphereLight::new(Point::new(0., 2., 0.), 0.5, Vec3::new(4., 4., 4.));
+        let expected = albedo * 4. * (0.5_f64 / 2.).powi(2);
+        let estimate = radiance(Arc::new(ball));
+        assert!(
+            (estimate - expected).abs() < 0.02 * expected,
+            "{}",
+            estimate
+        );
+    }
+}
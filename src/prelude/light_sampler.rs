@@ -0,0 +1,471 @@
+use crate::prelude::{AliasTable, Light, LightBounds, Point, Vec3};
+use std::sync::Arc;
+
+/// How a path picks the light to sample at each bounce.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LightSelection {
+    Uniform,
+    /// In proportion to the power of each light.
+    Power,
+    /// By the estimated contribution of each light to the point, through a `LightTree`.
+    #[default]
+    Tree,
+}
+
+/// Picks lights by a `LightSelection`. Lights at infinity have no bounds and are picked
+/// uniformly, each as likely as the rest of the lights together.
+#[derive(Clone, Debug)]
+pub struct LightSampler {
+    /// Picked uniformly.
+    uniform: Vec<usize>,
+    bounded: Option<BoundedLights>,
+    /// Where each light is within `bounded`, if it is there.
+    bounded_index: Vec<Option<usize>>,
+}
+
+#[derive(Clone, Debug)]
+enum BoundedLights {
+    Power {
+        table: AliasTable,
+        lights: Vec<usize>,
+    },
+    Tree(LightTree),
+}
+
+impl LightSampler {
+    pub fn new(lights: &[Arc<dyn Light>], selection: LightSelection) -> Self {
+        let bounds = lights
+            .iter()
+            .map(|light| match selection {
+                LightSelection::Uniform => None,
+                _ => light.bounds(),
+            })
+            .collect::<Vec<_>>();
+        let uniform = (0..lights.len())
+            .filter(|&index| bounds[index].is_none())
+            .collect::<Vec<_>>();
+        let bounded_lights = (0..lights.len())
+            .filter(|&index| bounds[index].is_some())
+            .collect::<Vec<_>>();
+
+        let mut bounded_index = vec![None; lights.len()];
+        for (position, &index) in bounded_lights.iter().enumerate() {
+            bounded_index[index] = Some(position);
+        }
+
+        let bounded = match (bounded_lights.is_empty(), selection) {
+            (true, _) | (_, LightSelection::Uniform) => None,
+            (false, LightSelection::Power) => Some(BoundedLights::Power {
+                table: AliasTable::new(
+                    &bounded_lights
+                        .iter()
+                        .map(|&index| bounds[index].unwrap().power)
+                        .collect::<Vec<_>>(),
+                ),
+                lights: bounded_lights,
+            }),
+            (false, LightSelection::Tree) => Some(BoundedLights::Tree(LightTree::new(
+                bounded_lights
+                    .iter()
+                    .map(|&index| (index, bounds[index].unwrap()))
+                    .collect(),
+            ))),
+        };
+
+        Self {
+            uniform,
+            bounded,
+            bounded_index,
+        }
+    }
+
+    /// The probability of picking one of the uniformly picked lights.
+    fn uniform_probability(&self) -> f64 {
+        let uniform = self.uniform.len() as f64;
+        match self.bounded {
+            Some(_) => uniform / (uniform + 1.),
+            None => 1.,
+        }
+    }
+
+    /// A light to sample at `point` on a surface with `normal` (zero in media) and the probability
+    /// of picking it, for `u` uniform in [0, 1).
+    pub fn sample(&self, point: Point, normal: Vec3, u: f64) -> Option<(usize, f64)> {
+        let uniform_probability = self.uniform_probability();
+        if u < uniform_probability {
+            let count = self.uniform.len();
+            let position = ((u / uniform_probability * count as f64) as usize).min(count - 1);
+            return Some((self.uniform[position], uniform_probability / count as f64));
+        }
+
+        let u = ((u - uniform_probability) / (1. - uniform_probability)).min(1. - f64::EPSILON);
+        let (index, pmf) = match self.bounded.as_ref()? {
+            BoundedLights::Power { table, lights } => {
+                let (position, pmf) = table.sample(u)?;
+                (lights[position], pmf)
+            }
+            BoundedLights::Tree(tree) => tree.sample(point, normal, u)?,
+        };
+        Some((index, (1. - uniform_probability) * pmf))
+    }
+
+    /// The probability of `sample` picking the light `index`.
+    pub fn pmf(&self, point: Point, normal: Vec3, index: usize) -> f64 {
+        let uniform_probability = self.uniform_probability();
+        let Some(position) = self.bounded_index[index] else {
+            return uniform_probability / self.uniform.len() as f64;
+        };
+        let pmf = match self.bounded.as_ref() {
+            Some(BoundedLights::Power { table, .. }) => table.pmf(position),
+            Some(BoundedLights::Tree(tree)) => tree.pmf(point, normal, position),
+            None => 0.,
+        };
+        (1. - uniform_probability) * pmf
+    }
+}
+
+/// A bounding volume hierarchy over lights, descended by the importance of each branch to a point
+/// (Conty Estevez and Kulla, "Importance Sampling of Many Lights with Adaptive Tree Splitting",
+/// 2018).
+#[derive(Clone, Debug)]
+pub struct LightTree {
+    /// Depth first, so the first child of an interior node follows it.
+    nodes: Vec<LightNode>,
+    /// The branches taken from the root to each leaf, one bit per level with the root's lowest,
+    /// in the order the lights were given.
+    trails: Vec<u64>,
+}
+
+#[derive(Clone, Debug)]
+struct LightNode {
+    bounds: LightBounds,
+    kind: LightNodeKind,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum LightNodeKind {
+    /// The light's index in the scene.
+    Leaf(usize),
+    /// Where the second child is.
+    Interior(usize),
+}
+
+impl LightTree {
+    /// Build a tree over lights given by their index in the scene and their bounds.
+    pub fn new(mut lights: Vec<(usize, LightBounds)>) -> Self {
+        let mut tree = Self {
+            nodes: vec![],
+            trails: vec![0; lights.len()],
+        };
+        let mut positions = (0..lights.len()).collect::<Vec<_>>();
+        if !lights.is_empty() {
+            tree.build(&mut lights, &mut positions, 0, 0);
+        }
+        tree
+    }
+
+    /// Add the nodes over `lights`, whose positions in the list the tree was built from are
+    /// `positions`, and return their bounds.
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        positions: &mut [usize],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        if let [(index, bounds)] = lights {
+            self.trails[positions[0]] = trail;
+            self.nodes.push(LightNode {
+                bounds: *bounds,
+                kind: LightNodeKind::Leaf(*index),
+            });
+            return *bounds;
+        }
+
+        // Split at the median along the axis the lights spread furthest on.
+        let (mut low, mut high) = (
+            Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        );
+        for (_, bounds) in lights.iter() {
+            let c = bounds.center;
+            low = Vec3::new(low.0.min(c.0), low.1.min(c.1), low.2.min(c.2));
+            high = Vec3::new(high.0.max(c.0), high.1.max(c.1), high.2.max(c.2));
+        }
+        let extent = high - low;
+        let axis = |v: Vec3| match (
+            extent.0 >= extent.1,
+            extent.0 >= extent.2,
+            extent.1 >= extent.2,
+        ) {
+            (true, true, _) => v.0,
+            (false, _, true) => v.1,
+            _ => v.2,
+        };
+        let mut order = (0..lights.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| axis(lights[a].1.center).total_cmp(&axis(lights[b].1.center)));
+        let sorted_lights = order.iter().map(|&i| lights[i]).collect::<Vec<_>>();
+        let sorted_positions = order.iter().map(|&i| positions[i]).collect::<Vec<_>>();
+        lights.copy_from_slice(&sorted_lights);
+        positions.copy_from_slice(&sorted_positions);
+
+        let node = self.nodes.len();
+        self.nodes.push(LightNode {
+            bounds: lights[0].1,
+            kind: LightNodeKind::Interior(0),
+        });
+        let middle = lights.len() / 2;
+        let (first_lights, second_lights) = lights.split_at_mut(middle);
+        let (first_positions, second_positions) = positions.split_at_mut(middle);
+        let first = self.build(first_lights, first_positions, trail, depth + 1);
+        let second_child = self.nodes.len();
+        let second = self.build(
+            second_lights,
+            second_positions,
+            trail | (1 << depth),
+            depth + 1,
+        );
+
+        let bounds = first.union(&second);
+        self.nodes[node] = LightNode {
+            bounds,
+            kind: LightNodeKind::Interior(second_child),
+        };
+        bounds
+    }
+
+    /// The probabilities of descending into each child of the interior node `node`.
+    fn branch_probabilities(
+        &self,
+        node: usize,
+        second: usize,
+        point: Point,
+        normal: Vec3,
+    ) -> Option<f64> {
+        let first = self.nodes[node + 1].bounds.importance(point, normal);
+        let second = self.nodes[second].bounds.importance(point, normal);
+        (first + second > 0.).then(|| first / (first + second))
+    }
+
+    /// A light to sample at `point` and the probability of picking it, for `u` uniform in [0, 1).
+    pub fn sample(&self, point: Point, normal: Vec3, mut u: f64) -> Option<(usize, f64)> {
+        let root = self.nodes.first()?;
+        if root.bounds.importance(point, normal) <= 0. {
+            return None;
+        }
+
+        let (mut node, mut pmf) = (0, 1.);
+        loop {
+            match self.nodes[node].kind {
+                LightNodeKind::Leaf(index) => return Some((index, pmf)),
+                LightNodeKind::Interior(second) => {
+                    let p_first = self.branch_probabilities(node, second, point, normal)?;
+                    // Reuse what is left of `u` for the levels below.
+                    if u < p_first {
+                        u = (u / p_first).min(1. - f64::EPSILON);
+                        pmf *= p_first;
+                        node += 1;
+                    } else {
+                        u = ((u - p_first) / (1. - p_first)).min(1. - f64::EPSILON);
+                        pmf *= 1. - p_first;
+                        node = second;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The probability of `sample` picking the light that was `position`th in the list the tree
+    /// was built from.
+    pub fn pmf(&self, point: Point, normal: Vec3, position: usize) -> f64 {
+        let Some(root) = self.nodes.first() else {
+            return 0.;
+        };
+        if root.bounds.importance(point, normal) <= 0. {
+            return 0.;
+        }
+
+        let (mut node, mut pmf, mut trail) = (0, 1., self.trails[position]);
+        while let LightNodeKind::Interior(second) = self.nodes[node].kind {
+            let Some(p_first) = self.branch_probabilities(node, second, point, normal) else {
+                return 0.;
+            };
+            match trail & 1 {
+                0 => {
+                    pmf *= p_first;
+                    node += 1;
+                }
+                _ => {
+                    pmf *= 1. - p_first;
+                    node = second;
+                }
+            }
+            trail >>= 1;
+        }
+        pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LightSampler, LightSelection};
+    use crate::prelude::{
+        Angle, Degrees, DirectionalLight, Light, LinAlgOp, Point, PointLight, Ray, RectLight,
+        SphereLight, SpotLight, Vec3,
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::Arc;
+
+    /// Hundreds of lamps of all kinds scattered over a plane, and the sun, the same every time.
+    fn lights() -> Vec<Arc<dyn Light>> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut lights: Vec<Arc<dyn Light>> = vec![];
+        for i in 0..300 {
+            let position = Point::new(
+                rng.gen_range(-20.0..20.),
+                rng.gen_range(0.2..3.),
+                rng.gen_range(-20.0..20.),
+            );
+            let color = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * rng.gen_range(0.1..10.);
+            lights.push(match i % 4 {
+                0 => Arc::new(SphereLight::new(position, 0.2, color)),
+                1 => Arc::new(PointLight::new(position, color, 0.)),
+                2 => Arc::new(SpotLight::new(
+                    position,
+                    position + Vec3::new(rng.gen_range(-1.0..1.), -1., rng.gen_range(-1.0..1.)),
+                    color,
+                    Angle::Degrees(Degrees(20.)),
+                    Angle::Degrees(Degrees(35.)),
+                )),
+                _ => Arc::new(RectLight::new(
+                    position,
+                    Vec3::new(0.5, 0., 0.),
+                    Vec3::new(0., rng.gen_range(-0.5..0.5), 0.5),
+                    color,
+                )),
+            });
+        }
+        lights.push(Arc::new(DirectionalLight::new(
+            Vec3::new(1., 2., 0.),
+            Vec3::new(1., 1., 1.),
+            Angle::Degrees(Degrees(0.)),
+        )));
+        lights
+    }
+
+    /// Irradiance from a light on a surface, averaged over many light samples.
+    fn irradiance(light: &dyn Light, point: Point, normal: Vec3) -> f64 {
+        let samples = 64;
+        (0..samples)
+            .filter_map(|_| light.sample(point, &Ray::default()))
+            .map(|sample| {
+                let cos_theta = sample.direction.dot(normal).max(0.);
+                match sample.is_delta() {
+                    true => sample.radiance.1 * cos_theta,
+                    false => sample.radiance.1 * cos_theta / sample.pdf,
+                }
+            })
+            .sum::<f64>()
+            / samples as f64
+    }
+
+    #[test]
+    fn light_selection_is_unbiased() {
+        let lights = lights();
+        let point = Point::new(1., 0., -2.);
+        let normal = Vec3::new(0., 1., 0.);
+        let contributions = lights
+            .iter()
+            .map(|light| irradiance(light.as_ref(), point, normal))
+            .collect::<Vec<_>>();
+        let total = contributions.iter().sum::<f64>();
+
+        for selection in [
+            LightSelection::Uniform,
+            LightSelection::Power,
+            LightSelection::Tree,
+        ] {
+            let sampler = LightSampler::new(&lights, selection);
+
+            // Every light that contributes may be picked. Trees skip branches that cannot reach
+            // the point, so the probabilities may sum to less than one.
+            let pmfs = (0..lights.len())
+                .map(|index| sampler.pmf(point, normal, index))
+                .collect::<Vec<_>>();
+            let sum = pmfs.iter().sum::<f64>();
+            assert!(sum <= 1. + 1e-9 && sum > 0.5, "{:?}: {}", selection, sum);
+            for (pmf, contribution) in pmfs.iter().zip(&contributions) {
+                assert!(*pmf > 0. || *contribution == 0., "{:?}", selection);
+            }
+
+            // Sampling follows them, so that weighting by them finds all the light.
+            let samples = 100_000;
+            let mut estimate = 0.;
+            for i in 0..samples {
+                let u = (i as f64 + 0.5) / samples as f64;
+                if let Some((index, pmf)) = sampler.sample(point, normal, u) {
+                    assert!((pmf - pmfs[index]).abs() < 1e-12);
+                    estimate += contributions[index] / pmf;
+                }
+            }
+            estimate /= samples as f64;
+            assert!(
+                (estimate - total).abs() < 0.01 * total,
+                "{:?}: {} instead of {}",
+                selection,
+                estimate,
+                total
+            );
+        }
+    }
+
+    #[test]
+    fn trees_prefer_lights_that_matter() {
+        let lights = lights();
+        let point = Point::new(1., 0., -2.);
+        let normal = Vec3::new(0., 1., 0.);
+        let uniform = LightSampler::new(&lights, LightSelection::Uniform);
+        let tree = LightSampler::new(&lights, LightSelection::Tree);
+
+        // The variance of estimating the irradiance from one light sample.
+        let contributions = lights
+            .iter()
+            .map(|light| irradiance(light.as_ref(), point, normal))
+            .collect::<Vec<_>>();
+        let second_moment = |sampler: &LightSampler| {
+            (0..lights.len())
+                .map(|index| match sampler.pmf(point, normal, index) {
+                    pmf if pmf > 0. => contributions[index].powi(2) / pmf,
+                    _ => 0.,
+                })
+                .sum::<f64>()
+        };
+        assert!(second_moment(&tree) < 0.2 * second_moment(&uniform));
+    }
+
+    #[test]
+    fn narrow_spots_weigh_less_than_points() {
+        // Both shine as brightly straight down on the point, but the spot only within a cone.
+        let (position, intensity) = (Point::new(0., 2., 0.), Vec3::new(1., 1., 1.));
+        let lights: Vec<Arc<dyn Light>> = vec![
+            Arc::new(SpotLight::new(
+                position,
+                Point::new(0., 0., 0.),
+                intensity,
+                Angle::Degrees(Degrees(5.)),
+                Angle::Degrees(Degrees(10.)),
+            )),
+            Arc::new(PointLight::new(position, intensity, 0.)),
+        ];
+        let (point, normal) = (Point::new(0., 0., 0.), Vec3::new(0., 1., 0.));
+        for selection in [LightSelection::Power, LightSelection::Tree] {
+            let sampler = LightSampler::new(&lights, selection);
+            assert!(
+                sampler.pmf(point, normal, 0) < sampler.pmf(point, normal, 1),
+                "{:?}",
+                selection
+            );
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Quick renders for reviewing layout rather than look: clay, where everything is the same gray
+//! `Lambertian`, and ambient occlusion, which shades hits by how open their surroundings are.
+use crate::prelude::{
+    HitRecord, Hittable, HittableList, LinAlgOp, LinAlgRandGen, Material, Ray, Scene, Vec3,
+};
+use std::sync::Arc;
+
+/// An object rendered in `clay` whatever its own material.
+pub struct Clay {
+    pub object: Arc<dyn Hittable>,
+    pub clay: Arc<Material>,
+}
+
+impl Clay {
+    pub fn new(object: Arc<dyn Hittable>, clay: Arc<Material>) -> Self {
+        Self { object, clay }
+    }
+
+    /// The hit on the object, made of clay and with no inside to pass into.
+    fn coat(&self, hit_record: HitRecord) -> HitRecord {
+        HitRecord {
+            material: self.clay.clone(),
+            interior: None,
+            surrounding_ior: 1.,
+            ..hit_record
+        }
+    }
+}
+
+impl Hittable for Clay {
+    /// Holes cut by masks stay, as clay has none of its own.
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.object
+            .hit_opaque(ray, t_min, t_max)
+            .map(|hit_record| self.coat(hit_record))
+    }
+
+    fn hit_all(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        self.object
+            .hit_all(ray, t_min, t_max)
+            .into_iter()
+            .map(|hit_record| self.coat(hit_record))
+            .collect()
+    }
+
+    fn metadata(&self) -> String {
+        format!("Clay {{ object: {} }}", self.object.metadata())
+    }
+}
+
+impl Scene {
+    /// The scene with every object in a neutral gray `Lambertian`, under the same lights.
+    pub fn clay(&self) -> Self {
+        let clay = Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5)));
+        let mut world = HittableList::new();
+        for object in &self.world.objects {
+            world.push(Arc::new(Clay::new(object.clone(), clay.clone())));
+        }
+
+        let mut scene = Scene::new(world);
+        scene.lights = self.lights.clone();
+        scene.background = self.background.clone();
+        scene.light_selection = self.light_selection;
+        scene
+    }
+}
+
+/// The fraction of `samples` cosine-distributed directions from where `ray` hits that are not
+/// blocked within `radius`, in white. Rays that escape see nothing blocking them.
+pub fn ambient_occlusion_color(ray: &Ray, scene: &Scene, radius: f64, samples: usize) -> Vec3 {
+    let Some(hit_record) = scene.hit(ray, 0.001, f64::INFINITY) else {
+        return Vec3::new(1., 1., 1.);
+    };
+    let hit_record = hit_record.with_shading(ray);
+    let normal = hit_record.normal;
+
+    let open = (0..samples)
+        .filter(|_| {
+            let mut direction = normal + Vec3::random_unit_vector();
+            if direction.near_zero() {
+                direction = normal;
+            }
+            let occlusion_ray = ray.spawn(&hit_record.point, &direction.unit_vector());
+            scene.hit(&occlusion_ray, 0.001, radius).is_none()
+        })
+        .count();
+    let visibility = open as f64 / samples.max(1) as f64;
+    Vec3::new(visibility, visibility, visibility)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ambient_occlusion_color;
+    use crate::prelude::{
+        Hittable, HittableList, Material, Nested, Point, Quad, Ray, Scene, Sphere, Vec3,
+    };
+    use std::sync::Arc;
+
+    fn floor(world: &mut HittableList) {
+        world.push(Arc::new(Quad::new(
+            Point::new(-100., 0., -100.),
+            Vec3::new(0., 0., 200.),
+            Vec3::new(200., 0., 0.),
+            Arc::new(Material::metal(Vec3::new(0.9, 0.9, 0.9), 0.)),
+        )));
+    }
+
+    #[test]
+    fn clay_replaces_every_material() {
+        let mut world = HittableList::new();
+        floor(&mut world);
+        world.push(Arc::new(Nested::new(
+            Arc::new(Sphere::new(
+                Point::new(0., 1., 0.),
+                0.5,
+                Arc::new(Material::dielectric(1.5)),
+            )),
+            None,
+            1,
+        )));
+        let scene = Scene::new(world).clay();
+
+        let down = Ray::new(&Point::new(0., 3., 0.), &Vec3::new(0., -1., 0.));
+        let hits = scene.world.hit_all(&down, 0.001, f64::INFINITY);
+        assert_eq!(hits.len(), 3);
+        for hit in hits {
+            assert!(matches!(*hit.material, Material::Lambertian { .. }));
+            assert!(hit.interior.is_none());
+        }
+    }
+
+    #[test]
+    fn occlusion_measures_how_open_hits_are() {
+        let down = |x: f64| Ray::new(&Point::new(x, 1., 0.), &Vec3::new(0., -1., 0.));
+
+        // An open floor sees nothing.
+        let mut world = HittableList::new();
+        floor(&mut world);
+        let open = Scene::new(world.clone());
+        assert_eq!(
+            ambient_occlusion_color(&down(0.), &open, 100., 64),
+            Vec3::new(1., 1., 1.)
+        );
+
+        // A wall next to it hides half of the cosine-weighted directions, unless it is too far.
+        world.push(Arc::new(Quad::new(
+            Point::new(0., 0., -100.),
+            Vec3::new(0., 100., 0.),
+            Vec3::new(0., 0., 200.),
+            Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5))),
+        )));
+        let cornered = Scene::new(world);
+        let visibility = ambient_occlusion_color(&down(-0.01), &cornered, 100., 10_000).0;
+        assert!((visibility - 0.5).abs() < 0.03, "{}", visibility);
+        assert_eq!(
+            ambient_occlusion_color(&down(-5.), &cornered, 1., 64),
+            Vec3::new(1., 1., 1.)
+        );
+    }
+}
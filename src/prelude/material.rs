@@ -1,19 +1,212 @@
-use crate::prelude::{reflectance, HitRecord, LinAlgOp, LinAlgRandGen, Ray, Vec3};
-use rand::{thread_rng, Rng};
+use crate::prelude::{
+    fresnel_dielectric, fuzz_pdf, ggx_d, reflectance, refract_through, rng, roughness_to_alpha,
+    sample_visible_normal, smith_g1, smith_g2, Bsdf, BsdfFlags, BsdfSample, ComplexIor, Dispersion,
+    HitRecord, LinAlgOp, LinAlgRandGen, NormalPerturbation, Onb, Principled, Ray, SolidColor,
+    Texture, ThinFilm, Vec3, D_LINE,
+};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub trait Scatter {
     fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)>;
+    fn emitted(&self, _ray: &Ray, _hit_record: &HitRecord) -> Vec3 {
+        Vec3::default()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum Material {
-    Lambertian { albedo: Vec3 },
-    Metal { albedo: Vec3, fuzz: f64 },
-    Dielectric { index_of_refraction: f64 },
+    Lambertian {
+        albedo: Arc<dyn Texture>,
+    },
+    Metal {
+        albedo: Arc<dyn Texture>,
+        fuzz: Arc<dyn Texture>,
+        thin_film: Option<ThinFilm>,
+    },
+    Dielectric {
+        index_of_refraction: Arc<dyn Texture>,
+        /// Fraction of light absorbed per unit distance travelled inside, per channel.
+        ///
+        /// Only the path between entering and leaving is attenuated, so other objects nested
+        /// inside the glass cut the absorption short.
+        absorption: Vec3,
+        /// Overrides `index_of_refraction` with a wavelength dependent one.
+        dispersion: Option<Dispersion>,
+        /// A film on the surface, whose interference replaces the plain Fresnel reflectance.
+        thin_film: Option<ThinFilm>,
+    },
+    /// A sheet of glass too thin to offset the light passing through, like a window pane. Light
+    /// reflected back and forth between its two sides is summed up analytically.
+    ThinDielectric {
+        index_of_refraction: Arc<dyn Texture>,
+    },
+    /// A metal with GGX distributed microfacets.
+    RoughConductor {
+        ior: ComplexIor,
+        /// Perceptual roughness in `[0, 1]`.
+        roughness: Arc<dyn Texture>,
+    },
+    /// Frosted glass with GGX distributed microfacets.
+    RoughDielectric {
+        index_of_refraction: Arc<dyn Texture>,
+        /// Perceptual roughness in `[0, 1]`.
+        roughness: Arc<dyn Texture>,
+    },
+    /// One material covering most real surfaces, see `Principled`.
+    Principled(Box<Principled>),
+    /// The isotropic phase function of a participating medium.
+    Isotropic {
+        albedo: Arc<dyn Texture>,
+        emission: Option<Arc<dyn Texture>>,
+    },
+    /// Scatters like `a` or `b`, choosing `b` with probability `factor`.
+    Mix {
+        a: Arc<Material>,
+        b: Arc<Material>,
+        factor: Arc<dyn Texture>,
+    },
+    /// A clear varnish over another material. Light the coat lets through bounces between it and
+    /// the base until it escapes or is absorbed.
+    Coated {
+        base: Arc<Material>,
+        coat_ior: f64,
+        /// Perceptual roughness of the coat in `[0, 1]`.
+        coat_roughness: Arc<dyn Texture>,
+    },
+    /// Another material, with holes where `opacity` is below one. Rays pass through masked out
+    /// surfaces as if they were not there, and partially opaque ones some of the time.
+    Masked {
+        base: Arc<Material>,
+        opacity: Arc<dyn Texture>,
+    },
+    /// Another material, shaded with a normal or bump mapped normal.
+    Perturbed {
+        base: Arc<Material>,
+        perturbation: NormalPerturbation,
+    },
+    /// No surface at all, only the boundary of a `Nested` medium, like the edge of a fog bank.
+    /// Rays cross it unchanged.
+    Interface,
 }
 
-impl Scatter for Material {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)> {
+impl Material {
+    pub fn lambertian(albedo: Vec3) -> Self {
+        Material::Lambertian {
+            albedo: albedo.into(),
+        }
+    }
+
+    pub fn metal(albedo: Vec3, fuzz: f64) -> Self {
+        Material::Metal {
+            albedo: albedo.into(),
+            fuzz: Arc::new(SolidColor::gray(fuzz)),
+            thin_film: None,
+        }
+    }
+
+    pub fn dielectric(index_of_refraction: f64) -> Self {
+        Material::Dielectric {
+            index_of_refraction: Arc::new(SolidColor::gray(index_of_refraction)),
+            absorption: Vec3::default(),
+            dispersion: None,
+            thin_film: None,
+        }
+    }
+
+    /// Glass tinted by absorbing light as it travels through, per unit distance.
+    pub fn colored_glass(index_of_refraction: f64, absorption: Vec3) -> Self {
+        Material::Dielectric {
+            index_of_refraction: Arc::new(SolidColor::gray(index_of_refraction)),
+            absorption,
+            dispersion: None,
+            thin_film: None,
+        }
+    }
+
+    pub fn thin_dielectric(index_of_refraction: f64) -> Self {
+        Material::ThinDielectric {
+            index_of_refraction: Arc::new(SolidColor::gray(index_of_refraction)),
+        }
+    }
+
+    pub fn rough_conductor(ior: ComplexIor, roughness: f64) -> Self {
+        Material::RoughConductor {
+            ior,
+            roughness: Arc::new(SolidColor::gray(roughness)),
+        }
+    }
+
+    pub fn rough_dielectric(index_of_refraction: f64, roughness: f64) -> Self {
+        Material::RoughDielectric {
+            index_of_refraction: Arc::new(SolidColor::gray(index_of_refraction)),
+            roughness: Arc::new(SolidColor::gray(roughness)),
+        }
+    }
+
+    pub fn mix(a: Material, b: Material, factor: f64) -> Self {
+        Material::Mix {
+            a: Arc::new(a),
+            b: Arc::new(b),
+            factor: Arc::new(SolidColor::gray(factor)),
+        }
+    }
+
+    pub fn coated(base: Material, coat_ior: f64, coat_roughness: f64) -> Self {
+        Material::Coated {
+            base: Arc::new(base),
+            coat_ior,
+            coat_roughness: Arc::new(SolidColor::gray(coat_roughness)),
+        }
+    }
+
+    /// How opaque the surface is at a hit, from 0 for fully cut out to 1.
+    pub fn opacity(&self, hit_record: &HitRecord) -> f64 {
+        match self {
+            Material::Masked { base, opacity } => {
+                opacity.scalar_at(hit_record).clamp(0., 1.) * base.opacity(hit_record)
+            }
+            Material::Perturbed { base, .. } => base.opacity(hit_record),
+            _ => 1.,
+        }
+    }
+
+    pub fn isotropic(albedo: Vec3) -> Self {
+        Material::Isotropic {
+            albedo: albedo.into(),
+            emission: None,
+        }
+    }
+
+    /// The index of refraction of the inside of an object of this material, 1 unless it refracts.
+    pub fn index_of_refraction(&self, hit_record: &HitRecord) -> f64 {
+        match self {
+            Material::Dielectric {
+                index_of_refraction,
+                dispersion,
+                ..
+            } => match dispersion {
+                Some(dispersion) => dispersion.index_of_refraction(D_LINE),
+                None => index_of_refraction.scalar_at(hit_record),
+            },
+            Material::RoughDielectric {
+                index_of_refraction,
+                ..
+            } => index_of_refraction.scalar_at(hit_record),
+            Material::Principled(principled) => {
+                principled.index_of_refraction.scalar_at(hit_record)
+            }
+            Material::Masked { base, .. } | Material::Perturbed { base, .. } => {
+                base.index_of_refraction(hit_record)
+            }
+            _ => 1.,
+        }
+    }
+}
+
+impl Bsdf for Material {
+    fn sample(&self, ray: &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
         match self {
             Material::Lambertian { albedo } => {
                 let mut scatter_direction = hit_record.normal + Vec3::random_unit_vector();
@@ -21,30 +214,56 @@ impl Scatter for Material {
                 if scatter_direction.near_zero() {
                     scatter_direction = hit_record.normal
                 };
+                let cos_theta = scatter_direction.unit_vector().dot(hit_record.normal);
 
-                Some((*albedo, Ray::new(&hit_record.point, &scatter_direction)))
+                Some(BsdfSample::new(
+                    ray.reflectance(albedo.value_at(hit_record)),
+                    ray.spawn(&hit_record.point, &scatter_direction),
+                    cos_theta / PI,
+                ))
             }
-            Material::Metal { albedo, fuzz } => {
+            Material::Metal {
+                albedo,
+                fuzz,
+                thin_film,
+            } => {
                 let reflected = ray.direction.unit_vector().reflect(hit_record.normal);
+                let fuzz = fuzz.scalar_at(hit_record);
 
-                let scattered = Ray::new(
+                let scattered = ray.spawn(
                     &hit_record.point,
-                    &(reflected + *fuzz * Vec3::random_in_unit_sphere()),
+                    &(reflected + fuzz * Vec3::random_in_unit_sphere()),
                 );
 
-                match scattered.direction.dot(hit_record.normal) > 0. {
-                    true => Some((*albedo, scattered)),
-                    false => None,
+                if scattered.direction.dot(hit_record.normal) <= 0. {
+                    return None;
                 }
+                let attenuation = metal_attenuation(albedo, thin_film.as_ref(), ray, hit_record);
+                Some(match fuzz > 0. {
+                    true => BsdfSample::new(
+                        attenuation,
+                        scattered,
+                        fuzz_pdf(scattered.direction.unit_vector(), reflected, fuzz),
+                    ),
+                    false => BsdfSample::delta(attenuation, scattered),
+                })
             }
             Material::Dielectric {
                 index_of_refraction,
+                absorption,
+                dispersion,
+                thin_film,
             } => {
-                let refraction_ratio: f64 = if hit_record.is_front_facing {
-                    1. / index_of_refraction
-                } else {
-                    *index_of_refraction
+                // A dispersive glass bends each wavelength differently, so a spectral path keeps
+                // only its hero wavelength from here on.
+                let (index_of_refraction, hero) = match (dispersion, ray.wavelengths) {
+                    (Some(dispersion), Some(lambda)) => {
+                        (dispersion.index_of_refraction(lambda[0]), Some(lambda[0]))
+                    }
+                    (Some(dispersion), None) => (dispersion.index_of_refraction(D_LINE), None),
+                    (None, _) => (index_of_refraction.scalar_at(hit_record), None),
                 };
+                let refraction_ratio = 1. / relative_eta(index_of_refraction, hit_record);
 
                 let unit_direction = ray.direction.unit_vector();
 
@@ -58,16 +277,809 @@ impl Scatter for Material {
                 // By Christophe Schlick.
                 let reflectivitiy_of_glass: f64 = reflectance(cos_theta, refraction_ratio);
 
-                let mut rng = thread_rng();
-                let scatter_direction =
-                    match total_internal_reflection_occurs || reflectivitiy_of_glass > rng.gen() {
-                        true => unit_direction.reflect(hit_record.normal),
-                        false => unit_direction.refract(hit_record.normal, refraction_ratio),
-                    };
+                let mut rng = rng();
+                let (is_reflection, weight) = match thin_film {
+                    None => (
+                        total_internal_reflection_occurs || reflectivitiy_of_glass > rng.gen(),
+                        Vec3::new(1., 1., 1.),
+                    ),
+                    // Reflectance differs per channel, so choose by its average and reweight.
+                    Some(film) => {
+                        let (outside, inside) = match hit_record.is_front_facing {
+                            true => (hit_record.surrounding_ior, index_of_refraction),
+                            false => (index_of_refraction, hit_record.surrounding_ior),
+                        };
+                        let reflectance = film.reflectance_over_dielectric(
+                            cos_theta, outside, inside, ray, hit_record,
+                        );
+                        let probability = (reflectance.0 + reflectance.1 + reflectance.2) / 3.;
+                        match probability > rng.gen() {
+                            true => (true, reflectance / probability),
+                            false => (
+                                false,
+                                (Vec3::new(1., 1., 1.) - reflectance) / (1. - probability),
+                            ),
+                        }
+                    }
+                };
+                let scatter_direction = match is_reflection {
+                    true => unit_direction.reflect(hit_record.normal),
+                    false => unit_direction.refract(hit_record.normal, refraction_ratio),
+                };
 
                 // let refraction_direction = unit_direction.refract(hit_record.normal, refraction_ratio);
-                let scattered = Ray::new(&hit_record.point, &scatter_direction);
-                Some((Vec3::new(1., 1., 1.), scattered))
+                let mut scattered = ray.spawn(&hit_record.point, &scatter_direction);
+
+                // Leaving the glass, attenuate by how far the ray travelled inside (Beer-Lambert).
+                let mut attenuation = match hit_record.is_front_facing {
+                    true => weight,
+                    false => {
+                        let distance = hit_record.time * ray.direction.norm();
+                        weight
+                            * ray.reflectance(Vec3::new(
+                                (-absorption.0 * distance).exp(),
+                                (-absorption.1 * distance).exp(),
+                                (-absorption.2 * distance).exp(),
+                            ))
+                    }
+                };
+                if let Some(hero) = hero {
+                    if scattered.wavelengths != Some([hero; 3]) {
+                        // The hero carries the other wavelengths' share of the estimate.
+                        scattered.wavelengths = Some([hero; 3]);
+                        attenuation = attenuation * Vec3::new(3., 0., 0.);
+                    }
+                }
+                Some(BsdfSample::delta(attenuation, scattered))
+            }
+            Material::ThinDielectric {
+                index_of_refraction,
+            } => {
+                let unit_direction = ray.direction.unit_vector();
+                let cos_theta = unit_direction.dot(hit_record.normal).abs();
+
+                // Of the light entering the sheet, the fraction R of each internal bounce
+                // returns, which sums to 2R / (1 + R) reflected in all.
+                let single =
+                    fresnel_dielectric(cos_theta, index_of_refraction.scalar_at(hit_record));
+                let reflectance = 2. * single / (1. + single);
+
+                let scatter_direction = match reflectance > rng().gen() {
+                    true => unit_direction.reflect(hit_record.normal),
+                    false => unit_direction,
+                };
+                Some(BsdfSample::delta(
+                    Vec3::new(1., 1., 1.),
+                    ray.spawn(&hit_record.point, &scatter_direction),
+                ))
+            }
+            Material::RoughConductor { ior, roughness } => {
+                let frame = Onb::new(hit_record.normal);
+                let wo = frame.to_local(-ray.direction.unit_vector());
+                if wo.2 <= 0. {
+                    return None;
+                }
+                let alpha = roughness_to_alpha(roughness.scalar_at(hit_record));
+
+                let mut rng = rng();
+                let microfacet = sample_visible_normal(wo, alpha, rng.gen(), rng.gen());
+                let wi = (-wo).reflect(microfacet);
+                if wi.2 <= 0. {
+                    return None;
+                }
+
+                // Visible normal sampling leaves only the Fresnel term and the shadowing of
+                // the microfacets that were visible.
+                let weight = ior.fresnel(wo.dot(microfacet), ray)
+                    * (smith_g2(wo, wi, alpha) / smith_g1(wo, alpha));
+                Some(BsdfSample::new(
+                    weight,
+                    ray.spawn(&hit_record.point, &frame.local(wi)),
+                    smith_g1(wo, alpha) * ggx_d(microfacet, alpha) / (4. * wo.2),
+                ))
+            }
+            Material::RoughDielectric {
+                index_of_refraction,
+                roughness,
+            } => {
+                let frame = Onb::new(hit_record.normal);
+                let wo = frame.to_local(-ray.direction.unit_vector());
+                if wo.2 <= 0. {
+                    return None;
+                }
+                let alpha = roughness_to_alpha(roughness.scalar_at(hit_record));
+                let index_of_refraction = index_of_refraction.scalar_at(hit_record);
+                let eta = if hit_record.is_front_facing {
+                    index_of_refraction
+                } else {
+                    1. / index_of_refraction
+                };
+
+                let mut rng = rng();
+                let microfacet = sample_visible_normal(wo, alpha, rng.gen(), rng.gen());
+
+                // Choose reflection or refraction in proportion to the Fresnel term, which then
+                // cancels out of the weight.
+                let reflectance = fresnel_dielectric(wo.dot(microfacet), eta);
+                let wi = match reflectance > rng.gen() {
+                    true => (-wo).reflect(microfacet),
+                    false => refract_through(wo, microfacet, eta)?,
+                };
+                let is_reflection = wi.2 > 0.;
+                if is_reflection != (wi.dot(microfacet) * wo.dot(microfacet) > 0.) {
+                    // Scattered to the wrong side of the macrosurface.
+                    return None;
+                }
+
+                let weight = smith_g2(wo, wi, alpha) / smith_g1(wo, alpha);
+                let (_, pdf) = rough_dielectric_density(wo, wi, alpha, eta);
+                Some(BsdfSample::new(
+                    Vec3::new(weight, weight, weight),
+                    ray.spawn(&hit_record.point, &frame.local(wi)),
+                    pdf,
+                ))
+            }
+            Material::Principled(principled) => principled.sample(ray, hit_record),
+            Material::Mix { a, b, factor } => {
+                let mut sample = match factor.scalar_at(hit_record) > rng().gen() {
+                    true => b.sample(ray, hit_record),
+                    false => a.sample(ray, hit_record),
+                }?;
+                // The chosen material's weight already accounts for the choice, but strategies
+                // are weighted by the density of the mixture.
+                if !sample.is_delta {
+                    sample.pdf = self.pdf(ray, hit_record, sample.ray.direction.unit_vector());
+                }
+                Some(sample)
+            }
+            Material::Coated {
+                base,
+                coat_ior,
+                coat_roughness,
+            } => {
+                let coat = Coat::new(*coat_ior, coat_roughness, hit_record);
+                let mut sample = coat.sample(base, ray, hit_record)?;
+                if !sample.is_delta {
+                    sample.pdf = self.pdf(ray, hit_record, sample.ray.direction.unit_vector());
+                }
+                Some(sample)
+            }
+            Material::Isotropic { albedo, .. } => Some(BsdfSample::new(
+                ray.reflectance(albedo.value_at(hit_record)),
+                ray.spawn(&hit_record.point, &Vec3::random_unit_vector()),
+                1. / (4. * PI),
+            )),
+            Material::Masked { base, .. } => base.sample(ray, hit_record),
+            Material::Perturbed { .. } => {
+                let hit_record = hit_record.clone().with_shading(ray);
+                hit_record.material.sample(ray, &hit_record)
+            }
+            Material::Interface => Some(BsdfSample::delta(
+                Vec3::new(1., 1., 1.),
+                ray.spawn(&hit_record.point, &ray.direction),
+            )),
+        }
+    }
+
+    fn eval(&self, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> Vec3 {
+        let cos_theta = wi.dot(hit_record.normal);
+
+        match self {
+            Material::Lambertian { albedo } if cos_theta > 0. => {
+                ray.reflectance(albedo.value_at(hit_record)) * (cos_theta / PI)
+            }
+            Material::Metal {
+                albedo,
+                fuzz,
+                thin_film,
+            } if cos_theta > 0. => {
+                let reflected = ray.direction.unit_vector().reflect(hit_record.normal);
+                let fuzz = fuzz.scalar_at(hit_record);
+                if fuzz <= 0. {
+                    return Vec3::default();
+                }
+                metal_attenuation(albedo, thin_film.as_ref(), ray, hit_record)
+                    * fuzz_pdf(wi, reflected, fuzz)
+            }
+            Material::RoughConductor { ior, roughness } if cos_theta > 0. => {
+                let frame = Onb::new(hit_record.normal);
+                let (wo, wi) = (
+                    frame.to_local(-ray.direction.unit_vector()),
+                    frame.to_local(wi),
+                );
+                if wo.2 <= 0. {
+                    return Vec3::default();
+                }
+                let alpha = roughness_to_alpha(roughness.scalar_at(hit_record));
+                let half = (wo + wi).unit_vector();
+
+                ior.fresnel(wo.dot(half), ray)
+                    * (ggx_d(half, alpha) * smith_g2(wo, wi, alpha) / (4. * wo.2))
+            }
+            Material::RoughDielectric {
+                index_of_refraction,
+                roughness,
+            } => {
+                let frame = Onb::new(hit_record.normal);
+                let (wo, wi) = (
+                    frame.to_local(-ray.direction.unit_vector()),
+                    frame.to_local(wi),
+                );
+                let alpha = roughness_to_alpha(roughness.scalar_at(hit_record));
+                let eta = relative_eta(index_of_refraction.scalar_at(hit_record), hit_record);
+
+                let (value, _) = rough_dielectric_density(wo, wi, alpha, eta);
+                Vec3::new(value, value, value)
+            }
+            Material::Principled(principled) => principled.eval(ray, hit_record, wi),
+            Material::Mix { a, b, factor } => {
+                let factor = factor.scalar_at(hit_record);
+                (1. - factor) * a.eval(ray, hit_record, wi) + factor * b.eval(ray, hit_record, wi)
+            }
+            Material::Coated {
+                base,
+                coat_ior,
+                coat_roughness,
+            } => Coat::new(*coat_ior, coat_roughness, hit_record).eval(base, ray, hit_record, wi),
+            Material::Isotropic { albedo, .. } => {
+                ray.reflectance(albedo.value_at(hit_record)) / (4. * PI)
+            }
+            Material::Masked { base, .. } => base.eval(ray, hit_record, wi),
+            Material::Perturbed { .. } => {
+                let hit_record = hit_record.clone().with_shading(ray);
+                hit_record.material.eval(ray, &hit_record, wi)
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    fn pdf(&self, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> f64 {
+        let cos_theta = wi.dot(hit_record.normal);
+
+        match self {
+            Material::Lambertian { .. } => cos_theta.max(0.) / PI,
+            Material::Metal { fuzz, .. } if cos_theta > 0. => {
+                let reflected = ray.direction.unit_vector().reflect(hit_record.normal);
+                match fuzz.scalar_at(hit_record) {
+                    fuzz if fuzz > 0. => fuzz_pdf(wi, reflected, fuzz),
+                    _ => 0.,
+                }
+            }
+            Material::RoughConductor { roughness, .. } if cos_theta > 0. => {
+                let frame = Onb::new(hit_record.normal);
+                let (wo, wi) = (
+                    frame.to_local(-ray.direction.unit_vector()),
+                    frame.to_local(wi),
+                );
+                if wo.2 <= 0. {
+                    return 0.;
+                }
+                let alpha = roughness_to_alpha(roughness.scalar_at(hit_record));
+                smith_g1(wo, alpha) * ggx_d((wo + wi).unit_vector(), alpha) / (4. * wo.2)
+            }
+            Material::RoughDielectric {
+                index_of_refraction,
+                roughness,
+            } => {
+                let frame = Onb::new(hit_record.normal);
+                let (wo, wi) = (
+                    frame.to_local(-ray.direction.unit_vector()),
+                    frame.to_local(wi),
+                );
+                let alpha = roughness_to_alpha(roughness.scalar_at(hit_record));
+                let eta = relative_eta(index_of_refraction.scalar_at(hit_record), hit_record);
+
+                rough_dielectric_density(wo, wi, alpha, eta).1
+            }
+            Material::Principled(principled) => principled.pdf(ray, hit_record, wi),
+            Material::Mix { a, b, factor } => {
+                let factor = factor.scalar_at(hit_record);
+                (1. - factor) * a.pdf(ray, hit_record, wi) + factor * b.pdf(ray, hit_record, wi)
+            }
+            Material::Coated {
+                base,
+                coat_ior,
+                coat_roughness,
+            } => Coat::new(*coat_ior, coat_roughness, hit_record).pdf(base, ray, hit_record, wi),
+            Material::Isotropic { .. } => 1. / (4. * PI),
+            Material::Masked { base, .. } => base.pdf(ray, hit_record, wi),
+            Material::Perturbed { .. } => {
+                let hit_record = hit_record.clone().with_shading(ray);
+                hit_record.material.pdf(ray, &hit_record, wi)
+            }
+            _ => 0.,
+        }
+    }
+
+    fn flags(&self, hit_record: &HitRecord) -> BsdfFlags {
+        match self {
+            Material::Metal { fuzz, .. } if fuzz.scalar_at(hit_record) <= 0. => BsdfFlags::DELTA,
+            Material::Dielectric { .. } | Material::ThinDielectric { .. } | Material::Interface => {
+                BsdfFlags::DELTA
+            }
+            Material::Mix { a, b, factor } => match factor.scalar_at(hit_record) {
+                factor if factor <= 0. => a.flags(hit_record),
+                factor if factor >= 1. => b.flags(hit_record),
+                _ => a.flags(hit_record).union(b.flags(hit_record)),
+            },
+            Material::Coated { base, .. } => match hit_record.is_front_facing {
+                true => BsdfFlags {
+                    approximate_pdf: true,
+                    ..BsdfFlags::NON_DELTA
+                }
+                .union(base.flags(hit_record)),
+                false => base.flags(hit_record),
+            },
+            Material::Masked { base, .. } | Material::Perturbed { base, .. } => {
+                base.flags(hit_record)
+            }
+            _ => BsdfFlags::NON_DELTA,
+        }
+    }
+}
+
+impl Scatter for Material {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)> {
+        self.sample(ray, hit_record)
+            .map(|sample| (sample.weight, sample.ray))
+    }
+
+    fn emitted(&self, ray: &Ray, hit_record: &HitRecord) -> Vec3 {
+        match self {
+            Material::Isotropic {
+                emission: Some(emission),
+                ..
+            } => ray.radiance(emission.value_at(hit_record)),
+            Material::Masked { base, .. } => base.emitted(ray, hit_record),
+            Material::Perturbed { .. } => {
+                let hit_record = hit_record.clone().with_shading(ray);
+                hit_record.material.emitted(ray, &hit_record)
+            }
+            Material::Principled(principled) => principled.emitted(ray, hit_record),
+            Material::Mix { a, b, factor } => {
+                let factor = factor.scalar_at(hit_record);
+                (1. - factor) * a.emitted(ray, hit_record) + factor * b.emitted(ray, hit_record)
+            }
+            Material::Coated { base, coat_ior, .. } => {
+                let emitted = base.emitted(ray, hit_record);
+                match hit_record.is_front_facing {
+                    true => {
+                        let cos_theta = (-ray.direction.unit_vector()).dot(hit_record.normal);
+                        (1. - fresnel_dielectric(cos_theta, *coat_ior)) * emitted
+                    }
+                    false => emitted,
+                }
+            }
+            _ => Vec3::default(),
+        }
+    }
+}
+
+impl HitRecord {
+    /// Whether a ray goes on through the surface here because its material is masked out.
+    /// Partially opaque surfaces are passed through at random, in proportion to their transparency.
+    pub fn is_masked_out(&self) -> bool {
+        match self.material.opacity(self) {
+            opacity if opacity <= 0. => true,
+            opacity if opacity >= 1. => false,
+            opacity => opacity <= rng().gen(),
+        }
+    }
+}
+
+/// The albedo of a metal, through its thin film if it has one.
+fn metal_attenuation(
+    albedo: &Arc<dyn Texture>,
+    thin_film: Option<&ThinFilm>,
+    ray: &Ray,
+    hit_record: &HitRecord,
+) -> Vec3 {
+    let albedo = ray.reflectance(albedo.value_at(hit_record));
+    match thin_film {
+        Some(film) => {
+            let cos_theta = -ray.direction.unit_vector().dot(hit_record.normal);
+            film.reflectance_over_metal(cos_theta, albedo, ray, hit_record)
+        }
+        None => albedo,
+    }
+}
+
+/// The index of refraction of the far side of a surface relative to the side a ray comes from.
+pub(crate) fn relative_eta(index_of_refraction: f64, hit_record: &HitRecord) -> f64 {
+    match hit_record.is_front_facing {
+        true => index_of_refraction / hit_record.surrounding_ior,
+        false => hit_record.surrounding_ior / index_of_refraction,
+    }
+}
+
+/// `eval` and `pdf` of a rough dielectric in the local shading frame, where reflection and
+/// refraction are chosen in proportion to the Fresnel term of a visible microfacet.
+fn rough_dielectric_density(wo: Vec3, wi: Vec3, alpha: f64, eta: f64) -> (f64, f64) {
+    if wo.2 <= 0. || wi.2 == 0. {
+        return (0., 0.);
+    }
+    let is_reflection = wi.2 > 0.;
+    let mut half = match is_reflection {
+        true => wo + wi,
+        false => wo + eta * wi,
+    }
+    .unit_vector();
+    if half.2 < 0. {
+        half = -half;
+    }
+    let (cos_o, cos_i) = (wo.dot(half), wi.dot(half));
+    // Both directions must be on the sides of the microfacet that the lobe puts them on.
+    if cos_o <= 0. || (cos_i > 0.) != is_reflection {
+        return (0., 0.);
+    }
+
+    let reflectance = fresnel_dielectric(cos_o, eta);
+    let visible = smith_g1(wo, alpha) * cos_o * ggx_d(half, alpha) / wo.2;
+    let pdf = match is_reflection {
+        true => reflectance * visible / (4. * cos_o),
+        false => {
+            (1. - reflectance) * visible * eta * eta * cos_i.abs() / (cos_o + eta * cos_i).powi(2)
+        }
+    };
+    // Samples are weighted by G2 / G1.
+    (pdf * smith_g2(wo, wi, alpha) / smith_g1(wo, alpha), pdf)
+}
+
+/// Bounces between the coat and the base after this many times are absorbed.
+const MAX_COAT_BOUNCES: usize = 16;
+
+/// The clear layer of a `Coated` material at a hit.
+struct Coat {
+    index_of_refraction: f64,
+    alpha: f64,
+}
+
+impl Coat {
+    fn new(index_of_refraction: f64, roughness: &Arc<dyn Texture>, hit_record: &HitRecord) -> Self {
+        Self {
+            index_of_refraction,
+            alpha: roughness_to_alpha(roughness.scalar_at(hit_record)),
+        }
+    }
+
+    /// Light crossing the coat to or from outside in a direction with this cosine to the normal.
+    fn transmittance(&self, cos_theta: f64) -> f64 {
+        1. - fresnel_dielectric(cos_theta, self.index_of_refraction)
+    }
+
+    /// The coat's own reflection, as `eval` and the density of choosing it, in the local frame.
+    fn reflection(&self, wo: Vec3, wi: Vec3) -> (f64, f64) {
+        if wo.2 <= 0. || wi.2 <= 0. {
+            return (0., 0.);
+        }
+        let half = (wo + wi).unit_vector();
+        let reflectance = fresnel_dielectric(wo.dot(half), self.index_of_refraction);
+        let d = ggx_d(half, self.alpha);
+        (
+            reflectance * d * smith_g2(wo, wi, self.alpha) / (4. * wo.2),
+            reflectance * smith_g1(wo, self.alpha) * d / (4. * wo.2),
+        )
+    }
+
+    fn sample(&self, base: &Material, ray: &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
+        // Seen from inside the object, only the base is there.
+        if !hit_record.is_front_facing {
+            return base.sample(ray, hit_record);
+        }
+
+        let frame = Onb::new(hit_record.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        if wo.2 <= 0. {
+            return None;
+        }
+
+        // Reflect off the coat in proportion to its Fresnel term, which cancels out of the weight.
+        let mut rng = rng();
+        let microfacet = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+        if fresnel_dielectric(wo.dot(microfacet), self.index_of_refraction) > rng.gen() {
+            let wi = (-wo).reflect(microfacet);
+            if wi.2 <= 0. {
+                return None;
+            }
+            let weight = smith_g2(wo, wi, self.alpha) / smith_g1(wo, self.alpha);
+            let (_, pdf) = self.reflection(wo, wi);
+            return Some(BsdfSample::new(
+                Vec3::new(weight, weight, weight),
+                ray.spawn(&hit_record.point, &frame.local(wi)),
+                pdf,
+            ));
+        }
+
+        // The coat is thin enough that light enters and leaves it where it hit.
+        let mut incoming = *ray;
+        let mut throughput = Vec3::new(1., 1., 1.);
+        for _ in 0..MAX_COAT_BOUNCES {
+            let mut sample = base.sample(&incoming, hit_record)?;
+            throughput = throughput * sample.weight;
+
+            // Escaping through the coat, or into the object through a transmissive base.
+            let cos_theta = sample.ray.direction.unit_vector().dot(hit_record.normal);
+            if cos_theta <= 0. || self.transmittance(cos_theta) > rng.gen() {
+                sample.weight = throughput;
+                return Some(sample);
+            }
+
+            // Reflected back down by the underside of the coat.
+            let direction = sample.ray.direction.reflect(hit_record.normal);
+            incoming = ray.spawn(&(hit_record.point - direction), &direction);
+        }
+        None
+    }
+
+    /// An unbiased estimate: the walk under the coat is followed as in `sample`, but instead of
+    /// escaping at random, each bounce adds the light it would send out towards `wi`.
+    fn eval(&self, base: &Material, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> Vec3 {
+        if !hit_record.is_front_facing {
+            return base.eval(ray, hit_record, wi);
+        }
+
+        let frame = Onb::new(hit_record.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        if wo.2 <= 0. {
+            return Vec3::default();
+        }
+        let (reflection, _) = self.reflection(wo, frame.to_local(wi));
+        let mut value = Vec3::new(reflection, reflection, reflection);
+
+        let mut rng = rng();
+        let microfacet = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+        let exit = match wi.dot(hit_record.normal) {
+            cos_theta if cos_theta > 0. => self.transmittance(cos_theta),
+            _ => 1.,
+        };
+
+        let mut incoming = *ray;
+        let mut throughput = Vec3::new(1., 1., 1.)
+            * (1. - fresnel_dielectric(wo.dot(microfacet), self.index_of_refraction));
+        for _ in 0..MAX_COAT_BOUNCES {
+            value += throughput * base.eval(&incoming, hit_record, wi) * exit;
+
+            let Some(sample) = base.sample(&incoming, hit_record) else {
+                break;
+            };
+            let cos_theta = sample.ray.direction.unit_vector().dot(hit_record.normal);
+            if cos_theta <= 0. {
+                break;
+            }
+            throughput = throughput * sample.weight * (1. - self.transmittance(cos_theta));
+            if throughput.0.max(throughput.1).max(throughput.2) < 1e-4 {
+                break;
+            }
+
+            let direction = sample.ray.direction.reflect(hit_record.normal);
+            incoming = ray.spawn(&(hit_record.point - direction), &direction);
+        }
+        value
+    }
+
+    /// Approximates the walk under the coat by its first bounce.
+    fn pdf(&self, base: &Material, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> f64 {
+        if !hit_record.is_front_facing {
+            return base.pdf(ray, hit_record, wi);
+        }
+
+        let frame = Onb::new(hit_record.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        if wo.2 <= 0. {
+            return 0.;
+        }
+        let (_, reflection) = self.reflection(wo, frame.to_local(wi));
+        reflection + self.transmittance(wo.2) * base.pdf(ray, hit_record, wi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::{
+        fresnel_dielectric, ComplexIor, Dispersion, Hittable, Material, Point, Ray, Scatter,
+        SolidColor, Sphere, ThinFilm, Vec3,
+    };
+    use std::sync::Arc;
+
+    const SAMPLES: usize = 20_000;
+
+    /// Shine a white furnace at a unit sphere from several angles, from outside and inside, and
+    /// return the mean throughput of each scattering event.
+    fn furnace(material: Material) -> Vec<(f64, Vec3)> {
+        let sphere = Sphere::new(Point::new(0., 0., 0.), 1., Arc::new(material));
+        let mut results = vec![];
+
+        for cos_theta in [1_f64, 0.7, 0.3, 0.05] {
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            for origin in [Point::new(0., 0., 3.), Point::new(0., 0., 0.)] {
+                // Aim at the top of the sphere from the given angle to its normal there.
+                let target = Point::new(0., 0., 1.);
+                let direction = if origin.2 > 0. {
+                    Vec3::new(sin_theta, 0., -cos_theta)
+                } else {
+                    Vec3::new(sin_theta, 0., cos_theta)
+                };
+                let ray = Ray::new(&(target - direction), &direction);
+                let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+                let mut total = Vec3::default();
+                for _ in 0..SAMPLES {
+                    if let Some((attenuation, _)) = sphere.material.scatter(&ray, &hit) {
+                        total += attenuation;
+                    }
+                }
+                results.push((cos_theta, total / SAMPLES as f64));
+            }
+        }
+        results
+    }
+
+    #[test]
+    fn rough_conductors_do_not_create_energy() {
+        // A lossless conductor: vanishing refraction, enormous extinction.
+        let mirror = ComplexIor::new(Vec3(1e-4, 1e-4, 1e-4), Vec3(1e4, 1e4, 1e4));
+
+        for roughness in [0.05, 0.4, 1.] {
+            for (cos_theta, throughput) in furnace(Material::rough_conductor(mirror, roughness)) {
+                assert!(throughput.0 <= 1.01, "{} at {}", throughput, cos_theta);
+                // Only masking and shadowing lose energy, which hardly happens when smooth.
+                if roughness < 0.1 {
+                    assert!(throughput.0 > 0.95, "{} at {}", throughput, cos_theta);
+                }
+            }
+        }
+
+        for (_, throughput) in furnace(Material::rough_conductor(ComplexIor::GOLD, 0.3)) {
+            assert!(throughput.0 <= 1. && throughput.0 > throughput.2);
+        }
+    }
+
+    #[test]
+    fn colored_glass_absorbs_along_the_path_inside() {
+        let absorption = Vec3::new(0.1, 0.5, 1.);
+        let sphere = Sphere::new(
+            Point::new(0., 0., 0.),
+            1.,
+            Arc::new(Material::colored_glass(1.5, absorption)),
+        );
+
+        // Straight through the center: entering is free, leaving pays for the diameter.
+        let entering = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -2.));
+        let hit = sphere.hit(&entering, 0.001, f64::INFINITY).unwrap();
+        let (attenuation, _) = sphere.material.scatter(&entering, &hit).unwrap();
+        assert_eq!(attenuation, Vec3::new(1., 1., 1.));
+
+        let inside = Ray::new(&Point::new(0., 0., 1.), &Vec3::new(0., 0., -2.));
+        let hit = sphere.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        let (attenuation, _) = sphere.material.scatter(&inside, &hit).unwrap();
+        let expected = Vec3::new((-0.2_f64).exp(), (-1_f64).exp(), (-2_f64).exp());
+        assert!((attenuation - expected).norm() < 1e-9);
+    }
+
+    #[test]
+    fn rough_dielectrics_do_not_create_energy() {
+        for roughness in [0.05, 0.4, 1.] {
+            for (cos_theta, throughput) in furnace(Material::rough_dielectric(1.5, roughness)) {
+                assert!(throughput.0 <= 1.01, "{} at {}", throughput, cos_theta);
+                if roughness < 0.1 && cos_theta > 0.1 {
+                    assert!(throughput.0 > 0.95, "{} at {}", throughput, cos_theta);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dispersive_glass_keeps_only_the_hero_wavelength() {
+        let glass = Material::Dielectric {
+            index_of_refraction: Arc::new(SolidColor::gray(1.5)),
+            absorption: Vec3::default(),
+            dispersion: Some(Dispersion::SF11),
+            thin_film: None,
+        };
+        let sphere = Sphere::new(Point::new(0., 0., 0.), 1., Arc::new(glass));
+
+        let mut ray = Ray::new(&Point::new(0.3, 0., 5.), &Vec3::new(0., 0., -1.));
+        ray.wavelengths = Some([450., 550., 650.]);
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+        let (attenuation, scattered) = sphere.material.scatter(&ray, &hit).unwrap();
+        assert_eq!(attenuation, Vec3::new(3., 0., 0.));
+        assert_eq!(scattered.wavelengths, Some([450.; 3]));
+
+        // Once alone, the hero is not weighted again.
+        let (attenuation, _) = sphere.material.scatter(&scattered, &hit).unwrap();
+        assert_eq!(attenuation, Vec3::new(1., 1., 1.));
+    }
+
+    #[test]
+    fn mix_chooses_in_proportion_to_its_factor() {
+        let gray = Material::mix(
+            Material::lambertian(Vec3::new(1., 1., 1.)),
+            Material::lambertian(Vec3::default()),
+            0.25,
+        );
+        for (_, throughput) in furnace(gray) {
+            assert!((throughput.0 - 0.75).abs() < 0.02, "{}", throughput);
+        }
+    }
+
+    #[test]
+    fn coats_do_not_create_energy() {
+        for roughness in [0., 0.3] {
+            let varnish =
+                Material::coated(Material::lambertian(Vec3::new(1., 1., 1.)), 1.5, roughness);
+            for (cos_theta, throughput) in furnace(varnish) {
+                assert!(throughput.0 <= 1.01, "{} at {}", throughput, cos_theta);
+                // Light trapped under the coat bounces until it escapes.
+                if cos_theta > 0.5 {
+                    assert!(throughput.0 > 0.9, "{} at {}", throughput, cos_theta);
+                }
+            }
+        }
+
+        // A coated metal keeps the metal's color under the clear reflection.
+        let paint = Material::coated(Material::metal(Vec3::new(0.9, 0.1, 0.1), 0.2), 1.5, 0.1);
+        let sphere = Sphere::new(Point::new(0., 0., 0.), 1., Arc::new(paint));
+        let ray = Ray::new(&Point::new(0., 0., 3.), &Vec3::new(0., 0., -1.));
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let mut total = Vec3::default();
+        for _ in 0..SAMPLES {
+            if let Some((attenuation, _)) = sphere.material.scatter(&ray, &hit) {
+                total += attenuation;
+            }
+        }
+        let average = total / SAMPLES as f64;
+        assert!(
+            average.0 > 0.8 && average.1 < 0.2 && average.1 > 0.04,
+            "{}",
+            average
+        );
+    }
+
+    #[test]
+    fn thin_sheets_pass_light_straight_through() {
+        let pane = Sphere::new(
+            Point::new(0., 0., 0.),
+            1.,
+            Arc::new(Material::thin_dielectric(1.5)),
+        );
+        let ray = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -1.));
+        let hit = pane.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+        let mut reflected = 0;
+        for _ in 0..SAMPLES {
+            let (attenuation, scattered) = pane.material.scatter(&ray, &hit).unwrap();
+            assert_eq!(attenuation, Vec3::new(1., 1., 1.));
+            match scattered.direction.2 > 0. {
+                true => reflected += 1,
+                false => assert_eq!(scattered.direction, ray.direction),
+            }
+        }
+        // Twice the 4% of a single surface, less what is lost bouncing between them.
+        let single = fresnel_dielectric(1., 1.5);
+        let expected = 2. * single / (1. + single);
+        assert!((reflected as f64 / SAMPLES as f64 - expected).abs() < 0.01);
+    }
+
+    #[test]
+    fn thin_films_tint_without_creating_energy() {
+        let bubble = Material::Dielectric {
+            index_of_refraction: Arc::new(SolidColor::gray(1.)),
+            absorption: Vec3::default(),
+            dispersion: None,
+            thin_film: Some(ThinFilm::new(350., 1.33)),
+        };
+        let oil_on_steel = Material::Metal {
+            albedo: Vec3::new(0.6, 0.6, 0.6).into(),
+            fuzz: Arc::new(SolidColor::gray(0.)),
+            thin_film: Some(ThinFilm::new(250., 1.45)),
+        };
+
+        for material in [bubble, oil_on_steel] {
+            for (cos_theta, throughput) in furnace(material) {
+                assert!(throughput.0 <= 1.02, "{} at {}", throughput, cos_theta);
+                assert!(throughput.1 <= 1.02, "{} at {}", throughput, cos_theta);
+                assert!(throughput.2 <= 1.02, "{} at {}", throughput, cos_theta);
             }
         }
     }
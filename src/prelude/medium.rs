@@ -0,0 +1,580 @@
+//! Participating media filling closed objects, which nest inside one another by priority
+//! (Schmidt and Budge, "Simple Nested Dielectrics in Ray Traced Images", 2002).
+//!
+//! Coefficients differ per channel. Free flights are sampled for one channel picked at random, the
+//! hero, and weighted by the balance heuristic over all three (spectral tracking, as formulated by
+//! Miller et al. in "A Null-Scattering Path Integral Formulation of Light Transport", 2019).
+use crate::prelude::{rng, HitRecord, Hittable, LinAlgOp, Material, Ray, Scene, Vec3, VoxelGrid};
+use rand::Rng;
+use std::sync::Arc;
+
+/// A medium absorbing and scattering light per unit distance, with a coefficient per channel.
+#[derive(Debug)]
+pub struct Medium {
+    pub absorption: Vec3,
+    pub scattering: Vec3,
+    /// Scales both coefficients from point to point, for heterogeneous media.
+    pub density: Option<Arc<VoxelGrid>>,
+    /// Scatters alike in all directions, leaving color to the coefficients.
+    pub phase_function: Arc<Material>,
+}
+
+/// How far a ray flies through a `Medium`, with the weight of its channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flight {
+    Scattered {
+        time: f64,
+        weight: Vec3,
+    },
+    /// Only in heterogeneous media, where homogeneous ones weigh absorption in.
+    Absorbed,
+    /// Reached the end of the segment it flew along.
+    Passed {
+        weight: Vec3,
+    },
+}
+
+impl Medium {
+    pub fn new(absorption: Vec3, scattering: Vec3) -> Self {
+        Self {
+            absorption,
+            scattering,
+            density: None,
+            phase_function: Arc::new(Material::isotropic(Vec3::new(1., 1., 1.))),
+        }
+    }
+
+    pub fn heterogeneous(absorption: Vec3, scattering: Vec3, density: Arc<VoxelGrid>) -> Self {
+        Self {
+            density: Some(density),
+            ..Self::new(absorption, scattering)
+        }
+    }
+
+    /// The absorption and scattering coefficients as `ray` sees them, where they are densest.
+    fn coefficients(&self, ray: &Ray) -> (Vec3, Vec3) {
+        (
+            ray.reflectance(self.absorption),
+            ray.reflectance(self.scattering),
+        )
+    }
+
+    /// Sample where `ray` first scatters or is absorbed between `t_min` and `t_max`.
+    pub fn sample(&self, ray: &Ray, t_min: f64, t_max: f64) -> Flight {
+        let (absorption, scattering) = self.coefficients(ray);
+        let extinction = absorption + scattering;
+        let mut rng = rng();
+        let hero = rng.gen_range(0..3);
+        let speed = ray.direction.norm();
+
+        let Some(density) = &self.density else {
+            let time = match extinction[hero] > 0. {
+                true => t_min - (1. - rng.gen::<f64>()).ln() / (extinction[hero] * speed),
+                false => f64::INFINITY,
+            };
+            if time >= t_max {
+                let transmittance = beer_lambert(extinction, (t_max - t_min) * speed);
+                return Flight::Passed {
+                    weight: balance(transmittance),
+                };
+            }
+            // Absorption weighs the channels in rather than ending the flight: over the many
+            // collisions of a dense medium, the channels that scatter more than the hero would
+            // otherwise gain weight at each one.
+            let transmittance = beer_lambert(extinction, (time - t_min) * speed);
+            let pdfs = transmittance * extinction;
+            return Flight::Scattered {
+                time,
+                weight: transmittance * scattering * (3. / (pdfs.0 + pdfs.1 + pdfs.2)),
+            };
+        };
+
+        // Tentative collisions against a majorant common to all channels, most of them null.
+        let majorant = max_channel(extinction) * density.max_value();
+        // The density of each channel's choices so far, relative to the majorant.
+        let mut pdfs = Vec3::new(1., 1., 1.);
+        density
+            .track(ray, t_min, t_max, majorant, |time, value| {
+                let (absorption, scattering) = (absorption * value, scattering * value);
+                let u = rng.gen::<f64>() * majorant;
+                if u < absorption[hero] {
+                    return Some(Flight::Absorbed);
+                }
+                if u < absorption[hero] + scattering[hero] {
+                    return Some(Flight::Scattered {
+                        time,
+                        weight: balance(pdfs * scattering / majorant),
+                    });
+                }
+                let null = Vec3::new(majorant, majorant, majorant) - absorption - scattering;
+                pdfs = pdfs * null / majorant;
+                None
+            })
+            .unwrap_or_else(|| Flight::Passed {
+                weight: balance(pdfs),
+            })
+    }
+
+    /// How much light gets through along `ray` between `t_min` and `t_max`, estimated by ratio
+    /// tracking in heterogeneous media.
+    pub fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec3 {
+        let (absorption, scattering) = self.coefficients(ray);
+        let extinction = absorption + scattering;
+        let speed = ray.direction.norm();
+
+        let Some(density) = &self.density else {
+            return beer_lambert(extinction, (t_max - t_min) * speed);
+        };
+        let majorant = max_channel(extinction) * density.max_value();
+        let mut transmittance = Vec3::new(1., 1., 1.);
+        density.track(ray, t_min, t_max, majorant, |_, value| {
+            transmittance = transmittance
+                * (Vec3::new(majorant, majorant, majorant) - extinction * value)
+                / majorant;
+            None::<()>
+        });
+        transmittance
+    }
+
+    /// Where `ray` scatters at `time`, as a hit on the phase function.
+    pub fn collision(&self, ray: &Ray, time: f64) -> HitRecord {
+        HitRecord::collision(ray, time, self.phase_function.clone())
+    }
+}
+
+impl HitRecord {
+    /// Where `ray` collides with a medium at `time`, as a hit on its `phase_function`.
+    pub fn collision(ray: &Ray, time: f64, phase_function: Arc<Material>) -> Self {
+        Self {
+            point: ray.at(time),
+            // Phase functions do not use the normal, but keep it well defined.
+            normal: -ray.direction.unit_vector(),
+            geometric_normal: -ray.direction.unit_vector(),
+            time,
+            is_front_facing: true,
+            u: 0.,
+            v: 0.,
+            uv_derivatives: None,
+            tangents: None,
+            material: phase_function,
+            interior: None,
+            surrounding_ior: 1.,
+        }
+    }
+}
+
+/// The transmittance of each channel over a distance.
+fn beer_lambert(extinction: Vec3, distance: f64) -> Vec3 {
+    // Clear channels let light through however far, even to infinity.
+    let channel = |extinction: f64| match extinction > 0. {
+        true => (-extinction * distance).exp(),
+        false => 1.,
+    };
+    Vec3::new(
+        channel(extinction.0),
+        channel(extinction.1),
+        channel(extinction.2),
+    )
+}
+
+fn max_channel(value: Vec3) -> f64 {
+    value.0.max(value.1).max(value.2)
+}
+
+/// The weight of a sample whose channels have these densities, when any channel is the hero
+/// with equal probability and the contribution of each is its density.
+fn balance(pdfs: Vec3) -> Vec3 {
+    match pdfs.0 + pdfs.1 + pdfs.2 {
+        total if total > 0. => pdfs * (3. / total),
+        _ => Vec3::default(),
+    }
+}
+
+/// What fills a `Nested` object. Where objects overlap, the inside of the one with the highest
+/// priority is there, and the surfaces of the others within it are not.
+#[derive(Debug)]
+pub struct Interior {
+    /// None for objects with nothing inside, like glass, which still cut out the media around.
+    pub medium: Option<Arc<Medium>>,
+    pub priority: u32,
+}
+
+/// An object with an `Interior`, so that rays know when they are inside it.
+pub struct Nested {
+    /// Closed, so that rays leave it as often as they enter.
+    pub boundary: Arc<dyn Hittable>,
+    pub interior: Arc<Interior>,
+}
+
+impl Nested {
+    pub fn new(boundary: Arc<dyn Hittable>, medium: Option<Medium>, priority: u32) -> Self {
+        Self {
+            boundary,
+            interior: Arc::new(Interior {
+                medium: medium.map(Arc::new),
+                priority,
+            }),
+        }
+    }
+
+    fn tag(&self, hit_record: HitRecord) -> HitRecord {
+        HitRecord {
+            interior: Some(self.interior.clone()),
+            ..hit_record
+        }
+    }
+}
+
+impl Hittable for Nested {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.boundary
+            .hit(ray, t_min, t_max)
+            .map(|hit_record| self.tag(hit_record))
+    }
+
+    fn hit_all(&self, ray: &Ray, t_min: f64, t_max: f64) -> Vec<HitRecord> {
+        self.boundary
+            .hit_all(ray, t_min, t_max)
+            .into_iter()
+            .map(|hit_record| self.tag(hit_record))
+            .collect()
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "Nested {{ boundary: {}, interior: {:?} }}",
+            self.boundary.metadata(),
+            self.interior
+        )
+    }
+}
+
+/// The `Nested` objects a ray is inside, in the order it entered them, each with its index of
+/// refraction.
+#[derive(Clone, Debug, Default)]
+pub struct MediumStack(Vec<(Arc<Interior>, f64)>);
+
+impl MediumStack {
+    /// The object whose inside the ray is in: of the highest priority, the last entered.
+    fn top(&self) -> Option<&(Arc<Interior>, f64)> {
+        self.0
+            .iter()
+            .rev()
+            .reduce(|top, entered| match entered.0.priority > top.0.priority {
+                true => entered,
+                false => top,
+            })
+    }
+
+    pub fn medium(&self) -> Option<&Arc<Medium>> {
+        self.top()?.0.medium.as_ref()
+    }
+
+    pub fn index_of_refraction(&self) -> f64 {
+        self.top()
+            .map_or(1., |(_, index_of_refraction)| *index_of_refraction)
+    }
+
+    /// Whether a ray goes on through the surface hit as if it were not there, because it bounds an
+    /// object within another of higher priority or is only an `Interface`.
+    pub fn passes(&self, hit_record: &HitRecord) -> bool {
+        let is_inside_higher = |interior: &Arc<Interior>| {
+            self.top()
+                .is_some_and(|(top, _)| top.priority > interior.priority)
+        };
+        matches!(*hit_record.material, Material::Interface)
+            || hit_record.interior.as_ref().is_some_and(is_inside_higher)
+    }
+
+    /// Go through the surface hit, into or out of its object.
+    pub fn cross(&mut self, hit_record: &HitRecord) {
+        let Some(interior) = &hit_record.interior else {
+            return;
+        };
+        match hit_record.is_front_facing {
+            true => {
+                let index_of_refraction = match *hit_record.material {
+                    // Nothing bends light on the way in, so nothing does on the way out.
+                    Material::Interface => self.index_of_refraction(),
+                    _ => hit_record.material.index_of_refraction(hit_record),
+                };
+                self.0.push((interior.clone(), index_of_refraction));
+            }
+            false => {
+                let entered = self
+                    .0
+                    .iter()
+                    .rposition(|(entered, _)| Arc::ptr_eq(entered, interior));
+                if let Some(index) = entered {
+                    self.0.remove(index);
+                }
+            }
+        }
+    }
+
+    /// The stack beyond the surface hit, in `direction` away from it.
+    pub fn toward(&self, hit_record: &HitRecord, direction: Vec3) -> Self {
+        let mut media = self.clone();
+        if direction.dot(hit_record.geometric_normal) < 0. {
+            media.cross(hit_record);
+        }
+        media
+    }
+
+    /// Tell the surface hit the index of refraction around its object, which is that of the
+    /// others it is inside.
+    pub fn surround(&self, hit_record: &mut HitRecord) {
+        let mut others = self.clone();
+        if let Some(interior) = &hit_record.interior {
+            others
+                .0
+                .retain(|(entered, _)| !Arc::ptr_eq(entered, interior));
+        }
+        hit_record.surrounding_ior = others.index_of_refraction();
+    }
+
+    /// How much light gets along `ray` from its origin to `t_max`, through the media and the
+    /// surfaces it passes. None does if any other surface is in the way.
+    pub fn transmittance(&self, ray: &Ray, t_max: f64, scene: &Scene) -> Vec3 {
+        let (mut media, mut ray, mut t_max) = (self.clone(), *ray, t_max);
+        let mut transmittance = Vec3::new(1., 1., 1.);
+        loop {
+            let hit_record = scene.hit(&ray, 0.001, t_max);
+            let time = hit_record
+                .as_ref()
+                .map_or(t_max, |hit_record| hit_record.time);
+            if let Some(medium) = media.medium() {
+                transmittance = transmittance * medium.transmittance(&ray, 0., time);
+            }
+            let Some(hit_record) = hit_record else {
+                return transmittance;
+            };
+            if !media.passes(&hit_record) {
+                return Vec3::default();
+            }
+            media.cross(&hit_record);
+            ray = ray.spawn(&hit_record.point, &ray.direction);
+            t_max -= hit_record.time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Flight, Medium, MediumStack, Nested};
+    use crate::prelude::{
+        ray_color, with_sampler, Background, Hittable, HittableList, Material, Point, Ray, Sampler,
+        Scene, Sphere, SphereLight, Vec3, VoxelGrid,
+    };
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::Arc;
+
+    /// Random numbers from a seeded generator, to put in place with `with_sampler`.
+    struct Seeded(StdRng);
+
+    impl Sampler for Seeded {
+        fn next(&mut self) -> f64 {
+            self.0.gen()
+        }
+    }
+
+    /// Each channel's average over many trials.
+    fn average(trials: usize, estimate: impl Fn() -> Vec3) -> Vec3 {
+        (0..trials).fold(Vec3::default(), |sum, _| sum + estimate()) / trials as f64
+    }
+
+    fn assert_close(estimate: Vec3, expected: Vec3, tolerance: f64) {
+        let error = estimate - expected;
+        assert!(
+            [error.0, error.1, error.2]
+                .iter()
+                .all(|error| error.abs() < tolerance),
+            "{} is not {}",
+            estimate,
+            expected
+        );
+    }
+
+    #[test]
+    fn flights_match_beer_lambert() {
+        let (absorption, scattering) = (Vec3::new(0.1, 0.4, 1.), Vec3::new(0.5, 0.2, 1.));
+        let grid = VoxelGrid::dense(
+            (1, 1, 1),
+            Point::new(0., 0., 0.),
+            Point::new(1., 1., 1.),
+            vec![1.],
+        )
+        .unwrap();
+        let homogeneous = Medium::new(absorption, scattering);
+        let heterogeneous = Medium::heterogeneous(absorption, scattering, Arc::new(grid));
+        // Through the unit box, from its near side at time 1 to its far side at 2.
+        let ray = Ray::new(&Point::new(-1., 0.5, 0.5), &Vec3::new(1., 0., 0.));
+
+        let extinction = absorption + scattering;
+        let transmittance = Vec3::new(
+            (-extinction.0).exp(),
+            (-extinction.1).exp(),
+            (-extinction.2).exp(),
+        );
+        let scattered = scattering * (Vec3::new(1., 1., 1.) - transmittance) / extinction;
+        for medium in [homogeneous, heterogeneous] {
+            let flights = (0..40_000)
+                .map(|_| medium.sample(&ray, 1., 2.))
+                .collect::<Vec<_>>();
+            let weights = |scatters: bool| {
+                flights
+                    .iter()
+                    .fold(Vec3::default(), |sum, flight| match *flight {
+                        Flight::Scattered { weight, .. } if scatters => sum + weight,
+                        Flight::Passed { weight } if !scatters => sum + weight,
+                        _ => sum,
+                    })
+                    / flights.len() as f64
+            };
+            assert_close(weights(false), transmittance, 0.02);
+            assert_close(weights(true), scattered, 0.02);
+            assert_close(
+                average(20_000, || medium.transmittance(&ray, 1., 2.)),
+                transmittance,
+                0.02,
+            );
+        }
+    }
+
+    #[test]
+    fn priorities_nest_glass_in_water() {
+        let water = Nested::new(
+            Arc::new(Sphere::new(
+                Point::new(0., 0., 0.),
+                2.,
+                Arc::new(Material::dielectric(1.33)),
+            )),
+            Some(Medium::new(Vec3::new(0.3, 0.1, 0.05), Vec3::default())),
+            1,
+        );
+        // Poking out of the water.
+        let glass = Nested::new(
+            Arc::new(Sphere::new(
+                Point::new(1.5, 0., 0.),
+                1.,
+                Arc::new(Material::dielectric(1.5)),
+            )),
+            None,
+            2,
+        );
+        let mut world = HittableList::new();
+        world.push(Arc::new(water));
+        world.push(Arc::new(glass));
+        let ray = Ray::new(&Point::new(-5., 0., 0.), &Vec3::new(1., 0., 0.));
+
+        // Into the water, into the glass, out of the water inside the glass, out of the glass.
+        let mut media = MediumStack::default();
+        let mut seen = vec![];
+        for mut hit_record in world.hit_all(&ray, 0., f64::INFINITY) {
+            let passes = media.passes(&hit_record);
+            media.surround(&mut hit_record);
+            media.cross(&hit_record);
+            seen.push((
+                hit_record.point.0,
+                passes,
+                hit_record.surrounding_ior,
+                media.medium().is_some(),
+                media.index_of_refraction(),
+            ));
+        }
+        assert_eq!(
+            seen,
+            vec![
+                (-2., false, 1., true, 1.33),
+                (0.5, false, 1.33, false, 1.5),
+                (2., true, 1.5, false, 1.5),
+                (2.5, false, 1., false, 1.),
+            ]
+        );
+    }
+
+    #[test]
+    fn paths_see_through_chromatic_absorbers() {
+        // A clear ball of ink in front of a white sky, seen through its middle.
+        let absorption = Vec3::new(0.1, 0.5, 1.);
+        let mut world = HittableList::new();
+        world.push(Arc::new(Nested::new(
+            Arc::new(Sphere::new(
+                Point::new(0., 0., 0.),
+                1.,
+                Arc::new(Material::Interface),
+            )),
+            Some(Medium::new(absorption, Vec3::default())),
+            0,
+        )));
+        let mut scene = Scene::new(world);
+        scene.background = Background::Color(Vec3::new(1., 1., 1.));
+        let ray = Ray::new(&Point::new(0., 0., 3.), &Vec3::new(0., 0., -1.));
+
+        let expected = Vec3::new(
+            (-2. * absorption.0).exp(),
+            (-2. * absorption.1).exp(),
+            (-2. * absorption.2).exp(),
+        );
+        assert_close(
+            average(20_000, || ray_color(&ray, &scene, 10)),
+            expected,
+            0.02,
+        );
+    }
+
+    #[test]
+    fn lights_shine_through_heterogeneous_media_alike() {
+        let (absorption, scattering) = (Vec3::new(0.2, 0.5, 0.1), Vec3::new(1.5, 0.5, 1.));
+        let grid = VoxelGrid::dense(
+            (1, 1, 1),
+            Point::new(-1., -1., -1.),
+            Point::new(1., 1., 1.),
+            vec![1.],
+        )
+        .unwrap();
+        let scene = |medium: Medium| {
+            let mut world = HittableList::new();
+            world.push(Arc::new(Nested::new(
+                Arc::new(Sphere::new(
+                    Point::new(0., 0., 0.),
+                    1.,
+                    Arc::new(Material::Interface),
+                )),
+                Some(medium),
+                0,
+            )));
+            let mut scene = Scene::new(world);
+            scene.background = Background::Color(Vec3::default());
+            // In the fog, so that rays also meet it on their way through.
+            scene.push_light(Arc::new(SphereLight::new(
+                Point::new(0., 0.5, 0.),
+                0.2,
+                Vec3::new(10., 10., 10.),
+            )));
+            scene
+        };
+        let homogeneous = scene(Medium::new(absorption, scattering));
+        let heterogeneous = scene(Medium::heterogeneous(
+            absorption,
+            scattering,
+            Arc::new(grid),
+        ));
+        let ray = Ray::new(&Point::new(0., 0., 3.), &Vec3::new(0., 0., -1.));
+
+        // Each path draws the same seeded random numbers in both, so that the estimates differ by
+        // how the media are tracked more than by chance, and the same on every run. Deep paths in
+        // the red channel, which scatters most, have too heavy a tail to compare.
+        let estimate = |scene: &Scene| {
+            let trials = 40_000;
+            (0..trials).fold(Vec3::default(), |sum, trial| {
+                let sampler = Seeded(StdRng::seed_from_u64(trial));
+                sum + with_sampler(sampler, || ray_color(&ray, scene, 5)).0
+            }) / trials as f64
+        };
+        let (homogeneous, heterogeneous) = (estimate(&homogeneous), estimate(&heterogeneous));
+        assert!(homogeneous.0 > 0.);
+        assert_close(heterogeneous, homogeneous, 0.05 * homogeneous.0);
+    }
+}
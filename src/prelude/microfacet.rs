@@ -0,0 +1,285 @@
+//! GGX (Trowbridge-Reitz) microfacet theory, in a local shading frame whose `z` axis is the
+//! macrosurface normal.
+use crate::prelude::{LinAlgOp, Ray, Vec3, RGB_WAVELENGTHS};
+use std::f64::consts::PI;
+
+/// Below this the distribution is so sharp that it breaks down numerically.
+const MIN_ALPHA: f64 = 1e-3;
+
+/// Convert a perceptual roughness in `[0, 1]` to the GGX `alpha` parameter.
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(MIN_ALPHA)
+}
+
+/// The complex index of refraction `eta + i k` of a conductor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ComplexIor {
+    /// Per RGB channel. Spectral rays see the upsampled Fresnel reflectance.
+    Rgb { eta: Vec3, k: Vec3 },
+    /// Measured `(wavelength in nm, eta, k)`, in increasing wavelength. RGB rays see it at
+    /// `RGB_WAVELENGTHS`.
+    Measured(&'static [(f64, f64, f64)]),
+}
+
+impl ComplexIor {
+    // Coarsely sampled from published measurements of the bulk metals.
+    pub const GOLD: ComplexIor = ComplexIor::Measured(&[
+        (400., 1.658, 1.956),
+        (440., 1.50, 1.88),
+        (480., 1.25, 1.80),
+        (500., 0.97, 1.87),
+        (520., 0.62, 2.08),
+        (540., 0.43, 2.39),
+        (560., 0.31, 2.67),
+        (580., 0.24, 2.88),
+        (600., 0.20, 3.08),
+        (650., 0.16, 3.55),
+        (700., 0.16, 3.95),
+        (750., 0.16, 4.40),
+        (800., 0.16, 4.85),
+    ]);
+    pub const COPPER: ComplexIor = ComplexIor::Measured(&[
+        (400., 1.18, 2.21),
+        (450., 1.17, 2.34),
+        (500., 1.13, 2.56),
+        (550., 1.02, 2.58),
+        (570., 0.83, 2.60),
+        (590., 0.47, 2.81),
+        (610., 0.27, 3.24),
+        (650., 0.21, 3.67),
+        (700., 0.21, 4.18),
+        (800., 0.26, 5.00),
+    ]);
+    pub const ALUMINIUM: ComplexIor = ComplexIor::Measured(&[
+        (400., 0.49, 4.86),
+        (450., 0.62, 5.47),
+        (500., 0.77, 6.08),
+        (550., 0.96, 6.69),
+        (600., 1.20, 7.26),
+        (650., 1.47, 7.79),
+        (700., 1.83, 8.31),
+        (750., 2.33, 8.62),
+        (800., 2.80, 8.45),
+    ]);
+
+    pub fn new(eta: Vec3, k: Vec3) -> Self {
+        ComplexIor::Rgb { eta, k }
+    }
+
+    /// The Fresnel reflectance seen by `ray`, per RGB channel or per wavelength it carries.
+    pub fn fresnel(&self, cos_theta_i: f64, ray: &Ray) -> Vec3 {
+        match self {
+            ComplexIor::Rgb { eta, k } => ray.reflectance(fresnel_conductor(cos_theta_i, *eta, *k)),
+            ComplexIor::Measured(table) => {
+                let lambda = ray.wavelengths.unwrap_or(RGB_WAVELENGTHS);
+                let [red, green, blue] = lambda.map(|lambda| interpolate_measured(table, lambda));
+                fresnel_conductor(
+                    cos_theta_i,
+                    Vec3(red.0, green.0, blue.0),
+                    Vec3(red.1, green.1, blue.1),
+                )
+            }
+        }
+    }
+}
+
+/// Linearly interpolate `(eta, k)` from a measured table, clamped to its ends.
+fn interpolate_measured(table: &[(f64, f64, f64)], lambda: f64) -> (f64, f64) {
+    let next = table.partition_point(|&(wavelength, _, _)| wavelength < lambda);
+    if next == 0 {
+        return (table[0].1, table[0].2);
+    }
+    if next == table.len() {
+        let last = table[table.len() - 1];
+        return (last.1, last.2);
+    }
+
+    let (before, after) = (table[next - 1], table[next]);
+    let t = (lambda - before.0) / (after.0 - before.0);
+    (
+        before.1 + t * (after.1 - before.1),
+        before.2 + t * (after.2 - before.2),
+    )
+}
+
+fn tan2_theta(w: Vec3) -> f64 {
+    (w.0 * w.0 + w.1 * w.1) / (w.2 * w.2)
+}
+
+/// The distribution of microfacet normals `m`, normalised so that its projected area is one.
+pub fn ggx_d(m: Vec3, alpha: f64) -> f64 {
+    if m.2 <= 0. {
+        return 0.;
+    }
+    let cos2 = m.2 * m.2;
+    let root = 1. + tan2_theta(m) / (alpha * alpha);
+    1. / (PI * alpha * alpha * cos2 * cos2 * root * root)
+}
+
+/// Smith's auxiliary function, the ratio of shadowed to visible microfacet area along `w`.
+pub fn smith_lambda(w: Vec3, alpha: f64) -> f64 {
+    let tan2 = tan2_theta(w);
+    if !tan2.is_finite() {
+        return f64::INFINITY;
+    }
+    0.5 * (-1. + (1. + alpha * alpha * tan2).sqrt())
+}
+
+/// The fraction of microfacets visible from `w`.
+pub fn smith_g1(w: Vec3, alpha: f64) -> f64 {
+    1. / (1. + smith_lambda(w, alpha))
+}
+
+/// Height-correlated masking and shadowing of microfacets seen from both `wo` and `wi`.
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    1. / (1. + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+/// Sample a microfacet normal from the distribution of normals visible from `wo`, which must be
+/// above the surface (Heitz, "Sampling the GGX Distribution of Visible Normals", 2018).
+pub fn sample_visible_normal(wo: Vec3, alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    // Stretch the view direction so that the distribution becomes a hemisphere.
+    let stretched = Vec3::new(alpha * wo.0, alpha * wo.1, wo.2).unit_vector();
+
+    let length_squared = stretched.0 * stretched.0 + stretched.1 * stretched.1;
+    let t1 = if length_squared > 0. {
+        Vec3::new(-stretched.1, stretched.0, 0.) / length_squared.sqrt()
+    } else {
+        Vec3::new(1., 0., 0.)
+    };
+    let t2 = stretched.cross(t1);
+
+    // A point on the projected disk, squeezed onto the visible half.
+    let radius = u1.sqrt();
+    let phi = 2. * PI * u2;
+    let p1 = radius * phi.cos();
+    let s = 0.5 * (1. + stretched.2);
+    let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * radius * phi.sin();
+    let height = (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+
+    let normal = p1 * t1 + p2 * t2 + height * stretched;
+    Vec3::new(alpha * normal.0, alpha * normal.1, normal.2.max(1e-6)).unit_vector()
+}
+
+/// Unpolarised Fresnel reflectance of a dielectric boundary, where `eta` is the index of refraction
+/// of the far side relative to the side `cos_theta_i` is measured on.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (mut cos_theta_i, mut eta) = (cos_theta_i.clamp(-1., 1.), eta);
+    if cos_theta_i < 0. {
+        eta = 1. / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+
+    let sin2_theta_t = (1. - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1. {
+        return 1.;
+    }
+    let cos_theta_t = (1. - sin2_theta_t).sqrt();
+
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Unpolarised Fresnel reflectance of a conductor with complex index `eta + i k`, per channel.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i.clamp(0., 1.).powi(2);
+        let sin2 = 1. - cos2;
+        let (eta2, k2) = (eta * eta, k * k);
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_theta_i * a;
+        let perpendicular = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+
+        0.5 * (parallel + perpendicular)
+    };
+
+    Vec3::new(
+        channel(eta.0, k.0),
+        channel(eta.1, k.1),
+        channel(eta.2, k.2),
+    )
+}
+
+/// Refract `wo` through the microfacet `m`, where `eta` is the relative index of refraction of the
+/// far side. `None` on total internal reflection.
+pub fn refract_through(wo: Vec3, m: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = wo.dot(m);
+    let sin2_theta_t = (1. - cos_theta_i * cos_theta_i).max(0.) / (eta * eta);
+    if sin2_theta_t >= 1. {
+        return None;
+    }
+    let cos_theta_t = (1. - sin2_theta_t).sqrt();
+
+    Some(-wo / eta + (cos_theta_i / eta - cos_theta_t) * m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn distribution_has_unit_projected_area() {
+        // Integrate D(m) cos(theta_m) over the hemisphere with a midpoint rule in theta.
+        for alpha in [0.1, 0.5, 1.] {
+            let steps = 20_000;
+            let d_theta = 0.5 * PI / steps as f64;
+            let integral: f64 = (0..steps)
+                .map(|step| {
+                    let theta = (step as f64 + 0.5) * d_theta;
+                    let m = Vec3::new(theta.sin(), 0., theta.cos());
+                    ggx_d(m, alpha) * theta.cos() * theta.sin() * 2. * PI * d_theta
+                })
+                .sum();
+            assert!(
+                (integral - 1.).abs() < 1e-3,
+                "alpha {}: {}",
+                alpha,
+                integral
+            );
+        }
+    }
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let mut rng = thread_rng();
+        let wo = Vec3::new(0.8, 0.1, 0.3).unit_vector();
+
+        for _ in 0..1000 {
+            let m = sample_visible_normal(wo, 0.6, rng.gen(), rng.gen());
+            assert!(m.2 > 0. && wo.dot(m) >= -1e-9);
+            assert!((m.norm() - 1.).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn fresnel_limits() {
+        // Normal incidence on glass reflects 4%, and grazing light is fully reflected.
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(1e-9, 1.5) - 1.).abs() < 1e-6);
+        // Beyond the critical angle inside the glass.
+        assert_eq!(fresnel_dielectric(-0.3, 1.5), 1.);
+
+        // A conductor without absorption is a dielectric.
+        let conductor = fresnel_conductor(0.6, Vec3(1.5, 1.5, 1.5), Vec3::default());
+        assert!((conductor.0 - fresnel_dielectric(0.6, 1.5)).abs() < 1e-9);
+
+        // Gold reflects red better than blue, whether seen in RGB or at single wavelengths.
+        let mut ray = Ray::default();
+        let gold = ComplexIor::GOLD.fresnel(1., &ray);
+        assert!(gold.0 > 0.9 && gold.2 < 0.5);
+
+        ray.wavelengths = Some([650., 550., 450.]);
+        let spectral = ComplexIor::GOLD.fresnel(1., &ray);
+        assert!(spectral.0 > spectral.1 && spectral.1 > spectral.2);
+    }
+}
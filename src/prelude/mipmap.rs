@@ -0,0 +1,282 @@
+use crate::prelude::{TextureImage, UvDerivatives, Vec3, WrapMode};
+
+/// How an image texture is filtered over the footprint of a pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Bilinear interpolation of the full resolution image, ignoring the footprint.
+    Bilinear,
+    /// Bilinear lookups in the two nearest pyramid levels, blended by the footprint's width.
+    Trilinear,
+    /// Elliptically weighted averaging over the footprint's anisotropic ellipse.
+    Ewa,
+}
+
+/// Ellipses more eccentric than this are widened, trading some blur for bounded lookup cost.
+const MAX_ANISOTROPY: f64 = 8.;
+
+/// Gaussian falloff of the EWA filter.
+const EWA_ALPHA: f64 = 2.;
+
+/// An image pyramid, each level half the resolution of the one before it down to a single texel.
+#[derive(Clone, Debug)]
+pub struct MipMap {
+    pub levels: Vec<TextureImage>,
+}
+
+impl MipMap {
+    pub fn new(image: TextureImage) -> Self {
+        let mut levels = vec![image];
+
+        while let Some(last) = levels.last() {
+            if (last.width <= 1 && last.height <= 1) || last.width == 0 || last.height == 0 {
+                break;
+            }
+            let next = downsample(last);
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    pub fn texel(&self, level: usize, x: isize, y: isize, wrap: WrapMode) -> Vec3 {
+        let image = &self.levels[level];
+        image.pixel(wrap.apply(x, image.width), wrap.apply(y, image.height))
+    }
+
+    /// Bilinearly interpolate one level. `v = 1` is the top of the image.
+    pub fn bilinear(&self, level: usize, u: f64, v: f64, wrap: WrapMode) -> Vec3 {
+        let image = &self.levels[level];
+        if image.width == 0 || image.height == 0 {
+            return Vec3::new(0., 1., 1.);
+        }
+
+        // Texel centers sit at half-integer coordinates.
+        let x = u * image.width as f64 - 0.5;
+        let y = (1. - v) * image.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        let texel = |dx: isize, dy: isize| self.texel(level, x0 + dx, y0 + dy, wrap);
+
+        (1. - fy) * ((1. - fx) * texel(0, 0) + fx * texel(1, 0))
+            + fy * ((1. - fx) * texel(0, 1) + fx * texel(1, 1))
+    }
+
+    pub fn lookup(
+        &self,
+        filter: FilterMode,
+        u: f64,
+        v: f64,
+        derivatives: Option<&UvDerivatives>,
+        wrap: WrapMode,
+    ) -> Vec3 {
+        match (filter, derivatives) {
+            (FilterMode::Trilinear, Some(derivatives)) => self.trilinear(u, v, derivatives, wrap),
+            (FilterMode::Ewa, Some(derivatives)) => self.ewa(u, v, derivatives, wrap),
+            _ => self.bilinear(0, u, v, wrap),
+        }
+    }
+
+    pub fn trilinear(&self, u: f64, v: f64, derivatives: &UvDerivatives, wrap: WrapMode) -> Vec3 {
+        let (dx, dy) = self.texel_space(derivatives);
+        let width = norm(dx).max(norm(dy));
+
+        self.blend_levels(width, |level| self.bilinear(level, u, v, wrap))
+    }
+
+    pub fn ewa(&self, u: f64, v: f64, derivatives: &UvDerivatives, wrap: WrapMode) -> Vec3 {
+        let (mut major, mut minor) = self.texel_space(derivatives);
+        if norm(minor) > norm(major) {
+            std::mem::swap(&mut major, &mut minor);
+        }
+
+        let (major_length, mut minor_length) = (norm(major), norm(minor));
+        if minor_length == 0. {
+            return self.bilinear(0, u, v, wrap);
+        }
+        if minor_length * MAX_ANISOTROPY < major_length {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+            minor_length *= scale;
+        }
+
+        // The minor axis picks the level, so that the ellipse stays a few texels wide there.
+        self.blend_levels(minor_length, |level| {
+            self.ewa_level(level, u, v, (major, minor), wrap)
+        })
+    }
+
+    /// Footprint axes in texels of the full resolution level, with y pointing down the image.
+    fn texel_space(&self, derivatives: &UvDerivatives) -> ((f64, f64), (f64, f64)) {
+        let (width, height) = (self.levels[0].width as f64, self.levels[0].height as f64);
+        (
+            (derivatives.dudx * width, -derivatives.dvdx * height),
+            (derivatives.dudy * width, -derivatives.dvdy * height),
+        )
+    }
+
+    fn blend_levels(&self, width_in_texels: f64, lookup: impl Fn(usize) -> Vec3) -> Vec3 {
+        let coarsest = (self.levels.len() - 1) as f64;
+        let level = width_in_texels.max(1e-8).log2().clamp(0., coarsest);
+
+        let lower = level.floor() as usize;
+        let fraction = level - lower as f64;
+        if fraction == 0. {
+            return lookup(lower);
+        }
+        (1. - fraction) * lookup(lower) + fraction * lookup(lower + 1)
+    }
+
+    fn ewa_level(
+        &self,
+        level: usize,
+        u: f64,
+        v: f64,
+        (axis_0, axis_1): ((f64, f64), (f64, f64)),
+        wrap: WrapMode,
+    ) -> Vec3 {
+        let image = &self.levels[level];
+        let scale_x = image.width as f64 / self.levels[0].width as f64;
+        let scale_y = image.height as f64 / self.levels[0].height as f64;
+
+        let (ds0, dt0) = (axis_0.0 * scale_x, axis_0.1 * scale_y);
+        let (ds1, dt1) = (axis_1.0 * scale_x, axis_1.1 * scale_y);
+        let s = u * image.width as f64 - 0.5;
+        let t = (1. - v) * image.height as f64 - 0.5;
+
+        // Implicit ellipse A s^2 + B s t + C t^2 < 1, padded to cover at least one texel.
+        let mut a = dt0 * dt0 + dt1 * dt1 + 1.;
+        let mut b = -2. * (ds0 * dt0 + ds1 * dt1);
+        let mut c = ds0 * ds0 + ds1 * ds1 + 1.;
+        let inverse_f = 1. / (a * c - b * b * 0.25);
+        a *= inverse_f;
+        b *= inverse_f;
+        c *= inverse_f;
+
+        let determinant = -b * b + 4. * a * c;
+        let inverse_determinant = 1. / determinant;
+        let u_sqrt = (determinant * c).sqrt();
+        let v_sqrt = (a * determinant).sqrt();
+        let s0 = (s - 2. * inverse_determinant * u_sqrt).ceil() as isize;
+        let s1 = (s + 2. * inverse_determinant * u_sqrt).floor() as isize;
+        let t0 = (t - 2. * inverse_determinant * v_sqrt).ceil() as isize;
+        let t1 = (t + 2. * inverse_determinant * v_sqrt).floor() as isize;
+
+        let mut sum = Vec3::default();
+        let mut weights = 0.;
+        for it in t0..=t1 {
+            let tt = it as f64 - t;
+            for is in s0..=s1 {
+                let ss = is as f64 - s;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1. {
+                    let weight = (-EWA_ALPHA * r2).exp() - (-EWA_ALPHA).exp();
+                    sum += weight * self.texel(level, is, it, wrap);
+                    weights += weight;
+                }
+            }
+        }
+
+        if weights <= 0. {
+            return self.bilinear(level, u, v, wrap);
+        }
+        sum / weights
+    }
+}
+
+fn norm((x, y): (f64, f64)) -> f64 {
+    (x * x + y * y).sqrt()
+}
+
+/// Halve an image in each dimension (rounding up) with a box filter.
+fn downsample(image: &TextureImage) -> TextureImage {
+    let width = image.width.div_ceil(2).max(1);
+    let height = image.height.div_ceil(2).max(1);
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let x0 = (2 * x).min(image.width - 1);
+            let x1 = (2 * x + 1).min(image.width - 1);
+            let y0 = (2 * y).min(image.height - 1);
+            let y1 = (2 * y + 1).min(image.height - 1);
+
+            pixels.push(
+                0.25 * (image.pixel(x0, y0)
+                    + image.pixel(x1, y0)
+                    + image.pixel(x0, y1)
+                    + image.pixel(x1, y1)),
+            );
+        }
+    }
+
+    TextureImage::new(width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterMode, MipMap};
+    use crate::prelude::{TextureImage, UvDerivatives, Vec3, WrapMode};
+
+    /// A 16x16 black and white checkerboard of single texels, which averages to gray.
+    fn checkerboard() -> MipMap {
+        let pixels = (0..256)
+            .map(|index| {
+                if (index % 16 + index / 16) % 2 == 0 {
+                    Vec3::new(1., 1., 1.)
+                } else {
+                    Vec3::default()
+                }
+            })
+            .collect();
+        MipMap::new(TextureImage::new(16, 16, pixels))
+    }
+
+    fn isotropic(width: f64) -> UvDerivatives {
+        UvDerivatives {
+            dudx: width,
+            dvdx: 0.,
+            dudy: 0.,
+            dvdy: width,
+        }
+    }
+
+    #[test]
+    fn pyramid_halves_down_to_one_texel() {
+        let mipmap = checkerboard();
+        let sizes = mipmap
+            .levels
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(16, 16), (8, 8), (4, 4), (2, 2), (1, 1)]);
+        assert!((mipmap.levels[4].pixel(0, 0) - Vec3::new(0.5, 0.5, 0.5)).norm() < 1e-12);
+
+        let odd = MipMap::new(TextureImage::new(5, 3, vec![Vec3::default(); 15]));
+        assert_eq!(odd.levels.len(), 4);
+    }
+
+    #[test]
+    fn wide_footprints_average_the_texture() {
+        let mipmap = checkerboard();
+        let gray = Vec3::new(0.5, 0.5, 0.5);
+
+        for filter in [FilterMode::Trilinear, FilterMode::Ewa] {
+            let value = mipmap.lookup(filter, 0.3, 0.6, Some(&isotropic(1.)), WrapMode::Repeat);
+            assert!((value - gray).norm() < 1e-6, "{:?} gave {}", filter, value);
+        }
+    }
+
+    #[test]
+    fn tiny_footprints_keep_full_resolution() {
+        let mipmap = checkerboard();
+        // The center of texel (0, 0) in the top left corner, which is white.
+        let (u, v) = (0.5 / 16., 1. - 0.5 / 16.);
+
+        for filter in [FilterMode::Bilinear, FilterMode::Trilinear, FilterMode::Ewa] {
+            let value = mipmap.lookup(filter, u, v, Some(&isotropic(1e-6)), WrapMode::Repeat);
+            assert!(value.0 > 0.9, "{:?} gave {}", filter, value);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+//! Primary sample space Metropolis light transport (Kelemen et al. 2002).
+//!
+//! A path traced by `ray_color` is a function of the random numbers it draws through `rng`, the
+//! first two of which pick where it crosses the viewport. Markov chains over those numbers visit
+//! paths in proportion to the light they carry, so the chains linger on the few paths that light
+//! hard-to-reach places.
+
+use crate::prelude::progress_bars::ProgressBar;
+use crate::prelude::{
+    gamma2_correct, luminance, ray_color, rng, viewport_ray, with_sampler, Camera, Distribution1D,
+    Film, Image, Integrator, Pixel, RenderConfig, Sampler, Scene, Vec3,
+};
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::f64::consts::PI;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// The random numbers where a Markov chain is, and those of the mutation it may move to, handed
+/// out in order as a `Sampler`. Numbers are drawn anew for as many more as a path needs.
+#[derive(Clone, Debug)]
+pub struct PrimarySamples {
+    /// Where the chain is.
+    current: Vec<f64>,
+    /// Handed out to the path being traced, and where the chain moves if it is accepted.
+    proposed: Vec<f64>,
+    /// The next number to hand out.
+    index: usize,
+    /// New numbers come from it, so the same seed traces the same path.
+    rng: StdRng,
+    /// Of small steps.
+    sigma: f64,
+}
+
+impl PrimarySamples {
+    /// Fresh numbers, the same ones for the same `seed`.
+    pub fn new(seed: u64, sigma: f64) -> Self {
+        Self {
+            current: vec![],
+            proposed: vec![],
+            index: 0,
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+        }
+    }
+
+    /// Propose numbers near the current ones, or all new ones for a large step.
+    pub fn mutate(&mut self, large_step: bool) {
+        self.index = 0;
+        self.proposed.clear();
+        if large_step {
+            return;
+        }
+        for &u in &self.current {
+            // Normally distributed by Box and Muller, wrapping around [0, 1).
+            let (u1, u2) = (1. - self.rng.gen::<f64>(), self.rng.gen::<f64>());
+            let step = self.sigma * (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos();
+            let u = u + step;
+            self.proposed.push(u - u.floor());
+        }
+    }
+
+    /// Move to the proposed numbers.
+    pub fn accept(&mut self) {
+        std::mem::swap(&mut self.current, &mut self.proposed);
+    }
+
+    /// Stay at the current numbers.
+    pub fn reject(&mut self) {
+        self.proposed.clear();
+    }
+}
+
+impl Sampler for PrimarySamples {
+    fn next(&mut self) -> f64 {
+        if self.index == self.proposed.len() {
+            let u = self.rng.gen();
+            self.proposed.push(u);
+        }
+        self.index += 1;
+        self.proposed[self.index - 1]
+    }
+}
+
+/// Where a path from the camera crosses the viewport and the light it brings, in RGB.
+type Contribution = ((f64, f64), Vec3);
+
+/// Trace the path that `samples` pick, handing them back.
+fn trace(
+    samples: PrimarySamples,
+    camera: &Camera,
+    scene: &Scene,
+    image: &Image,
+    render_config: &RenderConfig,
+) -> (Contribution, PrimarySamples) {
+    with_sampler(samples, || {
+        // Over the pixels as `process_pixels` makes rays, which reach past the viewport's edge.
+        let mut rng = rng();
+        let viewport = (
+            rng.gen::<f64>() * image.width as f64 / (image.width - 1) as f64,
+            rng.gen::<f64>() * image.height as f64 / (image.height - 1) as f64,
+        );
+        let (ray, wavelengths) = viewport_ray(viewport, camera, image, render_config);
+        let color = ray_color(&ray, scene, render_config.max_depth);
+        let color = match wavelengths {
+            Some(wavelengths) => wavelengths.to_rgb(color),
+            None => color,
+        };
+        (viewport, color)
+    })
+}
+
+/// What the chains look for.
+fn importance(color: Vec3) -> f64 {
+    match luminance(color) {
+        value if value > 0. && value.is_finite() => value,
+        _ => 0.,
+    }
+}
+
+/// Render by Metropolis light transport, with `render_config.integrator` set to `Metropolis`:
+/// there are as many mutations over all chains as samples over all pixels.
+pub fn metropolis(
+    image: &Image,
+    camera: &Camera,
+    scene: &Scene,
+    render_config: &RenderConfig,
+    progress_bar: ProgressBar,
+) -> Vec<Pixel> {
+    metropolis_colors(image, camera, scene, render_config, progress_bar)
+        .into_iter()
+        .map(|color| gamma2_correct(color, 2).into())
+        .collect()
+}
+
+/// The linear colors of `metropolis`, from the top row down.
+fn metropolis_colors(
+    image: &Image,
+    camera: &Camera,
+    scene: &Scene,
+    render_config: &RenderConfig,
+    progress_bar: ProgressBar,
+) -> Vec<Vec3> {
+    let Integrator::Metropolis {
+        bootstrap,
+        chains,
+        large_step_probability,
+        sigma,
+    } = render_config.integrator
+    else {
+        panic!("Metropolis light transport needs its integrator to be selected.");
+    };
+    let mutations = render_config.samples_per_pixel * image.width * image.height;
+    progress_bar.set_length(mutations as u64);
+    let seed = thread_rng().gen::<u64>();
+    // The path of the fresh numbers from `seed`, which a chain can start from again.
+    let replay = |seed: u64| {
+        trace(
+            PrimarySamples::new(seed, sigma),
+            camera,
+            scene,
+            image,
+            render_config,
+        )
+    };
+
+    // Independent paths measure how much light the image holds, and give chains their start.
+    #[cfg(feature = "parallel")]
+    let paths = (0..bootstrap as u64).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let paths = 0..bootstrap as u64;
+    let weights = paths
+        .map(|path| importance(replay(seed.wrapping_add(path)).0 .1))
+        .collect::<Vec<_>>();
+    let normalization = weights.iter().sum::<f64>() / bootstrap.max(1) as f64;
+    if normalization <= 0. {
+        return vec![Vec3::default(); image.width * image.height];
+    }
+    let starts = Distribution1D::new(&weights);
+
+    let film = Film::new(image.width, image.height);
+    #[cfg(feature = "parallel")]
+    let chain_iter = (0..chains).into_par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let chain_iter = 0..chains;
+    chain_iter.for_each(|chain| {
+        let mut rng = thread_rng();
+        let (_, _, start) = starts.sample(rng.gen());
+        let ((mut viewport, mut color), mut samples) = replay(seed.wrapping_add(start as u64));
+        samples.accept();
+        let mut current = importance(color);
+
+        let count = mutations / chains + usize::from(chain < mutations % chains);
+        for _ in 0..count {
+            samples.mutate(rng.gen::<f64>() < large_step_probability);
+            let ((proposed_viewport, proposed_color), proposed_samples) =
+                trace(samples, camera, scene, image, render_config);
+            samples = proposed_samples;
+            let proposed = importance(proposed_color);
+            let acceptance = match current > 0. {
+                true => (proposed / current).min(1.),
+                false => 1.,
+            };
+
+            // Both count, by how likely the chain is to be at each next.
+            if proposed > 0. {
+                film.splat(proposed_viewport, proposed_color * (acceptance / proposed));
+            }
+            if current > 0. {
+                film.splat(viewport, color * ((1. - acceptance) / current));
+            }
+            match rng.gen::<f64>() < acceptance {
+                true => {
+                    samples.accept();
+                    (viewport, color, current) = (proposed_viewport, proposed_color, proposed);
+                }
+                false => samples.reject(),
+            }
+        }
+        progress_bar.inc(count as u64);
+    });
+
+    // Splats hold as much luminance as there were mutations.
+    let scale = normalization / render_config.samples_per_pixel as f64;
+    (0..image.height)
+        .flat_map(|r| (0..image.width).map(move |col| (image.height - r - 1, col)))
+        .map(|(row, col)| film.sum(row, col) * scale)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metropolis_colors, PrimarySamples};
+    use crate::prelude::test_scenes::{assert_agrees_with_path_tracing, lit_ball, lit_ball_camera};
+    use crate::prelude::{progress_bars, Image, Integrator, Material, RenderConfig, Sampler, Vec3};
+
+    /// How far apart two numbers are, around [0, 1).
+    fn distance(a: f64, b: f64) -> f64 {
+        let d = (a - b).abs();
+        d.min(1. - d)
+    }
+
+    #[test]
+    fn samples_replay_and_mutate() {
+        let draw =
+            |samples: &mut PrimarySamples| (0..5).map(|_| samples.next()).collect::<Vec<_>>();
+        let mut samples = PrimarySamples::new(7, 0.01);
+        let start = draw(&mut samples);
+        assert_eq!(draw(&mut PrimarySamples::new(7, 0.01)), start);
+        samples.accept();
+
+        // Small steps stay near where the chain is, also once one is rejected.
+        for _ in 0..2 {
+            samples.mutate(false);
+            let proposed = draw(&mut samples);
+            assert!(proposed.iter().all(|u| (0. ..1.).contains(u)));
+            assert!(start
+                .iter()
+                .zip(&proposed)
+                .all(|(a, b)| distance(*a, *b) < 0.1));
+            assert_ne!(proposed, start);
+            samples.reject();
+        }
+
+        samples.mutate(true);
+        let proposed = draw(&mut samples);
+        assert!(start
+            .iter()
+            .zip(&proposed)
+            .any(|(a, b)| distance(*a, *b) > 0.1));
+        samples.accept();
+        samples.mutate(false);
+        assert!(proposed
+            .iter()
+            .zip(draw(&mut samples))
+            .all(|(a, b)| distance(*a, b) < 0.1));
+    }
+
+    #[test]
+    fn agrees_with_path_tracing() {
+        let scene = lit_ball(Material::lambertian(Vec3::new(0.8, 0.3, 0.3)));
+        let camera = lit_ball_camera();
+        let image = Image::new(8, 1.);
+        let render_config = RenderConfig {
+            integrator: Integrator::Metropolis {
+                bootstrap: 10_000,
+                chains: 512,
+                large_step_probability: 0.3,
+                sigma: 0.01,
+            },
+            ..RenderConfig::new(1000, 5)
+        };
+        let colors = metropolis_colors(
+            &image,
+            &camera,
+            &scene,
+            &render_config,
+            progress_bars::hidden(),
+        );
+
+        assert_agrees_with_path_tracing(&colors, &camera, &scene, &image, &render_config);
+    }
+}
@@ -1,19 +1,83 @@
+mod alias_table;
+mod bdpt;
+mod bsdf;
 mod camera;
 mod color;
+mod csg;
+mod dispersion;
+mod distribution;
+mod environment;
+mod film;
+mod guiding;
 mod hittable;
 mod hittable_list;
+mod light;
+mod light_sampler;
+mod lookdev;
 mod material;
+mod medium;
+mod microfacet;
+mod mipmap;
+mod mlt;
+mod photon;
+mod principled;
+mod quad;
 mod ray;
 mod render;
+mod sampler;
+mod scene;
+mod sdf;
+mod shading;
+mod sky;
+mod spectrum;
+mod subsurface;
+#[cfg(test)]
+mod test_scenes;
+mod texture;
+mod texture_image;
+mod thin_film;
+mod triangle;
 mod utils;
 mod vector;
+mod volume;
 
+pub use alias_table::*;
+pub use bdpt::*;
+pub use bsdf::*;
 pub use camera::*;
 pub use color::*;
+pub use csg::*;
+pub use dispersion::*;
+pub use distribution::*;
+pub use environment::*;
+pub use film::*;
+pub use guiding::*;
 pub use hittable::*;
 pub use hittable_list::*;
+pub use light::*;
+pub use light_sampler::*;
+pub use lookdev::*;
 pub use material::*;
+pub use medium::*;
+pub use microfacet::*;
+pub use mipmap::*;
+pub use mlt::*;
+pub use photon::*;
+pub use principled::*;
+pub use quad::*;
 pub use ray::*;
 pub use render::*;
+pub use sampler::*;
+pub use scene::*;
+pub use sdf::*;
+pub use shading::*;
+pub use sky::*;
+pub use spectrum::*;
+pub use subsurface::*;
+pub use texture::*;
+pub use texture_image::*;
+pub use thin_film::*;
+pub use triangle::*;
 pub use utils::*;
 pub use vector::*;
+pub use volume::*;
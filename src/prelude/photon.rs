@@ -0,0 +1,578 @@
+//! Photon mapping (Jensen 1996) and stochastic progressive photon mapping (Hachisuka and Jensen
+//! 2009).
+//!
+//! Camera rays follow mirrors and glass to the first surface that scatters light diffusely or
+//! glossily, where light arriving straight from the lights is sampled as `ray_color` does and
+//! everything else is estimated from the density of photons nearby. Photons are traced in RGB and
+//! are not stored in media, which are only lit directly.
+
+use crate::prelude::progress_bars::ProgressBar;
+use crate::prelude::{
+    gamma2_correct, luminance, nearest_lights, pixel_ray, rng, sample_light, Bsdf, Camera,
+    HitRecord, Image, Integrator, LinAlgOp, Material, MediumStack, Pixel, Point, Ray, RenderConfig,
+    Scatter, Scene, Vec3,
+};
+use rand::Rng;
+use std::f64::consts::PI;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Light from the lights, left where it met a surface that is not a mirror.
+#[derive(Clone, Copy, Debug)]
+pub struct Photon {
+    pub point: Point,
+    /// Towards where the photon came from, a unit vector.
+    pub direction: Vec3,
+    /// The true normal of the surface, on the side the photon arrived from.
+    pub normal: Vec3,
+    /// The flux the photon carries, times the number of photons emitted.
+    pub power: Vec3,
+}
+
+/// Photons in a balanced kd-tree, to find those near a point.
+#[derive(Clone, Debug)]
+pub struct PhotonMap {
+    /// Each range of the tree has its median as the node, which splits the rest along the axis
+    /// stored at the same index of `axes`.
+    photons: Vec<Photon>,
+    axes: Vec<u32>,
+    /// How many photons were emitted, including those that never landed.
+    pub emitted: usize,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>, emitted: usize) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self {
+            photons,
+            axes,
+            emitted,
+        }
+    }
+
+    /// Emit `count` photons from the scene's lights, in proportion to their power, and keep where
+    /// they land after bouncing at least once, for at most `max_depth` bounces.
+    pub fn trace(scene: &Scene, count: usize, max_depth: isize) -> Self {
+        #[cfg(feature = "parallel")]
+        let paths = (0..count).into_par_iter();
+        #[cfg(not(feature = "parallel"))]
+        let paths = 0..count;
+
+        let photons = paths
+            .map(|_| trace_photon(scene, max_depth))
+            .collect::<Vec<_>>()
+            .concat();
+        Self::new(photons, count)
+    }
+
+    /// The photon map `render_config` asks for, if it does photon mapping.
+    pub fn for_config(scene: &Scene, render_config: &RenderConfig) -> Option<Self> {
+        match render_config.integrator {
+            Integrator::PhotonMapping { photons, .. } => {
+                Some(Self::trace(scene, photons, render_config.max_depth))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Visit every photon within `radius` of `point`.
+    pub fn within(&self, point: Point, radius: f64, mut visit: impl FnMut(&Photon)) {
+        self.visit_range(0, self.photons.len(), point, radius, &mut visit);
+    }
+
+    fn visit_range(
+        &self,
+        start: usize,
+        end: usize,
+        point: Point,
+        radius: f64,
+        visit: &mut impl FnMut(&Photon),
+    ) {
+        if start >= end {
+            return;
+        }
+        let middle = start + (end - start) / 2;
+        let photon = &self.photons[middle];
+        if (photon.point - point).norm_squared() <= radius * radius {
+            visit(photon);
+        }
+
+        let axis = self.axes[middle];
+        let offset = point[axis] - photon.point[axis];
+        if offset - radius <= 0. {
+            self.visit_range(start, middle, point, radius, visit);
+        }
+        if offset + radius >= 0. {
+            self.visit_range(middle + 1, end, point, radius, visit);
+        }
+    }
+}
+
+/// Order `photons` into a kd-tree, split along the axis they spread the most over.
+fn build(photons: &mut [Photon], axes: &mut [u32]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (min, max) = photons.iter().fold(
+        (
+            Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), photon| {
+            (
+                Vec3::new(
+                    min.0.min(photon.point.0),
+                    min.1.min(photon.point.1),
+                    min.2.min(photon.point.2),
+                ),
+                Vec3::new(
+                    max.0.max(photon.point.0),
+                    max.1.max(photon.point.1),
+                    max.2.max(photon.point.2),
+                ),
+            )
+        },
+    );
+    let extent = max - min;
+    let axis = match (
+        extent.0 >= extent.1,
+        extent.0 >= extent.2,
+        extent.1 >= extent.2,
+    ) {
+        (true, true, _) => 0,
+        (false, _, true) => 1,
+        _ => 2,
+    };
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| a.point[axis].total_cmp(&b.point[axis]));
+    axes[middle] = axis;
+
+    let (photons_below, rest) = photons.split_at_mut(middle);
+    let (axes_below, axes_rest) = axes.split_at_mut(middle);
+    #[cfg(feature = "parallel")]
+    rayon::join(
+        || build(photons_below, axes_below),
+        || build(&mut rest[1..], &mut axes_rest[1..]),
+    );
+    #[cfg(not(feature = "parallel"))]
+    {
+        build(photons_below, axes_below);
+        build(&mut rest[1..], &mut axes_rest[1..]);
+    }
+}
+
+/// Where one photon from the lights lands.
+fn trace_photon(scene: &Scene, max_depth: isize) -> Vec<Photon> {
+    let mut photons = vec![];
+    let mut rng = rng();
+    let Some((index, pmf)) = scene.emitter_table().sample(rng.gen()) else {
+        return photons;
+    };
+    let Some(emission) = scene.lights[index].sample_emission(&Ray::default()) else {
+        return photons;
+    };
+    let pdf = emission.pdf;
+    if pdf.position <= 0. || pdf.direction <= 0. {
+        return photons;
+    }
+    let cos_theta = match pdf.is_delta_position() {
+        true => 1.,
+        false => emission.ray.direction.unit_vector().dot(pdf.normal).abs(),
+    };
+    let mut power = emission.radiance * (cos_theta / (pmf * pdf.position * pdf.direction));
+    let mut ray = emission.ray;
+
+    for bounce in 0..max_depth {
+        let Some(hit_record) = scene.hit(&ray, 0.001, f64::INFINITY) else {
+            return photons;
+        };
+        let hit_record = hit_record.with_shading(&ray);
+        let material = &hit_record.material;
+        let is_in_medium = matches!(**material, Material::Isotropic { .. });
+        // Light arriving straight from the lights is sampled where it lands instead.
+        if bounce > 0 && !is_in_medium && material.flags(&hit_record).non_delta {
+            photons.push(Photon {
+                point: hit_record.point,
+                direction: -ray.direction.unit_vector(),
+                normal: hit_record.geometric_normal,
+                power,
+            });
+        }
+
+        let Some(sample) = material.sample(&ray, &hit_record) else {
+            return photons;
+        };
+        // Shading normals can send rays through the true surface, which would leak light.
+        if !hit_record.agrees_with_geometry(sample.ray.direction) {
+            return photons;
+        }
+        // Photons keep their power and end as often as it would fade.
+        let scattered = power * sample.weight;
+        let survival = (luminance(scattered) / luminance(power)).min(1.);
+        if survival.is_nan() || survival <= 0. || rng.gen::<f64>() >= survival {
+            return photons;
+        }
+        power = scattered / survival;
+        ray = sample.ray;
+    }
+    photons
+}
+
+/// Where a camera ray first reaches a surface that is not a mirror, to gather photons at.
+#[derive(Clone, Debug)]
+pub struct VisiblePoint {
+    pub hit_record: HitRecord,
+    /// The ray that arrived there.
+    pub ray: Ray,
+    /// The throughput of the camera path up to the point.
+    pub beta: Vec3,
+}
+
+impl VisiblePoint {
+    /// The flux that the photons within `radius` send back along the ray, scaled by the number
+    /// of photons emitted, and how many there are.
+    pub fn gather(&self, photons: &PhotonMap, radius: f64) -> (Vec3, usize) {
+        let hit_record = &self.hit_record;
+        let (mut flux, mut count) = (Vec3::default(), 0);
+        photons.within(hit_record.point, radius, |photon| {
+            // Photons on the other side of a thin surface do not light this one.
+            if photon.normal.dot(hit_record.geometric_normal) <= 0. {
+                return;
+            }
+            count += 1;
+            let cos_theta = photon.direction.dot(hit_record.normal).abs();
+            if cos_theta > 0. {
+                let value = hit_record
+                    .material
+                    .eval(&self.ray, hit_record, photon.direction);
+                flux += value / cos_theta * self.ray.radiance(photon.power);
+            }
+        });
+        (flux, count)
+    }
+}
+
+/// Follow `ray` through mirrors and glass to the first surface that photons can be gathered at,
+/// with the light seen on the way and the light reaching that surface straight from the lights.
+pub fn visible_point(ray: &Ray, scene: &Scene, max_depth: isize) -> (Vec3, Option<VisiblePoint>) {
+    let (mut ray, mut beta, mut color) = (*ray, Vec3::new(1., 1., 1.), Vec3::default());
+    for _ in 0..max_depth {
+        let hit_record = scene.hit(&ray, 0.001, f64::INFINITY);
+        let t_max = hit_record.as_ref().map_or(f64::INFINITY, |hit| hit.time);
+        for (_, radiance) in nearest_lights(&ray, scene, t_max) {
+            color += beta * radiance;
+        }
+        let Some(hit_record) = hit_record else {
+            return (color + beta * scene.background.radiance(&ray), None);
+        };
+
+        let hit_record = hit_record.with_shading(&ray);
+        let material = &hit_record.material;
+        color += beta * material.emitted(&ray, &hit_record);
+        if material.flags(&hit_record).non_delta {
+            let is_in_medium = matches!(**material, Material::Isotropic { .. });
+            let normal = match is_in_medium {
+                true => Vec3::default(),
+                false => hit_record.normal,
+            };
+            color += beta
+                * sample_light(
+                    &ray,
+                    &hit_record,
+                    normal,
+                    scene,
+                    &MediumStack::default(),
+                    None,
+                );
+            let point = (!is_in_medium).then_some(VisiblePoint {
+                hit_record,
+                ray,
+                beta,
+            });
+            return (color, point);
+        }
+
+        let Some(sample) = material.sample(&ray, &hit_record) else {
+            return (color, None);
+        };
+        if !hit_record.agrees_with_geometry(sample.ray.direction) {
+            return (color, None);
+        }
+        beta = beta * sample.weight;
+        ray = sample.ray;
+    }
+    (color, None)
+}
+
+/// Light reaching the camera along `ray`, with the density of `photons` within `radius` of where
+/// it lands standing in for light that bounced on its way from the lights.
+pub fn photon_color(
+    ray: &Ray,
+    scene: &Scene,
+    photons: &PhotonMap,
+    radius: f64,
+    max_depth: isize,
+) -> Vec3 {
+    let (color, point) = visible_point(ray, scene, max_depth);
+    let Some(point) = point else {
+        return color;
+    };
+    let (flux, _) = point.gather(photons, radius);
+    color + point.beta * flux / (PI * radius * radius * photons.emitted.max(1) as f64)
+}
+
+/// What stochastic progressive photon mapping knows of a pixel after some passes.
+#[derive(Clone, Debug)]
+pub struct ProgressivePixel {
+    /// Photons are gathered within it.
+    pub radius: f64,
+    /// Photons counted so far, fewer than were found as the radius shrinks.
+    pub count: f64,
+    /// The flux of the photons counted, scaled as those found are.
+    pub flux: Vec3,
+    /// Light found without photons, summed over passes.
+    pub direct: Vec3,
+}
+
+impl ProgressivePixel {
+    pub fn new(radius: f64) -> Self {
+        Self {
+            radius,
+            count: 0.,
+            flux: Vec3::default(),
+            direct: Vec3::default(),
+        }
+    }
+
+    /// Add the `count` photons with `flux` found in a pass, keeping the fraction `alpha` of
+    /// them and shrinking the radius to match.
+    pub fn update(&mut self, flux: Vec3, count: usize, alpha: f64) {
+        if count == 0 {
+            return;
+        }
+        let kept = self.count + alpha * count as f64;
+        let shrink = kept / (self.count + count as f64);
+        self.radius *= shrink.sqrt();
+        self.flux = (self.flux + flux) * shrink;
+        self.count = kept;
+    }
+
+    /// The radiance after `passes` passes of `photons_per_pass` photons.
+    pub fn radiance(&self, passes: usize, photons_per_pass: usize) -> Vec3 {
+        let emitted = (passes * photons_per_pass).max(1) as f64;
+        self.direct / passes.max(1) as f64 + self.flux / (PI * self.radius * self.radius * emitted)
+    }
+}
+
+/// Render by stochastic progressive photon mapping, with `render_config.integrator` set to
+/// `ProgressivePhotonMapping`: every pass traces a sample per pixel and a new photon map.
+pub fn progressive_photon_mapping(
+    image: &Image,
+    camera: &Camera,
+    scene: &Scene,
+    render_config: &RenderConfig,
+    progress_bar: ProgressBar,
+) -> Vec<Pixel> {
+    let Integrator::ProgressivePhotonMapping {
+        photons_per_pass,
+        radius,
+        alpha,
+    } = render_config.integrator
+    else {
+        panic!("Progressive photon mapping needs its integrator to be selected.");
+    };
+    let passes = render_config.samples_per_pixel;
+    let pixel_count = image.width * image.height;
+    progress_bar.set_length((pixel_count * passes) as u64);
+
+    // In the order they are written, from the top row down.
+    let mut pixels = vec![ProgressivePixel::new(radius); pixel_count];
+    for _ in 0..passes {
+        let photons = PhotonMap::trace(scene, photons_per_pass, render_config.max_depth);
+
+        #[cfg(feature = "parallel")]
+        let pixel_iter = pixels.par_iter_mut();
+        #[cfg(not(feature = "parallel"))]
+        let pixel_iter = pixels.iter_mut();
+
+        pixel_iter.enumerate().for_each(|(index, pixel)| {
+            let (row, col) = (image.height - index / image.width - 1, index % image.width);
+            let (ray, wavelengths) = pixel_ray(row, col, camera, image, render_config);
+            let to_rgb = |color: Vec3| match &wavelengths {
+                Some(wavelengths) => wavelengths.to_rgb(color),
+                None => color,
+            };
+
+            let (color, point) = visible_point(&ray, scene, render_config.max_depth);
+            pixel.direct += to_rgb(color);
+            if let Some(point) = point {
+                let (flux, count) = point.gather(&photons, pixel.radius);
+                pixel.update(to_rgb(point.beta * flux), count, alpha);
+            }
+        });
+        progress_bar.inc(pixel_count as u64);
+    }
+
+    pixels
+        .iter()
+        .map(|pixel| gamma2_correct(pixel.radiance(passes, photons_per_pass), 2).into())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{photon_color, progressive_photon_mapping, Photon, PhotonMap, ProgressivePixel};
+    use crate::prelude::test_scenes::{camera, glass_ball, room};
+    use crate::prelude::{
+        bidirectional_color, luminance, progress_bars, Camera, Image, Integrator, LinAlgRandGen,
+        Point, PointLight, RenderConfig, Scene, Vec3,
+    };
+    use rand::{thread_rng, Rng};
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    #[test]
+    fn finds_the_photons_within_a_radius() {
+        let mut rng = thread_rng();
+        let photons = (0..2000)
+            .map(|_| Photon {
+                point: Vec3::random_in_unit_disk() + Vec3::new(0., 0., rng.gen()),
+                direction: Vec3::new(0., 1., 0.),
+                normal: Vec3::new(0., 1., 0.),
+                power: Vec3::new(1., 1., 1.),
+            })
+            .collect::<Vec<_>>();
+        let map = PhotonMap::new(photons.clone(), photons.len());
+        assert_eq!(map.len(), photons.len());
+
+        for _ in 0..50 {
+            let (point, radius) = (Vec3::random_in_unit_disk(), rng.gen::<f64>() * 0.3);
+            let mut found = vec![];
+            map.within(point, radius, |photon| found.push(photon.point));
+            let mut expected = photons
+                .iter()
+                .map(|photon| photon.point)
+                .filter(|p| (*p - point).norm() <= radius)
+                .collect::<Vec<_>>();
+            let order = |a: &Vec3, b: &Vec3| (a.0, a.1, a.2).partial_cmp(&(b.0, b.1, b.2)).unwrap();
+            found.sort_by(order);
+            expected.sort_by(order);
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn shrinking_keeps_the_density() {
+        // Photons found in proportion to the area they are gathered from.
+        let (density, mut pixel) = (1000., ProgressivePixel::new(0.1));
+        for passes in 1..=20 {
+            let count = (density * PI * pixel.radius * pixel.radius).round() as usize;
+            pixel.update(Vec3::new(1., 1., 1.) * count as f64, count, 0.7);
+            let estimate = pixel.flux.0 / (PI * pixel.radius * pixel.radius * passes as f64);
+            assert!((estimate - density).abs() < 0.05 * density, "{}", estimate);
+        }
+        assert!(pixel.radius < 0.07, "{}", pixel.radius);
+    }
+
+    /// A glass ball over a white floor in a black room, lit from above, and a camera looking at
+    /// the light it focuses.
+    fn caustic() -> (Scene, Camera) {
+        let scene = room(
+            0.7,
+            vec![glass_ball(1.5)],
+            Arc::new(PointLight::new(
+                Point::new(0., 3., -1.5),
+                Vec3::new(1., 1., 1.),
+                0.,
+            )),
+        );
+        let camera = camera(Point::new(0., 2., 4.), Point::new(0., 0., 0.75), 12.);
+        (scene, camera)
+    }
+
+    /// The average luminance over the viewport, found bidirectionally. Only light tracing reaches
+    /// the caustic, so this is good to a few percent.
+    fn reference(scene: &Scene, camera: &Camera) -> f64 {
+        let (samples, mut rng) = (100_000, thread_rng());
+        let (mut total, mut splats) = (0., 0.);
+        for _ in 0..samples {
+            let ray = camera.get_ray(rng.gen(), rng.gen());
+            total += luminance(bidirectional_color(
+                &ray,
+                camera,
+                scene,
+                6,
+                &mut |(s, t), color| {
+                    if (0. ..1.).contains(&s) && (0. ..1.).contains(&t) {
+                        splats += luminance(color);
+                    }
+                },
+            ));
+        }
+        (total + splats) / samples as f64
+    }
+
+    #[test]
+    fn finds_caustics() {
+        let (scene, camera) = caustic();
+        let reference = reference(&scene, &camera);
+
+        let photons = PhotonMap::trace(&scene, 500_000, 6);
+        let (samples, mut rng) = (20_000, thread_rng());
+        let estimate = (0..samples)
+            .map(|_| {
+                let ray = camera.get_ray(rng.gen(), rng.gen());
+                luminance(photon_color(&ray, &scene, &photons, 0.03, 6))
+            })
+            .sum::<f64>()
+            / samples as f64;
+        assert!(
+            (estimate - reference).abs() < 0.15 * reference,
+            "{} and {}",
+            estimate,
+            reference
+        );
+
+        let image = Image::new(24, 1.);
+        let render_config = RenderConfig {
+            integrator: Integrator::ProgressivePhotonMapping {
+                photons_per_pass: 50_000,
+                radius: 0.1,
+                alpha: 0.7,
+            },
+            ..RenderConfig::new(16, 6)
+        };
+        let pixels = progressive_photon_mapping(
+            &image,
+            &camera,
+            &scene,
+            &render_config,
+            progress_bars::hidden(),
+        );
+        let estimate = pixels
+            .iter()
+            .map(|pixel| {
+                let color = Vec3::from(*pixel);
+                luminance(color * color)
+            })
+            .sum::<f64>()
+            / pixels.len() as f64;
+        assert!(
+            (estimate - reference).abs() < 0.15 * reference,
+            "{} and {}",
+            estimate,
+            reference
+        );
+    }
+}
@@ -0,0 +1,527 @@
+use crate::prelude::{
+    fresnel_dielectric, ggx_d, luminance, refract_through, relative_eta, rng, roughness_to_alpha,
+    sample_visible_normal, smith_g1, smith_g2, Bsdf, BsdfFlags, BsdfSample, HitRecord, LinAlgOp,
+    LinAlgRandGen, Onb, Ray, Scatter, SolidColor, Texture, Vec3,
+};
+use rand::Rng;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A Disney-style "uber" material that blends diffuse, metallic, glossy, sheen, clearcoat and
+/// glass behaviour from a handful of artist friendly parameters.
+#[derive(Clone, Debug)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    /// Blends from a dielectric (0) to a metal tinted by the base color (1).
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// Dielectric reflectance at normal incidence, scaled so that 0.5 is 4%.
+    pub specular: Arc<dyn Texture>,
+    /// Color of the soft retroreflective rim of cloth-like surfaces.
+    pub sheen: Arc<dyn Texture>,
+    /// Strength of a clear varnish layer over everything else.
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    /// Blends the dielectric part from opaque (0) to glass (1).
+    pub transmission: Arc<dyn Texture>,
+    /// Index of refraction of the glass part.
+    pub index_of_refraction: Arc<dyn Texture>,
+    pub emission: Arc<dyn Texture>,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: Arc::new(SolidColor::gray(0.8)),
+            metallic: Arc::new(SolidColor::gray(0.)),
+            roughness: Arc::new(SolidColor::gray(0.5)),
+            specular: Arc::new(SolidColor::gray(0.5)),
+            sheen: Arc::new(SolidColor::gray(0.)),
+            clearcoat: Arc::new(SolidColor::gray(0.)),
+            clearcoat_roughness: Arc::new(SolidColor::gray(0.1)),
+            transmission: Arc::new(SolidColor::gray(0.)),
+            index_of_refraction: Arc::new(SolidColor::gray(1.5)),
+            emission: Arc::new(SolidColor::gray(0.)),
+        }
+    }
+}
+
+/// The lobes of a `Principled` material at one point, in the local frame of the shading normal.
+#[derive(Clone, Debug)]
+pub struct PrincipledLobes {
+    base_color: Vec3,
+    /// Normal incidence reflectance of the specular layer over the diffuse lobe.
+    dielectric_reflectance: f64,
+    alpha: f64,
+    sheen: Vec3,
+    /// Reflectance at normal incidence of the opaque specular lobe.
+    specular_color: Vec3,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    /// Relative index of refraction across the surface, from the side being shaded.
+    eta: f64,
+    /// Color picked up by light crossing the glass lobe's boundary.
+    transmission_tint: Vec3,
+    /// Mixture weights of the diffuse, specular, glass and clearcoat lobes.
+    weights: [f64; 4],
+    /// Probabilities of sampling each lobe.
+    probabilities: [f64; 4],
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const GLASS: usize = 2;
+const CLEARCOAT: usize = 3;
+
+impl Principled {
+    /// Evaluate the textures at a hit, for light leaving along the local direction `wo`.
+    pub fn lobes(&self, hit_record: &HitRecord, wo: Vec3) -> PrincipledLobes {
+        let base_color = self.base_color.value_at(hit_record);
+        let metallic = self.metallic.scalar_at(hit_record).clamp(0., 1.);
+        let roughness = self.roughness.scalar_at(hit_record).clamp(0., 1.);
+        let specular = self.specular.scalar_at(hit_record).max(0.);
+        let transmission = self.transmission.scalar_at(hit_record).clamp(0., 1.);
+        let clearcoat = self.clearcoat.scalar_at(hit_record).max(0.);
+        let index_of_refraction = self.index_of_refraction.scalar_at(hit_record);
+
+        let dielectric_reflectance = (0.08 * specular).min(1.);
+        let specular_color = (1. - metallic) * dielectric_reflectance * Vec3::new(1., 1., 1.)
+            + metallic * base_color;
+        let sheen = self.sheen.value_at(hit_record);
+
+        // Light inside the object only ever meets the glass boundary.
+        let weights = if hit_record.is_front_facing {
+            [
+                (1. - metallic) * (1. - transmission),
+                1. - (1. - metallic) * transmission,
+                (1. - metallic) * transmission,
+                0.25 * clearcoat,
+            ]
+        } else {
+            [0., 0., 1., 0.]
+        };
+
+        let mut probabilities = [
+            weights[DIFFUSE] * (luminance(base_color) + luminance(sheen)),
+            weights[SPECULAR] * luminance(schlick(specular_color, wo.2)),
+            weights[GLASS],
+            weights[CLEARCOAT] * luminance(schlick(Vec3::new(0.04, 0.04, 0.04), wo.2)),
+        ];
+        let total: f64 = probabilities.iter().sum();
+        if total > 0. {
+            probabilities.iter_mut().for_each(|p| *p /= total);
+        }
+
+        PrincipledLobes {
+            base_color,
+            dielectric_reflectance,
+            alpha: roughness_to_alpha(roughness),
+            sheen,
+            specular_color,
+            clearcoat,
+            clearcoat_alpha: roughness_to_alpha(self.clearcoat_roughness.scalar_at(hit_record)),
+            eta: relative_eta(index_of_refraction, hit_record),
+            // Applied on the way in and again on the way out.
+            transmission_tint: Vec3::new(
+                base_color.0.max(0.).sqrt(),
+                base_color.1.max(0.).sqrt(),
+                base_color.2.max(0.).sqrt(),
+            ),
+            weights,
+            probabilities,
+        }
+    }
+}
+
+impl PrincipledLobes {
+    /// Pick a lobe and sample a local direction `wi` from it.
+    pub fn sample(&self, wo: Vec3) -> Option<Vec3> {
+        let mut rng = rng();
+        let mut choice = rng.gen::<f64>();
+        let lobe = (0..4)
+            .find(|&lobe| {
+                choice -= self.probabilities[lobe];
+                choice < 0. && self.probabilities[lobe] > 0.
+            })
+            .or_else(|| (0..4).rev().find(|&lobe| self.probabilities[lobe] > 0.))?;
+
+        let is_reflection = lobe != GLASS;
+        let wi = match lobe {
+            DIFFUSE => {
+                let wi = Vec3::new(0., 0., 1.) + Vec3::random_unit_vector();
+                if wi.near_zero() {
+                    Vec3::new(0., 0., 1.)
+                } else {
+                    wi.unit_vector()
+                }
+            }
+            SPECULAR => {
+                let microfacet = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+                (-wo).reflect(microfacet)
+            }
+            GLASS => {
+                let microfacet = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+                let wi = match fresnel_dielectric(wo.dot(microfacet), self.eta) > rng.gen() {
+                    true => (-wo).reflect(microfacet),
+                    false => refract_through(wo, microfacet, self.eta)?,
+                };
+                // A refraction that stays above the surface is judged by its reflection rules.
+                if (wi.2 > 0.) != (wi.dot(microfacet) > 0.) {
+                    return None;
+                }
+                return Some(wi);
+            }
+            _ => {
+                let microfacet =
+                    sample_visible_normal(wo, self.clearcoat_alpha, rng.gen(), rng.gen());
+                (-wo).reflect(microfacet)
+            }
+        };
+
+        // Directions that leave through the wrong side of the macrosurface are absorbed; the
+        // lobe's density there is not part of `evaluate`'s.
+        match is_reflection && wi.2 <= 0. {
+            true => None,
+            false => Some(wi),
+        }
+    }
+
+    /// The value of the BSDF and the probability density of sampling `wi`, summed over the lobes.
+    pub fn evaluate(&self, wo: Vec3, wi: Vec3) -> (Vec3, f64) {
+        let mut value = Vec3::default();
+        let mut pdf = 0.;
+        if wo.2 <= 0. {
+            return (value, pdf);
+        }
+
+        if wi.2 > 0. {
+            let half = (wo + wi).unit_vector();
+            let cos_d = wi.dot(half);
+
+            if self.weights[DIFFUSE] > 0. {
+                // Lambertian, minus what the specular layer on top reflects on the way in and
+                // on the way out, so that the two together never create energy.
+                let layer = |cosine: f64| {
+                    1. - self.dielectric_reflectance
+                        - (1. - self.dielectric_reflectance) * schlick_weight(cosine)
+                };
+                let diffuse = layer(wi.2) * layer(wo.2);
+                value += self.weights[DIFFUSE]
+                    * (self.base_color / PI * diffuse + self.sheen * schlick_weight(cos_d));
+                pdf += self.probabilities[DIFFUSE] * wi.2 / PI;
+            }
+
+            let mut glossy = |weight: f64, probability: f64, alpha: f64, color: Vec3| {
+                let d = ggx_d(half, alpha);
+                value += weight * d * smith_g2(wo, wi, alpha) / (4. * wi.2 * wo.2)
+                    * schlick(color, cos_d);
+                pdf += probability * smith_g1(wo, alpha) * d / (4. * wo.2);
+            };
+            if self.weights[SPECULAR] > 0. {
+                glossy(
+                    self.weights[SPECULAR],
+                    self.probabilities[SPECULAR],
+                    self.alpha,
+                    self.specular_color,
+                );
+            }
+            if self.weights[CLEARCOAT] > 0. {
+                glossy(
+                    self.weights[CLEARCOAT] * self.clearcoat,
+                    self.probabilities[CLEARCOAT],
+                    self.clearcoat_alpha,
+                    Vec3::new(0.04, 0.04, 0.04),
+                );
+            }
+        }
+
+        if self.weights[GLASS] > 0. {
+            let (glass_value, glass_pdf) = self.evaluate_glass(wo, wi);
+            value += self.weights[GLASS] * glass_value;
+            pdf += self.probabilities[GLASS] * glass_pdf;
+        }
+
+        (value, pdf)
+    }
+
+    /// A rough dielectric interface (Walter et al., "Microfacet Models for Refraction through
+    /// Rough Surfaces", 2007).
+    fn evaluate_glass(&self, wo: Vec3, wi: Vec3) -> (Vec3, f64) {
+        let is_reflection = wi.2 > 0.;
+        let half = if is_reflection {
+            wo + wi
+        } else {
+            wo + self.eta * wi
+        };
+        if half.near_zero() || wi.2 == 0. {
+            return (Vec3::default(), 0.);
+        }
+        let mut half = half.unit_vector();
+        if half.2 < 0. {
+            half = -half;
+        }
+        // Microfacets facing away from either direction cannot connect them.
+        if half.dot(wi) * wi.2 < 0. || half.dot(wo) <= 0. {
+            return (Vec3::default(), 0.);
+        }
+
+        let reflectance = fresnel_dielectric(wo.dot(half), self.eta);
+        let d = ggx_d(half, self.alpha);
+        let g2 = smith_g2(wo, wi, self.alpha);
+        let visible = smith_g1(wo, self.alpha) * d * wo.dot(half) / wo.2;
+
+        if is_reflection {
+            let value = reflectance * d * g2 / (4. * wi.2 * wo.2);
+            (
+                Vec3::new(value, value, value),
+                reflectance * visible / (4. * wo.dot(half)),
+            )
+        } else {
+            let denominator = (wi.dot(half) + wo.dot(half) / self.eta).powi(2);
+            let value = d * g2 * (1. - reflectance) * (wi.dot(half) * wo.dot(half)).abs()
+                / (wi.2.abs() * wo.2 * denominator);
+            (
+                value * self.transmission_tint,
+                (1. - reflectance) * visible * wi.dot(half).abs() / denominator,
+            )
+        }
+    }
+}
+
+impl Bsdf for Principled {
+    fn sample(&self, ray: &Ray, hit_record: &HitRecord) -> Option<BsdfSample> {
+        let frame = Onb::new(hit_record.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        if wo.2 <= 0. {
+            return None;
+        }
+
+        let lobes = self.lobes(hit_record, wo);
+        let wi = lobes.sample(wo)?;
+        let (value, pdf) = lobes.evaluate(wo, wi);
+        if pdf <= 0. {
+            return None;
+        }
+
+        Some(BsdfSample::new(
+            ray.reflectance(value * (wi.2.abs() / pdf)),
+            ray.spawn(&hit_record.point, &frame.local(wi)),
+            pdf,
+        ))
+    }
+
+    fn eval(&self, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> Vec3 {
+        let frame = Onb::new(hit_record.normal);
+        let (wo, wi) = (
+            frame.to_local(-ray.direction.unit_vector()),
+            frame.to_local(wi),
+        );
+        if wo.2 <= 0. {
+            return Vec3::default();
+        }
+        let (value, _) = self.lobes(hit_record, wo).evaluate(wo, wi);
+        ray.reflectance(value * wi.2.abs())
+    }
+
+    fn pdf(&self, ray: &Ray, hit_record: &HitRecord, wi: Vec3) -> f64 {
+        let frame = Onb::new(hit_record.normal);
+        let (wo, wi) = (
+            frame.to_local(-ray.direction.unit_vector()),
+            frame.to_local(wi),
+        );
+        if wo.2 <= 0. {
+            return 0.;
+        }
+        self.lobes(hit_record, wo).evaluate(wo, wi).1
+    }
+
+    fn flags(&self, _hit_record: &HitRecord) -> BsdfFlags {
+        BsdfFlags::NON_DELTA
+    }
+}
+
+impl Scatter for Principled {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Ray)> {
+        self.sample(ray, hit_record)
+            .map(|sample| (sample.weight, sample.ray))
+    }
+
+    fn emitted(&self, ray: &Ray, hit_record: &HitRecord) -> Vec3 {
+        match hit_record.is_front_facing {
+            true => ray.radiance(self.emission.value_at(hit_record)),
+            false => Vec3::default(),
+        }
+    }
+}
+
+fn schlick_weight(cosine: f64) -> f64 {
+    (1. - cosine.clamp(0., 1.)).powi(5)
+}
+
+fn schlick(normal_incidence: Vec3, cosine: f64) -> Vec3 {
+    normal_incidence + schlick_weight(cosine) * (Vec3::new(1., 1., 1.) - normal_incidence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Principled;
+    use crate::prelude::{
+        Hittable, LinAlgOp, Material, Onb, Point, Ray, Scatter, SolidColor, Sphere, Vec3,
+    };
+    use rand::{thread_rng, Rng};
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    const SAMPLES: usize = 200_000;
+
+    fn materials() -> Vec<Principled> {
+        let plastic = Principled::default();
+        let metal = Principled {
+            base_color: Arc::new(SolidColor::new(Vec3::new(0.9, 0.6, 0.3))),
+            metallic: Arc::new(SolidColor::gray(1.)),
+            roughness: Arc::new(SolidColor::gray(0.4)),
+            ..Principled::default()
+        };
+        let frosted = Principled {
+            transmission: Arc::new(SolidColor::gray(0.8)),
+            roughness: Arc::new(SolidColor::gray(0.6)),
+            ..Principled::default()
+        };
+        let velvet = Principled {
+            base_color: Arc::new(SolidColor::new(Vec3::new(0.5, 0.1, 0.1))),
+            sheen: Arc::new(SolidColor::gray(0.5)),
+            clearcoat: Arc::new(SolidColor::gray(1.)),
+            clearcoat_roughness: Arc::new(SolidColor::gray(0.4)),
+            roughness: Arc::new(SolidColor::gray(1.)),
+            ..Principled::default()
+        };
+        vec![plastic, metal, frosted, velvet]
+    }
+
+    /// A hit on the top of a unit sphere, seen from `direction`'s opposite, and the local `wo`.
+    fn hit(material: Principled, direction: Vec3) -> (Ray, crate::prelude::HitRecord, Vec3) {
+        let sphere = Sphere::new(
+            Point::new(0., 0., 0.),
+            1.,
+            Arc::new(Material::Principled(Box::new(material))),
+        );
+        let ray = Ray::new(&(Point::new(0., 0., 1.) - direction), &direction);
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let wo = Onb::new(hit.normal).to_local(-direction.unit_vector());
+        (ray, hit, wo)
+    }
+
+    #[test]
+    fn sampling_agrees_with_evaluation() {
+        // The albedo found by importance sampling must match a brute force estimate with
+        // directions spread evenly over the sphere, which only holds if the PDFs are right.
+        let mut rng = thread_rng();
+        for material in materials() {
+            let (ray, hit_record, wo) = hit(material.clone(), Vec3::new(0.6, 0., -0.8));
+            let lobes = material.lobes(&hit_record, wo);
+
+            // Importance sampled, with the sum of squares for the standard error.
+            let (mut sampled, mut squares) = (Vec3::default(), Vec3::default());
+            for _ in 0..SAMPLES {
+                if let Some((weight, _)) = material.scatter(&ray, &hit_record) {
+                    sampled += weight;
+                    squares += weight * weight;
+                }
+            }
+            let n = SAMPLES as f64;
+            let sampled = sampled / n;
+            let variance = squares / n - sampled * sampled;
+
+            // Over directions stratified on the sphere, which find the peaks of glossy lobes far
+            // more reliably than independent ones.
+            let (rows, columns) = (400, SAMPLES / 400);
+            let mut uniform = Vec3::default();
+            let mut pdf_integral = 0.;
+            for row in 0..rows {
+                for column in 0..columns {
+                    let cos_theta = -1. + 2. * (row as f64 + rng.gen::<f64>()) / rows as f64;
+                    let phi = 2. * PI * (column as f64 + rng.gen::<f64>()) / columns as f64;
+                    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+                    let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                    let (value, pdf) = lobes.evaluate(wo, wi);
+                    uniform += value * (4. * PI * cos_theta.abs());
+                    pdf_integral += 4. * PI * pdf;
+                }
+            }
+            let (uniform, pdf_integral) = (uniform / n, pdf_integral / n);
+
+            // Five standard errors of the sampled estimate, with a little room for the error of
+            // the stratified one, which is an order of magnitude smaller.
+            let difference = sampled - uniform;
+            for channel in 0..3 {
+                let error = 5. * (variance[channel] / n).sqrt() + 1e-3;
+                assert!(
+                    difference[channel].abs() < error,
+                    "sampled {} uniform {}",
+                    sampled,
+                    uniform
+                );
+            }
+
+            // Stratified, the integral of the pdf varies by well under 1e-3 between runs.
+            assert!(
+                pdf_integral < 1.005 && pdf_integral > 0.9,
+                "{}",
+                pdf_integral
+            );
+        }
+    }
+
+    #[test]
+    fn white_furnace() {
+        let white = Principled {
+            base_color: Arc::new(SolidColor::gray(1.)),
+            ..Principled::default()
+        };
+        let white_metal = Principled {
+            metallic: Arc::new(SolidColor::gray(1.)),
+            ..white.clone()
+        };
+
+        for material in [white, white_metal] {
+            for cos_theta in [1_f64, 0.5, 0.1] {
+                let direction = Vec3::new((1. - cos_theta * cos_theta).sqrt(), 0., -cos_theta);
+                let (ray, hit_record, _) = hit(material.clone(), direction);
+
+                let (mut total, mut squares) = (Vec3::default(), Vec3::default());
+                for _ in 0..SAMPLES {
+                    if let Some((weight, _)) = material.scatter(&ray, &hit_record) {
+                        total += weight;
+                        squares += weight * weight;
+                    }
+                }
+                let albedo = total / SAMPLES as f64;
+                // Four standard errors of the estimate above one.
+                let variance = squares / SAMPLES as f64 - albedo * albedo;
+                let error =
+                    4. * (variance.0.max(variance.1).max(variance.2) / SAMPLES as f64).sqrt();
+                assert!(
+                    [albedo.0, albedo.1, albedo.2]
+                        .iter()
+                        .all(|&channel| channel <= 1. + error),
+                    "{} at {}",
+                    albedo,
+                    cos_theta
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn emission_is_texturable() {
+        let glowing = Principled {
+            emission: Arc::new(SolidColor::new(Vec3::new(2., 1., 0.))),
+            ..Principled::default()
+        };
+        let (ray, hit_record, _) = hit(glowing, Vec3::new(0., 0., -1.));
+        assert_eq!(
+            hit_record.material.emitted(&ray, &hit_record),
+            Vec3::new(2., 1., 0.)
+        );
+    }
+}
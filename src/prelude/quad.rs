@@ -0,0 +1,207 @@
+use crate::prelude::{
+    HitRecord, Hittable, LinAlgOp, Material, Point, Ray, SurfaceTangents, UvDerivatives, Vec3,
+};
+use std::sync::Arc;
+
+/// A parallelogram spanned by the edges `u` and `v` from `corner`, textured over its area with
+/// coordinates running from 0 to 1 along each edge.
+///
+/// The outward normal is `u × v`.
+pub struct Quad {
+    pub corner: Point,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<Material>,
+}
+
+impl Quad {
+    pub fn new(corner: Point, u: Vec3, v: Vec3, material: Arc<Material>) -> Self {
+        Self {
+            corner,
+            u,
+            v,
+            material,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let normal = self.u.cross(self.v);
+        let denominator = normal.dot(ray.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+
+        let time = normal.dot(self.corner - ray.origin) / denominator;
+        if time < t_min || time > t_max {
+            return None;
+        }
+
+        // Coordinates of the hit along each edge.
+        let point = ray.at(time);
+        let offset = point - self.corner;
+        let w = normal / normal.norm_squared();
+        let alpha = w.dot(offset.cross(self.v));
+        let beta = w.dot(self.u.cross(offset));
+        if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+            return None;
+        }
+
+        let outward_facing_normal = normal.unit_vector();
+        let is_front_facing = denominator < 0.;
+        let geometric_normal = if is_front_facing {
+            outward_facing_normal
+        } else {
+            -outward_facing_normal
+        };
+
+        Some(HitRecord {
+            point,
+            normal: geometric_normal,
+            geometric_normal,
+            time,
+            is_front_facing,
+            u: alpha,
+            v: beta,
+            uv_derivatives: UvDerivatives::from_ray(
+                ray,
+                point,
+                outward_facing_normal,
+                self.u,
+                self.v,
+            ),
+            tangents: Some(SurfaceTangents {
+                dpdu: self.u,
+                dpdv: self.v,
+            }),
+            material: self.material.clone(),
+            interior: None,
+            surrounding_ior: 1.,
+        })
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "Quad {{ corner: {}, u: {}, v: {} }}",
+            self.corner, self.u, self.v
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quad;
+    use crate::prelude::{
+        CheckerUv, Csg, Hittable, HittableList, Material, Point, Ray, SdfHittable, SdfSphere,
+        SolidColor, Sphere, Texture, Vec3,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn masked_out_texels_let_rays_through() {
+        // Opaque and cut out squares alternating over a leaf in front of a wall.
+        let mask: Arc<dyn Texture> = Arc::new(CheckerUv::new(
+            Arc::new(SolidColor::gray(1.)),
+            Arc::new(SolidColor::gray(0.)),
+            2.,
+            2.,
+        ));
+        let leaf = Material::Masked {
+            base: Arc::new(Material::lambertian(Vec3::new(0.2, 0.6, 0.1))),
+            opacity: mask,
+        };
+        let leaf = Quad::new(
+            Point::new(0., 0., 1.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 2., 0.),
+            Arc::new(leaf),
+        );
+        let wall = Quad::new(
+            Point::new(-5., -5., 0.),
+            Vec3::new(10., 0., 0.),
+            Vec3::new(0., 10., 0.),
+            Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5))),
+        );
+        let mut world = HittableList::new();
+        world.push(Arc::new(leaf));
+        world.push(Arc::new(wall));
+
+        let down = Vec3::new(0., 0., -1.);
+        let opaque = Ray::new(&Point::new(0.5, 0.5, 5.), &down);
+        assert!((world.hit_opaque(&opaque, 1e-3, f64::INFINITY).unwrap().time - 4.).abs() < 1e-9);
+
+        let cut_out = Ray::new(&Point::new(1.5, 0.5, 5.), &down);
+        assert!(
+            (world
+                .hit_opaque(&cut_out, 1e-3, f64::INFINITY)
+                .unwrap()
+                .time
+                - 5.)
+                .abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn partial_opacity_is_stochastic() {
+        let veil = Material::Masked {
+            base: Arc::new(Material::lambertian(Vec3::new(1., 1., 1.))),
+            opacity: Arc::new(SolidColor::gray(0.3)),
+        };
+        let veil = Quad::new(
+            Point::new(-1., -1., 0.),
+            Vec3::new(2., 0., 0.),
+            Vec3::new(0., 2., 0.),
+            Arc::new(veil),
+        );
+
+        let ray = Ray::new(&Point::new(0., 0., 1.), &Vec3::new(0., 0., -1.));
+        let trials = 20_000;
+        let hits = (0..trials)
+            .filter(|_| veil.hit_opaque(&ray, 1e-3, f64::INFINITY).is_some())
+            .count();
+        assert!((hits as f64 / trials as f64 - 0.3).abs() < 0.02);
+    }
+
+    #[test]
+    fn masks_cut_holes_in_any_object() {
+        let hidden = Arc::new(Material::Masked {
+            base: Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5))),
+            opacity: Arc::new(SolidColor::gray(0.)),
+        });
+        let wall = Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5)));
+        let ray = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -1.));
+
+        // A sphere, whose far side must be seen through too, one traced as a distance field and a
+        // union of two. Each hides a wall behind it unless its mask is applied.
+        let objects: [Arc<dyn Hittable>; 3] = [
+            Arc::new(Sphere::new(Point::new(0., 0., 0.), 1., hidden.clone())),
+            Arc::new(SdfHittable::new(
+                Arc::new(SdfSphere {
+                    center: Point::new(0., 0., 0.),
+                    radius: 1.,
+                }),
+                hidden.clone(),
+            )),
+            Arc::new(Csg::union(
+                Arc::new(Sphere::new(Point::new(0., 0., 0.3), 1., hidden.clone())),
+                Arc::new(Sphere::new(Point::new(0., 0., -0.3), 1., hidden.clone())),
+            )),
+        ];
+        for object in objects {
+            assert!(object.hit(&ray, 1e-3, f64::INFINITY).is_some());
+
+            let mut world = HittableList::new();
+            world.push(object);
+            world.push(Arc::new(Quad::new(
+                Point::new(-5., -5., -3.),
+                Vec3::new(10., 0., 0.),
+                Vec3::new(0., 10., 0.),
+                wall.clone(),
+            )));
+            let hit_record = world.hit_opaque(&ray, 1e-3, f64::INFINITY).unwrap();
+            assert!((hit_record.time - 8.).abs() < 1e-9);
+        }
+    }
+}
@@ -5,6 +5,19 @@ use std::fmt::{Display, Formatter};
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Point,
+    /// Rays through the neighbouring pixels, if known. Used to size texture filters.
+    pub differentials: Option<RayDifferentials>,
+    /// The wavelengths the ray carries in spectral mode, in nanometres, in place of RGB channels.
+    pub wavelengths: Option<[f64; 3]>,
+}
+
+/// Offset rays one pixel to the right (x) and one pixel up (y) of a camera ray.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RayDifferentials {
+    pub x_origin: Point,
+    pub x_direction: Vec3,
+    pub y_origin: Point,
+    pub y_direction: Vec3,
 }
 
 impl Default for Ray {
@@ -12,6 +25,8 @@ impl Default for Ray {
         Self {
             origin: Vec3::default(),
             direction: Vec3::default(),
+            differentials: None,
+            wavelengths: None,
         }
     }
 }
@@ -29,6 +44,8 @@ impl Ray {
         Self {
             origin: *origin,
             direction: *direction,
+            differentials: None,
+            wavelengths: None,
         }
     }
 
@@ -1,11 +1,52 @@
 use crate::prelude::*;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Copy)]
 pub struct RenderConfig {
     pub samples_per_pixel: usize,
     pub max_depth: isize,
+    /// Trace sampled wavelengths instead of RGB channels, see `SampledWavelengths`.
+    pub spectral: bool,
+    /// Render every object in a neutral gray `Lambertian`, see `Scene::clay`.
+    pub clay: bool,
+    pub integrator: Integrator,
+}
+
+/// How light reaching the camera is estimated.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Integrator {
+    /// Paths from the camera, see `ray_color`.
+    #[default]
+    PathTracer,
+    /// Paths from the camera joined to paths from the lights, see `bidirectional_color`.
+    Bidirectional,
+    /// Camera paths gathering `photons` traced from the lights within `radius` of where they
+    /// land, see `photon_color`.
+    PhotonMapping { photons: usize, radius: f64 },
+    /// One pass of photon mapping per sample, over which the radius each pixel gathers in
+    /// shrinks from `radius`, see `progressive_photon_mapping`. `alpha` in (0, 1) is the fraction
+    /// of each pass's photons kept, which trades noise for blur.
+    ProgressivePhotonMapping {
+        photons_per_pass: usize,
+        radius: f64,
+        alpha: f64,
+    },
+    /// Markov chains over the random numbers of `ray_color`, started from `bootstrap` paths
+    /// picked by the light they carry, see `metropolis`. Mutations move every number by about
+    /// `sigma`, or with `large_step_probability` draw them all anew.
+    Metropolis {
+        bootstrap: usize,
+        chains: usize,
+        large_step_probability: f64,
+        sigma: f64,
+    },
+    /// Paths from the camera that learn where light comes from over passes of doubling samples,
+    /// and sample directions by it in the passes after, see `guided_path_tracing`.
+    GuidedPathTracer,
+    /// How open the surroundings of what the camera sees are, by `samples` rays that count as
+    /// blocked when they hit anything within `radius`, see `ambient_occlusion_color`.
+    AmbientOcclusion { radius: f64, samples: usize },
 }
 
 impl Default for RenderConfig {
@@ -13,6 +54,9 @@ impl Default for RenderConfig {
         Self {
             samples_per_pixel: 100,
             max_depth: 100,
+            spectral: false,
+            clay: false,
+            integrator: Integrator::default(),
         }
     }
 }
@@ -22,6 +66,9 @@ impl RenderConfig {
         Self {
             samples_per_pixel,
             max_depth,
+            spectral: false,
+            clay: false,
+            integrator: Integrator::default(),
         }
     }
 }
@@ -36,51 +83,387 @@ pub fn gamma2_correct(color: Vec3, gamma: usize) -> Vec3 {
         .into()
 }
 
-pub fn ray_color(ray: &Ray, world: &HittableList, depth: isize) -> Vec3 {
+pub fn ray_color(ray: &Ray, scene: &Scene, depth: isize) -> Vec3 {
+    trace(ray, scene, depth, None, &MediumStack::default(), None)
+}
+
+/// `ray_color` with directions sampled by `guide` as well as by materials, see
+/// `guided_path_tracing`.
+pub(crate) fn guided_ray_color(ray: &Ray, scene: &Scene, depth: isize, guide: &SdTree) -> Vec3 {
+    trace(
+        ray,
+        scene,
+        depth,
+        None,
+        &MediumStack::default(),
+        Some(guide),
+    )
+}
+
+/// A bounce whose sampled direction a light sample could also have found.
+#[derive(Clone, Copy)]
+struct Bounce {
+    /// Where the direction was sampled, which rays going on through surfaces leave behind.
+    point: Point,
+    /// The density the direction was sampled with.
+    pdf: f64,
+    /// The surface normal lights were picked for, zero in media.
+    normal: Vec3,
+}
+
+/// Lights found by `ray` after `bounce` are weighted against having sampled them directly. The
+/// ray starts out inside `media`, and bounces sample `guide` too when there is one.
+fn trace(
+    ray: &Ray,
+    scene: &Scene,
+    depth: isize,
+    bounce: Option<Bounce>,
+    media: &MediumStack,
+    guide: Option<&SdTree>,
+) -> Vec3 {
     if depth <= 0 {
         return Vec3::new(0., 0., 0.);
     }
 
-    if let Some(hit_record) = world.hit(ray, 0.001, f64::INFINITY) {
-        return if let Some((attenuation, scattered)) = hit_record.material.scatter(ray, &hit_record)
-        {
-            attenuation * ray_color(&scattered, world, depth - 1)
-        } else {
-            Vec3::default()
-        };
+    let hit_record = scene.hit(ray, 0.001, f64::INFINITY);
+    let t_max = hit_record.as_ref().map_or(f64::INFINITY, |hit| hit.time);
+
+    let Some(medium) = media.medium() else {
+        let color = weigh_lights(nearest_lights(ray, scene, t_max), ray, scene, bounce);
+        return color + arrive(ray, hit_record, scene, depth, bounce, media, guide);
+    };
+
+    // Fly through the medium a light at a time, for lights inside it to be seen through it too.
+    let mut color = Vec3::default();
+    let mut weight = Vec3::new(1., 1., 1.);
+    let mut time = 0.001;
+    loop {
+        let lights = lights_between(ray, scene, time, t_max);
+        let end = lights.as_ref().map_or(t_max, |(time, _)| *time);
+        match medium.sample(ray, time, end) {
+            Flight::Scattered {
+                time,
+                weight: flight,
+            } => {
+                let collision = medium.collision(ray, time);
+                return color
+                    + weight * flight * scatter(ray, collision, scene, depth, media, guide);
+            }
+            Flight::Absorbed => return color,
+            Flight::Passed { weight: flight } => weight = weight * flight,
+        }
+        match lights {
+            Some((end, lights)) => {
+                color += weight * weigh_lights(lights, ray, scene, bounce);
+                time = end;
+            }
+            None => {
+                return color + weight * arrive(ray, hit_record, scene, depth, bounce, media, guide)
+            }
+        }
     }
+}
 
-    let unit_vector_in_direction_of_ray = ray.direction.unit_vector();
-    let time = 0.5 * (unit_vector_in_direction_of_ray.1 + 1.);
+/// Light from the surface `ray` reaches inside `media`, or from the background if it escapes.
+fn arrive(
+    ray: &Ray,
+    hit_record: Option<HitRecord>,
+    scene: &Scene,
+    depth: isize,
+    bounce: Option<Bounce>,
+    media: &MediumStack,
+    guide: Option<&SdTree>,
+) -> Vec3 {
+    let Some(mut hit_record) = hit_record else {
+        return scene.background.radiance(ray);
+    };
+    if media.passes(&hit_record) {
+        let mut media = media.clone();
+        media.cross(&hit_record);
+        let ray = ray.spawn(&hit_record.point, &ray.direction);
+        return trace(&ray, scene, depth, bounce, &media, guide);
+    }
+    media.surround(&mut hit_record);
+    scatter(ray, hit_record, scene, depth, media, guide)
+}
 
-    interpolate_linear(
-        Vec3::new(1., 1., 1.),    // White
-        Vec3::new(0.5, 0.7, 1.0), // Blue
-        time,
-    )
+/// Light leaving a surface or a point in a medium back along `ray`.
+fn scatter(
+    ray: &Ray,
+    hit_record: HitRecord,
+    scene: &Scene,
+    depth: isize,
+    media: &MediumStack,
+    guide: Option<&SdTree>,
+) -> Vec3 {
+    let hit_record = hit_record.with_shading(ray);
+    let material = &hit_record.material;
+    let mut color = material.emitted(ray, &hit_record);
+
+    // Phase functions take light from all around.
+    let normal = match **material {
+        Material::Isotropic { .. } => Vec3::default(),
+        _ => hit_record.normal,
+    };
+    let samples_lights = !scene.lights.is_empty() && material.flags(&hit_record).non_delta;
+    if samples_lights {
+        color += sample_light(ray, &hit_record, normal, scene, media, guide);
+    }
+
+    let guide = guide.filter(|_| material.flags(&hit_record).non_delta);
+    let sample = match guide {
+        Some(guide) => guide.sample(ray, &hit_record),
+        None => material.sample(ray, &hit_record),
+    };
+    match sample {
+        // Shading normals can send rays through the true surface, which would leak light.
+        Some(sample) if hit_record.agrees_with_geometry(sample.ray.direction) => {
+            let bounce = (samples_lights && !sample.is_delta).then_some(Bounce {
+                point: hit_record.point,
+                pdf: sample.pdf,
+                normal,
+            });
+            let media = media.toward(&hit_record, sample.ray.direction);
+            let incident = trace(&sample.ray, scene, depth - 1, bounce, &media, guide);
+            if let Some(guide) = guide.filter(|guide| guide.is_learning && !sample.is_delta) {
+                guide.record(
+                    hit_record.point,
+                    sample.ray.direction,
+                    luminance(incident) / sample.pdf,
+                );
+            }
+            color + sample.weight * incident
+        }
+        _ => color,
+    }
+}
+
+/// The radiance of lights `ray` sees, weighted against having sampled them at `bounce`.
+fn weigh_lights(
+    lights: Vec<(usize, Vec3)>,
+    ray: &Ray,
+    scene: &Scene,
+    bounce: Option<Bounce>,
+) -> Vec3 {
+    lights
+        .into_iter()
+        .map(|(index, radiance)| match bounce {
+            Some(bounce) => {
+                let direction = ray.direction.unit_vector();
+                let selection = scene
+                    .light_sampler()
+                    .pmf(bounce.point, bounce.normal, index);
+                let light_pdf = selection * scene.lights[index].pdf(bounce.point, direction);
+                radiance * power_heuristic(bounce.pdf, light_pdf)
+            }
+            None => radiance,
+        })
+        .fold(Vec3::default(), |sum, radiance| sum + radiance)
+}
+
+/// The lights `ray` sees nearest in front of `t_max`, with the radiance each sends along it. All
+/// lights at infinity are seen together when the ray escapes.
+pub(crate) fn nearest_lights(ray: &Ray, scene: &Scene, t_max: f64) -> Vec<(usize, Vec3)> {
+    lights_between(ray, scene, 0.001, t_max).map_or(vec![], |(_, lights)| lights)
+}
+
+/// The lights `ray` sees nearest after `t_min` and up to `t_max`, with when it sees them.
+fn lights_between(
+    ray: &Ray,
+    scene: &Scene,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, Vec<(usize, Vec3)>)> {
+    let light_hits = scene
+        .lights
+        .iter()
+        .enumerate()
+        .filter_map(|(index, light)| Some((index, light.hit(ray)?)))
+        .filter(|(_, (time, _))| *time > t_min && *time <= t_max)
+        .collect::<Vec<_>>();
+    let nearest = light_hits
+        .iter()
+        .map(|(_, (time, _))| *time)
+        .reduce(f64::min)?;
+    let lights = light_hits
+        .into_iter()
+        .filter(|(_, (time, _))| *time == nearest)
+        .map(|(index, (_, radiance))| (index, radiance))
+        .collect();
+    Some((nearest, lights))
+}
+
+/// Light reaching a hit inside `media` directly from one of the scene's lights, picked by its
+/// light sampler. It is weighted against bounces that sample `guide` too when there is one.
+pub(crate) fn sample_light(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    normal: Vec3,
+    scene: &Scene,
+    media: &MediumStack,
+    guide: Option<&SdTree>,
+) -> Vec3 {
+    let Some((index, selection)) =
+        scene
+            .light_sampler()
+            .sample(hit_record.point, normal, rng().gen())
+    else {
+        return Vec3::default();
+    };
+    let Some(sample) = scene.lights[index].sample(hit_record.point, ray) else {
+        return Vec3::default();
+    };
+    if !hit_record.agrees_with_geometry(sample.direction) {
+        return Vec3::default();
+    }
+    let value = hit_record.material.eval(ray, hit_record, sample.direction);
+    if value == Vec3::default() {
+        return Vec3::default();
+    }
+
+    let shadow_ray = ray.spawn(&hit_record.point, &sample.direction);
+    let transmittance = media.toward(hit_record, sample.direction).transmittance(
+        &shadow_ray,
+        sample.distance - 0.001,
+        scene,
+    );
+    if transmittance == Vec3::default() {
+        return Vec3::default();
+    }
+
+    let radiance = transmittance * sample.radiance;
+    match sample.is_delta() {
+        true => value * radiance / selection,
+        false => {
+            let light_pdf = selection * sample.pdf;
+            let bsdf_pdf = match guide {
+                Some(guide) => guide.pdf(ray, hit_record, sample.direction),
+                None => hit_record.material.pdf(ray, hit_record, sample.direction),
+            };
+            value * radiance * (power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
+        }
+    }
+}
+
+/// The weight of a sample drawn with density `pdf` that another strategy could have drawn with
+/// density `other_pdf`.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
+}
+
+/// What paths traced from the lights leave for every pixel to use.
+struct FromLights {
+    /// Splats of bidirectional paths.
+    film: Film,
+    /// When photon mapping.
+    photons: Option<PhotonMap>,
 }
 
-#[allow(clippy::too_many_arguments)]
+impl FromLights {
+    fn new(image: &Image, scene: &Scene, render_config: &RenderConfig) -> Self {
+        Self {
+            film: Film::new(image.width, image.height),
+            photons: PhotonMap::for_config(scene, render_config),
+        }
+    }
+}
+
+/// The linear color a pixel's camera samples find. Light that paths from the lights bring is
+/// splatted onto the film of `from_lights`.
 fn process_pixel(
     row: usize,
     col: usize,
     camera: Arc<Camera>,
-    world: Arc<HittableList>,
-    samples_per_pixel: usize,
+    scene: Arc<Scene>,
     image: Arc<Image>,
-    max_depth: isize,
-) -> Pixel {
-    let mut rng = thread_rng();
+    render_config: &RenderConfig,
+    from_lights: &FromLights,
+) -> Vec3 {
+    let samples_per_pixel = render_config.samples_per_pixel;
     let mut pixel_color: Vec3 = Vec3::new(0., 0., 0.);
 
     for _ in 0..samples_per_pixel {
-        let u = (col as f64 + rng.gen::<f64>()) / (image.width - 1) as f64;
-        let v = (row as f64 + rng.gen::<f64>()) / (image.height - 1) as f64;
-        let ray: Ray = camera.get_ray(u, v);
-        pixel_color += ray_color(&ray, &world, max_depth);
+        let (ray, wavelengths) = pixel_ray(row, col, &camera, &image, render_config);
+        let to_rgb = |color: Vec3| match &wavelengths {
+            Some(wavelengths) => wavelengths.to_rgb(color),
+            None => color,
+        };
+
+        pixel_color += to_rgb(match render_config.integrator {
+            Integrator::PathTracer => ray_color(&ray, &scene, render_config.max_depth),
+            Integrator::AmbientOcclusion { radius, samples } => {
+                ambient_occlusion_color(&ray, &scene, radius, samples)
+            }
+            Integrator::Bidirectional => bidirectional_color(
+                &ray,
+                &camera,
+                &scene,
+                render_config.max_depth,
+                &mut |viewport, color| from_lights.film.splat(viewport, to_rgb(color)),
+            ),
+            Integrator::PhotonMapping { radius, .. } => from_lights
+                .photons
+                .as_ref()
+                .map_or(Vec3::default(), |photons| {
+                    photon_color(&ray, &scene, photons, radius, render_config.max_depth)
+                }),
+            // Rendered over the whole image at once instead.
+            Integrator::ProgressivePhotonMapping { .. }
+            | Integrator::Metropolis { .. }
+            | Integrator::GuidedPathTracer => Vec3::default(),
+        });
     }
 
-    gamma2_correct(pixel_color / samples_per_pixel as f64, 2).into()
+    pixel_color / samples_per_pixel as f64
+}
+
+/// A camera ray through a random point of the pixel at `(row, col)`, counting rows up from the
+/// bottom, with the wavelengths it carries when rendering spectrally.
+pub(crate) fn pixel_ray(
+    row: usize,
+    col: usize,
+    camera: &Camera,
+    image: &Image,
+    render_config: &RenderConfig,
+) -> (Ray, Option<SampledWavelengths>) {
+    let mut rng = rng();
+    let u = (col as f64 + rng.gen::<f64>()) / (image.width - 1) as f64;
+    let v = (row as f64 + rng.gen::<f64>()) / (image.height - 1) as f64;
+    viewport_ray((u, v), camera, image, render_config)
+}
+
+/// The camera ray through the viewport coordinates `(u, v)`, with the wavelengths it carries when
+/// rendering spectrally.
+pub(crate) fn viewport_ray(
+    (u, v): (f64, f64),
+    camera: &Camera,
+    image: &Image,
+    render_config: &RenderConfig,
+) -> (Ray, Option<SampledWavelengths>) {
+    let mut ray: Ray = camera.get_ray_with_differentials(
+        u,
+        v,
+        1. / (image.width - 1) as f64,
+        1. / (image.height - 1) as f64,
+    );
+
+    let wavelengths = render_config
+        .spectral
+        .then(|| SampledWavelengths::sample(rng().gen()));
+    ray.wavelengths = wavelengths.as_ref().map(|wavelengths| wavelengths.lambda);
+    (ray, wavelengths)
+}
+
+/// The pixel seen at `(row, col)`, counting rows up from the bottom.
+fn finish_pixel(
+    row: usize,
+    col: usize,
+    color: Vec3,
+    film: &Film,
+    render_config: &RenderConfig,
+) -> Pixel {
+    let splats = film.radiance(row, col, render_config.samples_per_pixel);
+    gamma2_correct(color + splats, 2).into()
 }
 
 // Export the parallel pixel processor if feature `parallel` is enabled (default).
@@ -92,7 +475,7 @@ pub use process_pixels_factory::process_pixels_seq as process_pixels;
 pub use process_pixels_factory::process_pixels_par as process_pixels;
 
 mod process_pixels_factory {
-    use super::process_pixel;
+    use super::{finish_pixel, process_pixel, FromLights};
     use crate::prelude::*;
     use std::sync::Arc;
 
@@ -108,7 +491,7 @@ mod process_pixels_factory {
     pub fn process_pixels_par(
         image: Arc<Image>,
         camera: Arc<Camera>,
-        world: Arc<HittableList>,
+        scene: Arc<Scene>,
         render_config: Arc<RenderConfig>,
         progress_bar: ProgressBar,
     ) -> Vec<Pixel> {
@@ -131,6 +514,29 @@ mod process_pixels_factory {
         // To prevent frequent updating of the progress bar.
         // https://github.com/console-rs/indicatif/issues/170#issuecomment-617128991
 
+        let scene = match render_config.clay {
+            true => Arc::new(scene.clay()),
+            false => scene,
+        };
+        match render_config.integrator {
+            Integrator::ProgressivePhotonMapping { .. } => {
+                return progressive_photon_mapping(
+                    &image,
+                    &camera,
+                    &scene,
+                    &render_config,
+                    progress_bar,
+                );
+            }
+            Integrator::Metropolis { .. } => {
+                return metropolis(&image, &camera, &scene, &render_config, progress_bar);
+            }
+            Integrator::GuidedPathTracer => {
+                return guided_path_tracing(&image, &camera, &scene, &render_config, progress_bar);
+            }
+            _ => {}
+        }
+        let from_lights = FromLights::new(&image, &scene, &render_config);
         let mut pixels = cross
             .as_slice()
             .par_iter() // Rayon goes brrrr...
@@ -140,23 +546,32 @@ mod process_pixels_factory {
                     image.height - item.0 - 1,
                     item.1,
                     camera.clone(),
-                    world.clone(),
-                    render_config.samples_per_pixel,
+                    scene.clone(),
                     image.clone(),
-                    render_config.max_depth,
+                    &render_config,
+                    &from_lights,
                 );
                 (*item, value)
             })
-            .collect::<Vec<((usize, usize), Pixel)>>();
+            .collect::<Vec<((usize, usize), Vec3)>>();
 
         // Since we have the (row, col) as the first component,
         // the sort would happen on the first component and
         // we'd get the pixels in the correct order that will
         // then be written to a ppm file.
-        pixels.par_sort();
+        pixels.par_sort_by_key(|(item, _)| *item);
+        // Splats land anywhere on the image, so pixels are only finished once all are traced.
         pixels
             .into_iter()
-            .map(|((_r, _c), px): ((usize, usize), Pixel)| px)
+            .map(|((r, c), color): ((usize, usize), Vec3)| {
+                finish_pixel(
+                    image.height - r - 1,
+                    c,
+                    color,
+                    &from_lights.film,
+                    &render_config,
+                )
+            })
             .collect::<Vec<Pixel>>()
     }
 
@@ -164,7 +579,7 @@ mod process_pixels_factory {
     pub fn process_pixels_seq(
         image: Arc<Image>,
         camera: Arc<Camera>,
-        world: Arc<HittableList>,
+        scene: Arc<Scene>,
         render_config: Arc<RenderConfig>,
         progress_bar: ProgressBar,
     ) -> Vec<Pixel> {
@@ -176,7 +591,30 @@ mod process_pixels_factory {
                 .collect::<Vec<(usize, usize)>>(),
         );
 
-        cross
+        let scene = match render_config.clay {
+            true => Arc::new(scene.clay()),
+            false => scene,
+        };
+        match render_config.integrator {
+            Integrator::ProgressivePhotonMapping { .. } => {
+                return progressive_photon_mapping(
+                    &image,
+                    &camera,
+                    &scene,
+                    &render_config,
+                    progress_bar,
+                );
+            }
+            Integrator::Metropolis { .. } => {
+                return metropolis(&image, &camera, &scene, &render_config, progress_bar);
+            }
+            Integrator::GuidedPathTracer => {
+                return guided_path_tracing(&image, &camera, &scene, &render_config, progress_bar);
+            }
+            _ => {}
+        }
+        let from_lights = FromLights::new(&image, &scene, &render_config);
+        let colors = cross
             .iter()
             // .progress_with(progress_bar)
             .map(|item: &(usize, usize)| {
@@ -184,14 +622,28 @@ mod process_pixels_factory {
                     image.height - item.0 - 1,
                     item.1,
                     camera.clone(),
-                    world.clone(),
-                    render_config.samples_per_pixel,
+                    scene.clone(),
                     image.clone(),
-                    render_config.max_depth,
+                    &render_config,
+                    &from_lights,
                 );
                 progress_bar.inc(1);
                 value
             })
+            .collect::<Vec<Vec3>>();
+        // Splats land anywhere on the image, so pixels are only finished once all are traced.
+        cross
+            .iter()
+            .zip(colors)
+            .map(|((r, c), color)| {
+                finish_pixel(
+                    image.height - r - 1,
+                    *c,
+                    color,
+                    &from_lights.film,
+                    &render_config,
+                )
+            })
             .collect::<Vec<Pixel>>()
     }
 }
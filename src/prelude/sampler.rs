@@ -0,0 +1,109 @@
+use rand::{thread_rng, Error, RngCore};
+use std::any::Any;
+use std::cell::RefCell;
+
+/// A source of the uniform numbers in [0, 1) that rendering draws, one per call to `next`.
+pub trait Sampler: Any {
+    fn next(&mut self) -> f64;
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Option<Box<dyn Sampler>>> = const { RefCell::new(None) };
+}
+
+/// The random numbers rendering draws: those of the sampler `with_sampler` put on this thread,
+/// or else `thread_rng`'s.
+///
+/// Every `f64` or `bool` generated takes exactly one number from a sampler.
+pub fn rng() -> SamplerRng {
+    SamplerRng
+}
+
+/// Run `f` with its random numbers drawn from `sampler`, which is handed back afterwards.
+pub fn with_sampler<S: Sampler, R>(sampler: S, f: impl FnOnce() -> R) -> (R, S) {
+    /// Puts back whatever sampler came before, even if `f` panics.
+    struct Installed(Option<Box<dyn Sampler>>);
+
+    impl Drop for Installed {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            SAMPLER.with(|sampler| *sampler.borrow_mut() = previous);
+        }
+    }
+
+    let previous = SAMPLER.with(|installed| installed.borrow_mut().replace(Box::new(sampler)));
+    let installed = Installed(previous);
+    let result = f();
+    let sampler = SAMPLER.with(|installed| installed.borrow_mut().take());
+    drop(installed);
+
+    let sampler: Box<dyn Any> = sampler.expect("The sampler was taken while in use.");
+    match sampler.downcast::<S>() {
+        Ok(sampler) => (result, *sampler),
+        Err(_) => panic!("The sampler was replaced while in use."),
+    }
+}
+
+/// See `rng`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SamplerRng;
+
+impl RngCore for SamplerRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let u = SAMPLER.with(|sampler| sampler.borrow_mut().as_mut().map(|sampler| sampler.next()));
+        match u {
+            // The top bits are what floats and booleans are made of.
+            Some(u) => (u.clamp(0., 1.) * 2_f64.powi(64)).min(u64::MAX as f64) as u64,
+            None => thread_rng().next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rng, with_sampler, Sampler};
+    use rand::Rng;
+
+    struct Sequence(Vec<f64>);
+
+    impl Sampler for Sequence {
+        fn next(&mut self) -> f64 {
+            self.0.remove(0)
+        }
+    }
+
+    #[test]
+    fn draws_come_from_the_installed_sampler() {
+        let sampler = Sequence(vec![0.25, 0.75, 0.5, 0.999, 0.]);
+        let ((x, y, z), sampler) = with_sampler(sampler, || {
+            let mut rng = rng();
+            let x = rng.gen::<f64>();
+            let y: f64 = rng.gen_range(2. ..4.);
+            (x, y, rng.gen::<bool>())
+        });
+        assert!((x - 0.25).abs() < 1e-12);
+        assert!((y - 3.5).abs() < 1e-12);
+        assert!(z);
+        assert_eq!(sampler.0, vec![0.999, 0.]);
+
+        // Nothing is left installed.
+        let draws = (0..100).map(|_| rng().gen::<f64>()).collect::<Vec<_>>();
+        assert!(draws.iter().any(|&u| u != draws[0]));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::prelude::{
+    interpolate_linear, AliasTable, EnvironmentMap, HitRecord, Hittable, HittableList, Light,
+    LightSampler, LightSelection, LinAlgOp, PhysicalSky, Ray, Vec3,
+};
+use std::sync::{Arc, OnceLock};
+
+/// Everything a render needs besides the camera.
+#[derive(Clone, Default)]
+pub struct Scene {
+    pub world: HittableList,
+    /// Lights sampled directly at every bounce, in addition to emissive geometry in `world`.
+    pub lights: Vec<Arc<dyn Light>>,
+    /// What rays that miss everything see.
+    pub background: Background,
+    pub light_selection: LightSelection,
+    /// Built from `lights` on first use.
+    light_sampler: OnceLock<LightSampler>,
+    /// Built from `lights` on first use.
+    emitter_table: OnceLock<AliasTable>,
+}
+
+impl Scene {
+    pub fn new(world: HittableList) -> Self {
+        Self {
+            world,
+            lights: vec![],
+            background: Background::default(),
+            light_selection: LightSelection::default(),
+            light_sampler: OnceLock::new(),
+            emitter_table: OnceLock::new(),
+        }
+    }
+
+    /// The nearest hit on `world`, seen through the holes of masked materials.
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.world.hit_opaque(ray, t_min, t_max)
+    }
+
+    pub fn push_light(&mut self, light: Arc<dyn Light>) {
+        self.lights.push(light);
+        self.light_sampler = OnceLock::new();
+        self.emitter_table = OnceLock::new();
+    }
+
+    /// Picks which of `lights` to sample. Changes to `lights` or `light_selection` made other
+    /// than through `push_light` after the first render are not seen.
+    pub fn light_sampler(&self) -> &LightSampler {
+        self.light_sampler
+            .get_or_init(|| LightSampler::new(&self.lights, self.light_selection))
+    }
+
+    /// Picks which of `lights` to trace paths from, in proportion to their power. Lights at
+    /// infinity are never picked.
+    pub fn emitter_table(&self) -> &AliasTable {
+        self.emitter_table.get_or_init(|| {
+            AliasTable::new(
+                &self
+                    .lights
+                    .iter()
+                    .map(|light| light.bounds().map_or(0., |bounds| bounds.power))
+                    .collect::<Vec<_>>(),
+            )
+        })
+    }
+
+    /// Light the scene with daylight: the sky as the background and the sun as a light.
+    pub fn set_sky(&mut self, sky: PhysicalSky) {
+        self.push_light(Arc::new(sky.sun()));
+        self.background = Background::Sky(Arc::new(sky));
+    }
+
+    /// Light the scene with an environment map. The map becomes a light that rays escaping the
+    /// scene see, so the background turns black rather than counting it twice.
+    pub fn set_environment(&mut self, environment: EnvironmentMap) {
+        self.push_light(Arc::new(environment));
+        self.background = Background::Color(Vec3::default());
+    }
+}
+
+impl From<HittableList> for Scene {
+    fn from(world: HittableList) -> Self {
+        Self::new(world)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub enum Background {
+    /// White at the horizon fading to blue overhead.
+    #[default]
+    Gradient,
+    Sky(Arc<PhysicalSky>),
+    Color(Vec3),
+}
+
+impl Background {
+    /// Radiance arriving along `ray` from infinitely far away.
+    pub fn radiance(&self, ray: &Ray) -> Vec3 {
+        let direction = ray.direction.unit_vector();
+        match self {
+            Background::Gradient => {
+                let time = 0.5 * (direction.1 + 1.);
+                ray.radiance(interpolate_linear(
+                    Vec3::new(1., 1., 1.),    // White
+                    Vec3::new(0.5, 0.7, 1.0), // Blue
+                    time,
+                ))
+            }
+            Background::Sky(sky) => ray.radiance(sky.radiance(direction)),
+            Background::Color(color) => ray.radiance(*color),
+        }
+    }
+}
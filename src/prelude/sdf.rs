@@ -0,0 +1,503 @@
+use crate::prelude::{HitRecord, Hittable, LinAlgOp, Material, Point, Ray, Vec3};
+use std::sync::Arc;
+
+/// A signed distance field: negative inside the shape, positive outside, and never larger than
+/// the true distance to the surface.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, point: Point) -> f64;
+}
+
+/// Renders an [`Sdf`] by sphere tracing it.
+pub struct SdfHittable {
+    pub sdf: Arc<dyn Sdf>,
+    pub material: Arc<Material>,
+    pub max_steps: usize,
+    /// How close to the surface counts as a hit. Also used as the central difference step.
+    pub epsilon: f64,
+    /// How far from a ray's origin, either way along it, it is marched before giving up. Keeps
+    /// queries over unbounded ranges, like those of CSG, finite.
+    pub max_distance: f64,
+    /// Fraction of the distance bound taken per step. Fields that only approximate the distance,
+    /// like `SdfTwist` or fractals, need a value below one to avoid stepping through the surface.
+    pub step_scale: f64,
+}
+
+impl SdfHittable {
+    pub fn new(sdf: Arc<dyn Sdf>, material: Arc<Material>) -> Self {
+        Self {
+            sdf,
+            material,
+            max_steps: 512,
+            epsilon: 1e-4,
+            max_distance: 100.,
+            step_scale: 1.,
+        }
+    }
+
+    pub fn normal(&self, point: Point) -> Vec3 {
+        let h = self.epsilon;
+        let dx = Vec3::new(h, 0., 0.);
+        let dy = Vec3::new(0., h, 0.);
+        let dz = Vec3::new(0., 0., h);
+
+        Vec3::new(
+            self.sdf.distance(point + dx) - self.sdf.distance(point - dx),
+            self.sdf.distance(point + dy) - self.sdf.distance(point - dy),
+            self.sdf.distance(point + dz) - self.sdf.distance(point - dz),
+        )
+        .unit_vector()
+    }
+}
+
+impl Hittable for SdfHittable {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let speed = ray.direction.norm();
+        let reach = self.max_distance / speed;
+        let t_max = t_max.min(reach);
+        let mut time = t_min.max(-reach);
+        if time > t_max {
+            return None;
+        }
+        let mut steps = 0;
+
+        // Rays spawned on the surface itself would otherwise hit it again right away.
+        while self.sdf.distance(ray.at(time)).abs() < self.epsilon && steps < self.max_steps {
+            time += self.epsilon / speed;
+            steps += 1;
+        }
+        let started_inside = self.sdf.distance(ray.at(time)) < 0.;
+
+        while steps < self.max_steps && time <= t_max {
+            let distance = self.sdf.distance(ray.at(time));
+
+            if distance.abs() < self.epsilon || (distance < 0.) != started_inside {
+                let point = ray.at(time);
+                let outward_facing_normal = self.normal(point);
+                let is_front_facing = ray.direction.dot(outward_facing_normal) < 0.;
+                let normal = if is_front_facing {
+                    outward_facing_normal
+                } else {
+                    -outward_facing_normal
+                };
+
+                return Some(HitRecord {
+                    point,
+                    normal,
+                    geometric_normal: normal,
+                    time,
+                    is_front_facing,
+                    u: 0.,
+                    v: 0.,
+                    uv_derivatives: None,
+                    tangents: None,
+                    material: self.material.clone(),
+                    interior: None,
+                    surrounding_ior: 1.,
+                });
+            }
+
+            time += self.step_scale * distance.abs() / speed;
+            steps += 1;
+        }
+        None
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "SdfHittable {{ max_steps: {}, epsilon: {}, max_distance: {} }}",
+            self.max_steps, self.epsilon, self.max_distance
+        )
+    }
+}
+
+fn component_wise(v: Vec3, f: impl Fn(f64) -> f64) -> Vec3 {
+    Vec3::new(f(v.0), f(v.1), f(v.2))
+}
+
+pub struct SdfSphere {
+    pub center: Point,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, point: Point) -> f64 {
+        (point - self.center).norm() - self.radius
+    }
+}
+
+/// An axis-aligned box given by its center and half of its size along each axis.
+pub struct SdfBox {
+    pub center: Point,
+    pub half_extents: Vec3,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, point: Point) -> f64 {
+        let q = component_wise(point - self.center, f64::abs) - self.half_extents;
+        let outside = component_wise(q, |x| x.max(0.)).norm();
+        let inside = q.0.max(q.1).max(q.2).min(0.);
+        outside + inside
+    }
+}
+
+/// A torus lying in the xz-plane.
+pub struct SdfTorus {
+    pub center: Point,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, point: Point) -> f64 {
+        let p = point - self.center;
+        let ring = (p.0 * p.0 + p.2 * p.2).sqrt() - self.major_radius;
+        (ring * ring + p.1 * p.1).sqrt() - self.minor_radius
+    }
+}
+
+/// The half-space below a plane with unit `normal` passing through `normal * offset`.
+pub struct SdfPlane {
+    pub normal: Vec3,
+    pub offset: f64,
+}
+
+impl Sdf for SdfPlane {
+    fn distance(&self, point: Point) -> f64 {
+        point.dot(self.normal) - self.offset
+    }
+}
+
+/// A line segment from `start` to `end` thickened by `radius`.
+pub struct SdfCapsule {
+    pub start: Point,
+    pub end: Point,
+    pub radius: f64,
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, point: Point) -> f64 {
+        let pa = point - self.start;
+        let ba = self.end - self.start;
+        let h = (pa.dot(ba) / ba.norm_squared()).clamp(0., 1.);
+        (pa - ba * h).norm() - self.radius
+    }
+}
+
+pub struct SdfUnion(pub Arc<dyn Sdf>, pub Arc<dyn Sdf>);
+
+impl Sdf for SdfUnion {
+    fn distance(&self, point: Point) -> f64 {
+        self.0.distance(point).min(self.1.distance(point))
+    }
+}
+
+pub struct SdfIntersection(pub Arc<dyn Sdf>, pub Arc<dyn Sdf>);
+
+impl Sdf for SdfIntersection {
+    fn distance(&self, point: Point) -> f64 {
+        self.0.distance(point).max(self.1.distance(point))
+    }
+}
+
+/// The first field with the second one carved out of it.
+pub struct SdfSubtraction(pub Arc<dyn Sdf>, pub Arc<dyn Sdf>);
+
+impl Sdf for SdfSubtraction {
+    fn distance(&self, point: Point) -> f64 {
+        self.0.distance(point).max(-self.1.distance(point))
+    }
+}
+
+/// A union that blends the two fields together over a distance of roughly `smoothness`.
+pub struct SdfSmoothUnion {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub smoothness: f64,
+}
+
+impl Sdf for SdfSmoothUnion {
+    fn distance(&self, point: Point) -> f64 {
+        let a = self.a.distance(point);
+        let b = self.b.distance(point);
+        let k = self.smoothness;
+
+        if k <= 0. {
+            return a.min(b);
+        }
+        let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+        b * (1. - h) + a * h - k * h * (1. - h)
+    }
+}
+
+/// Twists a field around the y-axis by `rate` radians per unit of height.
+///
+/// This does not preserve distances, so trace it with a `step_scale` below one.
+pub struct SdfTwist {
+    pub sdf: Arc<dyn Sdf>,
+    pub rate: f64,
+}
+
+impl Sdf for SdfTwist {
+    fn distance(&self, point: Point) -> f64 {
+        let (sin, cos) = (self.rate * point.1).sin_cos();
+        let twisted = Point::new(
+            cos * point.0 - sin * point.2,
+            point.1,
+            sin * point.0 + cos * point.2,
+        );
+        self.sdf.distance(twisted)
+    }
+}
+
+/// Repeats a field infinitely with the given period along each axis. A period of zero leaves that
+/// axis alone. The repeated shape should fit within one cell.
+pub struct SdfRepeat {
+    pub sdf: Arc<dyn Sdf>,
+    pub period: Vec3,
+}
+
+impl Sdf for SdfRepeat {
+    fn distance(&self, point: Point) -> f64 {
+        let wrap = |x: f64, period: f64| {
+            if period > 0. {
+                x - period * (x / period).round()
+            } else {
+                x
+            }
+        };
+        self.sdf.distance(Point::new(
+            wrap(point.0, self.period.0),
+            wrap(point.1, self.period.1),
+            wrap(point.2, self.period.2),
+        ))
+    }
+}
+
+/// Rounds off the edges of a field by growing it by `radius`.
+pub struct SdfRound {
+    pub sdf: Arc<dyn Sdf>,
+    pub radius: f64,
+}
+
+impl Sdf for SdfRound {
+    fn distance(&self, point: Point) -> f64 {
+        self.sdf.distance(point) - self.radius
+    }
+}
+
+pub struct SdfTranslate {
+    pub sdf: Arc<dyn Sdf>,
+    pub offset: Vec3,
+}
+
+impl Sdf for SdfTranslate {
+    fn distance(&self, point: Point) -> f64 {
+        self.sdf.distance(point - self.offset)
+    }
+}
+
+/// Uniformly scales a field about the origin.
+pub struct SdfScale {
+    pub sdf: Arc<dyn Sdf>,
+    pub factor: f64,
+}
+
+impl Sdf for SdfScale {
+    fn distance(&self, point: Point) -> f64 {
+        self.sdf.distance(point / self.factor) * self.factor
+    }
+}
+
+/// The Mandelbulb fractal, via its distance estimator, scaled to fit in a sphere of `scale`.
+pub struct Mandelbulb {
+    pub center: Point,
+    pub scale: f64,
+    pub power: f64,
+    pub iterations: usize,
+}
+
+impl Mandelbulb {
+    pub fn new(center: Point, scale: f64) -> Self {
+        Self {
+            center,
+            scale,
+            power: 8.,
+            iterations: 12,
+        }
+    }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, point: Point) -> f64 {
+        let c = (point - self.center) / self.scale;
+
+        // The bulb fits inside a ball of radius ~1.2; outside it the estimator is poorly behaved.
+        let bounding = c.norm() - 1.25;
+        if bounding > 0.1 {
+            return bounding * self.scale;
+        }
+
+        let mut z = c;
+        let mut dr = 1.;
+        let mut r = z.norm();
+
+        for _ in 0..self.iterations {
+            if r > 2. {
+                break;
+            }
+            if r == 0. {
+                // Zero raised to any power is zero, so the next iterate is just the constant.
+                z = c;
+                r = z.norm();
+                if r == 0. {
+                    break;
+                }
+                continue;
+            }
+
+            let theta = (z.2 / r).clamp(-1., 1.).acos() * self.power;
+            let phi = z.1.atan2(z.0) * self.power;
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+
+            let zr = r.powf(self.power);
+            z =
+                zr * Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ) + c;
+            r = z.norm();
+        }
+
+        if r <= 0. {
+            return 0.;
+        }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::Sphere;
+
+    const TOLERANCE: f64 = 1e-3;
+
+    fn material() -> Arc<Material> {
+        Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let sphere = SdfSphere {
+            center: Point::new(0., 0., 0.),
+            radius: 1.,
+        };
+        assert!((sphere.distance(Point::new(3., 0., 0.)) - 2.).abs() < 1e-12);
+        assert!((sphere.distance(Point::new(0., 0., 0.)) + 1.).abs() < 1e-12);
+
+        let cube = SdfBox {
+            center: Point::new(0., 0., 0.),
+            half_extents: Vec3::new(1., 1., 1.),
+        };
+        assert!((cube.distance(Point::new(2., 0., 0.)) - 1.).abs() < 1e-12);
+        assert!((cube.distance(Point::new(2., 2., 1.)) - 2f64.sqrt()).abs() < 1e-12);
+        assert!((cube.distance(Point::new(0.5, 0., 0.)) + 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn unbounded_queries_find_every_hit() {
+        let traced = SdfHittable::new(
+            Arc::new(SdfSphere {
+                center: Point::new(0., 0., 0.),
+                radius: 1.,
+            }),
+            material(),
+        );
+        let ray = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -1.));
+        let hits = traced.hit_all(&ray, f64::NEG_INFINITY, f64::INFINITY);
+        assert_eq!(hits.len(), 2);
+        assert!((hits[0].time - 4.).abs() < TOLERANCE && hits[0].is_front_facing);
+        assert!((hits[1].time - 6.).abs() < TOLERANCE && !hits[1].is_front_facing);
+
+        // Behind the origin too.
+        let away = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., 1.));
+        let hit = traced.hit(&away, f64::NEG_INFINITY, f64::INFINITY).unwrap();
+        assert!((hit.time + 6.).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn sphere_tracing_matches_analytic_sphere() {
+        let center = Point::new(0., 0., -3.);
+        let traced = SdfHittable::new(Arc::new(SdfSphere { center, radius: 1. }), material());
+        let analytic = Sphere::new(center, 1., material());
+
+        for direction in [
+            Vec3::new(0., 0., -1.),
+            Vec3::new(0.2, 0.1, -1.),
+            Vec3::new(-0.25, 0.05, -1.),
+        ] {
+            let ray = Ray::new(&Point::new(0., 0., 0.), &direction);
+            let expected = analytic.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            let observed = traced.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+            assert!((observed.time - expected.time).abs() < TOLERANCE);
+            assert!((observed.normal - expected.normal).norm() < TOLERANCE);
+            assert!(observed.is_front_facing);
+        }
+    }
+
+    #[test]
+    fn sphere_tracing_from_inside() {
+        let traced = SdfHittable::new(
+            Arc::new(SdfSphere {
+                center: Point::new(0., 0., 0.),
+                radius: 1.,
+            }),
+            material(),
+        );
+        let ray = Ray::new(&Point::new(0., 0., 0.), &Vec3::new(1., 0., 0.));
+        let hit = traced.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+        assert!((hit.time - 1.).abs() < TOLERANCE);
+        assert!(!hit.is_front_facing);
+        assert!((hit.normal - Vec3::new(-1., 0., 0.)).norm() < TOLERANCE);
+    }
+
+    #[test]
+    fn repetition_and_smooth_union() {
+        let repeated = SdfRepeat {
+            sdf: Arc::new(SdfSphere {
+                center: Point::new(0., 0., 0.),
+                radius: 0.5,
+            }),
+            period: Vec3::new(2., 0., 0.),
+        };
+        assert!((repeated.distance(Point::new(4., 0., 0.)) + 0.5).abs() < 1e-12);
+        assert!((repeated.distance(Point::new(4., 1., 0.)) - 0.5).abs() < 1e-12);
+
+        let a: Arc<dyn Sdf> = Arc::new(SdfSphere {
+            center: Point::new(-1., 0., 0.),
+            radius: 0.75,
+        });
+        let b: Arc<dyn Sdf> = Arc::new(SdfSphere {
+            center: Point::new(1., 0., 0.),
+            radius: 0.75,
+        });
+        let hard = SdfUnion(a.clone(), b.clone());
+        let smooth = SdfSmoothUnion {
+            a,
+            b,
+            smoothness: 0.5,
+        };
+        let midpoint = Point::new(0., 0., 0.);
+        assert!(smooth.distance(midpoint) < hard.distance(midpoint));
+    }
+
+    #[test]
+    fn mandelbulb_is_bounded() {
+        let bulb = Mandelbulb::new(Point::new(0., 0., 0.), 1.);
+        assert!(bulb.distance(Point::new(3., 0., 0.)) > 1.);
+        assert!(bulb.distance(Point::new(0., 0., 0.)) <= 0.);
+    }
+}
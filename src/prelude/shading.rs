@@ -0,0 +1,246 @@
+use crate::prelude::{HitRecord, LinAlgOp, Material, Onb, Ray, SurfaceTangents, Texture, Vec3};
+use std::sync::Arc;
+
+/// Surface detail that tilts the shading normal without changing the geometry.
+#[derive(Clone, Debug)]
+pub enum NormalPerturbation {
+    /// A tangent-space normal map, with the tangent, bitangent and normal components remapped from
+    /// `[-1, 1]` to the red, green and blue channels. It should be loaded with
+    /// `ColorEncoding::Linear`. `strength` scales the tilt.
+    NormalMap {
+        map: Arc<dyn Texture>,
+        strength: f64,
+    },
+    /// A height field along the normal, in world units once multiplied by `scale`.
+    BumpMap {
+        height: Arc<dyn Texture>,
+        scale: f64,
+    },
+}
+
+/// The smallest cosine allowed between a shading normal and the direction towards the viewer.
+const MIN_VIEW_COSINE: f64 = 1e-3;
+
+/// Texture coordinate offset for bump map finite differences when the ray has no differentials.
+const BUMP_DELTA: f64 = 5e-4;
+
+impl NormalPerturbation {
+    /// The perturbed version of the outward facing shading normal `normal` at a hit.
+    pub fn perturb(&self, hit_record: &HitRecord, normal: Vec3) -> Vec3 {
+        let SurfaceTangents { dpdu, dpdv } = hit_record.tangents.unwrap_or_else(|| {
+            let onb = Onb::new(normal);
+            SurfaceTangents {
+                dpdu: onb.u,
+                dpdv: onb.v,
+            }
+        });
+
+        match self {
+            NormalPerturbation::NormalMap { map, strength } => {
+                // Gram-Schmidt the tangent against the shading normal; the bitangent follows dpdv
+                // so that mirrored texture coordinates keep their handedness.
+                let mut tangent = dpdu - normal.dot(dpdu) * normal;
+                if tangent.near_zero() {
+                    tangent = Onb::new(normal).u;
+                }
+                let tangent = tangent.unit_vector();
+                let mut bitangent = normal.cross(tangent);
+                if bitangent.dot(dpdv) < 0. {
+                    bitangent = -bitangent;
+                }
+
+                let encoded = 2. * map.value_at(hit_record) - Vec3::new(1., 1., 1.);
+                let perturbed = strength * encoded.0 * tangent
+                    + strength * encoded.1 * bitangent
+                    + encoded.2 * normal;
+                if perturbed.near_zero() {
+                    return normal;
+                }
+                perturbed.unit_vector()
+            }
+            NormalPerturbation::BumpMap { height, scale } => {
+                let (mut du, mut dv) = match &hit_record.uv_derivatives {
+                    Some(derivatives) => (
+                        0.5 * (derivatives.dudx.abs() + derivatives.dudy.abs()),
+                        0.5 * (derivatives.dvdx.abs() + derivatives.dvdy.abs()),
+                    ),
+                    None => (0., 0.),
+                };
+                if du == 0. {
+                    du = BUMP_DELTA;
+                }
+                if dv == 0. {
+                    dv = BUMP_DELTA;
+                }
+
+                let (u, v, point) = (hit_record.u, hit_record.v, hit_record.point);
+                let displacement = scale * height.scalar(u, v, point);
+                let displacement_u = scale * height.scalar(u + du, v, point + du * dpdu);
+                let displacement_v = scale * height.scalar(u, v + dv, point + dv * dpdv);
+
+                // Differentiate the displaced surface p + h(u, v) n, neglecting how n itself
+                // changes, which is small for the fine detail bump maps are used for.
+                let displaced_dpdu = dpdu + (displacement_u - displacement) / du * normal;
+                let displaced_dpdv = dpdv + (displacement_v - displacement) / dv * normal;
+
+                let perturbed = displaced_dpdu.cross(displaced_dpdv);
+                if perturbed.near_zero() {
+                    return normal;
+                }
+                let perturbed = perturbed.unit_vector();
+                if perturbed.dot(normal) < 0. {
+                    -perturbed
+                } else {
+                    perturbed
+                }
+            }
+        }
+    }
+}
+
+impl HitRecord {
+    /// Replace the shading normal, given on the same side of the surface as the ray.
+    ///
+    /// A shading normal that faces away from the viewer would send reflected light through the
+    /// surface, so it is bent back until the viewer is just in front of it.
+    pub fn set_shading_normal(&mut self, ray: &Ray, normal: Vec3) {
+        let towards_viewer = -ray.direction.unit_vector();
+        let cosine = normal.dot(towards_viewer);
+
+        self.normal = if cosine < MIN_VIEW_COSINE {
+            (normal + (MIN_VIEW_COSINE - cosine) * towards_viewer).unit_vector()
+        } else {
+            normal
+        };
+    }
+
+    /// Apply any normal perturbing materials, leaving the record with its final shading normal and
+    /// the material that scatters light.
+    pub fn with_shading(mut self, ray: &Ray) -> Self {
+        while let Material::Perturbed { base, perturbation } = self.material.clone().as_ref() {
+            let orientation = if self.is_front_facing { 1. } else { -1. };
+            let perturbed = perturbation.perturb(&self, orientation * self.normal);
+
+            self.set_shading_normal(ray, orientation * perturbed);
+            self.material = base.clone();
+        }
+        self
+    }
+
+    /// Whether a scattered direction is on the same side of the shading and geometric surfaces.
+    ///
+    /// Directions that are not would leak light through the surface.
+    pub fn agrees_with_geometry(&self, direction: Vec3) -> bool {
+        direction.dot(self.normal) * direction.dot(self.geometric_normal) > 0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NormalPerturbation;
+    use crate::prelude::{
+        Hittable, LinAlgOp, Material, Point, Ray, Scatter, SolidColor, Sphere, Texture, Vec3,
+    };
+    use std::sync::Arc;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    /// A height field rising with `u`.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _point: Point) -> Vec3 {
+            Vec3::new(u, u, u)
+        }
+    }
+
+    /// Hit a unit sphere at the origin on its +z side, where dpdu points towards +x.
+    fn hit_sphere(perturbation: NormalPerturbation) -> (Ray, crate::prelude::HitRecord) {
+        let material = Material::Perturbed {
+            base: Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5))),
+            perturbation,
+        };
+        let sphere = Sphere::new(Point::new(0., 0., 0.), 1., Arc::new(material));
+        let ray = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -1.));
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        (ray, hit)
+    }
+
+    #[test]
+    fn flat_maps_leave_the_normal_alone() {
+        for perturbation in [
+            NormalPerturbation::NormalMap {
+                map: Arc::new(SolidColor::new(Vec3::new(0.5, 0.5, 1.))),
+                strength: 1.,
+            },
+            NormalPerturbation::BumpMap {
+                height: Arc::new(SolidColor::gray(0.7)),
+                scale: 1.,
+            },
+        ] {
+            let (ray, hit) = hit_sphere(perturbation);
+            let shaded = hit.with_shading(&ray);
+
+            assert!((shaded.normal - Vec3::new(0., 0., 1.)).norm() < TOLERANCE);
+            assert!(matches!(*shaded.material, Material::Lambertian { .. }));
+        }
+    }
+
+    #[test]
+    fn maps_tilt_the_normal_along_the_tangent() {
+        let tangent = Vec3::new(1., 0., 0.);
+
+        // Full strength towards +tangent, at 45 degrees.
+        let (ray, hit) = hit_sphere(NormalPerturbation::NormalMap {
+            map: Arc::new(SolidColor::new(Vec3::new(1., 0.5, 1.))),
+            strength: 1.,
+        });
+        let shaded = hit.with_shading(&ray);
+        let expected = (tangent + Vec3::new(0., 0., 1.)).unit_vector();
+        assert!((shaded.normal - expected).norm() < TOLERANCE);
+
+        // A surface rising along the tangent leans away from it.
+        let (ray, hit) = hit_sphere(NormalPerturbation::BumpMap {
+            height: Arc::new(Ramp),
+            scale: 0.5,
+        });
+        let shaded = hit.clone().with_shading(&ray);
+        assert!(shaded.normal.dot(tangent) < -0.05);
+        assert!(shaded.normal.dot(Vec3::new(0., 1., 0.)).abs() < TOLERANCE);
+        assert!((shaded.normal.norm() - 1.).abs() < TOLERANCE);
+        assert_eq!(shaded.geometric_normal, hit.geometric_normal);
+    }
+
+    #[test]
+    fn shading_normals_never_face_away_from_the_viewer() {
+        // Tilted as far as the map allows, nearly perpendicular to the view direction.
+        let (ray, hit) = hit_sphere(NormalPerturbation::NormalMap {
+            map: Arc::new(SolidColor::new(Vec3::new(1., 0.5, 0.5))),
+            strength: 1.,
+        });
+        let shaded = hit.with_shading(&ray);
+        assert!(shaded.normal.dot(-ray.direction) > 0.);
+
+        // Scattering through the wrapper matches scattering with the resolved record.
+        let (ray, hit) = hit_sphere(NormalPerturbation::NormalMap {
+            map: Arc::new(SolidColor::new(Vec3::new(0.5, 0.5, 1.))),
+            strength: 1.,
+        });
+        let (attenuation, _) = hit.material.scatter(&ray, &hit).unwrap();
+        assert!((attenuation - Vec3::new(0.5, 0.5, 0.5)).norm() < TOLERANCE);
+    }
+
+    #[test]
+    fn leaking_directions_disagree_with_the_geometry() {
+        let (ray, mut hit) = hit_sphere(NormalPerturbation::BumpMap {
+            height: Arc::new(SolidColor::gray(0.)),
+            scale: 1.,
+        });
+        hit.set_shading_normal(&ray, Vec3::new(1., 0., 1.).unit_vector());
+
+        assert!(hit.agrees_with_geometry(Vec3::new(0., 0., 1.)));
+        assert!(hit.agrees_with_geometry(Vec3::new(-1., 0., -1.)));
+        // Above the shading normal's horizon but below the true surface.
+        assert!(!hit.agrees_with_geometry(Vec3::new(1., 0., -0.5)));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::prelude::{
+    luminance, xyz_to_linear_srgb, Angle, Degrees, DirectionalLight, LinAlgOp, Radians, Vec3,
+};
+use std::f64::consts::PI;
+
+/// The angle the sun's disk spans seen from the ground.
+pub const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+
+/// Illuminance of sunlight above the atmosphere, in klx.
+const SOLAR_ILLUMINANCE: f64 = 128.;
+
+/// Daylight from a clear sky, after Preetham et al. ("A Practical Analytic Model for Daylight",
+/// 1999), with the y axis up.
+///
+/// Below the horizon is flat ground of `ground_albedo`, lit by the sun and the sky. Radiance is in
+/// kcd/m² times `scale`, which `new` picks so that a white floor in the open has a radiance of
+/// about one. The sun itself is a separate light, see `sun`.
+#[derive(Clone, Debug)]
+pub struct PhysicalSky {
+    /// Unit vector towards the sun.
+    sun_direction: Vec3,
+    turbidity: f64,
+    ground_albedo: Vec3,
+    pub scale: f64,
+    /// Perez distribution coefficients for luminance and the two chromaticities.
+    perez: [[f64; 5]; 3],
+    /// Luminance and chromaticities at the zenith.
+    zenith: [f64; 3],
+    /// Irradiance from the sun on a surface facing it, per channel.
+    sun_irradiance: Vec3,
+    ground_radiance: Vec3,
+}
+
+impl PhysicalSky {
+    /// `elevation` is the sun's angle above the horizon and `azimuth` its angle around the y axis,
+    /// from +z towards +x. `turbidity` runs from 2 for a very clear sky to 10 for haze.
+    pub fn new(elevation: Angle, azimuth: Angle, turbidity: f64, ground_albedo: Vec3) -> Self {
+        let (elevation, azimuth) = (Radians::from(elevation).0, Radians::from(azimuth).0);
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+        // The model holds for the sun above the horizon.
+        let theta_sun = PI / 2. - elevation.max(0.);
+        let t = turbidity;
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let angles = [theta_sun.powi(3), theta_sun.powi(2), theta_sun, 1.];
+            [t * t, t, 1.]
+                .iter()
+                .zip(m)
+                .map(|(weight, row)| {
+                    weight * angles.iter().zip(row).map(|(a, m)| a * m).sum::<f64>()
+                })
+                .sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let mut sky = Self {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            scale: 1.,
+            perez,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            sun_irradiance: sun_transmittance(theta_sun, elevation > 0., t) * SOLAR_ILLUMINANCE,
+            ground_radiance: Vec3::default(),
+        };
+
+        // Light falling on the ground, from the sky with a midpoint rule over the hemisphere.
+        let (steps_theta, steps_phi) = (64, 128);
+        let (d_theta, d_phi) = (0.5 * PI / steps_theta as f64, 2. * PI / steps_phi as f64);
+        let mut irradiance = sky.sun_irradiance * sun_direction.1.max(0.);
+        for i in 0..steps_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..steps_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let direction = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                irradiance +=
+                    sky.sky_radiance(direction) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        sky.ground_radiance = ground_albedo * irradiance / PI;
+        sky.scale = PI / luminance(irradiance);
+        sky
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        self.sun_direction
+    }
+
+    pub fn turbidity(&self) -> f64 {
+        self.turbidity
+    }
+
+    pub fn ground_albedo(&self) -> Vec3 {
+        self.ground_albedo
+    }
+
+    /// Linear sRGB radiance arriving from `direction`, a unit vector.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        self.scale
+            * match direction.1 > 0. {
+                true => self.sky_radiance(direction),
+                false => self.ground_radiance,
+            }
+    }
+
+    /// The sun's disk, to add to a scene's lights along with the sky.
+    pub fn sun(&self) -> DirectionalLight {
+        DirectionalLight::new(
+            self.sun_direction,
+            self.sun_irradiance * self.scale,
+            Angle::Degrees(Degrees(SUN_ANGULAR_DIAMETER)),
+        )
+    }
+
+    /// Unscaled radiance of the sky above the horizon.
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        let cos_theta = direction.1.max(1e-4);
+        let cos_gamma = direction.dot(self.sun_direction).clamp(-1., 1.);
+        let theta_sun = self.sun_direction.1.clamp(0., 1.).acos();
+
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let [a, b, c, d, e] = self.perez[i];
+            let perez = |cos_theta: f64, cos_gamma: f64| {
+                (1. + a * (b / cos_theta).exp())
+                    * (1. + c * (d * cos_gamma.acos()).exp() + e * cos_gamma * cos_gamma)
+            };
+            self.zenith[i] * perez(cos_theta, cos_gamma) / perez(1., theta_sun.cos())
+        });
+
+        let xyz = Vec3::new(x / y * luminance, luminance, (1. - x - y) / y * luminance);
+        let rgb = xyz_to_linear_srgb(xyz);
+        Vec3::new(rgb.0.max(0.), rgb.1.max(0.), rgb.2.max(0.))
+    }
+}
+
+/// The fraction of sunlight at the zenith angle `theta_sun` that crosses the atmosphere, through
+/// Rayleigh and aerosol scattering at the wavelengths of the red, green and blue primaries.
+fn sun_transmittance(theta_sun: f64, is_up: bool, turbidity: f64) -> Vec3 {
+    if !is_up {
+        return Vec3::default();
+    }
+    let relative_air_mass =
+        1. / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let [red, green, blue] = [0.630, 0.532, 0.465].map(|micrometres: f64| {
+        let rayleigh = -0.008735 * micrometres.powf(-4.08);
+        let aerosol = -beta * micrometres.powf(-1.3);
+        ((rayleigh + aerosol) * relative_air_mass).exp()
+    });
+    Vec3::new(red, green, blue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhysicalSky;
+    use crate::prelude::{luminance, Angle, Degrees, Light, LinAlgOp, Point, Ray, Vec3};
+
+    fn sky(elevation: f64, turbidity: f64) -> PhysicalSky {
+        PhysicalSky::new(
+            Angle::Degrees(Degrees(elevation)),
+            Angle::Degrees(Degrees(30.)),
+            turbidity,
+            Vec3::new(0.3, 0.3, 0.3),
+        )
+    }
+
+    #[test]
+    fn sky_is_brighter_towards_the_sun_and_blue_above() {
+        let sky = sky(30., 3.);
+        let sun = sky.sun_direction();
+        let near_sun = (sun + Vec3::new(0., 0.1, 0.)).unit_vector();
+        let away = Vec3::new(-sun.0, 0.5, -sun.2).unit_vector();
+        assert!(luminance(sky.radiance(near_sun)) > 2. * luminance(sky.radiance(away)));
+
+        let zenith = sky.radiance(Vec3::new(0., 1., 0.));
+        assert!(zenith.2 > zenith.0);
+        assert!((luminance(zenith) - sky.scale * sky.zenith[0]).abs() < 0.02 * luminance(zenith));
+    }
+
+    #[test]
+    fn ground_reflects_sun_and_sky() {
+        // The scale makes a white floor in the open come out at one.
+        for (elevation, turbidity) in [(60., 2.), (20., 5.), (5., 9.)] {
+            let sky = sky(elevation, turbidity);
+            let ground = luminance(sky.radiance(Vec3::new(0.2, -1., 0.).unit_vector()));
+            assert!((ground - 0.3).abs() < 1e-9, "{}", ground);
+        }
+    }
+
+    #[test]
+    fn sunlight_reddens_through_more_air() {
+        let sunlight = |elevation, turbidity| {
+            let sky = sky(elevation, turbidity);
+            sky.sun()
+                .sample(Point::new(0., 0., 0.), &Ray::default())
+                .unwrap()
+                .radiance
+                / sky.scale
+        };
+        let (noon, evening) = (sunlight(70., 3.), sunlight(5., 3.));
+        assert!(noon.1 > evening.1);
+        assert!(evening.0 / evening.2 > noon.0 / noon.2);
+        assert!(sunlight(40., 9.).1 < sunlight(40., 2.).1);
+        assert_eq!(sunlight(-5., 3.), Vec3::default());
+    }
+}
@@ -0,0 +1,547 @@
+//! Spectral rendering support.
+//!
+//! In spectral mode each path carries three wavelengths in place of the red, green and blue
+//! channels, so that a `Vec3` of throughput or radiance holds one value per wavelength. RGB
+//! material parameters are upsampled to smooth reflectance spectra with the sigmoid-polynomial
+//! model of Jakob and Hanika ("A Low-Dimensional Function Space for Efficient Spectral Upsampling",
+//! 2019), and each sample's radiance is converted to XYZ and then linear sRGB.
+use crate::prelude::{xyz_to_linear_srgb, Point, Ray, Vec3};
+use std::sync::OnceLock;
+
+/// The range of wavelengths that are rendered, in nanometres.
+pub const LAMBDA_MIN: f64 = 360.;
+pub const LAMBDA_MAX: f64 = 830.;
+
+/// Representative wavelengths of the red, green and blue channels, for spectral data used in RGB
+/// mode.
+pub const RGB_WAVELENGTHS: [f64; 3] = [630., 532., 465.];
+
+/// Spacing of the quadrature used to integrate spectra, in nanometres.
+const STEP: f64 = 5.;
+
+/// The wavelengths carried by one camera path, and the densities they were sampled with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampledWavelengths {
+    /// The first wavelength is the hero, which survives when the others have to be dropped.
+    pub lambda: [f64; 3],
+    pub pdf: [f64; 3],
+}
+
+impl SampledWavelengths {
+    /// Sample a hero wavelength from `u`, and two companions evenly spaced from it in sample space.
+    pub fn sample(u: f64) -> Self {
+        let mut lambda = [0.; 3];
+        let mut pdf = [0.; 3];
+
+        for index in 0..3 {
+            let u = (u + index as f64 / 3.).fract();
+            lambda[index] = sample_visible_wavelength(u);
+            pdf[index] = visible_wavelength_pdf(lambda[index]);
+        }
+        Self { lambda, pdf }
+    }
+
+    /// Convert radiance carried at these wavelengths to linear sRGB.
+    pub fn to_rgb(&self, radiance: Vec3) -> Vec3 {
+        let colorimetry = colorimetry();
+        let values = [radiance.0, radiance.1, radiance.2];
+
+        let mut xyz = Vec3::default();
+        for ((value, lambda), pdf) in values.iter().zip(self.lambda).zip(self.pdf) {
+            if pdf > 0. {
+                xyz += value / pdf * cie_xyz(lambda);
+            }
+        }
+        colorimetry.xyz_to_rgb(xyz / (3. * colorimetry.y_integral))
+    }
+}
+
+/// Sample wavelengths in proportion to how visible they are (Radziszewski et al., 2009), which
+/// converges faster than sampling them uniformly.
+pub fn sample_visible_wavelength(u: f64) -> f64 {
+    538. - 138.888889 * (0.85691062 - 1.82750197 * u).atanh()
+}
+
+pub fn visible_wavelength_pdf(lambda: f64) -> f64 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.)).cosh().powi(2)
+}
+
+/// The CIE 1931 colour matching functions, from the multi-lobe Gaussian fit of Wyman, Sloan and
+/// Shirley ("Simple Analytic Approximations to the CIE XYZ Color Matching Functions", 2013).
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let spread = if lambda < mean { below } else { above };
+        (-0.5 * ((lambda - mean) / spread).powi(2)).exp()
+    };
+
+    Vec3::new(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// The CIE standard illuminant D65 from 360 to 830 nm in 10 nm steps, relative to 100 at 560 nm.
+const D65: [f64; 48] = [
+    46.6383, 52.0891, 49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008,
+    117.812, 114.861, 115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.,
+    96.3342, 95.788, 88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146,
+    82.2778, 78.2842, 69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054,
+    63.3828, 64.304, 59.4519, 51.959, 57.4406, 60.3125,
+];
+
+fn d65_relative(lambda: f64) -> f64 {
+    let position = ((lambda - LAMBDA_MIN) / 10.).clamp(0., (D65.len() - 1) as f64);
+    let index = (position.floor() as usize).min(D65.len() - 2);
+    let fraction = position - index as f64;
+    (1. - fraction) * D65[index] + fraction * D65[index + 1]
+}
+
+/// The wavelengths and widths of the quadrature over the rendered range.
+fn quadrature() -> impl Iterator<Item = (f64, f64)> {
+    let steps = ((LAMBDA_MAX - LAMBDA_MIN) / STEP) as usize;
+    (0..=steps).map(move |step| {
+        let weight = if step == 0 || step == steps {
+            0.5 * STEP
+        } else {
+            STEP
+        };
+        (LAMBDA_MIN + step as f64 * STEP, weight)
+    })
+}
+
+/// Normalisation constants shared by every spectral conversion.
+struct Colorimetry {
+    /// Integral of the `y` colour matching function, so that a constant spectrum of one has Y = 1.
+    y_integral: f64,
+    /// Scales D65 so that it has Y = 1.
+    d65_scale: f64,
+    /// Per channel correction that maps D65 to exactly white despite the approximate CMFs.
+    white_balance: Vec3,
+}
+
+impl Colorimetry {
+    fn xyz_to_rgb(&self, xyz: Vec3) -> Vec3 {
+        self.white_balance * xyz_to_linear_srgb(xyz)
+    }
+}
+
+fn colorimetry() -> &'static Colorimetry {
+    static COLORIMETRY: OnceLock<Colorimetry> = OnceLock::new();
+
+    COLORIMETRY.get_or_init(|| {
+        let mut y_integral = 0.;
+        let mut d65_xyz = Vec3::default();
+        for (lambda, weight) in quadrature() {
+            let cmf = cie_xyz(lambda);
+            y_integral += weight * cmf.1;
+            d65_xyz += weight * d65_relative(lambda) * cmf;
+        }
+        let d65_xyz = d65_xyz / y_integral;
+        let white = xyz_to_linear_srgb(d65_xyz / d65_xyz.1);
+
+        Colorimetry {
+            y_integral,
+            d65_scale: 1. / d65_xyz.1,
+            white_balance: Vec3::new(1. / white.0, 1. / white.1, 1. / white.2),
+        }
+    })
+}
+
+/// D65 scaled so that it has unit luminance, the spectrum of RGB white light.
+pub fn d65(lambda: f64) -> f64 {
+    colorimetry().d65_scale * d65_relative(lambda)
+}
+
+/// A smooth spectrum bounded by `[0, 1]`: a sigmoid of a quadratic in normalised wavelength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SigmoidPolynomial {
+    pub coefficients: [f64; 3],
+}
+
+impl SigmoidPolynomial {
+    pub fn evaluate(&self, lambda: f64) -> f64 {
+        sigmoid(self.polynomial(normalised_wavelength(lambda)))
+    }
+
+    fn polynomial(&self, t: f64) -> f64 {
+        let [c0, c1, c2] = self.coefficients;
+        (c0 * t + c1) * t + c2
+    }
+}
+
+fn normalised_wavelength(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+fn sigmoid(x: f64) -> f64 {
+    if x.is_infinite() {
+        return if x > 0. { 1. } else { 0. };
+    }
+    0.5 + x / (2. * (1. + x * x).sqrt())
+}
+
+/// Resolution of the upsampling table along each chromaticity axis and along brightness.
+const TABLE_RESOLUTION: usize = 16;
+
+/// The darkest brightness level that is looked up. Fits below it are steep enough that
+/// interpolating between them loses the color, so darker colors are scaled from this level.
+const DARKEST_LEVEL: usize = 3;
+
+/// Fitted sigmoid polynomials over a grid of RGB colors, laid out as in Jakob and Hanika: for each
+/// choice of largest channel, by the largest channel's value and the ratios of the other two to it.
+struct UpsamplingTable {
+    /// Values of the largest channel at each brightness level, increasing to one.
+    brightness: [f64; TABLE_RESOLUTION],
+    coefficients: Vec<[f64; 3]>,
+}
+
+impl UpsamplingTable {
+    fn index(largest: usize, z: usize, x: usize, y: usize) -> usize {
+        ((largest * TABLE_RESOLUTION + z) * TABLE_RESOLUTION + x) * TABLE_RESOLUTION + y
+    }
+
+    fn build() -> Self {
+        let brightness: [f64; TABLE_RESOLUTION] = std::array::from_fn(|level| {
+            let smoothstep = |x: f64| x * x * (3. - 2. * x);
+            smoothstep(smoothstep((level + 1) as f64 / TABLE_RESOLUTION as f64))
+        });
+
+        // The linear sRGB each wavelength of the quadrature contributes under D65, so that the
+        // color of a spectrum is the weighted sum of its values.
+        let colorimetry = colorimetry();
+        let weights = quadrature()
+            .map(|(lambda, weight)| {
+                let xyz = weight * d65(lambda) * cie_xyz(lambda) / colorimetry.y_integral;
+                (normalised_wavelength(lambda), colorimetry.xyz_to_rgb(xyz))
+            })
+            .collect::<Vec<_>>();
+
+        let mut coefficients = vec![[0.; 3]; 3 * TABLE_RESOLUTION.pow(3)];
+        let grid = |index: usize| index as f64 / (TABLE_RESOLUTION - 1) as f64;
+
+        for largest in 0..3 {
+            for x in 0..TABLE_RESOLUTION {
+                for y in 0..TABLE_RESOLUTION {
+                    // Fit from the brightest level down, starting each fit from the last.
+                    let mut guess = [0., 0., 0.];
+                    for z in (DARKEST_LEVEL..TABLE_RESOLUTION).rev() {
+                        let mut target = [0.; 3];
+                        target[largest] = brightness[z];
+                        target[(largest + 1) % 3] = grid(x) * brightness[z];
+                        target[(largest + 2) % 3] = grid(y) * brightness[z];
+
+                        guess = fit_sigmoid_polynomial(&weights, target.into(), guess);
+                        coefficients[Self::index(largest, z, x, y)] = guess;
+                    }
+                }
+            }
+        }
+
+        Self {
+            brightness,
+            coefficients,
+        }
+    }
+
+    /// Interpolate the fitted spectrum of a color whose largest channel is within the table.
+    fn lookup(&self, rgb: [f64; 3]) -> SigmoidPolynomial {
+        let largest = (0..3)
+            .max_by(|&a, &b| rgb[a].partial_cmp(&rgb[b]).unwrap())
+            .unwrap();
+        let z = rgb[largest];
+        let x = rgb[(largest + 1) % 3] / z * (TABLE_RESOLUTION - 1) as f64;
+        let y = rgb[(largest + 2) % 3] / z * (TABLE_RESOLUTION - 1) as f64;
+
+        let z_index = self
+            .brightness
+            .iter()
+            .rposition(|&level| level <= z)
+            .unwrap_or(0)
+            .min(TABLE_RESOLUTION - 2);
+        let z_fraction = ((z - self.brightness[z_index])
+            / (self.brightness[z_index + 1] - self.brightness[z_index]))
+            .clamp(0., 1.);
+        let x_index = (x.floor() as usize).min(TABLE_RESOLUTION - 2);
+        let y_index = (y.floor() as usize).min(TABLE_RESOLUTION - 2);
+        let (x_fraction, y_fraction) = (x - x_index as f64, y - y_index as f64);
+
+        let mut coefficients = [0.; 3];
+        for (dz, wz) in [(0, 1. - z_fraction), (1, z_fraction)] {
+            for (dx, wx) in [(0, 1. - x_fraction), (1, x_fraction)] {
+                for (dy, wy) in [(0, 1. - y_fraction), (1, y_fraction)] {
+                    let corner = self.coefficients
+                        [Self::index(largest, z_index + dz, x_index + dx, y_index + dy)];
+                    for (total, value) in coefficients.iter_mut().zip(corner) {
+                        *total += wz * wx * wy * value;
+                    }
+                }
+            }
+        }
+        SigmoidPolynomial { coefficients }
+    }
+}
+
+fn upsampling_table() -> &'static UpsamplingTable {
+    static TABLE: OnceLock<UpsamplingTable> = OnceLock::new();
+    TABLE.get_or_init(UpsamplingTable::build)
+}
+
+/// Fit the coefficients of a sigmoid polynomial whose color under D65 is `target`, with
+/// Levenberg-Marquardt iterations from `guess`. Residuals are relative to the target's brightness.
+fn fit_sigmoid_polynomial(weights: &[(f64, Vec3)], target: Vec3, guess: [f64; 3]) -> [f64; 3] {
+    let scale = 1. / target.0.max(target.1).max(target.2);
+    let residual = |coefficients: [f64; 3]| {
+        let polynomial = SigmoidPolynomial { coefficients };
+        let color = weights
+            .iter()
+            .map(|&(t, rgb)| sigmoid(polynomial.polynomial(t)) * rgb)
+            .fold(Vec3::default(), |total, value| total + value);
+        scale * (color - target)
+    };
+
+    let mut coefficients = guess;
+    let mut error = residual(coefficients);
+    let mut damping = 1e-3;
+
+    for _ in 0..100 {
+        if error.norm_squared() < 1e-14 {
+            break;
+        }
+
+        // Jacobian columns, one per coefficient.
+        let polynomial = SigmoidPolynomial { coefficients };
+        let mut jacobian = [Vec3::default(); 3];
+        for &(t, rgb) in weights {
+            let x = polynomial.polynomial(t);
+            let slope = 0.5 / (1. + x * x).powf(1.5);
+            jacobian[0] += scale * slope * t * t * rgb;
+            jacobian[1] += scale * slope * t * rgb;
+            jacobian[2] += scale * slope * rgb;
+        }
+
+        let dot = |a: Vec3, b: Vec3| a.0 * b.0 + a.1 * b.1 + a.2 * b.2;
+        let mut normal = [[0.; 3]; 3];
+        let mut gradient = [0.; 3];
+        for row in 0..3 {
+            for column in 0..3 {
+                normal[row][column] = dot(jacobian[row], jacobian[column]);
+            }
+            gradient[row] = -dot(jacobian[row], error);
+        }
+        for (row, values) in normal.iter_mut().enumerate() {
+            values[row] *= 1. + damping;
+        }
+
+        let step = match solve_3x3(normal, gradient) {
+            Some(step) => step,
+            None => break,
+        };
+        let candidate = [
+            coefficients[0] + step[0],
+            coefficients[1] + step[1],
+            coefficients[2] + step[2],
+        ];
+        let candidate_error = residual(candidate);
+
+        if candidate_error.norm_squared() < error.norm_squared()
+            && candidate.iter().all(|c| c.is_finite())
+        {
+            coefficients = candidate;
+            error = candidate_error;
+            damping = (damping * 0.3).max(1e-9);
+        } else {
+            damping *= 10.;
+            if damping > 1e10 {
+                break;
+            }
+        }
+    }
+    coefficients
+}
+
+fn solve_3x3(matrix: [[f64; 3]; 3], rhs: [f64; 3]) -> Option<[f64; 3]> {
+    let determinant = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let full = determinant(matrix);
+    if full.abs() < 1e-300 || !full.is_finite() {
+        return None;
+    }
+
+    // Cramer's rule.
+    let mut solution = [0.; 3];
+    for (column, value) in solution.iter_mut().enumerate() {
+        let mut replaced = matrix;
+        for row in 0..3 {
+            replaced[row][column] = rhs[row];
+        }
+        *value = determinant(replaced) / full;
+    }
+    Some(solution)
+}
+
+/// The reflectance spectrum of a linear sRGB color, at each of `lambda`.
+///
+/// Colors brighter than one (as weights may be) or very dark are scaled into the table and back,
+/// which keeps their chromaticity.
+pub fn reflectance_spectrum(rgb: Vec3, lambda: &[f64; 3]) -> Vec3 {
+    let rgb = [rgb.0.max(0.), rgb.1.max(0.), rgb.2.max(0.)];
+    let z = rgb[0].max(rgb[1]).max(rgb[2]);
+    if z <= 0. {
+        return Vec3::default();
+    }
+    // Grays are flat spectra.
+    if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+        return Vec3::new(z, z, z);
+    }
+
+    let table = upsampling_table();
+    let in_table = z.clamp(table.brightness[DARKEST_LEVEL], 1.);
+    let spectrum = table.lookup(rgb.map(|channel| channel * in_table / z));
+    let scale = z / in_table;
+
+    Vec3::new(
+        scale * spectrum.evaluate(lambda[0]),
+        scale * spectrum.evaluate(lambda[1]),
+        scale * spectrum.evaluate(lambda[2]),
+    )
+}
+
+/// The spectrum of light with a linear sRGB color, at each of `lambda`: the color's reflectance
+/// spectrum lit by D65.
+pub fn illuminant_spectrum(rgb: Vec3, lambda: &[f64; 3]) -> Vec3 {
+    reflectance_spectrum(rgb, lambda) * Vec3::new(d65(lambda[0]), d65(lambda[1]), d65(lambda[2]))
+}
+
+impl Ray {
+    /// A ray leaving `origin` after scattering off something, carrying this ray's wavelengths.
+    pub fn spawn(&self, origin: &Point, direction: &Vec3) -> Ray {
+        let mut ray = Ray::new(origin, direction);
+        ray.wavelengths = self.wavelengths;
+        ray
+    }
+
+    /// An RGB reflectance as this ray sees it: unchanged in RGB mode, or at its wavelengths.
+    pub fn reflectance(&self, rgb: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(lambda) => reflectance_spectrum(rgb, lambda),
+            None => rgb,
+        }
+    }
+
+    /// An RGB emission as this ray sees it: unchanged in RGB mode, or at its wavelengths.
+    pub fn radiance(&self, rgb: Vec3) -> Vec3 {
+        match &self.wavelengths {
+            Some(lambda) => illuminant_spectrum(rgb, lambda),
+            None => rgb,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, Rng};
+
+    /// The linear sRGB color of a reflectance spectrum under D65, by quadrature.
+    fn color_of(spectrum: impl Fn(f64) -> f64) -> Vec3 {
+        let colorimetry = colorimetry();
+        let xyz = quadrature().fold(Vec3::default(), |total, (lambda, weight)| {
+            total + weight * spectrum(lambda) * d65(lambda) * cie_xyz(lambda)
+        });
+        colorimetry.xyz_to_rgb(xyz / colorimetry.y_integral)
+    }
+
+    #[test]
+    fn color_matching_functions() {
+        let colorimetry = colorimetry();
+        assert!((colorimetry.y_integral - 106.9).abs() < 1.);
+
+        let peak = (400..700)
+            .max_by(|&a, &b| {
+                cie_xyz(a as f64)
+                    .1
+                    .partial_cmp(&cie_xyz(b as f64).1)
+                    .unwrap()
+            })
+            .unwrap();
+        assert!((550..=570).contains(&peak));
+
+        // D65 is white, and the white balance only nudges the approximate CMFs.
+        let white = color_of(|_| 1.);
+        assert!((white - Vec3::new(1., 1., 1.)).norm() < 1e-9);
+        let balance = colorimetry.white_balance;
+        assert!((balance - Vec3::new(1., 1., 1.)).norm() < 0.05);
+    }
+
+    #[test]
+    fn wavelength_sampling_covers_the_visible_range() {
+        let mut rng = thread_rng();
+        for _ in 0..1000 {
+            let wavelengths = SampledWavelengths::sample(rng.gen());
+            for (lambda, pdf) in wavelengths.lambda.iter().zip(wavelengths.pdf) {
+                assert!((LAMBDA_MIN..=LAMBDA_MAX).contains(lambda));
+                assert!((pdf - visible_wavelength_pdf(*lambda)).abs() < 1e-12);
+            }
+        }
+
+        let integral: f64 = quadrature()
+            .map(|(lambda, weight)| weight * visible_wavelength_pdf(lambda))
+            .sum();
+        assert!((integral - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn upsampled_spectra_reproduce_their_color() {
+        let table = upsampling_table();
+        for rgb in [
+            Vec3::new(0.2, 0.5, 0.8),
+            Vec3::new(0.9, 0.1, 0.1),
+            Vec3::new(0.05, 0.3, 0.02),
+            Vec3::new(0.7, 0.6, 0.1),
+            Vec3::new(0.01, 0.004, 0.008),
+        ] {
+            let z = rgb.0.max(rgb.1).max(rgb.2);
+            let in_table = z.max(table.brightness[DARKEST_LEVEL]);
+            let spectrum = table.lookup([rgb.0, rgb.1, rgb.2].map(|c| c * in_table / z));
+
+            let color = color_of(|lambda| spectrum.evaluate(lambda)) * (z / in_table);
+            assert!(
+                (color - rgb).norm() < 0.03 * z.max(0.1),
+                "{} became {}",
+                rgb,
+                color
+            );
+            assert!((0..=100).all(|step| {
+                let value = spectrum.evaluate(LAMBDA_MIN + step as f64 * 4.7);
+                (0. ..=1.).contains(&value)
+            }));
+        }
+    }
+
+    #[test]
+    fn spectral_estimate_of_white_light_is_white() {
+        let mut rng = thread_rng();
+        let samples = 20_000;
+        let mut total = Vec3::default();
+
+        for _ in 0..samples {
+            let wavelengths = SampledWavelengths::sample(rng.gen());
+            let radiance = illuminant_spectrum(Vec3::new(1., 1., 1.), &wavelengths.lambda);
+            total += wavelengths.to_rgb(radiance);
+        }
+        let average = total / samples as f64;
+        assert!(
+            (average - Vec3::new(1., 1., 1.)).norm() < 0.03,
+            "{}",
+            average
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use crate::prelude::{Medium, Vec3};
+
+impl Medium {
+    /// What scatters light beneath the surface of an object like skin, wax, milk or marble, to
+    /// fill it as a `Nested` one. Its boundary's material is the interface light crosses, typically
+    /// a `Dielectric`.
+    ///
+    /// `albedo` is the color of the object when lit from all sides, which takes a much whiter
+    /// single-scattering albedo after many collisions, and `mean_free_path` the mean distance
+    /// between collisions, both per channel. Light takes a random walk inside, tracked like in any
+    /// other medium, with many bounces that `RenderConfig::max_depth` needs to allow for.
+    pub fn subsurface(albedo: Vec3, mean_free_path: Vec3) -> Self {
+        let extinction = Vec3::new(
+            1. / mean_free_path.0,
+            1. / mean_free_path.1,
+            1. / mean_free_path.2,
+        );
+        let scattering = single_scattering_albedo(albedo) * extinction;
+        Self::new(extinction - scattering, scattering)
+    }
+}
+
+/// Invert the reflectance of a thick, index-matched medium for the albedo of each collision that
+/// produces it, with the fit of Chiang et al. ("Practical and Controllable Subsurface Scattering
+/// for Production Path Tracing", 2016).
+pub fn single_scattering_albedo(multiple_scattering_albedo: Vec3) -> Vec3 {
+    let invert = |albedo: f64| {
+        let albedo = albedo.clamp(0., 1.);
+        let root = 4.09712 + 4.20863 * albedo
+            - (9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo).sqrt();
+        1. - root * root
+    };
+    Vec3::new(
+        invert(multiple_scattering_albedo.0),
+        invert(multiple_scattering_albedo.1),
+        invert(multiple_scattering_albedo.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::{
+        Flight, Hittable, LinAlgRandGen, Material, Medium, MediumStack, Nested, Point, Ray,
+        Scatter, Sphere, Vec3,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn thick_media_reflect_their_albedo() {
+        // A huge index-matched sphere lit evenly from above is a semi-infinite slab. Channels
+        // differ in how far light goes between collisions, which must not change their color.
+        let interface = Arc::new(Material::dielectric(1.));
+        let boundary = Arc::new(Sphere::new(Point::new(0., -1000., 0.), 1000., interface));
+        let albedo = Vec3::new(0.8, 0.5, 0.2);
+        let mean_free_path = Vec3::new(0.012, 0.01, 0.008);
+        let object = Nested::new(
+            boundary,
+            Some(Medium::subsurface(albedo, mean_free_path)),
+            1,
+        );
+
+        let walks = 4_000;
+        let mut reflected = Vec3::default();
+        for _ in 0..walks {
+            let direction = -(Vec3::new(0., 1., 0.) + Vec3::random_unit_vector());
+            let mut ray = Ray::new(&-direction, &direction);
+            let mut throughput = Vec3::new(1., 1., 1.);
+            let mut media = MediumStack::default();
+
+            for _ in 0..10_000 {
+                let Some(mut hit) = object.hit(&ray, 1e-9, f64::INFINITY) else {
+                    reflected += throughput;
+                    break;
+                };
+                if let Some(medium) = media.medium() {
+                    match medium.sample(&ray, 1e-9, hit.time) {
+                        Flight::Scattered { time, weight } => {
+                            throughput = throughput * weight;
+                            hit = medium.collision(&ray, time);
+                        }
+                        Flight::Absorbed => break,
+                        Flight::Passed { weight } => throughput = throughput * weight,
+                    }
+                }
+                let Some((attenuation, scattered)) = hit.material.scatter(&ray, &hit) else {
+                    break;
+                };
+                throughput = throughput * attenuation;
+                media = media.toward(&hit, scattered.direction);
+                ray = scattered;
+            }
+        }
+
+        let estimate = reflected / walks as f64;
+        // The fit is good to a few percent.
+        let error = estimate - albedo;
+        assert!(
+            [error.0, error.1, error.2].iter().all(|e| e.abs() < 0.06),
+            "{}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn mean_free_paths_set_the_extinction() {
+        let medium = Medium::subsurface(Vec3::new(0.9, 0.5, 0.1), Vec3::new(1., 0.5, 0.25));
+        let extinction = medium.absorption + medium.scattering;
+        assert!((extinction - Vec3::new(1., 2., 4.)).norm() < 1e-12);
+        // Whiter colors scatter more of what they meet.
+        let albedo = medium.scattering / extinction;
+        assert!(albedo.0 > albedo.1 && albedo.1 > albedo.2 && albedo.0 < 1.);
+    }
+}
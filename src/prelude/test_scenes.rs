@@ -0,0 +1,107 @@
+//! Scenes and checks shared by the tests of the integrators.
+use crate::prelude::{
+    luminance, pixel_ray, ray_color, Angle, Camera, Degrees, Hittable, HittableList, Image, Light,
+    Material, Point, Quad, RenderConfig, Scene, Sphere, SphereLight, Vec3,
+};
+use std::sync::Arc;
+
+/// `objects` on a floor of the given albedo, in a black room lit by `light`.
+pub fn room(floor: f64, objects: Vec<Arc<dyn Hittable>>, light: Arc<dyn Light>) -> Scene {
+    let mut world = HittableList::new();
+    world.push(Arc::new(Quad::new(
+        Point::new(-4., 0., -4.),
+        Vec3::new(0., 0., 8.),
+        Vec3::new(8., 0., 0.),
+        Arc::new(Material::lambertian(Vec3::new(floor, floor, floor))),
+    )));
+    for object in objects {
+        world.push(object);
+    }
+    world.push(Arc::new(Sphere::new(
+        Point::new(0., 0., 0.),
+        20.,
+        Arc::new(Material::lambertian(Vec3::new(0., 0., 0.))),
+    )));
+    let mut scene = Scene::new(world);
+    scene.push_light(light);
+    scene
+}
+
+/// A glass ball hovering over the floor.
+pub fn glass_ball(index_of_refraction: f64) -> Arc<dyn Hittable> {
+    Arc::new(Sphere::new(
+        Point::new(0., 1., 0.),
+        0.6,
+        Arc::new(Material::dielectric(index_of_refraction)),
+    ))
+}
+
+/// A ball of `material` on a gray floor, lit by a small light off to the side and casting a
+/// shadow, as `lit_ball_camera` sees it.
+pub fn lit_ball(material: Material) -> Scene {
+    room(
+        0.5,
+        vec![Arc::new(Sphere::new(
+            Point::new(0., 0.5, 0.),
+            0.5,
+            Arc::new(material),
+        ))],
+        Arc::new(SphereLight::new(
+            Point::new(1., 2.5, 0.),
+            0.8,
+            Vec3::new(2., 2., 2.),
+        )),
+    )
+}
+
+pub fn lit_ball_camera() -> Camera {
+    camera(Point::new(0., 2., 3.), Point::new(0., 0.3, 0.), 40.)
+}
+
+/// A pinhole camera with a square image, upright.
+pub fn camera(look_from: Point, look_at: Point, vfov: f64) -> Camera {
+    Camera::new(
+        look_from,
+        look_at,
+        Vec3::new(0., 1., 0.),
+        Angle::Degrees(Degrees(vfov)),
+        1.,
+        0.,
+        1.,
+    )
+}
+
+/// Asserts that `colors`, rendered into the 8 by 8 `image`, agree with path tracing to within a
+/// fifth over each quarter of the image.
+pub fn assert_agrees_with_path_tracing(
+    colors: &[Vec3],
+    camera: &Camera,
+    scene: &Scene,
+    image: &Image,
+    render_config: &RenderConfig,
+) {
+    let quarter = |index: usize| index / 32 * 2 + index % 8 / 4;
+    let mut estimates = [0.; 4];
+    let mut references = [0.; 4];
+    for (index, color) in colors.iter().enumerate() {
+        let (row, col) = (7 - index / 8, index % 8);
+        estimates[quarter(index)] += luminance(*color) / 16.;
+        let samples = 1000;
+        references[quarter(index)] += (0..samples)
+            .map(|_| {
+                let (ray, _) = pixel_ray(row, col, camera, image, render_config);
+                luminance(ray_color(&ray, scene, render_config.max_depth))
+            })
+            .sum::<f64>()
+            / samples as f64
+            / 16.;
+    }
+    for (estimate, reference) in estimates.iter().zip(references) {
+        assert!(
+            (estimate - reference).abs() < 0.2 * reference,
+            "{:?} and {:?}",
+            estimates,
+            references
+        );
+    }
+}
@@ -0,0 +1,608 @@
+use crate::prelude::{
+    ColorEncoding, FilterMode, HitRecord, LinAlgOp, MipMap, Point, Ray, TextureImage, Vec3,
+    VoxelGrid,
+};
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use std::fmt::{Debug, Formatter};
+use std::io::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A spatially varying material parameter, evaluated at surface coordinates `(u, v)` and the
+/// world-space point being shaded.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, point: Point) -> Vec3;
+
+    /// The texture as a single number, for parameters like roughness that are not colors.
+    fn scalar(&self, u: f64, v: f64, point: Point) -> f64 {
+        luminance(self.value(u, v, point))
+    }
+
+    /// The texture averaged over a pixel footprint. Textures that cannot alias ignore it.
+    fn value_filtered(&self, u: f64, v: f64, point: Point, _derivatives: &UvDerivatives) -> Vec3 {
+        self.value(u, v, point)
+    }
+
+    fn value_at(&self, hit_record: &HitRecord) -> Vec3 {
+        match &hit_record.uv_derivatives {
+            Some(derivatives) => {
+                self.value_filtered(hit_record.u, hit_record.v, hit_record.point, derivatives)
+            }
+            None => self.value(hit_record.u, hit_record.v, hit_record.point),
+        }
+    }
+
+    fn scalar_at(&self, hit_record: &HitRecord) -> f64 {
+        self.scalar(hit_record.u, hit_record.v, hit_record.point)
+    }
+
+    fn metadata(&self) -> String {
+        String::from("Unknown")
+    }
+}
+
+impl Debug for dyn Texture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Texture {{ metadata: {}}}", self.metadata())
+    }
+}
+
+/// How texture coordinates change between neighbouring pixels, i.e. the footprint of a pixel in
+/// texture space.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UvDerivatives {
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+impl UvDerivatives {
+    /// Project a ray's differentials onto the tangent plane at a hit, and express the offsets in
+    /// terms of the surface's partial derivatives `dpdu` and `dpdv`.
+    pub fn from_ray(ray: &Ray, point: Point, normal: Vec3, dpdu: Vec3, dpdv: Vec3) -> Option<Self> {
+        let differentials = ray.differentials.as_ref()?;
+
+        let plane_offset = normal.dot(point);
+        let project = |origin: Point, direction: Vec3| {
+            let time = (plane_offset - normal.dot(origin)) / normal.dot(direction);
+            time.is_finite().then(|| origin + time * direction - point)
+        };
+        let dpdx = project(differentials.x_origin, differentials.x_direction)?;
+        let dpdy = project(differentials.y_origin, differentials.y_direction)?;
+
+        // Least squares solution of dpdu * du + dpdv * dv = dp.
+        let (a00, a01, a11) = (dpdu.dot(dpdu), dpdu.dot(dpdv), dpdv.dot(dpdv));
+        let determinant = a00 * a11 - a01 * a01;
+        if determinant.abs() < 1e-20 {
+            return None;
+        }
+        let solve = |dp: Vec3| {
+            let (b0, b1) = (dpdu.dot(dp), dpdv.dot(dp));
+            (
+                (a11 * b0 - a01 * b1) / determinant,
+                (a00 * b1 - a01 * b0) / determinant,
+            )
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+
+        Some(Self {
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        })
+    }
+}
+
+/// Relative luminance of a linear sRGB color.
+pub fn luminance(color: Vec3) -> f64 {
+    0.2126 * color.0 + 0.7152 * color.1 + 0.0722 * color.2
+}
+
+impl From<Vec3> for Arc<dyn Texture> {
+    fn from(color: Vec3) -> Self {
+        Arc::new(SolidColor::new(color))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SolidColor {
+    pub color: Vec3,
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> Self {
+        Self { color }
+    }
+
+    /// A constant for scalar parameters.
+    pub fn gray(value: f64) -> Self {
+        Self::new(Vec3::new(value, value, value))
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _point: Point) -> Vec3 {
+        self.color
+    }
+
+    fn scalar(&self, _u: f64, _v: f64, _point: Point) -> f64 {
+        // Constant scalars are stored in every channel; avoid round-off from weighting them.
+        if self.color.0 == self.color.1 && self.color.1 == self.color.2 {
+            self.color.0
+        } else {
+            luminance(self.color)
+        }
+    }
+
+    fn metadata(&self) -> String {
+        format!("SolidColor {{ color: {} }}", self.color)
+    }
+}
+
+/// A checkerboard of cubes of side `scale` filling space.
+pub struct Checker3d {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub scale: f64,
+}
+
+impl Checker3d {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f64) -> Self {
+        Self { even, odd, scale }
+    }
+}
+
+impl Texture for Checker3d {
+    fn value(&self, u: f64, v: f64, point: Point) -> Vec3 {
+        let cell = (point.0 / self.scale).floor()
+            + (point.1 / self.scale).floor()
+            + (point.2 / self.scale).floor();
+
+        if cell.rem_euclid(2.) == 0. {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+
+    fn metadata(&self) -> String {
+        format!("Checker3d {{ scale: {} }}", self.scale)
+    }
+}
+
+/// A checkerboard in texture space with the given number of squares along `u` and `v`.
+pub struct CheckerUv {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub u_squares: f64,
+    pub v_squares: f64,
+}
+
+impl CheckerUv {
+    pub fn new(
+        even: Arc<dyn Texture>,
+        odd: Arc<dyn Texture>,
+        u_squares: f64,
+        v_squares: f64,
+    ) -> Self {
+        Self {
+            even,
+            odd,
+            u_squares,
+            v_squares,
+        }
+    }
+}
+
+impl Texture for CheckerUv {
+    fn value(&self, u: f64, v: f64, point: Point) -> Vec3 {
+        let cell = (u * self.u_squares).floor() + (v * self.v_squares).floor();
+
+        if cell.rem_euclid(2.) == 0. {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "CheckerUv {{ u_squares: {}, v_squares: {} }}",
+            self.u_squares, self.v_squares
+        )
+    }
+}
+
+/// How texture coordinates outside `[0, 1]` map back onto an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    /// Map a (possibly out of range) texel index onto `0..size`.
+    pub fn apply(&self, index: isize, size: usize) -> usize {
+        let size = size as isize;
+        match self {
+            WrapMode::Repeat => index.rem_euclid(size) as usize,
+            WrapMode::Clamp => index.clamp(0, size - 1) as usize,
+            WrapMode::Mirror => {
+                let period = index.rem_euclid(2 * size);
+                if period < size {
+                    period as usize
+                } else {
+                    (2 * size - 1 - period) as usize
+                }
+            }
+        }
+    }
+}
+
+/// An image mapped onto `(u, v)`, filtered over the pixel footprint when ray differentials are
+/// available. `v = 1` is the top of the image.
+pub struct ImageTexture {
+    pub mipmap: Arc<MipMap>,
+    pub wrap: WrapMode,
+    pub filter: FilterMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: Arc<TextureImage>, wrap: WrapMode) -> Self {
+        Self {
+            mipmap: Arc::new(MipMap::new(image.as_ref().clone())),
+            wrap,
+            filter: FilterMode::Trilinear,
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, encoding: ColorEncoding, wrap: WrapMode) -> Result<Self> {
+        Ok(Self::new(
+            Arc::new(TextureImage::open(path, encoding)?),
+            wrap,
+        ))
+    }
+
+    pub fn bilinear(&self, u: f64, v: f64) -> Vec3 {
+        self.mipmap.bilinear(0, u, v, self.wrap)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: Point) -> Vec3 {
+        self.bilinear(u, v)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, _point: Point, derivatives: &UvDerivatives) -> Vec3 {
+        self.mipmap
+            .lookup(self.filter, u, v, Some(derivatives), self.wrap)
+    }
+
+    fn metadata(&self) -> String {
+        let image = &self.mipmap.levels[0];
+        format!(
+            "ImageTexture {{ width: {}, height: {}, wrap: {:?}, filter: {:?} }}",
+            image.width, image.height, self.wrap, self.filter
+        )
+    }
+}
+
+const PERLIN_POINT_COUNT: usize = 256;
+
+/// Gradient noise after Ken Perlin, with random unit gradients and Hermite smoothing.
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutation_x: Vec<usize>,
+    permutation_y: Vec<usize>,
+    permutation_z: Vec<usize>,
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        let gradients = (0..PERLIN_POINT_COUNT)
+            .map(|_| Vec3::rand_uniform(-1., 1.).unit_vector())
+            .collect();
+
+        let permutation = || {
+            let mut indices = (0..PERLIN_POINT_COUNT).collect::<Vec<usize>>();
+            indices.shuffle(&mut thread_rng());
+            indices
+        };
+
+        Self {
+            gradients,
+            permutation_x: permutation(),
+            permutation_y: permutation(),
+            permutation_z: permutation(),
+        }
+    }
+
+    /// Noise in roughly `[-1, 1]`.
+    pub fn noise(&self, point: Point) -> f64 {
+        let (u, v, w) = (
+            point.0 - point.0.floor(),
+            point.1 - point.1.floor(),
+            point.2 - point.2.floor(),
+        );
+        let (i, j, k) = (
+            point.0.floor() as isize,
+            point.1.floor() as isize,
+            point.2.floor() as isize,
+        );
+
+        let (uu, vv, ww) = (
+            u * u * (3. - 2. * u),
+            v * v * (3. - 2. * v),
+            w * w * (3. - 2. * w),
+        );
+        let mask = PERLIN_POINT_COUNT as isize - 1;
+
+        let mut accumulated = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.permutation_x[((i + di) & mask) as usize]
+                        ^ self.permutation_y[((j + dj) & mask) as usize]
+                        ^ self.permutation_z[((k + dk) & mask) as usize]];
+                    let weight = Vec3::new(u - di as f64, v - dj as f64, w - dk as f64);
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+
+                    accumulated += (fi * uu + (1. - fi) * (1. - uu))
+                        * (fj * vv + (1. - fj) * (1. - vv))
+                        * (fk * ww + (1. - fk) * (1. - ww))
+                        * gradient.dot(weight);
+                }
+            }
+        }
+        accumulated
+    }
+
+    /// A sum of `depth` octaves of absolute noise, each twice the frequency and half the weight.
+    pub fn turbulence(&self, point: Point, depth: usize) -> f64 {
+        let mut accumulated = 0.;
+        let mut point = point;
+        let mut weight = 1.;
+
+        for _ in 0..depth {
+            accumulated += weight * self.noise(point);
+            weight *= 0.5;
+            point *= 2.;
+        }
+        accumulated.abs()
+    }
+}
+
+/// Smooth noise mapped to `[0, 1]` and scaled by a color.
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub color: Vec3,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, color: Vec3) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            color,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, point: Point) -> Vec3 {
+        0.5 * (1. + self.noise.noise(self.scale * point)) * self.color
+    }
+
+    fn metadata(&self) -> String {
+        format!("NoiseTexture {{ scale: {} }}", self.scale)
+    }
+}
+
+pub struct TurbulenceTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub depth: usize,
+    pub color: Vec3,
+}
+
+impl TurbulenceTexture {
+    pub fn new(scale: f64, color: Vec3) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            depth: 7,
+            color,
+        }
+    }
+}
+
+impl Texture for TurbulenceTexture {
+    fn value(&self, _u: f64, _v: f64, point: Point) -> Vec3 {
+        self.noise
+            .turbulence(self.scale * point, self.depth)
+            .min(1.)
+            * self.color
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "TurbulenceTexture {{ scale: {}, depth: {} }}",
+            self.scale, self.depth
+        )
+    }
+}
+
+/// Veins of `color` running along the z-axis, perturbed by turbulence.
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub depth: usize,
+    pub color: Vec3,
+}
+
+impl MarbleTexture {
+    pub fn new(scale: f64, color: Vec3) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            depth: 7,
+            color,
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, point: Point) -> Vec3 {
+        let phase = self.scale * point.2 + 10. * self.noise.turbulence(point, self.depth);
+        0.5 * (1. + phase.sin()) * self.color
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "MarbleTexture {{ scale: {}, depth: {} }}",
+            self.scale, self.depth
+        )
+    }
+}
+
+/// A voxel grid looked up at the shaded point and scaled by a color, e.g. volume emission.
+pub struct GridTexture {
+    pub grid: Arc<VoxelGrid>,
+    pub color: Vec3,
+}
+
+impl GridTexture {
+    pub fn new(grid: Arc<VoxelGrid>, color: Vec3) -> Self {
+        Self { grid, color }
+    }
+}
+
+impl Texture for GridTexture {
+    fn value(&self, _u: f64, _v: f64, point: Point) -> Vec3 {
+        self.grid.sample(point) * self.color
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "GridTexture {{ grid: {:?}, color: {} }}",
+            self.grid, self.color
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(value: f64) -> Arc<dyn Texture> {
+        Arc::new(SolidColor::gray(value))
+    }
+
+    #[test]
+    fn checkers_alternate() {
+        let checker = Checker3d::new(solid(0.), solid(1.), 1.);
+        let origin = Point::new(0.5, 0.5, 0.5);
+        assert_eq!(checker.scalar(0., 0., origin), 0.);
+        assert_eq!(checker.scalar(0., 0., origin + Vec3::new(1., 0., 0.)), 1.);
+        assert_eq!(checker.scalar(0., 0., origin + Vec3::new(1., 1., 0.)), 0.);
+        assert_eq!(checker.scalar(0., 0., origin - Vec3::new(1., 0., 0.)), 1.);
+
+        let checker = CheckerUv::new(solid(0.), solid(1.), 4., 2.);
+        assert_eq!(checker.scalar(0.1, 0.1, origin), 0.);
+        assert_eq!(checker.scalar(0.3, 0.1, origin), 1.);
+        assert_eq!(checker.scalar(0.3, 0.6, origin), 0.);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        assert_eq!(WrapMode::Repeat.apply(-1, 4), 3);
+        assert_eq!(WrapMode::Repeat.apply(5, 4), 1);
+        assert_eq!(WrapMode::Clamp.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Clamp.apply(9, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(-1, 4), 0);
+        assert_eq!(WrapMode::Mirror.apply(4, 4), 3);
+        assert_eq!(WrapMode::Mirror.apply(9, 4), 1);
+    }
+
+    #[test]
+    fn image_texture_bilinear() {
+        let black = Vec3::new(0., 0., 0.);
+        let white = Vec3::new(1., 1., 1.);
+        let image = Arc::new(TextureImage::new(2, 1, vec![black, white]));
+        let texture = ImageTexture::new(image, WrapMode::Clamp);
+        let point = Point::default();
+
+        assert_eq!(texture.value(0.25, 0.5, point), black);
+        assert_eq!(texture.value(0.75, 0.5, point), white);
+        assert!((texture.value(0.5, 0.5, point) - 0.5 * white).norm() < 1e-12);
+        // Clamped, so the far left edge is pure black rather than blended with the right texel.
+        assert_eq!(texture.value(0., 0.5, point), black);
+    }
+
+    #[test]
+    fn sphere_uv_derivatives_match_finite_differences() {
+        use crate::prelude::{Hittable, Material, Ray, RayDifferentials, Sphere};
+
+        let sphere = Sphere::new(
+            Point::new(0.3, -0.2, -5.),
+            2.,
+            Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5))),
+        );
+        let origin = Point::new(0., 0., 0.);
+        let direction = Vec3::new(0.1, 0.15, -1.);
+        let step = 1e-4;
+
+        let mut ray = Ray::new(&origin, &direction);
+        ray.differentials = Some(RayDifferentials {
+            x_origin: origin,
+            x_direction: direction + Vec3::new(step, 0., 0.),
+            y_origin: origin,
+            y_direction: direction + Vec3::new(0., step, 0.),
+        });
+
+        let hit = sphere.hit(&ray, 0.001, f64::INFINITY).unwrap();
+        let derivatives = hit.uv_derivatives.unwrap();
+        let offset_uv = |direction: Vec3| {
+            let offset_hit = sphere
+                .hit(&Ray::new(&origin, &direction), 0.001, f64::INFINITY)
+                .unwrap();
+            (offset_hit.u, offset_hit.v)
+        };
+        let (ux, vx) = offset_uv(direction + Vec3::new(step, 0., 0.));
+        let (uy, vy) = offset_uv(direction + Vec3::new(0., step, 0.));
+
+        let tolerance = 1e-2 * step;
+        assert!((derivatives.dudx - (ux - hit.u)).abs() < tolerance);
+        assert!((derivatives.dvdx - (vx - hit.v)).abs() < tolerance);
+        assert!((derivatives.dudy - (uy - hit.u)).abs() < tolerance);
+        assert!((derivatives.dvdy - (vy - hit.v)).abs() < tolerance);
+    }
+
+    #[test]
+    fn noise_textures_stay_in_range() {
+        let noise = NoiseTexture::new(4., Vec3::new(1., 1., 1.));
+        let marble = MarbleTexture::new(4., Vec3::new(1., 1., 1.));
+        let turbulence = TurbulenceTexture::new(4., Vec3::new(1., 1., 1.));
+
+        for _ in 0..1000 {
+            let point = Vec3::rand_uniform(-10., 10.);
+            for texture in [&noise as &dyn Texture, &marble, &turbulence] {
+                let value = texture.scalar(0., 0., point);
+                assert!((-1e-9..=1. + 1e-9).contains(&value), "{}", value);
+            }
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use crate::prelude::{srgb_to_linear, Vec3};
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Result};
+use std::path::Path;
+
+/// How the values stored in an 8 or 16 bit image relate to light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorEncoding {
+    /// Colors such as albedo maps, stored with the sRGB transfer curve.
+    Srgb,
+    /// Data such as roughness or normal maps, stored as-is.
+    Linear,
+}
+
+impl ColorEncoding {
+    fn decode(&self, value: f64) -> f64 {
+        match self {
+            ColorEncoding::Srgb => srgb_to_linear(value),
+            ColorEncoding::Linear => value,
+        }
+    }
+}
+
+/// A linear RGB raster, stored top row first.
+#[derive(Clone, Debug)]
+pub struct TextureImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl TextureImage {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "A texture image needs exactly one value per pixel."
+        );
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Load a PPM (`P3`/`P6`), PNG or PFM (`PF`/`Pf`) image, detected from its contents.
+    ///
+    /// `encoding` applies to the integer formats; PFM files always hold linear values.
+    pub fn open<P: AsRef<Path>>(path: P, encoding: ColorEncoding) -> Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?), encoding)
+    }
+
+    pub fn read<R: Read>(reader: &mut R, encoding: ColorEncoding) -> Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        match bytes.get(..2) {
+            Some(b"P3") | Some(b"P6") => Self::from_ppm(&bytes, encoding),
+            Some(b"PF") | Some(b"Pf") => Self::from_pfm(&bytes),
+            _ if bytes.starts_with(b"\x89PNG") => Self::from_png(&bytes, encoding),
+            _ => Err(invalid_data("Unrecognised image format.")),
+        }
+    }
+
+    pub fn from_ppm(bytes: &[u8], encoding: ColorEncoding) -> Result<Self> {
+        let mut header = HeaderReader::new(bytes);
+        let magic = header.token()?;
+        let width = header.number::<usize>()?;
+        let height = header.number::<usize>()?;
+        let max_value = header.number::<u32>()?;
+
+        if max_value == 0 || max_value > u16::MAX as u32 {
+            return Err(invalid_data("PPM maximum value must be in 1..=65535."));
+        }
+        let samples = raster_size(&[width, height, 3])?;
+        let scale = max_value as f64;
+
+        let values: Vec<f64> = match magic {
+            b"P3" => (0..samples)
+                .map(|_| header.number::<u32>().map(|value| value as f64 / scale))
+                .collect::<Result<Vec<f64>>>()?,
+            b"P6" => {
+                // A single whitespace byte separates the header from the raster.
+                let raster = bytes
+                    .get(header.position + 1..)
+                    .ok_or_else(|| invalid_data("PPM raster is missing."))?;
+                let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+
+                if raster.len() < raster_size(&[samples, bytes_per_sample])? {
+                    return Err(invalid_data("PPM raster is truncated."));
+                }
+                (0..samples)
+                    .map(|index| {
+                        let value = if bytes_per_sample == 1 {
+                            raster[index] as u32
+                        } else {
+                            u16::from_be_bytes([raster[2 * index], raster[2 * index + 1]]) as u32
+                        };
+                        value as f64 / scale
+                    })
+                    .collect()
+            }
+            _ => return Err(invalid_data("Not a PPM image.")),
+        };
+
+        Ok(Self::from_samples(width, height, &values, 3, encoding))
+    }
+
+    pub fn from_png(bytes: &[u8], encoding: ColorEncoding) -> Result<Self> {
+        let (width, height, values, channels) = decode_png(bytes)?;
+        Ok(Self::from_samples(
+            width, height, &values, channels, encoding,
+        ))
+    }
+
+    /// Load the alpha channel of an image as a gray image, such as an opacity mask. Images
+    /// without one are opaque.
+    pub fn open_alpha<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_alpha(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read_alpha<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        if !bytes.starts_with(b"\x89PNG") {
+            let image = Self::read(&mut bytes.as_slice(), ColorEncoding::Linear)?;
+            let opaque = vec![Vec3::new(1., 1., 1.); image.pixels.len()];
+            return Ok(Self::new(image.width, image.height, opaque));
+        }
+
+        let (width, height, values, channels) = decode_png(&bytes)?;
+        let pixels = values
+            .chunks_exact(channels)
+            .map(|pixel| match channels {
+                2 | 4 => {
+                    let alpha = pixel[channels - 1];
+                    Vec3::new(alpha, alpha, alpha)
+                }
+                _ => Vec3::new(1., 1., 1.),
+            })
+            .collect();
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn from_pfm(bytes: &[u8]) -> Result<Self> {
+        let mut header = HeaderReader::new(bytes);
+        let channels = match header.token()? {
+            b"PF" => 3,
+            b"Pf" => 1,
+            _ => return Err(invalid_data("Not a PFM image.")),
+        };
+        let width = header.number::<usize>()?;
+        let height = header.number::<usize>()?;
+        let scale = header.number::<f64>()?;
+        let little_endian = scale < 0.;
+
+        let raster = bytes
+            .get(header.position + 1..)
+            .ok_or_else(|| invalid_data("PFM raster is missing."))?;
+        let samples = raster_size(&[width, height, channels])?;
+        if raster.len() < raster_size(&[samples, 4])? {
+            return Err(invalid_data("PFM raster is truncated."));
+        }
+
+        let mut values = raster[..samples * 4]
+            .chunks_exact(4)
+            .map(|word| {
+                let word = [word[0], word[1], word[2], word[3]];
+                if little_endian {
+                    f32::from_le_bytes(word) as f64
+                } else {
+                    f32::from_be_bytes(word) as f64
+                }
+            })
+            .collect::<Vec<f64>>();
+
+        // PFM rows run from the bottom of the image to the top.
+        values = values
+            .chunks_exact(width * channels)
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+
+        Ok(Self::from_samples(
+            width,
+            height,
+            &values,
+            channels,
+            ColorEncoding::Linear,
+        ))
+    }
+
+    fn from_samples(
+        width: usize,
+        height: usize,
+        values: &[f64],
+        channels: usize,
+        encoding: ColorEncoding,
+    ) -> Self {
+        let pixels = values
+            .chunks_exact(channels)
+            .map(|pixel| match channels {
+                // Gray, possibly with alpha.
+                1 | 2 => {
+                    let gray = encoding.decode(pixel[0]);
+                    Vec3::new(gray, gray, gray)
+                }
+                // RGB, possibly with alpha.
+                _ => Vec3::new(
+                    encoding.decode(pixel[0]),
+                    encoding.decode(pixel[1]),
+                    encoding.decode(pixel[2]),
+                ),
+            })
+            .collect();
+
+        Self::new(width, height, pixels)
+    }
+}
+
+/// Decode a PNG to its size, its samples in `[0, 1]` and the number of channels per pixel.
+fn decode_png(bytes: &[u8]) -> Result<(usize, usize, Vec<f64>, usize)> {
+    let mut decoder = png::Decoder::new(bytes);
+    // Expand palettes and low bit depths so that every sample is a whole byte (or two).
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(invalid_data)?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(invalid_data)?;
+    let buffer = &buffer[..info.buffer_size()];
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => {
+            return Err(invalid_data("PNG palette was not expanded."));
+        }
+    };
+
+    let values: Vec<f64> = match info.bit_depth {
+        png::BitDepth::Sixteen => buffer
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]) as f64 / u16::MAX as f64)
+            .collect(),
+        _ => buffer
+            .iter()
+            .map(|&byte| byte as f64 / u8::MAX as f64)
+            .collect(),
+    };
+
+    Ok((info.width as usize, info.height as usize, values, channels))
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+/// The product of sizes read from a header, which must not overflow or be zero.
+fn raster_size(factors: &[usize]) -> Result<usize> {
+    match factors
+        .iter()
+        .try_fold(1usize, |size, &factor| size.checked_mul(factor))
+    {
+        Some(0) => Err(invalid_data("Image is empty.")),
+        Some(size) => Ok(size),
+        None => Err(invalid_data("Image dimensions are too large.")),
+    }
+}
+
+/// Reads whitespace separated tokens from a Netpbm-style text header, skipping `#` comments.
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn token(&mut self) -> Result<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.position) {
+                Some(byte) if byte.is_ascii_whitespace() => self.position += 1,
+                Some(b'#') => {
+                    while !matches!(self.bytes.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(_) => break,
+                None => return Err(invalid_data("Unexpected end of image header.")),
+            }
+        }
+
+        let start = self.position;
+        while matches!(self.bytes.get(self.position), Some(byte) if !byte.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        Ok(&self.bytes[start..self.position])
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| invalid_data("Malformed number in image header."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorEncoding, TextureImage};
+    use crate::prelude::Vec3;
+
+    const TOLERANCE: f64 = 1e-6;
+
+    fn close_enough(v1: Vec3, v2: Vec3) -> bool {
+        (v1 - v2).norm() < TOLERANCE
+    }
+
+    #[test]
+    fn read_ascii_ppm_with_comments() {
+        let bytes = b"P3\n# a comment\n2 1\n255\n255 0 0  0 0 255\n";
+        let image = TextureImage::read(&mut &bytes[..], ColorEncoding::Linear).unwrap();
+
+        assert_eq!((image.width, image.height), (2, 1));
+        assert!(close_enough(image.pixel(0, 0), Vec3::new(1., 0., 0.)));
+        assert!(close_enough(image.pixel(1, 0), Vec3::new(0., 0., 1.)));
+    }
+
+    #[test]
+    fn read_binary_ppm_linearises_srgb() {
+        let mut bytes = b"P6 1 1 255\n".to_vec();
+        bytes.extend([128, 255, 0]);
+        let image = TextureImage::read(&mut bytes.as_slice(), ColorEncoding::Srgb).unwrap();
+
+        let expected = Vec3::new(crate::prelude::srgb_to_linear(128. / 255.), 1., 0.);
+        assert!(close_enough(image.pixel(0, 0), expected));
+    }
+
+    #[test]
+    fn read_pfm_flips_rows() {
+        let mut bytes = b"Pf\n1 2\n-1.0\n".to_vec();
+        bytes.extend(0.25f32.to_le_bytes());
+        bytes.extend(2.5f32.to_le_bytes());
+        let image = TextureImage::read(&mut bytes.as_slice(), ColorEncoding::Srgb).unwrap();
+
+        // The first stored row is the bottom one, and values are never gamma decoded.
+        assert!(close_enough(image.pixel(0, 0), Vec3::new(2.5, 2.5, 2.5)));
+        assert!(close_enough(image.pixel(0, 1), Vec3::new(0.25, 0.25, 0.25)));
+    }
+
+    #[test]
+    fn read_png() {
+        let mut bytes: Vec<u8> = vec![];
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 2, 1);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[255, 0, 0, 255, 0, 255, 0, 128])
+                .unwrap();
+        }
+
+        let image = TextureImage::read(&mut bytes.as_slice(), ColorEncoding::Linear).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert!(close_enough(image.pixel(0, 0), Vec3::new(1., 0., 0.)));
+        assert!(close_enough(image.pixel(1, 0), Vec3::new(0., 1., 0.)));
+
+        let alpha = TextureImage::read_alpha(&mut bytes.as_slice()).unwrap();
+        assert!(close_enough(alpha.pixel(0, 0), Vec3::new(1., 1., 1.)));
+        let half = 128. / 255.;
+        assert!(close_enough(alpha.pixel(1, 0), Vec3::new(half, half, half)));
+    }
+
+    #[test]
+    fn reject_overflowing_dimensions() {
+        let huge = usize::MAX / 2;
+        for bytes in [
+            format!("P3 {} 4 255\n0 0 0", huge),
+            format!("P6 {} 4 255\n\0\0\0", huge),
+            format!("PF\n{} 4\n-1\n\0\0\0\0", huge),
+        ] {
+            let error = TextureImage::read(&mut bytes.as_bytes(), ColorEncoding::Linear);
+            assert_eq!(error.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn reject_empty_images() {
+        for bytes in ["P3 0 0 255\n", "P6 0 4 255\n", "PF\n4 0\n-1\n"] {
+            let error = TextureImage::read(&mut bytes.as_bytes(), ColorEncoding::Linear);
+            assert_eq!(error.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn reject_unknown_format() {
+        let bytes = b"GIF89a";
+        assert!(TextureImage::read(&mut &bytes[..], ColorEncoding::Srgb).is_err());
+    }
+}
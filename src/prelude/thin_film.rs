@@ -0,0 +1,161 @@
+//! Interference in transparent films a few hundred nanometres thick, which colors soap bubbles and
+//! oil slicks.
+use crate::prelude::{HitRecord, Ray, SolidColor, Texture, Vec3, RGB_WAVELENGTHS};
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// A transparent film over a surface.
+#[derive(Clone, Debug)]
+pub struct ThinFilm {
+    /// In nanometres.
+    pub thickness: Arc<dyn Texture>,
+    pub index_of_refraction: f64,
+}
+
+impl ThinFilm {
+    pub fn new(thickness: f64, index_of_refraction: f64) -> Self {
+        Self {
+            thickness: Arc::new(SolidColor::gray(thickness)),
+            index_of_refraction,
+        }
+    }
+
+    /// Reflectance of the film between a medium of index `outside`, where `cos_theta` is measured,
+    /// and a dielectric of index `inside`. One per channel or wavelength of `ray`.
+    pub fn reflectance_over_dielectric(
+        &self,
+        cos_theta: f64,
+        outside: f64,
+        inside: f64,
+        ray: &Ray,
+        hit_record: &HitRecord,
+    ) -> Vec3 {
+        let film = self.index_of_refraction;
+        self.reflectance(cos_theta, outside, ray, hit_record, |cos_film, _| {
+            let sin2_inside = (film / inside).powi(2) * (1. - cos_film * cos_film);
+            if sin2_inside >= 1. {
+                return (1., 1.);
+            }
+            fresnel_amplitudes(cos_film, film, (1. - sin2_inside).sqrt(), inside)
+        })
+    }
+
+    /// Reflectance of the film over a metal reflecting `albedo` (as `ray` sees it). The metal is
+    /// approximated as turning the phase of light over, like a very dense dielectric.
+    pub fn reflectance_over_metal(
+        &self,
+        cos_theta: f64,
+        albedo: Vec3,
+        ray: &Ray,
+        hit_record: &HitRecord,
+    ) -> Vec3 {
+        let amplitudes = [albedo.0, albedo.1, albedo.2].map(|albedo| -albedo.max(0.).sqrt());
+        self.reflectance(cos_theta, 1., ray, hit_record, |_, channel| {
+            (amplitudes[channel], amplitudes[channel])
+        })
+    }
+
+    /// Sum the light reflected back and forth inside the film (the Airy formula), given the
+    /// amplitudes `(s, p)` the film's far side reflects for the cosine inside it, per channel.
+    fn reflectance(
+        &self,
+        cos_theta: f64,
+        outside: f64,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        far_side: impl Fn(f64, usize) -> (f64, f64),
+    ) -> Vec3 {
+        let film = self.index_of_refraction;
+        let cos_theta = cos_theta.clamp(0., 1.);
+        let sin2_film = (outside / film).powi(2) * (1. - cos_theta * cos_theta);
+        if sin2_film >= 1. {
+            return Vec3::new(1., 1., 1.);
+        }
+        let cos_film = (1. - sin2_film).sqrt();
+        let (near_s, near_p) = fresnel_amplitudes(cos_theta, outside, cos_film, film);
+        let thickness = self.thickness.scalar_at(hit_record).max(0.);
+
+        let lambda = ray.wavelengths.unwrap_or(RGB_WAVELENGTHS);
+        let [red, green, blue] = [0, 1, 2].map(|channel| {
+            let (far_s, far_p) = far_side(cos_film, channel);
+            let cos_phase = (4. * PI * film * thickness * cos_film / lambda[channel]).cos();
+            let airy = |near: f64, far: f64| {
+                let interference = 2. * near * far * cos_phase;
+                (near * near + far * far + interference)
+                    / (1. + near * near * far * far + interference)
+            };
+            0.5 * (airy(near_s, far_s) + airy(near_p, far_p))
+        });
+        Vec3::new(red, green, blue)
+    }
+}
+
+/// Amplitude reflection coefficients `(s, p)` of light going from index `n1` to `n2`.
+fn fresnel_amplitudes(cos_1: f64, n1: f64, cos_2: f64, n2: f64) -> (f64, f64) {
+    (
+        (n1 * cos_1 - n2 * cos_2) / (n1 * cos_1 + n2 * cos_2),
+        (n2 * cos_1 - n1 * cos_2) / (n2 * cos_1 + n1 * cos_2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThinFilm;
+    use crate::prelude::{
+        fresnel_dielectric, HitRecord, Hittable, Material, Point, Ray, Sphere, Vec3,
+    };
+    use std::sync::Arc;
+
+    fn hit() -> HitRecord {
+        let material = Arc::new(Material::dielectric(1.5));
+        let sphere = Sphere::new(Point::new(0., 0., 0.), 1., material);
+        let ray = Ray::new(&Point::new(0., 0., 5.), &Vec3::new(0., 0., -1.));
+        sphere.hit(&ray, 0.001, f64::INFINITY).unwrap()
+    }
+
+    #[test]
+    fn films_reduce_to_bare_interfaces() {
+        let hit = hit();
+        let ray = Ray::default();
+
+        for cos_theta in [1., 0.6, 0.2] {
+            let bare = fresnel_dielectric(cos_theta, 1.5);
+
+            // Too thin to interfere, or no different from the glass under it.
+            let vanishing = ThinFilm::new(0., 1.33);
+            let matching = ThinFilm::new(400., 1.5);
+            for film in [vanishing, matching] {
+                let reflectance = film.reflectance_over_dielectric(cos_theta, 1., 1.5, &ray, &hit);
+                assert!((reflectance.1 - bare).abs() < 1e-9, "{}", reflectance);
+            }
+        }
+    }
+
+    #[test]
+    fn quarter_wave_coating_cancels_reflection() {
+        let hit = hit();
+        let ray = Ray {
+            wavelengths: Some([550., 450., 650.]),
+            ..Ray::default()
+        };
+
+        let index = 1.5_f64.sqrt();
+        let coating = ThinFilm::new(550. / (4. * index), index);
+        let reflectance = coating.reflectance_over_dielectric(1., 1., 1.5, &ray, &hit);
+        assert!(reflectance.0 < 1e-9);
+        assert!(reflectance.1 > 1e-3 && reflectance.2 > 1e-3);
+
+        // A soap film in air changes color as it thins, and never reflects more than it receives.
+        let colors = [200., 300., 400.].map(|thickness| {
+            ThinFilm::new(thickness, 1.33).reflectance_over_dielectric(0.8, 1., 1., &ray, &hit)
+        });
+        assert!(colors[0] != colors[1] && colors[1] != colors[2]);
+        assert!(colors
+            .iter()
+            .all(|c| [c.0, c.1, c.2].iter().all(|&r| (0. ..=1.).contains(&r))));
+
+        // Over a perfect mirror all light comes back, whatever the interference.
+        let mirror = coating.reflectance_over_metal(0.7, Vec3::new(1., 1., 1.), &ray, &hit);
+        assert!((mirror - Vec3::new(1., 1., 1.)).norm() < 1e-9);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::prelude::{
+    HitRecord, Hittable, HittableList, LinAlgOp, Material, Onb, Point, Ray, SurfaceTangents,
+    UvDerivatives, Vec3,
+};
+use std::sync::Arc;
+
+/// A triangle, with optional per-vertex shading normals for smooth meshes.
+///
+/// The outward face normal follows the counter-clockwise winding of `vertices`.
+pub struct Triangle {
+    pub vertices: [Point; 3],
+    /// Normals at each vertex, interpolated across the face to shade it.
+    pub normals: Option<[Vec3; 3]>,
+    /// Texture coordinates at each vertex.
+    pub uvs: [(f64, f64); 3],
+    pub material: Arc<Material>,
+}
+
+impl Triangle {
+    pub fn new(a: Point, b: Point, c: Point, material: Arc<Material>) -> Self {
+        Self {
+            vertices: [a, b, c],
+            normals: None,
+            uvs: [(0., 0.), (1., 0.), (0., 1.)],
+            material,
+        }
+    }
+
+    /// Build a mesh out of shared vertex attributes and faces given as indices into them.
+    pub fn mesh(
+        positions: &[Point],
+        normals: Option<&[Vec3]>,
+        uvs: Option<&[(f64, f64)]>,
+        faces: &[[usize; 3]],
+        material: Arc<Material>,
+    ) -> HittableList {
+        let mut mesh = HittableList::new();
+
+        for &[a, b, c] in faces {
+            let mut triangle =
+                Triangle::new(positions[a], positions[b], positions[c], material.clone());
+            triangle.normals = normals.map(|normals| [normals[a], normals[b], normals[c]]);
+            if let Some(uvs) = uvs {
+                triangle.uvs = [uvs[a], uvs[b], uvs[c]];
+            }
+            mesh.push(Arc::new(triangle));
+        }
+        mesh
+    }
+
+    pub fn face_normal(&self) -> Vec3 {
+        let [p0, p1, p2] = self.vertices;
+        (p1 - p0).cross(p2 - p0).unit_vector()
+    }
+
+    /// The tangents of the texture mapping, which is linear across the face.
+    pub fn tangents(&self) -> SurfaceTangents {
+        let [p0, p1, p2] = self.vertices;
+        let [uv0, uv1, uv2] = self.uvs;
+        let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+        let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+
+        let determinant = du02 * dv12 - dv02 * du12;
+        if determinant.abs() < 1e-12 {
+            // Degenerate texture coordinates; any frame in the plane will do.
+            let onb = Onb::new(self.face_normal());
+            return SurfaceTangents {
+                dpdu: onb.u,
+                dpdv: onb.v,
+            };
+        }
+
+        SurfaceTangents {
+            dpdu: (dv12 * dp02 - dv02 * dp12) / determinant,
+            dpdv: (du02 * dp12 - du12 * dp02) / determinant,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    /// Möller–Trumbore intersection.
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.vertices;
+        let (edge_1, edge_2) = (p1 - p0, p2 - p0);
+
+        let p = ray.direction.cross(edge_2);
+        let determinant = edge_1.dot(p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inverse_determinant = 1. / determinant;
+
+        let to_origin = ray.origin - p0;
+        let b1 = to_origin.dot(p) * inverse_determinant;
+        if !(0. ..=1.).contains(&b1) {
+            return None;
+        }
+        let q = to_origin.cross(edge_1);
+        let b2 = ray.direction.dot(q) * inverse_determinant;
+        if b2 < 0. || b1 + b2 > 1. {
+            return None;
+        }
+        let time = edge_2.dot(q) * inverse_determinant;
+        if time < t_min || time > t_max {
+            return None;
+        }
+
+        let b0 = 1. - b1 - b2;
+        let point = ray.at(time);
+        let outward_facing_normal = edge_1.cross(edge_2).unit_vector();
+        let is_front_facing = ray.direction.dot(outward_facing_normal) < 0.;
+        let geometric_normal = if is_front_facing {
+            outward_facing_normal
+        } else {
+            -outward_facing_normal
+        };
+
+        let [uv0, uv1, uv2] = self.uvs;
+        let tangents = self.tangents();
+
+        let mut hit_record = HitRecord {
+            point,
+            normal: geometric_normal,
+            geometric_normal,
+            time,
+            is_front_facing,
+            u: b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            v: b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            uv_derivatives: UvDerivatives::from_ray(
+                ray,
+                point,
+                outward_facing_normal,
+                tangents.dpdu,
+                tangents.dpdv,
+            ),
+            tangents: Some(tangents),
+            material: self.material.clone(),
+            interior: None,
+            surrounding_ior: 1.,
+        };
+
+        if let Some([n0, n1, n2]) = self.normals {
+            let mut shading_normal = (b0 * n0 + b1 * n1 + b2 * n2).unit_vector();
+            if shading_normal.dot(geometric_normal) < 0. {
+                shading_normal = -shading_normal;
+            }
+            hit_record.set_shading_normal(ray, shading_normal);
+        }
+
+        Some(hit_record)
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "Triangle {{ vertices: [{}, {}, {}] }}",
+            self.vertices[0], self.vertices[1], self.vertices[2]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Triangle;
+    use crate::prelude::{Hittable, LinAlgOp, Material, Point, Ray, Vec3};
+    use std::sync::Arc;
+
+    const TOLERANCE: f64 = 1e-9;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            Point::new(0., 0., 0.),
+            Point::new(2., 0., 0.),
+            Point::new(0., 2., 0.),
+            Arc::new(Material::lambertian(Vec3::new(0.5, 0.5, 0.5))),
+        )
+    }
+
+    #[test]
+    fn hit_interpolates_uvs_and_tangents() {
+        let triangle = triangle();
+        let ray = Ray::new(&Point::new(0.5, 0.25, 1.), &Vec3::new(0., 0., -1.));
+        let hit = triangle.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+        assert!((hit.time - 1.).abs() < TOLERANCE);
+        assert!(hit.is_front_facing);
+        assert!((hit.u - 0.25).abs() < TOLERANCE && (hit.v - 0.125).abs() < TOLERANCE);
+        assert!((hit.normal - Vec3::new(0., 0., 1.)).norm() < TOLERANCE);
+
+        let tangents = hit.tangents.unwrap();
+        assert!((tangents.dpdu - Vec3::new(2., 0., 0.)).norm() < TOLERANCE);
+        assert!((tangents.dpdv - Vec3::new(0., 2., 0.)).norm() < TOLERANCE);
+
+        let miss = Ray::new(&Point::new(1.5, 1.5, 1.), &Vec3::new(0., 0., -1.));
+        assert!(triangle.hit(&miss, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn facing_follows_the_geometry_not_the_shading_normals() {
+        let mut triangle = triangle();
+        // Judged by these shading normals, a grazing ray from below would look front facing.
+        let tilted = Vec3::new(-1., 0., 0.1).unit_vector();
+        triangle.normals = Some([tilted; 3]);
+
+        let ray = Ray::new(&Point::new(-0.4, 0.5, -0.1), &Vec3::new(1., 0., 0.2));
+        let hit = triangle.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+        assert!(!hit.is_front_facing);
+        assert!((hit.geometric_normal - Vec3::new(0., 0., -1.)).norm() < TOLERANCE);
+        assert!(hit.normal.dot(hit.geometric_normal) > 0.);
+        assert!(hit.normal.dot(-ray.direction) > 0.);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::prelude::rng;
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
 use std::fmt::{Debug, Display, Formatter};
@@ -142,7 +143,7 @@ impl Vec3 {
 
 impl Vec3 {
     pub fn rand_uniform(min: f64, max: f64) -> Self {
-        let mut rng = rand::thread_rng();
+        let mut rng = rng();
         Self(
             rng.gen_range(min..max),
             rng.gen_range(min..max),
@@ -331,6 +332,40 @@ impl LinAlgOp for Vec3 {
     }
 }
 
+/// An orthonormal basis whose `w` axis is a given unit vector.
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// Complete a basis around the unit vector `w` (Duff et al., "Building an Orthonormal Basis,
+    /// Revisited").
+    pub fn new(w: Vec3) -> Self {
+        let sign = 1_f64.copysign(w.2);
+        let a = -1. / (sign + w.2);
+        let b = w.0 * w.1 * a;
+
+        Self {
+            u: Vec3::new(1. + sign * w.0 * w.0 * a, sign * b, -sign * w.0),
+            v: Vec3::new(b, sign + w.1 * w.1 * a, -w.1),
+            w,
+        }
+    }
+
+    /// A vector given in this basis, expressed in world coordinates.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.0 * self.u + a.1 * self.v + a.2 * self.w
+    }
+
+    /// A world space vector, expressed in this basis.
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
+
 pub fn reflectance(cosine: f64, refractive_index: f64) -> f64 {
     let mut r0 = (1. - refractive_index) / (1. + refractive_index);
     r0 *= r0;
@@ -341,7 +376,7 @@ pub fn reflectance(cosine: f64, refractive_index: f64) -> f64 {
 #[cfg(test)]
 mod tests {
 
-    use crate::prelude::{LinAlgOp, LinAlgRandGen, Vec3};
+    use crate::prelude::{LinAlgOp, LinAlgRandGen, Onb, Vec3};
 
     use rand::Rng;
 
@@ -392,6 +427,25 @@ mod tests {
         assert_eq!(v.norm(), 14.0_f64.sqrt());
     }
 
+    #[test]
+    fn test_onb_is_orthonormal() {
+        for w in [
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0., 0., -1.),
+            Vec3::new(1., 2., -3.).unit_vector(),
+        ] {
+            let onb = Onb::new(w);
+            for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+                assert!(a.dot(b).abs() < 1e-12);
+                assert!((a.norm() - 1.).abs() < 1e-12);
+            }
+            assert!((onb.u.cross(onb.v) - w).norm() < 1e-12);
+
+            let a = Vec3::new(0.3, -0.2, 0.9);
+            assert!((onb.to_local(onb.local(a)) - a).norm() < 1e-12);
+        }
+    }
+
     #[test]
     fn test_near_zero() {
         let v: Vec3 = [1e-21, 1e-21, 1e-24].into();
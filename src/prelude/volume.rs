@@ -0,0 +1,511 @@
+//! Heterogeneous participating media backed by voxel grids.
+//!
+//! Grids are stored in a small binary format (all values little-endian):
+//!
+//! | field      | type       | notes                                              |
+//! |------------|------------|----------------------------------------------------|
+//! | magic      | `[u8; 4]`  | `b"TGRD"`                                          |
+//! | version    | `u32`      | currently `1`                                      |
+//! | dimensions | `[u32; 3]` | voxel count along x, y and z                       |
+//! | bounds     | `[f64; 6]` | world-space min corner, then max corner            |
+//! | encoding   | `u8`       | `0` for dense, `1` for sparse                      |
+//! | payload    |            | see below                                          |
+//!
+//! A dense payload is `nx * ny * nz` `f32` values with x varying fastest, then y, then z.
+//! A sparse payload is a `u64` entry count followed by that many `(u32, f32)` pairs of
+//! linear voxel index (same ordering as dense) and value. Voxels not listed are zero.
+use crate::prelude::{rng, HitRecord, Material, Point, Ray};
+use rand::Rng;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const GRID_MAGIC: &[u8; 4] = b"TGRD";
+const GRID_VERSION: u32 = 1;
+
+#[derive(Clone)]
+enum VoxelData {
+    Dense(Vec<f32>),
+    Sparse(HashMap<u32, f32>),
+}
+
+/// A scalar field sampled on a regular grid of voxels spanning an axis-aligned box.
+#[derive(Clone)]
+pub struct VoxelGrid {
+    pub dimensions: (usize, usize, usize),
+    pub min: Point,
+    pub max: Point,
+    data: VoxelData,
+    max_value: f64,
+}
+
+impl Debug for VoxelGrid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "VoxelGrid {{ dimensions: {:?}, min: {}, max: {}, sparse: {} }}",
+            self.dimensions,
+            self.min,
+            self.max,
+            matches!(self.data, VoxelData::Sparse(_))
+        )
+    }
+}
+
+/// The number of voxels of a grid, which needs at least one along each axis.
+fn voxel_count(dimensions: (usize, usize, usize)) -> Result<usize> {
+    let (nx, ny, nz) = dimensions;
+    if nx == 0 || ny == 0 || nz == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Voxel grid dimensions {:?} must not be zero.", dimensions),
+        ));
+    }
+    nx.checked_mul(ny)
+        .and_then(|count| count.checked_mul(nz))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Voxel grid dimensions {:?} are too large.", dimensions),
+            )
+        })
+}
+
+impl VoxelGrid {
+    pub fn dense(
+        dimensions: (usize, usize, usize),
+        min: Point,
+        max: Point,
+        values: Vec<f32>,
+    ) -> Result<Self> {
+        if values.len() != voxel_count(dimensions)? {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "A dense grid needs exactly one value per voxel.",
+            ));
+        }
+        let max_value = values
+            .iter()
+            .fold(0., |acc: f64, &value| acc.max(value as f64));
+
+        Ok(Self {
+            dimensions,
+            min,
+            max,
+            data: VoxelData::Dense(values),
+            max_value,
+        })
+    }
+
+    pub fn sparse<I: IntoIterator<Item = (usize, f32)>>(
+        dimensions: (usize, usize, usize),
+        min: Point,
+        max: Point,
+        entries: I,
+    ) -> Result<Self> {
+        let voxel_count = voxel_count(dimensions)?;
+        let values: HashMap<u32, f32> = entries
+            .into_iter()
+            .filter(|&(index, value)| index < voxel_count && value != 0.)
+            .map(|(index, value)| (index as u32, value))
+            .collect();
+        let max_value = values
+            .values()
+            .fold(0., |acc: f64, &value| acc.max(value as f64));
+
+        Ok(Self {
+            dimensions,
+            min,
+            max,
+            data: VoxelData::Sparse(values),
+            max_value,
+        })
+    }
+
+    /// The largest value stored in the grid, used as the majorant for tracking.
+    pub fn max_value(&self) -> f64 {
+        self.max_value
+    }
+
+    pub fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        let (nx, ny, nz) = self.dimensions;
+        if x >= nx || y >= ny || z >= nz {
+            return 0.;
+        }
+        let index = x + nx * (y + ny * z);
+
+        match &self.data {
+            VoxelData::Dense(values) => values[index] as f64,
+            VoxelData::Sparse(values) => values.get(&(index as u32)).copied().unwrap_or(0.) as f64,
+        }
+    }
+
+    /// Trilinearly interpolate the grid at a world-space point. Voxel values live at voxel centers,
+    /// lookups between the outermost centers and the bounds clamp to the edge, and the field is
+    /// zero outside the bounds.
+    pub fn sample(&self, point: Point) -> f64 {
+        let extent = self.max - self.min;
+        let local = (point - self.min) / extent;
+
+        if !(0. ..=1.).contains(&local.0)
+            || !(0. ..=1.).contains(&local.1)
+            || !(0. ..=1.).contains(&local.2)
+        {
+            return 0.;
+        }
+
+        let (nx, ny, nz) = self.dimensions;
+        let gx = local.0 * nx as f64 - 0.5;
+        let gy = local.1 * ny as f64 - 0.5;
+        let gz = local.2 * nz as f64 - 0.5;
+
+        let (x0, y0, z0) = (gx.floor(), gy.floor(), gz.floor());
+        let (fx, fy, fz) = (gx - x0, gy - y0, gz - z0);
+
+        let mut value = 0.;
+        for (dz, wz) in [(0, 1. - fz), (1, fz)] {
+            for (dy, wy) in [(0, 1. - fy), (1, fy)] {
+                for (dx, wx) in [(0, 1. - fx), (1, fx)] {
+                    let weight = wx * wy * wz;
+                    if weight == 0. {
+                        continue;
+                    }
+                    let x = (x0 as isize + dx).clamp(0, nx as isize - 1) as usize;
+                    let y = (y0 as isize + dy).clamp(0, ny as isize - 1) as usize;
+                    let z = (z0 as isize + dz).clamp(0, nz as isize - 1) as usize;
+                    value += weight * self.voxel(x, y, z);
+                }
+            }
+        }
+        value
+    }
+
+    /// Clip a ray against the grid bounds (slab test).
+    pub fn clip(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+
+        for axis in 0..3 {
+            let inverse_direction = 1. / ray.direction[axis];
+            let mut near = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let mut far = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaNs from rays parallel to a slab through its boundary are ignored by max/min.
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t0 > t1 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    /// Walk `ray` through the grid between `t_min` and `t_max`, stopping at tentative collisions
+    /// as frequent as in a medium of extinction `majorant` throughout, which must bound the real
+    /// extinction where the grid is densest. `collide` is given the time and density of each, and
+    /// ends the walk with what it returns, if anything. Delta and ratio tracking differ only there.
+    pub fn track<T>(
+        &self,
+        ray: &Ray,
+        t_min: f64,
+        t_max: f64,
+        majorant: f64,
+        mut collide: impl FnMut(f64, f64) -> Option<T>,
+    ) -> Option<T> {
+        if majorant <= 0. {
+            return None;
+        }
+        let (t0, t1) = self.clip(ray, t_min, t_max)?;
+
+        let mut rng = rng();
+        let speed = ray.direction.norm();
+        let mut time = t0;
+        loop {
+            time -= (1. - rng.gen::<f64>()).ln() / (majorant * speed);
+            if time >= t1 {
+                return None;
+            }
+            if let Some(result) = collide(time, self.sample(ray.at(time))) {
+                return Some(result);
+            }
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != GRID_MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Not a voxel grid: bad magic.",
+            ));
+        }
+
+        let version = read_u32(reader)?;
+        if version != GRID_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported voxel grid version {}.", version),
+            ));
+        }
+
+        let dimensions = (
+            read_u32(reader)? as usize,
+            read_u32(reader)? as usize,
+            read_u32(reader)? as usize,
+        );
+        let min = Point::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        let max = Point::new(read_f64(reader)?, read_f64(reader)?, read_f64(reader)?);
+        let voxel_count = voxel_count(dimensions)?;
+
+        let mut encoding = [0u8; 1];
+        reader.read_exact(&mut encoding)?;
+
+        match encoding[0] {
+            0 => {
+                let values = (0..voxel_count)
+                    .map(|_| read_f32(reader))
+                    .collect::<Result<Vec<f32>>>()?;
+                Self::dense(dimensions, min, max, values)
+            }
+            1 => {
+                let mut count = [0u8; 8];
+                reader.read_exact(&mut count)?;
+                let entries = (0..u64::from_le_bytes(count))
+                    .map(|_| Ok((read_u32(reader)? as usize, read_f32(reader)?)))
+                    .collect::<Result<Vec<(usize, f32)>>>()?;
+                Self::sparse(dimensions, min, max, entries)
+            }
+            other => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unknown voxel grid encoding {}.", other),
+            )),
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(GRID_MAGIC)?;
+        writer.write_all(&GRID_VERSION.to_le_bytes())?;
+        for dimension in [self.dimensions.0, self.dimensions.1, self.dimensions.2] {
+            writer.write_all(&(dimension as u32).to_le_bytes())?;
+        }
+        for bound in [
+            self.min.0, self.min.1, self.min.2, self.max.0, self.max.1, self.max.2,
+        ] {
+            writer.write_all(&bound.to_le_bytes())?;
+        }
+
+        match &self.data {
+            VoxelData::Dense(values) => {
+                writer.write_all(&[0])?;
+                for value in values {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            VoxelData::Sparse(values) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&(values.len() as u64).to_le_bytes())?;
+                let mut entries = values.iter().collect::<Vec<_>>();
+                entries.sort_by_key(|(&index, _)| index);
+                for (index, value) in entries {
+                    writer.write_all(&index.to_le_bytes())?;
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> Result<f32> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(f32::from_le_bytes(buffer))
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> Result<f64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(f64::from_le_bytes(buffer))
+}
+
+/// A heterogeneous medium whose extinction coefficient is read from a voxel grid.
+///
+/// Free-flight distances are sampled with delta tracking against the grid's majorant, and each
+/// real collision is reported as a hit whose material is the volume's phase function.
+pub struct GridVolume {
+    pub density: Arc<VoxelGrid>,
+    pub density_scale: f64,
+    pub phase_function: Arc<Material>,
+}
+
+impl GridVolume {
+    pub fn new(density: Arc<VoxelGrid>, density_scale: f64, phase_function: Arc<Material>) -> Self {
+        Self {
+            density,
+            density_scale,
+            phase_function,
+        }
+    }
+
+    /// The extinction coefficient at a point.
+    pub fn extinction(&self, point: Point) -> f64 {
+        self.density_scale * self.density.sample(point)
+    }
+
+    pub fn majorant(&self) -> f64 {
+        self.density_scale * self.density.max_value()
+    }
+
+    /// Estimate the transmittance along a ray segment with ratio tracking.
+    pub fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let majorant = self.majorant();
+        let mut transmittance = 1.;
+        self.density
+            .track(ray, t_min, t_max, majorant, |_, density| {
+                transmittance *= 1. - self.density_scale * density / majorant;
+                None::<()>
+            });
+        transmittance
+    }
+}
+
+impl crate::prelude::Hittable for GridVolume {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let majorant = self.majorant();
+        let mut rng = rng();
+
+        // Delta tracking: accept each tentative collision with probability equal to the ratio of
+        // the real to the majorant extinction.
+        self.density
+            .track(ray, t_min, t_max, majorant, |time, density| {
+                (self.density_scale * density / majorant > rng.gen::<f64>())
+                    .then(|| HitRecord::collision(ray, time, self.phase_function.clone()))
+            })
+    }
+
+    fn metadata(&self) -> String {
+        format!(
+            "GridVolume {{ density: {:?}, density_scale: {} }}",
+            self.density, self.density_scale
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GridVolume, VoxelGrid};
+    use crate::prelude::{Material, Point, Ray, Vec3};
+    use std::sync::Arc;
+
+    fn unit_box() -> (Point, Point) {
+        (Point::new(0., 0., 0.), Point::new(1., 1., 1.))
+    }
+
+    #[test]
+    fn grid_roundtrip_dense_and_sparse() {
+        let (min, max) = unit_box();
+        let dense = VoxelGrid::dense((2, 1, 1), min, max, vec![0.25, 0.75]).unwrap();
+        let sparse = VoxelGrid::sparse((4, 4, 4), min, max, vec![(5, 2.0), (63, 0.5)]).unwrap();
+
+        for grid in [dense, sparse] {
+            let mut bytes: Vec<u8> = vec![];
+            grid.write(&mut bytes).unwrap();
+            let read = VoxelGrid::read(&mut bytes.as_slice()).unwrap();
+
+            assert_eq!(read.dimensions, grid.dimensions);
+            assert_eq!(read.max_value(), grid.max_value());
+            for index in 0..(grid.dimensions.0 * grid.dimensions.1 * grid.dimensions.2) {
+                let (nx, ny) = (grid.dimensions.0, grid.dimensions.1);
+                let (x, y, z) = (index % nx, (index / nx) % ny, index / (nx * ny));
+                assert_eq!(read.voxel(x, y, z), grid.voxel(x, y, z));
+            }
+        }
+    }
+
+    #[test]
+    fn grid_rejects_bad_magic() {
+        let bytes = b"NOPE".to_vec();
+        assert!(VoxelGrid::read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn grid_rejects_empty_and_overflowing_dimensions() {
+        let (min, max) = unit_box();
+        assert!(VoxelGrid::dense((0, 0, 0), min, max, vec![]).is_err());
+        assert!(VoxelGrid::sparse((4, 0, 4), min, max, vec![]).is_err());
+        assert!(VoxelGrid::sparse((usize::MAX, 2, 1), min, max, vec![]).is_err());
+
+        // A header with a zero dimension and nothing after it.
+        let mut bytes = b"TGRD".to_vec();
+        bytes.extend(1u32.to_le_bytes());
+        for dimension in [2u32, 0, 2] {
+            bytes.extend(dimension.to_le_bytes());
+        }
+        for bound in [0f64, 0., 0., 1., 1., 1.] {
+            bytes.extend(bound.to_le_bytes());
+        }
+        bytes.push(0);
+        let error = VoxelGrid::read(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn grid_trilinear_lookup() {
+        let (min, max) = unit_box();
+        let grid = VoxelGrid::dense((2, 1, 1), min, max, vec![0., 1.]).unwrap();
+
+        // Voxel centers sit at x = 0.25 and x = 0.75.
+        assert!((grid.sample(Point::new(0.25, 0.5, 0.5)) - 0.).abs() < 1e-12);
+        assert!((grid.sample(Point::new(0.5, 0.5, 0.5)) - 0.5).abs() < 1e-12);
+        assert!((grid.sample(Point::new(0.75, 0.5, 0.5)) - 1.).abs() < 1e-12);
+        assert_eq!(grid.sample(Point::new(2., 0.5, 0.5)), 0.);
+    }
+
+    #[test]
+    fn ratio_tracking_matches_beer_lambert() {
+        let (min, max) = unit_box();
+        let grid = VoxelGrid::dense((1, 1, 1), min, max, vec![1.]).unwrap();
+        let volume = GridVolume::new(
+            Arc::new(grid),
+            2.,
+            Arc::new(Material::isotropic(Vec3::new(1., 1., 1.))),
+        );
+        let ray = Ray::new(&Point::new(-1., 0.5, 0.5), &Vec3::new(1., 0., 0.));
+
+        let trials = 20_000;
+        let estimate = (0..trials)
+            .map(|_| volume.transmittance(&ray, 0., f64::INFINITY))
+            .sum::<f64>()
+            / trials as f64;
+
+        let expected = (-2.0_f64).exp();
+        assert!(
+            (estimate - expected).abs() < 0.02,
+            "Ratio tracking estimated {} but Beer-Lambert gives {}.",
+            estimate,
+            expected
+        );
+    }
+}